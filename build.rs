@@ -0,0 +1,26 @@
+//! Builds the assembly shim (with the `cc_shim` feature) for the targets on
+//! which inline assembly is not stable yet.
+
+fn main() {
+    println!("cargo::rerun-if-changed=build.rs");
+    println!("cargo::rustc-check-cfg=cfg(eraser_shim)");
+    #[cfg(feature = "cc_shim")]
+    build_shim();
+}
+
+/// Assemble `src/shim/<arch>.S` if there is one for the target, and tell the
+/// crate to use it instead of its inline assembly.
+#[cfg(feature = "cc_shim")]
+fn build_shim() {
+    let arch = std::env::var("CARGO_CFG_TARGET_ARCH").unwrap();
+    let os = std::env::var("CARGO_CFG_TARGET_OS").unwrap();
+    let source = match (arch.as_str(), os.as_str()) {
+        ("mips", "linux") => "src/shim/mips.S",
+        ("mips64", "linux") => "src/shim/mips64.S",
+        ("sparc64", "linux") => "src/shim/sparc64.S",
+        _ => return,
+    };
+    println!("cargo::rerun-if-changed={source}");
+    cc::Build::new().file(source).compile("eraser_shim");
+    println!("cargo::rustc-cfg=eraser_shim");
+}
@@ -0,0 +1,219 @@
+#![deny(missing_docs)]
+
+/*!
+Procedural macros for the [`eraser`](https://docs.rs/eraser) crate.
+
+Do not use this crate directly; enable the `macros` feature of `eraser`
+instead.
+*/
+
+use proc_macro::{Delimiter, Group, Ident, Punct, Spacing, Span, TokenStream, TokenTree};
+
+/// Run the body of a function on an ephemeral stack that is erased afterwards.
+///
+/// The body of the annotated function is wrapped in a `move` closure and run
+/// with `eraser::run_then_erase`.  The arguments of the function are moved
+/// into the closure, and the value returned by the closure is returned from
+/// the function.
+///
+/// The stack size can be configured with `stack_size = <expr>`; it defaults to
+/// `eraser::DEFAULT_STACK_SIZE`.
+///
+/// ```ignore
+/// #[eraser::protected(stack_size = 64 * 1024)]
+/// fn hmac(key: &[u8; 32], msg: &[u8]) -> [u8; 32] {
+///     // ...
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn protected(attr: TokenStream, item: TokenStream) -> TokenStream {
+    match expand_protected(attr, item) {
+        Ok(tokens) => tokens,
+        Err((span, msg)) => compile_error(span, msg),
+    }
+}
+
+type Error = (Span, &'static str);
+
+fn expand_protected(attr: TokenStream, item: TokenStream) -> Result<TokenStream, Error> {
+    let stack_size = parse_stack_size(attr)?;
+
+    let mut sig: Vec<TokenTree> = item.into_iter().collect();
+    let body = match sig.pop() {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace => group,
+        Some(tt) => return Err((tt.span(), "expected a function with a body")),
+        None => return Err((Span::call_site(), "expected a function")),
+    };
+
+    let mut is_fn = false;
+    for tt in &sig {
+        if let TokenTree::Ident(ident) = tt {
+            match ident.to_string().as_str() {
+                "fn" => is_fn = true,
+                "async" if !is_fn => {
+                    return Err((ident.span(), "async functions cannot be protected"))
+                }
+                "const" if !is_fn => {
+                    return Err((ident.span(), "const functions cannot be protected"))
+                }
+                _ => {}
+            }
+        }
+    }
+    if !is_fn {
+        return Err((body.span(), "expected a function"));
+    }
+
+    // move || -> Ret { body }
+    let mut closure = TokenStream::new();
+    closure.extend([
+        TokenTree::Ident(Ident::new("move", Span::call_site())),
+        TokenTree::Punct(Punct::new('|', Spacing::Joint)),
+        TokenTree::Punct(Punct::new('|', Spacing::Alone)),
+    ]);
+    if let Some(ret) = return_type(&sig) {
+        closure.extend(ret);
+    }
+    closure.extend([TokenTree::Group(body.clone())]);
+
+    // ::eraser::run_then_erase(closure, stack_size)
+    let mut args = closure;
+    args.extend([TokenTree::Punct(Punct::new(',', Spacing::Alone))]);
+    args.extend(stack_size);
+    let mut call = path(&["eraser", "run_then_erase"]);
+    call.extend([TokenTree::Group(Group::new(Delimiter::Parenthesis, args))]);
+
+    let mut new_body = Group::new(Delimiter::Brace, call);
+    new_body.set_span(body.span());
+
+    let mut output: TokenStream = sig.into_iter().collect();
+    output.extend([TokenTree::Group(new_body)]);
+    Ok(output)
+}
+
+/// Parse the attribute arguments, which are either empty or
+/// `stack_size = <expr>`.
+fn parse_stack_size(attr: TokenStream) -> Result<TokenStream, Error> {
+    let mut tokens = attr.into_iter();
+    match tokens.next() {
+        None => Ok(path(&["eraser", "DEFAULT_STACK_SIZE"])),
+        Some(TokenTree::Ident(ident)) if ident.to_string() == "stack_size" => {
+            match tokens.next() {
+                Some(TokenTree::Punct(punct)) if punct.as_char() == '=' => {}
+                _ => return Err((ident.span(), "expected `stack_size = <expr>`")),
+            }
+            let expr: TokenStream = tokens.collect();
+            if expr.is_empty() {
+                return Err((ident.span(), "expected `stack_size = <expr>`"));
+            }
+            Ok(expr)
+        }
+        Some(tt) => Err((
+            tt.span(),
+            "unknown argument, expected `stack_size = <expr>`",
+        )),
+    }
+}
+
+/// Find the `-> Ret` part of the signature, so that the closure can be
+/// annotated with the same return type.  This is needed when the body uses
+/// `?` or `return`.
+///
+/// Only the tokens between the parameter list and the `where` clause are
+/// searched, because the generic parameters and the `where` clause can
+/// contain an `->` of their own (as in `F: Fn() -> u8`).
+///
+/// Returns `None` if the function returns `()`, or if the return type cannot
+/// be written on a closure (i.e. it contains `impl Trait`).
+fn return_type(sig: &[TokenTree]) -> Option<Vec<TokenTree>> {
+    let params = parameter_list(sig)?;
+    let ret: Vec<TokenTree> = sig[params + 1..]
+        .iter()
+        .take_while(|tt| !matches!(tt, TokenTree::Ident(ident) if ident.to_string() == "where"))
+        .cloned()
+        .collect();
+    let arrow = ret.windows(2).position(|pair| match pair {
+        [TokenTree::Punct(a), TokenTree::Punct(b)] => {
+            a.as_char() == '-' && a.spacing() == Spacing::Joint && b.as_char() == '>'
+        }
+        _ => false,
+    })?;
+    let ret = ret[arrow..].to_vec();
+    let has_impl = ret
+        .iter()
+        .any(|tt| matches!(tt, TokenTree::Ident(ident) if ident.to_string() == "impl"));
+    if has_impl {
+        None
+    } else {
+        Some(ret)
+    }
+}
+
+/// Return the index of the parameter list of the function in `sig`, i.e. the
+/// first parenthesized group after the name of the function that is not
+/// nested in its generic parameters.
+fn parameter_list(sig: &[TokenTree]) -> Option<usize> {
+    let name = sig
+        .iter()
+        .position(|tt| matches!(tt, TokenTree::Ident(ident) if ident.to_string() == "fn"))?
+        + 1;
+    let mut depth = 0usize;
+    for (index, tt) in sig.iter().enumerate().skip(name + 1) {
+        match tt {
+            TokenTree::Punct(punct) if punct.as_char() == '<' => depth += 1,
+            // The `>` of an `->` does not close a generic parameter list
+            TokenTree::Punct(punct) if punct.as_char() == '>' => {
+                let is_arrow = match &sig[index - 1] {
+                    TokenTree::Punct(prev) => {
+                        prev.as_char() == '-' && prev.spacing() == Spacing::Joint
+                    }
+                    _ => false,
+                };
+                if !is_arrow {
+                    depth = depth.saturating_sub(1);
+                }
+            }
+            TokenTree::Group(group)
+                if depth == 0 && group.delimiter() == Delimiter::Parenthesis =>
+            {
+                return Some(index);
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Build an absolute path like `::eraser::run_then_erase`.
+fn path(segments: &[&str]) -> TokenStream {
+    let mut tokens = TokenStream::new();
+    for segment in segments {
+        tokens.extend([
+            TokenTree::Punct(Punct::new(':', Spacing::Joint)),
+            TokenTree::Punct(Punct::new(':', Spacing::Alone)),
+            TokenTree::Ident(Ident::new(segment, Span::call_site())),
+        ]);
+    }
+    tokens
+}
+
+fn compile_error(span: Span, msg: &str) -> TokenStream {
+    let mut tokens = path(&["core", "compile_error"]);
+    let mut msg = proc_macro::Literal::string(msg);
+    msg.set_span(span);
+    tokens.extend([
+        TokenTree::Punct(Punct::new('!', Spacing::Alone)),
+        TokenTree::Group(Group::new(
+            Delimiter::Parenthesis,
+            TokenStream::from(TokenTree::Literal(msg)),
+        )),
+        TokenTree::Punct(Punct::new(';', Spacing::Alone)),
+    ]);
+    tokens
+        .into_iter()
+        .map(|mut tt| {
+            tt.set_span(span);
+            tt
+        })
+        .collect()
+}
@@ -0,0 +1,76 @@
+#[eraser::protected]
+fn add(a: u32, b: u32) -> u32 {
+    a + b
+}
+
+#[eraser::protected(stack_size = 16 * 1024)]
+fn checked_sum(key: &[u8]) -> Result<u8, &'static str> {
+    let mut sum = 0u8;
+    for &x in key {
+        sum = sum.checked_add(x).ok_or("overflow")?;
+    }
+    Ok(sum)
+}
+
+struct Key([u8; 4]);
+
+impl Key {
+    #[eraser::protected(stack_size = 8192)]
+    fn xor(&self) -> u8 {
+        self.0.iter().fold(0, |acc, &x| acc ^ x)
+    }
+}
+
+#[eraser::protected]
+fn generic<T>(value: &T) -> Vec<T>
+where
+    T: Clone + Default,
+{
+    if false {
+        return Vec::new();
+    }
+    vec![value.clone(), T::default()]
+}
+
+#[eraser::protected]
+fn apply<G: Fn() -> u8>(g: G) -> u32 {
+    if false {
+        return 0;
+    }
+    g() as u32 + 1
+}
+
+#[eraser::protected]
+fn apply_where<G, T>(g: G) -> Option<T>
+where
+    G: Fn() -> T,
+{
+    if false {
+        return None;
+    }
+    Some(g())
+}
+
+#[eraser::protected]
+fn call_where<G>(g: G)
+where
+    G: Fn() -> u8,
+{
+    assert_eq!(g(), 3);
+}
+
+#[test]
+fn arguments_and_return_values() {
+    assert_eq!(add(2, 3), 5);
+    assert_eq!(checked_sum(&[1, 2, 3]), Ok(6));
+    assert_eq!(checked_sum(&[255, 1]), Err("overflow"));
+    assert_eq!(Key([1, 2, 4, 8]).xor(), 15);
+    assert_eq!(generic(&7), vec![7, 0]);
+}
+
+#[test]
+fn arrows_in_generic_bounds() {
+    assert_eq!(apply(|| 41), 42);
+    assert_eq!(apply_where(|| "x"), Some("x"));
+    call_where(|| 3);
+}
@@ -0,0 +1,134 @@
+//! Running the user function from an async task, for executors like Embassy.
+//!
+//! The user function cannot be suspended: it runs to completion on the
+//! ephemeral stack, and that stack is erased before control goes back to the
+//! executor.  So, from the point of view of the executor, a run is one long
+//! poll.  `BlockingInPlace` only makes sure that this poll is not longer than
+//! it needs to be, by yielding once before the run, so that the tasks that
+//! were already woken get to run first.
+
+use crate::{EraseConfig, EraserError};
+use core::future::Future;
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+/// Run a function on a caller-provided stack from an async task, and
+/// immediately erase the stack.
+///
+/// The returned future yields to the executor once, and then runs `f` like
+/// [`try_run_then_erase_with_uninit_stack`] does, in place, within a single
+/// poll.  The executor is blocked while `f` runs, so this is meant for short
+/// computations on a single-core MCU, where there is no other thread to move
+/// the work to.  The stack can be reused for any number of runs, e.g. by
+/// declaring it once per task with [`static_erased_stack!`].
+///
+/// Until the run, the future holds `f`.  When the future is dropped without
+/// having been run, `f` is dropped, and its slot is erased.
+///
+/// ## Safety
+///
+/// * The stack buffer must be large enough for the user function.
+///
+/// ## Example
+/// ```
+/// use core::mem::MaybeUninit;
+///
+/// async fn checksums(messages: &[[u8; 16]], stack: &mut [MaybeUninit<u8>]) -> u8 {
+///     let mut checksum = 0;
+///     for msg in messages {
+///         let run = unsafe {
+///             eraser::run_then_erase_blocking_in_place(|| msg.iter().fold(0, |acc, x| acc ^ x), stack)
+///         };
+///         checksum ^= run.await.unwrap();
+///     }
+///     checksum
+/// }
+///
+/// // In an Embassy application, this would be an `#[embassy_executor::task]`,
+/// // which is only started once, so it can declare the stack
+/// async fn worker(batches: &[[[u8; 16]; 4]]) -> u8 {
+///     let stack = eraser::static_erased_stack!(4096).unwrap();
+///     let mut checksum = 0;
+///     for batch in batches {
+///         checksum ^= checksums(batch, stack).await;
+///     }
+///     checksum
+/// }
+/// ```
+///
+/// [`try_run_then_erase_with_uninit_stack`]: crate::try_run_then_erase_with_uninit_stack
+/// [`static_erased_stack!`]: crate::static_erased_stack
+pub unsafe fn run_then_erase_blocking_in_place<F, R>(
+    f: F,
+    stack: &mut [MaybeUninit<u8>],
+) -> BlockingInPlace<'_, F, R>
+where
+    F: FnOnce() -> R,
+{
+    BlockingInPlace {
+        f: MaybeUninit::new(f),
+        stack,
+        state: State::Fresh,
+        _result: PhantomData,
+    }
+}
+
+/// The future returned by [`run_then_erase_blocking_in_place`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct BlockingInPlace<'a, F, R> {
+    f: MaybeUninit<F>,
+    stack: &'a mut [MaybeUninit<u8>],
+    state: State,
+    _result: PhantomData<fn() -> R>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Not polled yet.
+    Fresh,
+    /// Yielded to the executor once, the run happens on the next poll.
+    Yielded,
+    /// `f` has been moved out.
+    Done,
+}
+
+// `f` is never pinned: it is moved onto the ephemeral stack when it runs.
+impl<F, R> Unpin for BlockingInPlace<'_, F, R> {}
+
+impl<F, R> Future for BlockingInPlace<'_, F, R>
+where
+    F: FnOnce() -> R,
+{
+    type Output = Result<R, EraserError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match this.state {
+            State::Fresh => {
+                this.state = State::Yielded;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            State::Yielded => {
+                this.state = State::Done;
+                let f = unsafe { this.f.as_ptr().read() };
+                unsafe { crate::erase_slot(&mut this.f, &EraseConfig::default()) };
+                Poll::Ready(unsafe { crate::try_run_then_erase_with_uninit_stack(f, this.stack) })
+            }
+            State::Done => panic!("`BlockingInPlace` polled after completion"),
+        }
+    }
+}
+
+impl<F, R> Drop for BlockingInPlace<'_, F, R> {
+    fn drop(&mut self) {
+        if self.state != State::Done {
+            unsafe {
+                self.f.assume_init_drop();
+                crate::erase_slot(&mut self.f, &EraseConfig::default());
+            }
+        }
+    }
+}
@@ -0,0 +1,559 @@
+use crate::memory::{ProtectedMemory, Protections};
+use crate::thread::ThreadProtections;
+use crate::{
+    EraseConfig, ErasePattern, ErasePolicy, ErasedStack, EraserError, SwitchBackend,
+    MIN_STACK_SIZE, STACK_ALIGN,
+};
+
+/// The default size of the ephemeral stack used by [`Eraser`].
+pub const DEFAULT_STACK_SIZE: usize = 128 * 1024;
+
+/// Return the maximum number of bytes that this process may lock into memory
+/// (`RLIMIT_MEMLOCK`), or `None` if it is not known on this target.
+///
+/// Stacks that are set up with [`EraserBuilder::mlock`] count against this
+/// limit.  An unlimited limit is reported as `usize::MAX`.
+pub fn memlock_limit() -> Option<usize> {
+    crate::sys::memlock_limit()
+}
+
+/// Return the other hardware threads (SMT siblings) that are online on the
+/// same physical core as `cpu`, or `None` if this is not known on this target.
+///
+/// Code that runs on a sibling shares the caches and execution units of the
+/// core with code on `cpu`, which opens up cross-hyperthread side channels.
+/// Use this to check whether a CPU that is passed to [`EraserBuilder::pin_cpu`]
+/// is isolated; an empty list means that it is.
+pub fn smt_siblings(cpu: usize) -> Option<Vec<usize>> {
+    crate::sys::smt_siblings(cpu)
+}
+
+/// A reusable, configured erased context.
+///
+/// An `Eraser` is created with [`Eraser::builder`].  All the protections are
+/// configured up front, and the same `Eraser` can then be used to run any
+/// number of functions.
+///
+/// ## Example
+/// ```
+/// let eraser = eraser::Eraser::builder()
+///     .stack_size(64 * 1024)
+///     .erase_pattern(eraser::ErasePattern::Zeros)
+///     .erase_passes(2)
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(eraser.run(|| 1 + 1), 2);
+/// assert_eq!(eraser.run(|| 2 + 2), 4);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Eraser {
+    stack_size: usize,
+    protections: Protections,
+    thread: ThreadProtections,
+    erase: EraseConfig,
+    zero_stack: bool,
+    backend: SwitchBackend,
+    recover_overflow: bool,
+}
+
+impl Default for Eraser {
+    fn default() -> Self {
+        Eraser {
+            stack_size: DEFAULT_STACK_SIZE,
+            protections: Protections {
+                guard_page: cfg!(feature = "guard_page"),
+                ..Protections::default()
+            },
+            thread: ThreadProtections::default(),
+            erase: EraseConfig::default(),
+            zero_stack: false,
+            backend: SwitchBackend::default(),
+            recover_overflow: false,
+        }
+    }
+}
+
+impl Eraser {
+    /// Return a builder to configure a new `Eraser`.
+    pub fn builder() -> EraserBuilder {
+        EraserBuilder {
+            eraser: Eraser::default(),
+        }
+    }
+
+    /// Return a builder that is preconfigured with all the protections that
+    /// are available.
+    ///
+    /// The stack is preceded by a guard page, locked into memory, excluded
+    /// from core dumps, and wiped in forked child processes.  Building fails
+    /// if any of these protections cannot be applied; in particular, setting
+    /// up a stack fails with [`EraserError::LockFailed`] when
+    /// `RLIMIT_MEMLOCK` (see [`memlock_limit`]) is too low, unless
+    /// [`EraserBuilder::mlock_fallback`] is enabled.  Only supported on Linux,
+    /// FreeBSD and OpenBSD.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// let eraser = eraser::Eraser::paranoid().stack_size(64 * 1024).build().unwrap();
+    /// assert_eq!(eraser.run(|| 1 + 1), 2);
+    /// ```
+    pub fn paranoid() -> EraserBuilder {
+        Eraser::builder()
+            .guard_page(true)
+            .mlock(true)
+            .dont_dump(true)
+            .wipe_on_fork(true)
+    }
+
+    /// Run a function on an ephemeral stack and immediately erase the stack.
+    ///
+    /// A fresh stack with the configured protections is set up for every
+    /// call; use [`Eraser::new_stack`] to reuse the same stack instead.  This
+    /// function panics if the stack could not be set up; see
+    /// [`Eraser::try_run`] for a non-panicking version.
+    pub fn run<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        self.try_run(f).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Run a function on an ephemeral stack and immediately erase the stack,
+    /// returning an error if the stack could not be set up, or if the user
+    /// function overflowed a stack with a guard page (see
+    /// [`EraserBuilder::recover_from_overflow`]).
+    pub fn try_run<F, R>(&self, f: F) -> Result<R, EraserError>
+    where
+        F: FnOnce() -> R,
+    {
+        match self.backend {
+            SwitchBackend::Inline => self.new_stack()?.try_run(f),
+            SwitchBackend::Fiber => {
+                crate::fiber::run(f, self.stack_size, &self.thread, &self.erase)
+            }
+        }
+    }
+
+    /// Return a copy of this `Eraser` that also wipes its stacks in forked
+    /// child processes, where this is supported.
+    pub(crate) fn with_wipe_on_fork(&self) -> Eraser {
+        let mut eraser = self.clone();
+        eraser.protections.wipe_on_fork = cfg!(any(
+            target_os = "linux",
+            target_os = "freebsd",
+            target_os = "openbsd"
+        ));
+        eraser
+    }
+
+    /// Set up a new stack with the configured protections, which can be used
+    /// for many runs.
+    ///
+    /// Returns [`EraserError::Unsupported`] if the `Eraser` uses the
+    /// [`SwitchBackend::Fiber`] backend, whose stacks cannot be reused.
+    pub fn new_stack(&self) -> Result<ErasedStack, EraserError> {
+        crate::check_supported()?;
+        if self.backend != SwitchBackend::Inline {
+            return Err(EraserError::Unsupported);
+        }
+        let stack = if self.zero_stack {
+            ProtectedMemory::new(self.stack_size, STACK_ALIGN, &self.protections)?
+        } else {
+            ProtectedMemory::new_uninit(self.stack_size, STACK_ALIGN, &self.protections)?
+        };
+        Ok(ErasedStack::from_parts(
+            stack,
+            self.erase.clone(),
+            self.thread.clone(),
+            self.zero_stack,
+            self.recover_overflow,
+        ))
+    }
+}
+
+/// Builder for [`Eraser`].
+#[derive(Debug, Clone)]
+pub struct EraserBuilder {
+    eraser: Eraser,
+}
+
+impl EraserBuilder {
+    /// Set the size of the ephemeral stack in bytes.
+    ///
+    /// The size must be a multiple of 32 bytes.  Defaults to
+    /// [`DEFAULT_STACK_SIZE`].
+    pub fn stack_size(mut self, stack_size: usize) -> Self {
+        self.eraser.stack_size = stack_size;
+        self
+    }
+
+    /// Put an inaccessible guard page below the stack, so that a stack
+    /// overflow is detected instead of overwriting other memory.
+    ///
+    /// On x86_64 Linux, the stack is erased after an overflow, and then the
+    /// process is aborted (unless [`EraserBuilder::recover_from_overflow`] is
+    /// enabled).  On other targets, the overflow crashes the process without
+    /// erasing the stack.  Only an overflow that touches the guard page is
+    /// detected: Rust code probes every page of a frame that is larger than a
+    /// page, but foreign code that was compiled without stack probes (e.g. C
+    /// without `-fstack-clash-protection`) can skip over the guard page and
+    /// silently overwrite the memory below it.
+    ///
+    /// Defaults to `true` if the `guard_page` feature is enabled, and to
+    /// `false` otherwise.  Only supported on Linux, macOS, FreeBSD, OpenBSD,
+    /// illumos, Redox, QNX, Fuchsia and Windows.
+    pub fn guard_page(mut self, guard_page: bool) -> Self {
+        self.eraser.protections.guard_page = guard_page;
+        self
+    }
+
+    /// Return [`EraserError::StackOverflow`] when the user function overflows
+    /// a stack with a guard page, instead of aborting the process.
+    ///
+    /// The user function is abandoned where it overflowed: its frames are
+    /// dropped without being unwound, so none of its destructors run, and the
+    /// closure itself is never dropped.  Anything that it held stays held
+    /// forever, like a lock (e.g. the lock on `stdout` that `println!` takes,
+    /// after which the next `println!` deadlocks) or memory that it lent out.
+    /// The stack is still erased, and [`Eraser::try_run`] and
+    /// [`ErasedStack::try_run`] return the error (and the panicking variants
+    /// panic).  Defaults to `false`.  Only supported on x86_64 Linux; on other
+    /// targets, building fails with [`EraserError::Unsupported`] when this is
+    /// enabled.
+    ///
+    /// ## Safety
+    ///
+    /// * Every function that is run with this `Eraser` (or on the stacks that
+    ///   it sets up) must be sound to abandon at any point: it must not rely
+    ///   on its destructors being run, for example by spawning threads with
+    ///   [`std::thread::scope`] that borrow its frames, or by pinning values
+    ///   on its stack.
+    pub unsafe fn recover_from_overflow(mut self, recover: bool) -> Self {
+        self.eraser.recover_overflow = recover;
+        self
+    }
+
+    /// Seal the guard page (with `mseal`), so that it cannot be unmapped or
+    /// made accessible while the stack is in use.
+    ///
+    /// Without sealing, a memory-corruption bug elsewhere in the process could
+    /// remap or re-protect the guard page, and a stack overflow would then
+    /// silently overwrite the memory below the stack.  A sealed page can never
+    /// be unmapped, so every stack leaves one page of (inaccessible, unbacked)
+    /// address space behind when it is dropped; prefer reusing stacks (see
+    /// [`Eraser::new_stack`] and [`StackPool`](crate::StackPool)) when this is
+    /// enabled.
+    ///
+    /// Has no effect unless [`EraserBuilder::guard_page`] is enabled.  Setting
+    /// up a stack fails with [`EraserError::Unsupported`] if the kernel does
+    /// not support sealing.  Defaults to `false`.  Only supported on 64-bit
+    /// Linux 6.10 or later.
+    pub fn seal_guard_page(mut self, seal: bool) -> Self {
+        self.eraser.protections.seal_guard_page = seal;
+        self
+    }
+
+    /// Lock the stack into memory, so that it cannot be swapped out while
+    /// the user function is running.
+    ///
+    /// On macOS, the stack is also marked with `MADV_ZERO_WIRED_PAGES`, so that
+    /// the kernel zeroes it if the process exits while it is locked.  On
+    /// Windows, this uses `VirtualLock`, which is limited by the minimum
+    /// working set size of the process.  Fuchsia never pages anonymous memory
+    /// out, so there the pages are only committed up front.  Defaults to
+    /// `false`.  Only supported on Linux, macOS, FreeBSD, OpenBSD, illumos,
+    /// Redox, QNX, Fuchsia and Windows.
+    pub fn mlock(mut self, mlock: bool) -> Self {
+        self.eraser.protections.mlock = mlock;
+        self
+    }
+
+    /// Exclude the stack from core dumps (with `MADV_DONTDUMP`), so that a
+    /// crash elsewhere in the process cannot capture the secrets that are in
+    /// flight on the stack.  On FreeBSD and OpenBSD, the stack is mapped with
+    /// `MAP_NOCORE` and `MAP_CONCEAL` instead.
+    ///
+    /// Defaults to `false`.  Only supported on Linux, FreeBSD and OpenBSD.
+    pub fn dont_dump(mut self, dont_dump: bool) -> Self {
+        self.eraser.protections.dont_dump = dont_dump;
+        self
+    }
+
+    /// Make a forked child process see the stack as zeroed memory (with
+    /// `MADV_WIPEONFORK`), so that a child created by `fork()` never inherits
+    /// the secret residue on the stack.
+    ///
+    /// On FreeBSD and OpenBSD, this uses `minherit` with `INHERIT_ZERO`.
+    /// Defaults to `false`.  Only supported on Linux 4.14 or later, FreeBSD
+    /// and OpenBSD.
+    pub fn wipe_on_fork(mut self, wipe_on_fork: bool) -> Self {
+        self.eraser.protections.wipe_on_fork = wipe_on_fork;
+        self
+    }
+
+    /// Back the stack with `memfd_secret`, which removes its pages from the
+    /// kernel direct map.
+    ///
+    /// While the user function runs, the secrets on the stack can then only be
+    /// read through the stack mapping itself, so not even a kernel memory
+    /// disclosure can read them.
+    /// Combined with [`EraserBuilder::wipe_on_fork`], the stack is left out of
+    /// forked child processes altogether.
+    ///
+    /// Setting up a stack fails with [`EraserError::Unsupported`] if the
+    /// kernel does not support secret memory.  Defaults to `false`.  Only
+    /// supported on Linux 5.14 or later.
+    pub fn secret_memory(mut self, secret_memory: bool) -> Self {
+        self.eraser.protections.secret_memory = secret_memory;
+        self
+    }
+
+    /// Tag the stack with a memory protection key (Intel MPK), so that it can
+    /// only be accessed while the current thread is inside the erased context.
+    ///
+    /// Outside of a run, any access to the stack faults, so a stray pointer
+    /// dereference elsewhere in the program cannot read the secret residue on
+    /// it.  During a run, the stack is only accessible from the thread that
+    /// runs the user function; the user function must not hand out references
+    /// to its stack to other threads.  All such stacks share a single key.
+    ///
+    /// Setting up a stack fails with [`EraserError::Unsupported`] if the CPU or
+    /// the kernel does not support protection keys.  Defaults to `false`.
+    /// Only supported on x86_64 Linux.
+    pub fn protection_key(mut self, protection_key: bool) -> Self {
+        self.eraser.protections.protection_key = protection_key;
+        self
+    }
+
+    /// Tag the stack with the Arm Memory Tagging Extension (MTE).
+    ///
+    /// Every run tags the stack with a fresh random tag, and resets the tags
+    /// after the stack has been erased.  A pointer into the stack that is used
+    /// after the run, or a pointer from elsewhere that strays into the stack
+    /// during the run, then traps deterministically instead of reading the
+    /// secrets.  Tag checking is enabled in synchronous mode for the threads
+    /// that run on a tagged stack, unless they already had tag checking
+    /// enabled.
+    ///
+    /// Setting up a stack fails with [`EraserError::Unsupported`] if the CPU or
+    /// the kernel does not support MTE.  Defaults to `false`.  Only supported
+    /// on aarch64 Linux.
+    pub fn memory_tagging(mut self, memory_tagging: bool) -> Self {
+        self.eraser.protections.memory_tagging = memory_tagging;
+        self
+    }
+
+    /// Restrict speculative execution for the thread that runs the user
+    /// function, for the duration of the run.
+    ///
+    /// Before every run, speculative store bypass (SSBD) and indirect branch
+    /// speculation are disabled for the calling thread (with
+    /// `PR_SET_SPECULATION_CTRL`), and they are allowed again after the run if
+    /// they were allowed before.  This confines the cost of the Spectre
+    /// mitigations to exactly the code that handles the secrets.
+    ///
+    /// A run fails with [`EraserError::Unsupported`] if the kernel does not
+    /// let the thread restrict the speculation (e.g. because the mitigations
+    /// are disabled on the kernel command line).  A CPU that is not affected
+    /// is fine.  Defaults to `false`.  Only supported on Linux.
+    pub fn restrict_speculation(mut self, restrict: bool) -> Self {
+        self.eraser.thread.restrict_speculation = restrict;
+        self
+    }
+
+    /// Pin the thread that runs the user function to `cpu` for the duration of
+    /// the run.
+    ///
+    /// Before every run, the calling thread is restricted to run on `cpu`
+    /// only, and after the run it may run on the CPUs that it was allowed to
+    /// run on before.  Combined with a CPU whose SMT siblings are offline or
+    /// otherwise idle (see [`smt_siblings`]), this keeps the secret
+    /// computation away from cross-hyperthread side channels.
+    ///
+    /// A run fails with [`EraserError::PinFailed`] if the thread cannot be
+    /// pinned to `cpu`, e.g. because it is offline or not in the thread's
+    /// cpuset.  Defaults to `None`.  Only supported on Linux.
+    pub fn pin_cpu(mut self, cpu: Option<usize>) -> Self {
+        self.eraser.thread.pin_cpu = cpu;
+        self
+    }
+
+    /// Continue without locking the stack if it cannot be locked into
+    /// memory, instead of failing with [`EraserError::LockFailed`].
+    ///
+    /// Locking typically fails because the stack is larger than what is left
+    /// of `RLIMIT_MEMLOCK` (see [`memlock_limit`]).  Use
+    /// [`ErasedStack::is_locked`] to find out whether a stack was actually
+    /// locked.  Defaults to `false`.
+    pub fn mlock_fallback(mut self, fallback: bool) -> Self {
+        self.eraser.protections.mlock_fallback = fallback;
+        self
+    }
+
+    /// Choose how the stack is switched before the user function runs.
+    ///
+    /// Defaults to [`SwitchBackend::Inline`].  Building fails with
+    /// [`EraserError::Unsupported`] if the backend is not supported on this
+    /// target, or if it cannot apply the configured stack protections.
+    pub fn switch_backend(mut self, backend: SwitchBackend) -> Self {
+        self.eraser.backend = backend;
+        self
+    }
+
+    /// Zero the stack when it is allocated.
+    ///
+    /// Defaults to `false`: the stack is fully overwritten by the erase after
+    /// the run anyway, so zeroing it up front only doubles the memory traffic
+    /// for large stacks.
+    pub fn zero_stack(mut self, zero_stack: bool) -> Self {
+        self.eraser.zero_stack = zero_stack;
+        self
+    }
+
+    /// Set the number of times the stack is overwritten after the user
+    /// function has run.
+    ///
+    /// This is a shorthand for [`ErasePolicy::Repeat`] with the pattern that
+    /// is currently configured.  Defaults to 1.  Building fails with
+    /// [`EraserError::NoErasePasses`] if `passes` is 0.
+    pub fn erase_passes(mut self, passes: usize) -> Self {
+        let pattern = self.repeated_pattern();
+        self.eraser.erase.policy = ErasePolicy::Repeat(pattern, passes);
+        self
+    }
+
+    /// Set the value that is used to overwrite the stack.
+    ///
+    /// This is a shorthand for [`ErasePolicy::Repeat`] with the number of
+    /// passes that is currently configured.  A plain `usize` is accepted as a
+    /// shorthand for [`ErasePattern::Word`].  Defaults to
+    /// [`ErasePattern::Poison`].
+    pub fn erase_pattern(mut self, pattern: impl Into<ErasePattern>) -> Self {
+        let passes = match self.eraser.erase.policy {
+            ErasePolicy::Repeat(_, passes) => passes,
+            _ => 1,
+        };
+        self.eraser.erase.policy = ErasePolicy::Repeat(pattern.into(), passes);
+        self
+    }
+
+    /// Set the sequence of passes that is used to overwrite the stack.
+    ///
+    /// Defaults to a single pass with [`ErasePattern::Poison`].  Building fails
+    /// with [`EraserError::NoErasePasses`] if the policy has no passes.
+    pub fn erase_policy(mut self, policy: ErasePolicy) -> Self {
+        self.eraser.erase.policy = policy;
+        self
+    }
+
+    /// Only erase the part of a reused stack that was actually used.
+    ///
+    /// A reused stack (see [`Eraser::new_stack`]) keeps track of the value
+    /// that its erased words hold.  After a run, only the words from the
+    /// lowest one that was overwritten (the high-water mark) up to the top of
+    /// the stack are erased.  This does not apply if the last pass of the
+    /// erase policy is random.
+    ///
+    /// Defaults to `true`.  Set to `false` to always erase the whole stack.
+    pub fn erase_high_water_mark(mut self, high_water_mark: bool) -> Self {
+        self.eraser.erase.high_water_mark = high_water_mark;
+        self
+    }
+
+    /// Read the stack back after erasing it, and check that it holds the
+    /// pattern of the last pass.
+    ///
+    /// This catches erases that were elided or miscompiled (e.g. by exotic
+    /// LTO settings) at runtime, at the cost of reading the erased memory
+    /// once more.  A failed check panics, so the secrets are never silently
+    /// left behind.
+    ///
+    /// Defaults to `false`.
+    pub fn verify_erase(mut self, verify: bool) -> Self {
+        self.eraser.erase.verify = verify;
+        self
+    }
+
+    /// Flush the erased stack from the CPU caches.
+    ///
+    /// After the stack is erased, its cache lines are flushed (with
+    /// `clflushopt`, or `clflush` on older CPUs) and fenced, so the erased
+    /// values actually reach main memory.  This defends against cold-boot and
+    /// DMA attacks that snapshot the physical memory shortly after the run.
+    ///
+    /// Defaults to `false`.  Only has an effect on x86_64.
+    pub fn flush_cache(mut self, flush: bool) -> Self {
+        self.eraser.erase.flush = flush;
+        self
+    }
+
+    /// Also wipe the extended register state that the registers wipe does not
+    /// cover individually after every run.
+    ///
+    /// After the usual wipe, `xrstor` is used to put every state component
+    /// that the OS has enabled (e.g. the AVX-512 and AMX registers, and any
+    /// component that future CPUs add) in its initial configuration, except
+    /// for the protection key rights register `PKRU`.  This is a
+    /// forward-compatible scrub, at the cost of a few hundred cycles per run.
+    ///
+    /// Defaults to `false`.  Only has an effect on x86_64, on CPUs with XSAVE.
+    pub fn deep_register_wipe(mut self, deep: bool) -> Self {
+        self.eraser.erase.deep_wipe = deep;
+        self
+    }
+
+    /// Hide the frames of the caller from backtraces that are taken on the
+    /// ephemeral stack.
+    ///
+    /// Unwinders normally walk from the user function across the stack switch
+    /// into the caller (for example, a `std::backtrace::Backtrace` that is
+    /// captured in a panic hook), which reveals the return addresses and frame
+    /// pointers on the caller's stack.  With this option, the frame record that
+    /// links the ephemeral stack to the caller is zeroed before the user
+    /// function is called, so that every backtrace ends at the switch.
+    ///
+    /// Defaults to `false`.  Only has an effect on x86, x86_64, aarch64,
+    /// riscv64 and loongarch64, and not with the [`SwitchBackend::Fiber`]
+    /// backend.
+    pub fn redact_backtraces(mut self, redact: bool) -> Self {
+        self.eraser.erase.redact_backtraces = redact;
+        self
+    }
+
+    fn repeated_pattern(&self) -> ErasePattern {
+        match self.eraser.erase.policy {
+            ErasePolicy::Repeat(pattern, _) => pattern,
+            _ => ErasePattern::default(),
+        }
+    }
+
+    /// Check the configuration and build the [`Eraser`].
+    pub fn build(self) -> Result<Eraser, EraserError> {
+        let eraser = self.eraser;
+        crate::check_supported()?;
+        if eraser.stack_size < MIN_STACK_SIZE {
+            return Err(EraserError::StackTooSmall);
+        }
+        if !eraser.stack_size.is_multiple_of(STACK_ALIGN) {
+            return Err(EraserError::BadAlignment);
+        }
+        if eraser.erase.policy.passes() == 0 {
+            return Err(EraserError::NoErasePasses);
+        }
+        let protections = &eraser.protections;
+        let linux = cfg!(target_os = "linux");
+        if (protections.needs_pages() && !crate::sys::CAN_MAP) || (eraser.thread.any() && !linux) {
+            return Err(EraserError::Unsupported);
+        }
+        let overflow_handler = cfg!(all(target_os = "linux", target_arch = "x86_64"));
+        if eraser.recover_overflow && !overflow_handler {
+            return Err(EraserError::Unsupported);
+        }
+        let fiber = eraser.backend == SwitchBackend::Fiber;
+        if fiber && (!cfg!(windows) || protections.needs_pages()) {
+            return Err(EraserError::Unsupported);
+        }
+        Ok(eraser)
+    }
+}
@@ -0,0 +1,70 @@
+use crate::{check_supported, sys};
+
+/// What eraser can guarantee on the current target.
+///
+/// On most targets all of these hold.  A target without one of them still
+/// runs the user function, but with weaker guarantees; see the fields for the
+/// details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// The user function runs on a separate stack, which is erased as a whole
+    /// afterwards.  On WebAssembly, the user function runs on the current
+    /// (shadow) stack instead, and only as many bytes as the ephemeral stack
+    /// would have had are erased below the caller afterwards.  On targets that
+    /// are not supported at all, running a function fails with
+    /// [`EraserError::Unsupported`](crate::EraserError::Unsupported).
+    pub stack_switch: bool,
+    /// The CPU registers are wiped after the user function has run (see
+    /// [`wipe_registers`](crate::wipe_registers)).  On WebAssembly, the
+    /// operand stack and the locals are owned by the engine, and cannot be
+    /// wiped.  On the Linux targets that switch stacks with `swapcontext`,
+    /// only the registers that the calling convention preserves are restored.
+    pub register_wipe: bool,
+    /// Stacks can be mapped from the operating system, so that guard pages,
+    /// locking and the other page protections of
+    /// [`EraserBuilder`](crate::EraserBuilder) are available.  Otherwise,
+    /// stacks are allocated on the heap.
+    pub page_protection: bool,
+    /// [`ErasePattern::Random`](crate::ErasePattern::Random) draws its seeds
+    /// from an entropy source.  On `wasm32-unknown-unknown` there is none, so
+    /// the random patterns are predictable there (but still different for
+    /// every erase).
+    pub random_pattern: bool,
+}
+
+impl Capabilities {
+    /// Return whether all the guarantees hold.
+    pub fn is_complete(&self) -> bool {
+        self.stack_switch && self.register_wipe && self.page_protection && self.random_pattern
+    }
+}
+
+/// Report what eraser can guarantee on the current target.
+///
+/// ## Example
+/// ```
+/// let capabilities = eraser::capabilities();
+/// if !capabilities.register_wipe {
+///     eprintln!("warning: secrets may be left behind in registers");
+/// }
+/// ```
+pub fn capabilities() -> Capabilities {
+    let supported = check_supported().is_ok();
+    Capabilities {
+        stack_switch: supported && !cfg!(target_arch = "wasm32"),
+        register_wipe: supported && crate::WIPES_REGISTERS,
+        page_protection: sys::CAN_MAP,
+        random_pattern: !cfg!(all(target_arch = "wasm32", target_os = "unknown")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn capabilities() {
+        let capabilities = super::capabilities();
+        let complete = cfg!(any(target_os = "linux", target_os = "macos", windows));
+        assert_eq!(capabilities.is_complete(), complete);
+    }
+}
@@ -0,0 +1,314 @@
+use crate::EraseConfig;
+use core::fmt;
+
+/// A growable vector of secret values that never leaves copies behind.
+///
+/// A regular `Vec` leaves its old allocation behind in the heap (without
+/// erasing it) every time it grows.  `SecretVec` takes over the growing
+/// itself, and erases the old allocation after its contents have been moved
+/// to the new one.  Removed elements, and the whole allocation on drop, are
+/// erased as well.
+///
+/// Only `Copy` element types are supported, because the elements are erased
+/// by overwriting their bytes, without running any destructors.
+///
+/// ## Example
+/// ```
+/// use eraser::SecretVec;
+///
+/// let mut key = SecretVec::new();
+/// for chunk in [b"-----BEGIN", b" KEY-----\n"] {
+///     key.extend_from_slice(chunk);
+/// }
+/// assert_eq!(key.len(), 20);
+/// key.expose(|key| assert!(key.starts_with(b"-----BEGIN KEY")));
+/// ```
+pub struct SecretVec<T: Copy> {
+    inner: Vec<T>,
+}
+
+impl<T: Copy> SecretVec<T> {
+    /// Create a new, empty `SecretVec`.
+    pub const fn new() -> Self {
+        SecretVec { inner: Vec::new() }
+    }
+
+    /// Create a new, empty `SecretVec` that can hold at least `capacity`
+    /// elements without growing.
+    pub fn with_capacity(capacity: usize) -> Self {
+        SecretVec {
+            inner: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Return the number of elements in the vector.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Return `true` if the vector contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Return the number of elements that the vector can hold without
+    /// growing.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    /// Make sure that the vector can hold at least `additional` more
+    /// elements, growing it (and erasing the old allocation) if necessary.
+    pub fn reserve(&mut self, additional: usize) {
+        let needed = self
+            .inner
+            .len()
+            .checked_add(additional)
+            .expect("capacity overflow");
+        if needed <= self.inner.capacity() {
+            return;
+        }
+        let capacity = needed.max(self.inner.capacity() * 2).max(8);
+        let mut grown = Vec::with_capacity(capacity);
+        grown.extend_from_slice(&self.inner);
+        let mut old = core::mem::replace(&mut self.inner, grown);
+        erase_allocation(&mut old);
+    }
+
+    /// Append an element to the end of the vector.
+    pub fn push(&mut self, value: T) {
+        self.reserve(1);
+        self.inner.push(value);
+    }
+
+    /// Append all the elements of `other` to the end of the vector.
+    pub fn extend_from_slice(&mut self, other: &[T]) {
+        self.reserve(other.len());
+        self.inner.extend_from_slice(other);
+    }
+
+    /// Shorten the vector to `len` elements, erasing the removed elements.
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.inner.len() {
+            return;
+        }
+        let removed = &mut self.inner[len..];
+        let (ptr, bytes) = (removed.as_mut_ptr(), core::mem::size_of_val(removed));
+        unsafe { crate::erase_bytes(ptr as *mut u8, bytes, &EraseConfig::default()) };
+        self.inner.truncate(len);
+    }
+
+    /// Remove (and erase) all the elements.
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    /// Call `f` with a reference to the secret elements.
+    pub fn expose<R>(&self, f: impl FnOnce(&[T]) -> R) -> R {
+        f(&self.inner)
+    }
+
+    /// Call `f` with a mutable reference to the secret elements.
+    pub fn expose_mut<R>(&mut self, f: impl FnOnce(&mut [T]) -> R) -> R {
+        f(&mut self.inner)
+    }
+}
+
+/// Erase all of the allocation of `vec`, including its spare capacity.
+fn erase_allocation<T: Copy>(vec: &mut Vec<T>) {
+    let bytes = vec.capacity() * core::mem::size_of::<T>();
+    unsafe { crate::erase_bytes(vec.as_mut_ptr() as *mut u8, bytes, &EraseConfig::default()) };
+}
+
+impl<T: Copy> Default for SecretVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Copy> From<&[T]> for SecretVec<T> {
+    fn from(slice: &[T]) -> Self {
+        let mut vec = SecretVec::with_capacity(slice.len());
+        vec.extend_from_slice(slice);
+        vec
+    }
+}
+
+impl<T: Copy> Drop for SecretVec<T> {
+    fn drop(&mut self) {
+        erase_allocation(&mut self.inner);
+    }
+}
+
+/// Zero all of the allocation of `vec`, including its spare capacity, as
+/// `Zeroize` requires (instead of writing the erase pattern).
+#[cfg(feature = "zeroize")]
+fn zero_allocation<T: Copy>(vec: &mut Vec<T>) {
+    let ptr = vec.as_mut_ptr() as *mut u8;
+    for offset in 0..vec.capacity() * core::mem::size_of::<T>() {
+        unsafe { core::ptr::write_volatile(ptr.add(offset), 0) };
+    }
+    core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+}
+
+#[cfg(feature = "zeroize")]
+impl<T: Copy> zeroize::Zeroize for SecretVec<T> {
+    fn zeroize(&mut self) {
+        zero_allocation(&mut self.inner);
+        self.inner.clear();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<T: Copy> zeroize::ZeroizeOnDrop for SecretVec<T> {}
+
+impl<T: Copy> fmt::Debug for SecretVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretVec<{}>(..)", core::any::type_name::<T>())
+    }
+}
+
+/// A growable secret string that never leaves copies behind.
+///
+/// This is the string counterpart of [`SecretVec`]: the old allocation is
+/// erased every time the string grows, and the whole allocation is erased on
+/// drop.
+///
+/// ## Example
+/// ```
+/// use eraser::SecretString;
+///
+/// let mut password = SecretString::new();
+/// password.push_str("correct horse");
+/// password.push(' ');
+/// password.push_str("battery staple");
+/// password.expose(|s| assert_eq!(s, "correct horse battery staple"));
+/// ```
+#[derive(Default)]
+pub struct SecretString {
+    bytes: SecretVec<u8>,
+}
+
+impl SecretString {
+    /// Create a new, empty `SecretString`.
+    pub const fn new() -> Self {
+        SecretString {
+            bytes: SecretVec::new(),
+        }
+    }
+
+    /// Create a new, empty `SecretString` that can hold at least `capacity`
+    /// bytes without growing.
+    pub fn with_capacity(capacity: usize) -> Self {
+        SecretString {
+            bytes: SecretVec::with_capacity(capacity),
+        }
+    }
+
+    /// Return the length of the string in bytes.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Return `true` if the string is empty.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Append a string slice to the end of the string.
+    pub fn push_str(&mut self, s: &str) {
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+
+    /// Append a character to the end of the string.
+    pub fn push(&mut self, c: char) {
+        self.push_str(c.encode_utf8(&mut [0; 4]));
+    }
+
+    /// Remove (and erase) the contents of the string.
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+    }
+
+    /// Call `f` with a reference to the secret string.
+    pub fn expose<R>(&self, f: impl FnOnce(&str) -> R) -> R {
+        // The bytes only ever come from `&str` values, so they are valid UTF-8
+        self.bytes
+            .expose(|bytes| f(unsafe { core::str::from_utf8_unchecked(bytes) }))
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(s: &str) -> Self {
+        let mut string = SecretString::with_capacity(s.len());
+        string.push_str(s);
+        string
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl zeroize::Zeroize for SecretString {
+    fn zeroize(&mut self) {
+        self.bytes.zeroize();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl zeroize::ZeroizeOnDrop for SecretString {}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretString(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_vec() {
+        let mut vec = SecretVec::new();
+        for i in 0..100u32 {
+            vec.push(i);
+        }
+        assert_eq!(vec.len(), 100);
+        assert!(vec.capacity() >= 100);
+        vec.truncate(10);
+        vec.expose(|v| assert_eq!(v, (0..10).collect::<Vec<_>>()));
+        vec.expose_mut(|v| v[0] = 42);
+        vec.clear();
+        assert!(vec.is_empty());
+        assert_eq!(format!("{:?}", vec), "SecretVec<u32>(..)");
+    }
+
+    #[test]
+    fn secret_string() {
+        let mut s = SecretString::from("héllo");
+        s.push(',');
+        s.push_str(" wörld");
+        assert_eq!(s.len(), 14);
+        s.expose(|s| assert_eq!(s, "héllo, wörld"));
+        assert_eq!(format!("{:?}", s), "SecretString(..)");
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn zeroize() {
+        use zeroize::Zeroize;
+
+        let mut vec = SecretVec::from(&[0xAAu8; 40][..]);
+        vec.truncate(20);
+        vec.zeroize();
+        assert!(vec.is_empty());
+        let all = unsafe { core::slice::from_raw_parts(vec.inner.as_ptr(), vec.capacity()) };
+        assert!(all.iter().all(|&x| x == 0));
+
+        let mut s = SecretString::from("secret");
+        s.zeroize();
+        assert!(s.is_empty());
+        let bytes = &s.bytes.inner;
+        let all = unsafe { core::slice::from_raw_parts(bytes.as_ptr(), bytes.capacity()) };
+        assert!(all.iter().all(|&x| x == 0));
+    }
+}
@@ -0,0 +1,441 @@
+//! Running the user function on Cortex-M, without `std`.
+//!
+//! There are no threads and no unwinding on these targets, so the closure is
+//! passed to the ephemeral stack in a register instead of through `CTX`, and a
+//! panic in the user function goes straight to the panic handler of the
+//! application (which runs on the ephemeral stack, and the stack is not
+//! erased).
+//!
+//! Exception handlers always run on the main stack (MSP).  If the caller runs
+//! in privileged thread mode on the main stack, the ephemeral stack is
+//! installed as the process stack (PSP) for the duration of the run, so that
+//! interrupts that fire during the run keep using the main stack, and only
+//! the exception frames that are stacked on entry land on the ephemeral
+//! stack (where they are erased with the rest).  Otherwise (in handler mode,
+//! when the thread already runs on the process stack, for example under an
+//! RTOS, or in unprivileged mode) the stack pointer is switched directly.
+//!
+//! On ARMv8-M, the stack pointers and their limit registers are banked
+//! between the secure and the non-secure state.  Secure code sees only the
+//! secure copies, so a run from secure code (e.g. from a non-secure callable
+//! entry function) uses `MSP_S` or `PSP_S`, and an exception that interrupts
+//! the run stacks the secure context on the ephemeral stack, where it is
+//! erased with the rest.  With the `trustzone` feature, the limit registers
+//! are moved along with the stack (see `StackLimits`); without it, the
+//! ephemeral stack must lie above `MSPLIM` and `PSPLIM`.
+//!
+//! With the `mask_interrupts` feature, the interrupts are masked (with
+//! `PRIMASK`) from before the stack switch until the stack has been erased and
+//! the registers have been wiped, so that no interrupt handler runs while the
+//! secrets are still on the ephemeral stack or in the registers.  Faults and
+//! the NMI are not masked.
+
+use crate::{EraseConfig, Payload};
+use core::arch::asm;
+use core::mem::MaybeUninit;
+
+/// Run the user function on `stack`, which has already been aligned by
+/// `align_stack`.
+pub(crate) unsafe fn run_on_stack<F, R>(
+    f: F,
+    stack: &mut [MaybeUninit<u8>],
+    config: &EraseConfig,
+) -> R
+where
+    F: FnOnce() -> R,
+{
+    let stack_bottom = stack.as_mut_ptr() as *mut u8;
+    let stack_top = stack_bottom.add(stack.len());
+    let mut payload = Payload {
+        f: MaybeUninit::new(f),
+        result: MaybeUninit::<R>::uninit(),
+    };
+    let data = &mut payload as *mut Payload<F, R> as *mut u8;
+
+    crate::events::enter(stack_bottom, stack.len());
+    #[cfg(feature = "mask_interrupts")]
+    let primask = mask_interrupts();
+    let use_psp = privileged_thread_on_msp();
+    #[cfg(feature = "trustzone")]
+    let limits = StackLimits::enter(stack_bottom, use_psp);
+    stack_switch::<F, R>(data, stack_top, use_psp);
+    #[cfg(feature = "trustzone")]
+    limits.restore();
+    crate::events::check_overflow(stack_bottom);
+    crate::erase_words(stack_bottom, stack.len(), config);
+    crate::erase_slot(&mut payload.f, config);
+
+    let result = payload.result.as_ptr().read();
+    crate::erase_slot(&mut payload.result, config);
+    crate::custom_wipe::run();
+    wipe_all_registers();
+    #[cfg(feature = "mask_interrupts")]
+    restore_interrupts(primask);
+    crate::events::erased(stack.len());
+    result
+}
+
+extern "C" fn trampoline<F: FnOnce() -> R, R>(data: *mut u8) {
+    unsafe { crate::call_user_fn::<F, R>(data) }
+}
+
+/// Run `f` with the interrupts masked (with `PRIMASK`), and restore the mask
+/// afterwards.
+#[cfg_attr(target_has_atomic = "ptr", allow(dead_code))]
+pub(crate) fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let primask = mask_interrupts();
+    let result = f();
+    restore_interrupts(primask);
+    result
+}
+
+/// Mask the interrupts, and return the old value of `PRIMASK`.
+#[cfg_attr(
+    all(target_has_atomic = "ptr", not(feature = "mask_interrupts")),
+    allow(dead_code)
+)]
+fn mask_interrupts() -> u32 {
+    let primask: u32;
+    unsafe {
+        asm!(
+            "mrs {}, PRIMASK",
+            "cpsid i",
+            out(reg) primask,
+            options(nostack, preserves_flags),
+        );
+    }
+    primask
+}
+
+/// Restore `PRIMASK` to a value that `mask_interrupts` returned.
+#[cfg_attr(
+    all(target_has_atomic = "ptr", not(feature = "mask_interrupts")),
+    allow(dead_code)
+)]
+fn restore_interrupts(primask: u32) {
+    unsafe { asm!("msr PRIMASK, {}", in(reg) primask, options(nostack, preserves_flags)) };
+}
+
+/// Return whether the CPU runs in privileged thread mode on the main stack,
+/// i.e. whether the ephemeral stack can be installed as the process stack.
+fn privileged_thread_on_msp() -> bool {
+    let (ipsr, control): (u32, u32);
+    unsafe {
+        asm!(
+            "mrs {ipsr}, IPSR",
+            "mrs {control}, CONTROL",
+            ipsr = out(reg) ipsr,
+            control = out(reg) control,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+    // CONTROL.nPRIV is bit 0 and CONTROL.SPSEL is bit 1
+    ipsr == 0 && control & 0b11 == 0
+}
+
+/// Call `trampoline::<F, R>(data)` on the stack that ends at `stack_top`.
+///
+/// If `use_psp` is true, the stack is installed as the process stack and the
+/// CPU switches to it; otherwise the current stack pointer is switched.  Only
+/// Thumb-1 instructions are used, so that this also works on ARMv6-M
+/// (Cortex-M0 and M0+).
+#[inline(never)]
+unsafe fn stack_switch<F: FnOnce() -> R, R>(data: *mut u8, stack_top: *mut u8, use_psp: bool) {
+    asm!(
+        "cmp r2, #0",
+        "beq 2f",
+        // Install the new stack as the PSP, and switch to it
+        "mrs r3, PSP",
+        "msr PSP, r1",
+        "mrs r2, CONTROL",
+        "movs r1, #2",
+        "orrs r2, r1",
+        "msr CONTROL, r2",
+        "isb",
+        // Save the old PSP (and a padding word, which keeps the stack aligned
+        // to 8 bytes)
+        "push {{r2, r3}}",
+        "bl {user_fn}",
+        "pop {{r2, r3}}",
+        // Switch back to the MSP, leaving the other CONTROL bits alone
+        "mrs r1, CONTROL",
+        "movs r2, #2",
+        "bics r1, r2",
+        "msr CONTROL, r1",
+        "isb",
+        "msr PSP, r3",
+        "b 3f",
+        // Switch the current stack pointer
+        "2:",
+        "mov r2, sp",
+        "mov sp, r1",
+        "push {{r2, r3}}",
+        "bl {user_fn}",
+        "pop {{r2, r3}}",
+        "mov sp, r2",
+        "3:",
+        user_fn = sym trampoline::<F, R>,
+        inout("r0") data => _,
+        inout("r1") stack_top => _,
+        inout("r2") use_psp as u32 => _,
+        // The caller-saved registers of the AAPCS.  `clobber_abi("C")` would
+        // also list d16-d31, which do not exist on the single-precision FPUs
+        // of Cortex-M.
+        lateout("r3") _,
+        lateout("r12") _,
+        lateout("lr") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s0") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s1") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s2") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s3") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s4") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s5") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s6") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s7") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s8") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s9") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s10") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s11") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s12") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s13") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s14") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s15") _,
+    );
+}
+
+/// The stack limit registers of ARMv8-M.
+///
+/// The limits of the stack that is active while the user function runs must
+/// not lie above the ephemeral stack, or the first push onto it raises a
+/// UsageFault.  In the secure state these are the secure banked registers
+/// (`MSPLIM_S` and `PSPLIM_S`), just like `MSP` and `PSP` are.
+#[cfg(feature = "trustzone")]
+struct StackLimits {
+    msplim: u32,
+    psplim: u32,
+}
+
+#[cfg(feature = "trustzone")]
+impl StackLimits {
+    /// Save the stack limits, and set them up for a run on the stack that
+    /// starts at `stack_bottom`.
+    ///
+    /// When the stack becomes the process stack, `PSPLIM` is set to its
+    /// bottom, so that an overflow raises a UsageFault (`STKOF`) instead of
+    /// overwriting the memory below the stack.  When the current stack pointer
+    /// is switched, the limits are disabled for the duration of the run
+    /// instead, because they cannot be moved atomically with the stack
+    /// pointer.
+    unsafe fn enter(stack_bottom: *mut u8, use_psp: bool) -> Self {
+        let (msplim, psplim): (u32, u32);
+        asm!(
+            "mrs {msplim}, MSPLIM",
+            "mrs {psplim}, PSPLIM",
+            msplim = out(reg) msplim,
+            psplim = out(reg) psplim,
+            options(nomem, nostack, preserves_flags),
+        );
+        match use_psp {
+            true => set_limits(msplim, stack_bottom as u32),
+            false => set_limits(0, 0),
+        }
+        StackLimits { msplim, psplim }
+    }
+
+    /// Restore the saved stack limits.
+    unsafe fn restore(self) {
+        set_limits(self.msplim, self.psplim);
+    }
+}
+
+#[cfg(feature = "trustzone")]
+unsafe fn set_limits(msplim: u32, psplim: u32) {
+    asm!(
+        "msr MSPLIM, {msplim}",
+        "msr PSPLIM, {psplim}",
+        msplim = in(reg) msplim,
+        psplim = in(reg) psplim,
+        options(nomem, nostack, preserves_flags),
+    );
+}
+
+/// Wipe the general purpose registers and (if there is an FPU) the
+/// floating-point registers.
+///
+/// `r6` is reserved by the compiler and `r7` is the frame pointer, so neither
+/// can be wiped here.
+pub(crate) unsafe fn wipe_all_registers() {
+    asm!(
+        "movs r0, #0",
+        "mov r1, r0",
+        "mov r2, r0",
+        "mov r3, r0",
+        "mov r4, r0",
+        "mov r5, r0",
+        "mov r8, r0",
+        "mov r9, r0",
+        "mov r10, r0",
+        "mov r11, r0",
+        "mov r12, r0",
+        "mov lr, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s0, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s1, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s2, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s3, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s4, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s5, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s6, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s7, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s8, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s9, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s10, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s11, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s12, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s13, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s14, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s15, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s16, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s17, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s18, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s19, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s20, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s21, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s22, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s23, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s24, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s25, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s26, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s27, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s28, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s29, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s30, r0",
+        #[cfg(target_feature = "fpregs")]
+        "vmov s31, r0",
+        // Reset the floating-point status and control register
+        #[cfg(target_feature = "fpregs")]
+        "vmsr fpscr, r0",
+        lateout("r0") _,
+        lateout("r1") _,
+        lateout("r2") _,
+        lateout("r3") _,
+        lateout("r4") _,
+        lateout("r5") _,
+        lateout("r8") _,
+        lateout("r9") _,
+        lateout("r10") _,
+        lateout("r11") _,
+        lateout("r12") _,
+        lateout("lr") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s0") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s1") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s2") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s3") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s4") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s5") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s6") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s7") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s8") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s9") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s10") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s11") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s12") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s13") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s14") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s15") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s16") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s17") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s18") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s19") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s20") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s21") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s22") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s23") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s24") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s25") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s26") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s27") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s28") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s29") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s30") _,
+        #[cfg(target_feature = "fpregs")]
+        lateout("s31") _,
+    )
+}
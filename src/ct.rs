@@ -0,0 +1,96 @@
+/*!
+Constant-time utilities for use inside the erased context.
+
+Secrets that are handled on an ephemeral stack should not be compared with
+`==`, because the comparison returns as soon as it finds a difference, which
+leaks the position of the first differing byte through timing.  The functions
+in this module always touch every byte of their inputs, and hide the
+intermediate results from the optimizer so that it cannot reintroduce early
+exits.
+
+## Example
+```
+let tag = [0x42u8; 16];
+let ok = eraser::run_then_erase(|| eraser::ct::eq(&tag, &[0x42; 16]), 4096);
+assert!(ok);
+```
+*/
+
+use core::hint::black_box;
+use core::ptr;
+
+/// Turn `choice` into a mask that is `0xff` if `choice` is set and `0x00`
+/// otherwise, without branching on `choice`.
+fn mask(choice: bool) -> u8 {
+    (black_box(choice as u8)).wrapping_neg()
+}
+
+/// Compare `a` and `b` in constant time.
+///
+/// The running time only depends on the lengths of the slices, which are not
+/// considered secret.  Slices of different lengths are never equal.
+pub fn eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a
+        .iter()
+        .zip(b)
+        .fold(0u8, |acc, (&x, &y)| black_box(acc | (x ^ y)));
+    diff == 0
+}
+
+/// Return `a` if `choice` is set, and `b` otherwise, in constant time.
+pub fn select_u8(choice: bool, a: u8, b: u8) -> u8 {
+    let mask = mask(choice);
+    (a & mask) | (b & !mask)
+}
+
+/// Copy `a` into `out` if `choice` is set, and `b` otherwise, in constant
+/// time.
+///
+/// Panics if the slices do not all have the same length.
+pub fn select(choice: bool, a: &[u8], b: &[u8], out: &mut [u8]) {
+    assert_eq!(a.len(), b.len(), "selected slices differ in length");
+    assert_eq!(a.len(), out.len(), "output slice differs in length");
+    let mask = mask(choice);
+    for ((o, &x), &y) in out.iter_mut().zip(a).zip(b) {
+        *o = (x & mask) | (y & !mask);
+    }
+}
+
+/// Fill `buf` with `value`.
+///
+/// The bytes are written with volatile writes, so the compiler cannot remove
+/// the writes, or skip them based on the previous contents of `buf`.
+pub fn memset(buf: &mut [u8], value: u8) {
+    for byte in buf.iter_mut() {
+        unsafe { ptr::write_volatile(byte, value) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equality() {
+        assert!(eq(b"", b""));
+        assert!(eq(b"secret", b"secret"));
+        assert!(!eq(b"secret", b"secreT"));
+        assert!(!eq(b"secret", b"secrets"));
+    }
+
+    #[test]
+    fn selection() {
+        assert_eq!(select_u8(true, 1, 2), 1);
+        assert_eq!(select_u8(false, 1, 2), 2);
+        let mut out = [0; 3];
+        select(true, b"abc", b"xyz", &mut out);
+        assert_eq!(&out, b"abc");
+        select(false, b"abc", b"xyz", &mut out);
+        assert_eq!(&out, b"xyz");
+        memset(&mut out, 0x5a);
+        assert_eq!(out, [0x5a; 3]);
+    }
+}
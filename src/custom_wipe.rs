@@ -0,0 +1,78 @@
+//! Custom register wipes that downstream code registers with
+//! [`register_wipe`](crate::register_wipe).
+//!
+//! The wipes are kept in a fixed number of slots, so that this works without
+//! an allocator too.  A slot is claimed with a compare-and-swap, or, on
+//! Cortex-M cores without one (ARMv6-M) and on MSP430, with the interrupts
+//! masked.  Slots are never released.  On the other targets without a
+//! compare-and-swap (like RISC-V 32 without the A extension), no wipes can be
+//! registered.
+
+#[cfg(target_arch = "msp430")]
+use crate::msp430::AtomicPtr;
+use crate::EraserError;
+use core::ptr;
+#[cfg(not(target_arch = "msp430"))]
+use core::sync::atomic::AtomicPtr;
+use core::sync::atomic::Ordering;
+
+/// The maximum number of custom wipes.
+pub(crate) const MAX_WIPES: usize = 8;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+static WIPES: [AtomicPtr<()>; MAX_WIPES] = [EMPTY; MAX_WIPES];
+
+/// Add `wipe` to the first free slot.
+pub(crate) fn register(wipe: fn()) -> Result<(), EraserError> {
+    for slot in &WIPES {
+        if claim(slot, wipe as *mut ())? {
+            return Ok(());
+        }
+    }
+    Err(EraserError::TooManyWipes)
+}
+
+/// Store `wipe` in `slot` if the slot is free, and return whether it was.
+#[cfg(target_has_atomic = "ptr")]
+fn claim(slot: &AtomicPtr<()>, wipe: *mut ()) -> Result<bool, EraserError> {
+    let claimed = slot.compare_exchange(ptr::null_mut(), wipe, Ordering::AcqRel, Ordering::Acquire);
+    Ok(claimed.is_ok())
+}
+
+#[cfg(all(
+    not(target_has_atomic = "ptr"),
+    any(target_arch = "arm", target_arch = "msp430"),
+    target_os = "none"
+))]
+fn claim(slot: &AtomicPtr<()>, wipe: *mut ()) -> Result<bool, EraserError> {
+    Ok(crate::without_interrupts(|| {
+        if !slot.load(Ordering::Acquire).is_null() {
+            return false;
+        }
+        slot.store(wipe, Ordering::Release);
+        true
+    }))
+}
+
+#[cfg(not(any(
+    target_has_atomic = "ptr",
+    all(any(target_arch = "arm", target_arch = "msp430"), target_os = "none")
+)))]
+fn claim(_slot: &AtomicPtr<()>, _wipe: *mut ()) -> Result<bool, EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Call all the registered wipes, in the order in which they were registered.
+pub(crate) fn run() {
+    for slot in &WIPES {
+        let wipe = slot.load(Ordering::Acquire);
+        if wipe.is_null() {
+            // The slots are claimed in order, and never released
+            break;
+        }
+        let wipe = unsafe { core::mem::transmute::<*mut (), fn()>(wipe) };
+        wipe();
+    }
+}
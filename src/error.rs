@@ -0,0 +1,84 @@
+use core::fmt;
+
+/// Errors that can occur while setting up the erased context.
+///
+/// Note that these errors only describe failures of eraser itself.  If the
+/// user function panics, that panic is still propagated to the caller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum EraserError {
+    /// The requested stack size is not a multiple of the required stack
+    /// alignment.
+    BadAlignment,
+    /// The stack could not be allocated.
+    AllocFailed,
+    /// The requested stack is too small to run anything on.
+    StackTooSmall,
+    /// The requested operation is not supported on this target or with the
+    /// enabled features.
+    Unsupported,
+    /// The stack could not be locked into memory.
+    LockFailed,
+    /// The protection of the stack memory could not be changed.
+    ProtectFailed,
+    /// The user function overflowed the ephemeral stack.
+    ///
+    /// This is only returned for stacks with a guard page, on x86_64 Linux,
+    /// that were set up with [`EraserBuilder::recover_from_overflow`];
+    /// otherwise, the overflow aborts the process.  The user function is
+    /// abandoned without running any destructors, and the stack is erased.
+    ///
+    /// [`EraserBuilder::recover_from_overflow`]: crate::EraserBuilder::recover_from_overflow
+    StackOverflow,
+    /// The thread could not be pinned to the requested CPU.
+    PinFailed,
+    /// The user function could not be run in an isolated child process, or
+    /// the child process crashed.
+    IsolationFailed,
+    /// All the slots for custom register wipes are taken.
+    TooManyWipes,
+    /// The configured erase policy does not overwrite the stack at all.
+    NoErasePasses,
+}
+
+impl fmt::Display for EraserError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EraserError::BadAlignment => write!(
+                f,
+                "stack size is not a multiple of {} bytes",
+                crate::STACK_ALIGN
+            ),
+            EraserError::AllocFailed => write!(f, "could not allocate the ephemeral stack"),
+            EraserError::StackTooSmall => write!(
+                f,
+                "stack is too small (it must be at least {} bytes)",
+                crate::MIN_STACK_SIZE
+            ),
+            EraserError::Unsupported => write!(f, "operation not supported on this target"),
+            EraserError::LockFailed => write!(
+                f,
+                "could not lock the ephemeral stack into memory (is RLIMIT_MEMLOCK too low?)"
+            ),
+            EraserError::ProtectFailed => {
+                write!(f, "could not change the protection of the stack memory")
+            }
+            EraserError::StackOverflow => write!(f, "the user function overflowed the stack"),
+            EraserError::PinFailed => write!(f, "could not pin the thread to the requested CPU"),
+            EraserError::IsolationFailed => {
+                write!(f, "the isolated child process could not be run or crashed")
+            }
+            EraserError::TooManyWipes => write!(
+                f,
+                "too many custom register wipes (at most {})",
+                crate::custom_wipe::MAX_WIPES
+            ),
+            EraserError::NoErasePasses => {
+                write!(f, "the erase policy must overwrite the stack at least once")
+            }
+        }
+    }
+}
+
+impl core::error::Error for EraserError {}
@@ -0,0 +1,57 @@
+//! `defmt` events for the runs on bare-metal targets.
+//!
+//! With the `defmt` feature, a run logs an event when it switches to the
+//! ephemeral stack, and another one once the stack has been erased and the
+//! registers have been wiped, so that the protection can be seen at work over
+//! RTT (or whatever transport the application uses).  A canary word is also
+//! written to the lowest word of the stack before the run, and checked
+//! afterwards: if the user function overwrote it, it ran out of stack, and an
+//! error is logged.  Without the feature, these functions do nothing.
+
+/// The canary that is written to the lowest word of the ephemeral stack.
+#[cfg(feature = "defmt")]
+const CANARY: usize = 0x5afe_57ac_5afe_57ac_u64 as usize;
+
+/// Log the start of a run on the stack of `len` bytes at `stack_bottom`.
+#[inline(always)]
+pub(crate) unsafe fn enter(stack_bottom: *mut u8, len: usize) {
+    #[cfg(feature = "defmt")]
+    {
+        (stack_bottom as *mut usize).write_volatile(CANARY);
+        defmt::debug!(
+            "eraser: running on the ephemeral stack at {=usize:#x} ({=usize} bytes)",
+            stack_bottom as usize,
+            len
+        );
+    }
+    #[cfg(not(feature = "defmt"))]
+    let _ = (stack_bottom, len);
+}
+
+/// Check the canary of the stack at `stack_bottom` after the run (but before
+/// the stack is erased), and log an error if the user function overwrote it.
+#[inline(always)]
+pub(crate) unsafe fn check_overflow(stack_bottom: *mut u8) {
+    #[cfg(feature = "defmt")]
+    if (stack_bottom as *const usize).read_volatile() != CANARY {
+        defmt::error!(
+            "eraser: the user function overflowed the ephemeral stack at {=usize:#x}",
+            stack_bottom as usize
+        );
+    }
+    #[cfg(not(feature = "defmt"))]
+    let _ = stack_bottom;
+}
+
+/// Log that the stack of `len` bytes has been erased, and the registers have
+/// been wiped.
+#[inline(always)]
+pub(crate) fn erased(len: usize) {
+    #[cfg(feature = "defmt")]
+    defmt::debug!(
+        "eraser: erased the ephemeral stack ({=usize} bytes) and wiped the registers",
+        len
+    );
+    #[cfg(not(feature = "defmt"))]
+    let _ = len;
+}
@@ -0,0 +1,206 @@
+//! Running the user function on a Windows fiber.
+//!
+//! Instead of switching the stack pointer by hand, the
+//! [`SwitchBackend::Fiber`](crate::SwitchBackend::Fiber) backend creates a
+//! fiber (with `CreateFiberEx`) for every run, and switches to it with
+//! `SwitchToFiber`.  Windows then keeps its own bookkeeping of the stack (the
+//! stack bounds in the thread environment block, the guard page, and the
+//! exception handling chain) up to date, and debuggers can walk the stack of
+//! the fiber.  The stack of the fiber is owned by Windows, so none of the
+//! stack protections can be applied to it.
+//!
+//! Right before the user function returns to the calling fiber, the fiber
+//! records the bounds of its stack.  The calling fiber then erases that stack
+//! before deleting the fiber.
+
+use crate::thread::ThreadProtections;
+use crate::{EraseConfig, EraserError};
+
+/// Run `f` on a new fiber with a stack of `stack_size` bytes, and erase that
+/// stack before the fiber is deleted.
+pub(crate) fn run<F, R>(
+    f: F,
+    stack_size: usize,
+    thread: &ThreadProtections,
+    config: &EraseConfig,
+) -> Result<R, EraserError>
+where
+    F: FnOnce() -> R,
+{
+    crate::check_supported()?;
+    let _applied = thread.apply()?;
+    #[cfg(all(windows, target_arch = "x86_64"))]
+    {
+        unsafe { windows::run(f, stack_size, config) }
+    }
+    #[cfg(not(all(windows, target_arch = "x86_64")))]
+    {
+        let _ = (f, stack_size, config);
+        Err(EraserError::Unsupported)
+    }
+}
+
+#[cfg(all(windows, target_arch = "x86_64"))]
+mod windows {
+    use crate::{EraseConfig, EraserError};
+    use core::arch::asm;
+    use core::ffi::c_void;
+    use std::mem::MaybeUninit;
+    use std::{panic, thread};
+
+    const FIBER_FLAG_FLOAT_SWITCH: u32 = 0x1;
+
+    type FiberStart = unsafe extern "system" fn(param: *mut c_void);
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn IsThreadAFiber() -> i32;
+        fn ConvertThreadToFiber(param: *mut c_void) -> *mut c_void;
+        fn ConvertFiberToThread() -> i32;
+        fn CreateFiberEx(
+            commit_size: usize,
+            reserve_size: usize,
+            flags: u32,
+            start: FiberStart,
+            param: *mut c_void,
+        ) -> *mut c_void;
+        fn SwitchToFiber(fiber: *mut c_void);
+        fn DeleteFiber(fiber: *mut c_void);
+    }
+
+    /// The state that is shared between the calling fiber and the new fiber.
+    struct Payload<F, R> {
+        f: MaybeUninit<F>,
+        result: MaybeUninit<thread::Result<R>>,
+        /// The fiber to switch back to.
+        caller: *mut c_void,
+        /// The bounds (`StackLimit`, `StackBase`) of the stack of the new
+        /// fiber, recorded by that fiber.
+        stack: (usize, usize),
+    }
+
+    /// Return the fiber that is running on the current thread.
+    unsafe fn current_fiber() -> *mut c_void {
+        let fiber: *mut c_void;
+        asm!(
+            "mov {}, qword ptr gs:[0x20]",
+            out(reg) fiber,
+            options(nostack, readonly, preserves_flags),
+        );
+        fiber
+    }
+
+    /// Return the bounds of the stack of the current fiber.
+    unsafe fn stack_bounds() -> (usize, usize) {
+        let (limit, base): (usize, usize);
+        asm!(
+            "mov {limit}, qword ptr gs:[0x10]",
+            "mov {base}, qword ptr gs:[0x08]",
+            limit = out(reg) limit,
+            base = out(reg) base,
+            options(nostack, readonly, preserves_flags),
+        );
+        (limit, base)
+    }
+
+    unsafe extern "system" fn start<F: FnOnce() -> R, R>(param: *mut c_void) {
+        let payload = &mut *(param as *mut Payload<F, R>);
+        let f = payload.f.as_ptr().read();
+        payload
+            .result
+            .write(panic::catch_unwind(panic::AssertUnwindSafe(f)));
+        payload.stack = stack_bounds();
+        SwitchToFiber(payload.caller);
+        unreachable!("the fiber is deleted before it is resumed");
+    }
+
+    pub(super) unsafe fn run<F, R>(
+        f: F,
+        stack_size: usize,
+        config: &EraseConfig,
+    ) -> Result<R, EraserError>
+    where
+        F: FnOnce() -> R,
+    {
+        // Only a fiber can switch to another fiber
+        let converted = IsThreadAFiber() == 0;
+        let caller = match converted {
+            true => ConvertThreadToFiber(core::ptr::null_mut()),
+            false => current_fiber(),
+        };
+        if caller.is_null() {
+            return Err(EraserError::AllocFailed);
+        }
+
+        let mut payload = Payload {
+            f: MaybeUninit::new(f),
+            result: MaybeUninit::uninit(),
+            caller,
+            stack: (0, 0),
+        };
+        let param = &mut payload as *mut Payload<F, R> as *mut c_void;
+        // Commit the whole stack up front, so that it does not grow while the
+        // user function runs, and the recorded bounds cover all of it
+        let flags = FIBER_FLAG_FLOAT_SWITCH;
+        let fiber = CreateFiberEx(stack_size, stack_size, flags, start::<F, R>, param);
+        if fiber.is_null() {
+            if converted {
+                ConvertFiberToThread();
+            }
+            payload.f.assume_init_drop();
+            crate::erase_slot(&mut payload.f, config);
+            return Err(EraserError::AllocFailed);
+        }
+
+        SwitchToFiber(fiber);
+        let (limit, base) = payload.stack;
+        crate::erase_words(limit as *mut u8, base - limit, config);
+        DeleteFiber(fiber);
+        if converted {
+            ConvertFiberToThread();
+        }
+        crate::erase_slot(&mut payload.f, config);
+
+        let result = payload.result.as_ptr().read();
+        crate::erase_slot(&mut payload.result, config);
+        crate::wipe_registers_after_run(config);
+        match result {
+            Ok(result) => Ok(result),
+            Err(err) => panic::resume_unwind(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Eraser, EraserError, SwitchBackend};
+
+    #[test]
+    fn fiber() {
+        let eraser = Eraser::builder()
+            .stack_size(64 * 1024)
+            .switch_backend(SwitchBackend::Fiber)
+            .guard_page(false)
+            .build();
+        let eraser = match eraser {
+            Ok(eraser) => eraser,
+            Err(err) if !cfg!(windows) => {
+                assert_eq!(err, EraserError::Unsupported);
+                return;
+            }
+            Err(err) => panic!("{}", err),
+        };
+        assert_eq!(eraser.run(|| 6 * 7), 42);
+        // A fiber can start another fiber
+        assert_eq!(eraser.run(|| eraser.run(|| 1) + 1), 2);
+        let panicked = std::panic::catch_unwind(|| eraser.run(|| panic!("oops")));
+        assert!(panicked.is_err());
+        assert_eq!(eraser.new_stack().unwrap_err(), EraserError::Unsupported);
+
+        let protected = Eraser::builder()
+            .switch_backend(SwitchBackend::Fiber)
+            .guard_page(true)
+            .build();
+        assert_eq!(protected.unwrap_err(), EraserError::Unsupported);
+    }
+}
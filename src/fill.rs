@@ -0,0 +1,253 @@
+//! Fast paths for overwriting memory with a fixed word.
+//!
+//! Erasing a multi-megabyte stack one volatile word at a time is slow.  On
+//! x86_64 the fill is done with non-temporal AVX-512 or AVX2 stores (for large
+//! buffers, which would only evict useful data from the cache) or with `rep
+//! stosq` (on CPUs with fast string operations).  The feature checks are done
+//! at runtime, and the volatile loop is kept as the fallback, which is also
+//! the only path on bare-metal x86_64, where the checks are not available.
+//!
+//! All the fast paths are written in inline assembly: the compiler has to
+//! assume that an `asm!` block reads the memory that it is pointed to, so the
+//! writes can never be optimized away, just like the volatile writes.
+
+use core::ptr;
+
+/// Buffers of at least this many bytes are filled with non-temporal stores.
+#[cfg(not(target_pointer_width = "16"))]
+#[cfg_attr(not(all(target_arch = "x86_64", feature = "std")), allow(dead_code))]
+const NONTEMPORAL_THRESHOLD: usize = 1024 * 1024;
+
+const WORD: usize = core::mem::size_of::<usize>();
+
+/// Fill `len` bytes at `ptr_mut` with `word`.
+///
+/// `ptr_mut` must be aligned to a word, and `len` must be a multiple of the
+/// word size.
+pub(crate) unsafe fn fill_words(ptr_mut: *mut u8, len: usize, word: usize) {
+    debug_assert_eq!(ptr_mut.align_offset(WORD), 0);
+    debug_assert_eq!(len % WORD, 0);
+
+    #[cfg(all(target_arch = "x86_64", feature = "std"))]
+    {
+        if len >= NONTEMPORAL_THRESHOLD && std::is_x86_feature_detected!("avx512f") {
+            return x86_64::fill_nontemporal_avx512(ptr_mut, len, word);
+        }
+        if len >= NONTEMPORAL_THRESHOLD && std::is_x86_feature_detected!("avx2") {
+            return x86_64::fill_nontemporal(ptr_mut, len, word);
+        }
+        if std::is_x86_feature_detected!("ermsb") {
+            return x86_64::fill_rep_stos(ptr_mut, len, word);
+        }
+    }
+    fill_volatile(ptr_mut, len, word)
+}
+
+/// Flush the cache lines that hold the `len` bytes at `ptr`, so that the
+/// erased values reach main memory.
+///
+/// On targets other than x86_64 with `std` this function does nothing.
+pub(crate) unsafe fn flush(ptr: *const u8, len: usize) {
+    #[cfg(all(target_arch = "x86_64", feature = "std"))]
+    x86_64::flush(ptr, len);
+    #[cfg(not(all(target_arch = "x86_64", feature = "std")))]
+    let _ = (ptr, len);
+}
+
+unsafe fn fill_volatile(ptr_mut: *mut u8, len: usize, word: usize) {
+    for offset in (0..len).step_by(WORD) {
+        ptr::write_volatile(ptr_mut.add(offset) as *mut usize, word);
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", feature = "std"))]
+mod x86_64 {
+    use super::{fill_volatile, WORD};
+    use core::arch::asm;
+    use core::arch::x86_64::{__cpuid, __cpuid_count};
+    use std::sync::OnceLock;
+
+    /// The width of an AVX2 store.
+    const LANE: usize = 32;
+    /// The width of an AVX-512 store.
+    const LANE_AVX512: usize = 64;
+
+    pub(super) unsafe fn fill_rep_stos(ptr_mut: *mut u8, len: usize, word: usize) {
+        asm!(
+            "rep stosq",
+            inout("rcx") len / WORD => _,
+            inout("rdi") ptr_mut => _,
+            in("rax") word,
+            options(nostack, preserves_flags),
+        );
+    }
+
+    pub(super) unsafe fn fill_nontemporal(ptr_mut: *mut u8, len: usize, word: usize) {
+        fill_lanes(ptr_mut, len, word, LANE, |ptr, len| {
+            fill_avx2(ptr, len, word)
+        });
+    }
+
+    pub(super) unsafe fn fill_nontemporal_avx512(ptr_mut: *mut u8, len: usize, word: usize) {
+        fill_lanes(ptr_mut, len, word, LANE_AVX512, |ptr, len| {
+            fill_avx512(ptr, len, word)
+        });
+    }
+
+    /// Fill the part of the buffer that is aligned to `lane` bytes with
+    /// `fill_body`, and the rest with volatile writes.
+    unsafe fn fill_lanes(
+        ptr_mut: *mut u8,
+        len: usize,
+        word: usize,
+        lane: usize,
+        fill_body: impl FnOnce(*mut u8, usize),
+    ) {
+        // `vmovntdq` needs addresses that are aligned to its width, so fill
+        // the unaligned head and tail separately
+        let head = ptr_mut.align_offset(lane).min(len);
+        let body = (len - head) / lane * lane;
+        fill_volatile(ptr_mut, head, word);
+        if body > 0 {
+            fill_body(ptr_mut.add(head), body);
+        }
+        fill_volatile(ptr_mut.add(head + body), len - head - body, word);
+    }
+
+    /// The smallest cache line size of the x86_64 CPUs that we care about.
+    const CACHE_LINE: usize = 64;
+
+    /// `is_x86_feature_detected!` does not know about `clflushopt`, so ask
+    /// CPUID directly (leaf 7, EBX bit 23), and cache the answer.
+    fn has_clflushopt() -> bool {
+        static HAS_CLFLUSHOPT: OnceLock<bool> = OnceLock::new();
+        *HAS_CLFLUSHOPT
+            .get_or_init(|| __cpuid(0).eax >= 7 && __cpuid_count(7, 0).ebx & (1 << 23) != 0)
+    }
+
+    pub(super) unsafe fn flush(ptr: *const u8, len: usize) {
+        if len == 0 {
+            return;
+        }
+        let start = ptr as usize & !(CACHE_LINE - 1);
+        let end = ptr as usize + len;
+        if has_clflushopt() {
+            // `clflushopt` is weakly ordered, so it needs a fence
+            for line in (start..end).step_by(CACHE_LINE) {
+                asm!("clflushopt [{}]", in(reg) line, options(nostack, preserves_flags));
+            }
+            asm!("sfence", options(nostack, preserves_flags));
+        } else {
+            for line in (start..end).step_by(CACHE_LINE) {
+                asm!("clflush [{}]", in(reg) line, options(nostack, preserves_flags));
+            }
+            asm!("mfence", options(nostack, preserves_flags));
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn fill_avx2(ptr_mut: *mut u8, len: usize, word: usize) {
+        // Non-temporal stores are weakly ordered, so they are followed by a
+        // fence that makes them visible before this function returns.
+        asm!(
+            "vmovq xmm0, {word}",
+            "vpbroadcastq ymm0, xmm0",
+            "2:",
+            "vmovntdq ymmword ptr [{ptr}], ymm0",
+            "add {ptr}, 32",
+            "sub {len}, 32",
+            "jnz 2b",
+            "sfence",
+            "vzeroupper",
+            word = in(reg) word,
+            ptr = inout(reg) ptr_mut => _,
+            len = inout(reg) len => _,
+            out("ymm0") _,
+            options(nostack),
+        );
+    }
+
+    #[target_feature(enable = "avx512f")]
+    unsafe fn fill_avx512(ptr_mut: *mut u8, len: usize, word: usize) {
+        // Like `fill_avx2`, with 64-byte stores
+        asm!(
+            "vpbroadcastq zmm0, {word}",
+            "2:",
+            "vmovntdq zmmword ptr [{ptr}], zmm0",
+            "add {ptr}, 64",
+            "sub {len}, 64",
+            "jnz 2b",
+            "sfence",
+            "vzeroupper",
+            word = in(reg) word,
+            ptr = inout(reg) ptr_mut => _,
+            len = inout(reg) len => _,
+            out("zmm0") _,
+            options(nostack),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run `fill` on every word-aligned sub-range of a buffer, and check that
+    /// exactly that sub-range was filled.
+    fn check(fill: unsafe fn(*mut u8, usize, usize)) {
+        let mut buf = vec![0usize; 64];
+        for start in 0..8 {
+            for words in 0..48 {
+                buf.fill(0);
+                let ptr_mut = buf[start..].as_mut_ptr() as *mut u8;
+                unsafe { fill(ptr_mut, words * WORD, 0x5555_5555_5555_5555_u64 as usize) };
+                for (index, &w) in buf.iter().enumerate() {
+                    let inside = (start..start + words).contains(&index);
+                    assert_eq!(w != 0, inside, "start={} words={}", start, words);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn volatile() {
+        check(fill_volatile);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn rep_stos() {
+        check(x86_64::fill_rep_stos);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn nontemporal() {
+        if std::is_x86_feature_detected!("avx2") {
+            check(x86_64::fill_nontemporal);
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn nontemporal_avx512() {
+        if std::is_x86_feature_detected!("avx512f") {
+            check(x86_64::fill_nontemporal_avx512);
+        }
+    }
+
+    #[test]
+    fn flush_lines() {
+        let mut buf = vec![0u8; 1000];
+        buf.fill(3);
+        unsafe { flush(buf[5..].as_ptr(), 900) };
+        assert!(buf.iter().all(|&x| x == 3));
+    }
+
+    #[test]
+    fn large() {
+        let mut buf = vec![0usize; 2 * NONTEMPORAL_THRESHOLD / WORD];
+        unsafe { fill_words(buf.as_mut_ptr() as *mut u8, buf.len() * WORD, 7) };
+        assert!(buf.iter().all(|&w| w == 7));
+    }
+}
@@ -0,0 +1,160 @@
+//! Erasure of long-lived stacks in forked child processes.
+//!
+//! The stacks of a [`StackPool`](crate::StackPool) and of an
+//! [`EraserSession`](crate::EraserSession) live for a long time, and may
+//! still hold secret residue (for example, when the fork happens while a run
+//! is in progress on another thread).  These stacks are registered here, and
+//! a `pthread_atfork` child handler erases all of them in the child process
+//! right after a `fork()`.
+//!
+//! Stacks whose memory is wiped by the kernel already (see
+//! [`EraserBuilder::wipe_on_fork`](crate::EraserBuilder::wipe_on_fork)) are
+//! not registered.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// A memory region that is erased in forked child processes.
+#[cfg_attr(any(not(unix), target_os = "fuchsia"), allow(dead_code))]
+struct Region {
+    ptr: *mut u8,
+    len: usize,
+    /// The word that the region is erased with.
+    word: usize,
+    /// The protection key that the region is tagged with, if any.
+    key: Option<i32>,
+    /// Whether the region carries memory tags.
+    tagged: bool,
+}
+
+/// The registered regions, behind a spin lock.
+///
+/// A regular mutex cannot be used here: the lock is taken in the `prepare`
+/// handler and released in the `parent` and `child` handlers, which do not
+/// share a guard.
+struct Registry {
+    locked: AtomicBool,
+    regions: UnsafeCell<Vec<Region>>,
+}
+
+// The regions are only accessed while holding `locked`.
+unsafe impl Sync for Registry {}
+
+static REGISTRY: Registry = Registry {
+    locked: AtomicBool::new(false),
+    regions: UnsafeCell::new(Vec::new()),
+};
+
+impl Registry {
+    fn lock(&self) {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+    }
+
+    fn unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&mut Vec<Region>) -> T) -> T {
+        self.lock();
+        let result = f(unsafe { &mut *self.regions.get() });
+        self.unlock();
+        result
+    }
+}
+
+/// Keeps a region registered until it is dropped.
+#[derive(Debug)]
+pub(crate) struct Registration {
+    ptr: *mut u8,
+}
+
+// The registration only identifies a region by its address.
+unsafe impl Send for Registration {}
+unsafe impl Sync for Registration {}
+
+/// Erase the `len` bytes at `ptr` with `word` in every forked child process,
+/// until the returned value is dropped.
+///
+/// If the memory is tagged with a protection key or with memory tags, `key`
+/// and `tagged` say so.  `ptr` and `len` must be aligned to 32 bytes.
+pub(crate) fn register(
+    ptr: *mut u8,
+    len: usize,
+    word: usize,
+    key: Option<i32>,
+    tagged: bool,
+) -> Registration {
+    install_handler();
+    let region = Region {
+        ptr,
+        len,
+        word,
+        key,
+        tagged,
+    };
+    REGISTRY.with(|regions| regions.push(region));
+    Registration { ptr }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        REGISTRY.with(|regions| regions.retain(|region| region.ptr != self.ptr));
+    }
+}
+
+#[cfg(all(unix, not(target_os = "fuchsia")))]
+fn install_handler() {
+    use std::sync::Once;
+
+    extern "C" {
+        fn pthread_atfork(
+            prepare: Option<unsafe extern "C" fn()>,
+            parent: Option<unsafe extern "C" fn()>,
+            child: Option<unsafe extern "C" fn()>,
+        ) -> core::ffi::c_int;
+    }
+
+    unsafe extern "C" fn prepare() {
+        REGISTRY.lock();
+    }
+
+    unsafe extern "C" fn parent() {
+        REGISTRY.unlock();
+    }
+
+    unsafe extern "C" fn child() {
+        // Only async-signal-safe operations are allowed here, so the regions
+        // are not freed, only erased.  If `fork()` was called by a user
+        // function, the child continues on its stack, so that one is kept.
+        let marker = 0u8;
+        let sp = core::hint::black_box(&marker) as *const u8 as usize;
+        for region in &*REGISTRY.regions.get() {
+            if (region.ptr as usize..region.ptr as usize + region.len).contains(&sp) {
+                continue;
+            }
+            let _access = crate::pkey::Access::new(region.key);
+            let ptr = match region.tagged {
+                true => crate::mte::untag(region.ptr, region.len),
+                false => region.ptr,
+            };
+            crate::fill::fill_words(ptr, region.len, region.word);
+        }
+        REGISTRY.unlock();
+    }
+
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let result = unsafe { pthread_atfork(Some(prepare), Some(parent), Some(child)) };
+        assert_eq!(result, 0, "could not install the fork handlers");
+    });
+}
+
+/// Without `fork()`, there is nothing to do.
+#[cfg(any(not(unix), target_os = "fuchsia"))]
+fn install_handler() {}
@@ -0,0 +1,71 @@
+use crate::sys;
+
+/// The outcome of [`harden_process`]: which of the hardening steps succeeded.
+///
+/// A step that is not supported on the current target is reported as
+/// failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct HardenReport {
+    /// The process was marked as not dumpable (`PR_SET_DUMPABLE=0`), so that
+    /// it does not produce core dumps and other processes of the same user
+    /// cannot attach to it with `ptrace`.  On macOS and FreeBSD, debuggers are
+    /// denied from attaching to the process instead (`PT_DENY_ATTACH` and
+    /// `PROC_TRACE_CTL`).
+    pub not_dumpable: bool,
+    /// The core dump size limit (`RLIMIT_CORE`) was set to zero.
+    pub no_core_dumps: bool,
+    /// The process (and its children) can no longer gain privileges through
+    /// `execve` (`PR_SET_NO_NEW_PRIVS`).
+    pub no_new_privs: bool,
+}
+
+impl HardenReport {
+    /// Return whether all the hardening steps succeeded.
+    pub fn is_complete(&self) -> bool {
+        self.not_dumpable && self.no_core_dumps && self.no_new_privs
+    }
+}
+
+/// Apply the usual process-wide hardening before handling secrets.
+///
+/// This marks the process as not dumpable, sets the core dump size limit to
+/// zero, and disables gaining new privileges.  Together with the protections
+/// of the ephemeral stacks (see [`Eraser::paranoid`](crate::Eraser::paranoid)),
+/// this keeps secrets from leaking through core dumps and debuggers.  None of
+/// these settings can be undone by eraser, so call this once, early in the
+/// process.
+///
+/// Every step is attempted, even if an earlier one failed; the returned
+/// [`HardenReport`] tells which of them succeeded.  Only fully supported on
+/// Linux and FreeBSD 14 or later.  On macOS and OpenBSD, not all the steps are
+/// supported, and on other targets every step fails.
+///
+/// ## Example
+/// ```no_run
+/// let report = eraser::harden_process();
+/// if !report.is_complete() {
+///     eprintln!("warning: could not fully harden the process: {:?}", report);
+/// }
+/// ```
+pub fn harden_process() -> HardenReport {
+    HardenReport {
+        not_dumpable: sys::set_not_dumpable().is_ok(),
+        no_core_dumps: sys::disable_core_dumps().is_ok(),
+        no_new_privs: sys::set_no_new_privs().is_ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn harden() {
+        let report = harden_process();
+        let complete = cfg!(any(target_os = "linux", target_os = "freebsd"));
+        assert_eq!(report.is_complete(), complete);
+        // Hardening twice is fine
+        assert_eq!(harden_process(), report);
+    }
+}
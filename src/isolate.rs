@@ -0,0 +1,198 @@
+use crate::sys::{self, ChildExit};
+use crate::EraserError;
+use std::panic;
+
+/// The states of a run, as recorded by the child in the shared memory.  The
+/// memory starts out zeroed, so a child that ends before the user function
+/// returns leaves neither of them behind.
+const RETURNED: usize = 1;
+const PANICKED: usize = 2;
+
+const WORD: usize = core::mem::size_of::<usize>();
+
+/// Memory that is shared with a child process, erased and unmapped on drop.
+struct SharedMemory {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl SharedMemory {
+    fn new(len: usize) -> Result<Self, EraserError> {
+        let len = len
+            .checked_next_multiple_of(sys::page_size())
+            .ok_or(EraserError::AllocFailed)?;
+        Ok(SharedMemory {
+            ptr: sys::map_shared(len)?,
+            len,
+        })
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        unsafe {
+            crate::erase_bytes(self.ptr, self.len, &crate::EraseConfig::default());
+            sys::unmap(self.ptr, self.len);
+        }
+    }
+}
+
+/// Run a function in a short-lived child process, and write its output to
+/// `out`.
+///
+/// This is like [`run_then_erase_into`](crate::run_then_erase_into), but
+/// instead of running the user function on an ephemeral stack, it runs it in a
+/// forked child process.  The user function writes its output into memory that
+/// is shared with the parent, and the child exits right after.  The stack (and
+/// the heap, and the registers) of the user function then live in an address
+/// space that ceases to exist when the run finishes, which is stronger than
+/// any in-process erase.  The shared memory is erased and unmapped after the
+/// output has been copied to `out`.
+///
+/// Forking is expensive, so this is only worth it for rare operations with
+/// long-term secrets.  The child process only has the calling thread, and its
+/// effects on anything else than `out` are lost when the child exits.
+///
+/// Returns [`EraserError::IsolationFailed`] if the child could not be started,
+/// crashed, or exited before the user function returned.  If the user
+/// function panics, this function panics as well (the original panic is
+/// reported by the child).  Only supported on Linux, macOS, FreeBSD, OpenBSD,
+/// illumos, Redox and QNX.
+///
+/// ## Safety
+///
+/// * If the process has other threads, the user function must only call
+///   async-signal-safe functions (see `signal-safety(7)`).  A lock that
+///   another thread held at the time of the fork stays locked forever in the
+///   child, so taking it there (e.g. by allocating, printing or panicking)
+///   may deadlock the child, and then this function never returns.
+///
+/// ## Example
+/// ```
+/// let key = [0x5Au8; 16];
+/// let mut out = [0u8; 32];
+/// // This doctest is the only thread of its process
+/// let len = unsafe {
+///     eraser::run_isolated(
+///         |out| {
+///             for (o, k) in out.iter_mut().zip(key.iter()) {
+///                 *o = k ^ 0xFF;
+///             }
+///             key.len()
+///         },
+///         &mut out,
+///     )
+/// }
+/// .unwrap();
+/// assert_eq!(&out[..len], &[0xA5; 16]);
+/// ```
+///
+/// A panic in the user function is propagated to the caller:
+/// ```should_panic
+/// // This doctest is the only thread of its process, so the child can panic
+/// unsafe { eraser::run_isolated(|_| panic!("oops"), &mut []) }.unwrap();
+/// ```
+pub unsafe fn run_isolated<F>(f: F, out: &mut [u8]) -> Result<usize, EraserError>
+where
+    F: FnOnce(&mut [u8]) -> usize,
+{
+    // The shared memory holds the state of the run and the number of written
+    // bytes, followed by the output
+    let shared = SharedMemory::new(2 * WORD + out.len())?;
+    let state_ptr = shared.ptr as *mut usize;
+    let written_ptr = unsafe { state_ptr.add(1) };
+    let output = unsafe { core::slice::from_raw_parts_mut(shared.ptr.add(2 * WORD), out.len()) };
+
+    match sys::fork_process()? {
+        0 => {
+            let result = panic::catch_unwind(panic::AssertUnwindSafe(|| f(output)));
+            let state = match result {
+                Ok(written) => {
+                    unsafe { written_ptr.write_volatile(written) };
+                    RETURNED
+                }
+                Err(_) => PANICKED,
+            };
+            unsafe { state_ptr.write_volatile(state) };
+            sys::exit_child(0)
+        }
+        pid => match sys::wait_child(pid)? {
+            ChildExit::Exited(0) => {}
+            _ => return Err(EraserError::IsolationFailed),
+        },
+    }
+
+    match unsafe { state_ptr.read_volatile() } {
+        RETURNED => {}
+        PANICKED => panic!("the user function panicked in the isolated child process"),
+        _ => return Err(EraserError::IsolationFailed),
+    }
+    let written = unsafe { written_ptr.read_volatile() };
+    assert!(
+        written <= out.len(),
+        "user function wrote {} bytes to a buffer of {} bytes",
+        written,
+        out.len()
+    );
+    out[..written].copy_from_slice(&output[..written]);
+    Ok(written)
+}
+
+#[cfg(all(
+    test,
+    any(
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "illumos",
+        target_os = "redox",
+        target_os = "nto"
+    )
+))]
+mod tests {
+    use super::*;
+
+    // The test harness runs other tests on other threads, so the user
+    // functions in these tests do not allocate, panic, or take any other
+    // lock.  A panicking user function is covered by the doctest of
+    // `run_isolated` instead, which is the only thread of its process.
+
+    #[test]
+    fn isolated() {
+        let mut secret = [1u8, 2, 3, 0];
+        let mut out = [0u8; 8];
+        let written = unsafe {
+            run_isolated(
+                |out| {
+                    // Changes to the parent's memory are lost
+                    secret[3] = 4;
+                    out[..secret.len()].copy_from_slice(&secret);
+                    secret.len()
+                },
+                &mut out,
+            )
+        };
+        assert_eq!(written, Ok(4));
+        assert_eq!(out, [1, 2, 3, 4, 0, 0, 0, 0]);
+        assert_eq!(secret, [1, 2, 3, 0]);
+
+        let crashed = unsafe { run_isolated(|_| kill_self(), &mut out) };
+        assert_eq!(crashed, Err(EraserError::IsolationFailed));
+
+        // An exit status that the child chose itself is not mistaken for a
+        // panic
+        let exited = unsafe { run_isolated(|_| sys::exit_child(101), &mut out) };
+        assert_eq!(exited, Err(EraserError::IsolationFailed));
+        let exited = unsafe { run_isolated(|_| sys::exit_child(0), &mut out) };
+        assert_eq!(exited, Err(EraserError::IsolationFailed));
+    }
+
+    unsafe fn kill_self() -> usize {
+        extern "C" {
+            fn raise(signum: i32) -> i32;
+        }
+        const SIGKILL: i32 = 9;
+        raise(SIGKILL) as usize
+    }
+}
@@ -1,43 +1,820 @@
+#![cfg_attr(not(feature = "std"), no_std)]
 #![deny(missing_docs)]
+// Inline assembly is not stable yet on MIPS, SPARC, Xtensa and MSP430, so these
+// targets need a nightly compiler, unless the `cc_shim` feature provides the
+// assembly
+#![cfg_attr(
+    any(
+        all(
+            any(target_arch = "mips", target_arch = "mips64", target_arch = "sparc64"),
+            not(eraser_shim)
+        ),
+        target_arch = "xtensa",
+        target_arch = "msp430"
+    ),
+    feature(asm_experimental_arch)
+)]
 
 /*!
 This crate provides a runtime context that allows you to securely run code that
 deals with secrets, for example cryptographic code.  It does this by allocating
 a separate stack and on the heap and executing the user-supplied code with the
 separate stack.  After running the code, we erase the complete stack and (on
-x86_64) we wipe all the CPU registers before returning.
+x86_64 and aarch64) we wipe all the CPU registers before returning.
+
+## `no_std`, Cortex-M, RISC-V, Xtensa, MSP430 and bare-metal x86_64
+
+With the default `std` feature disabled, eraser builds for Cortex-M targets
+(`thumbv6m`, `thumbv7m`, `thumbv7em` and `thumbv8m`), for bare-metal 32-bit
+RISC-V targets (such as `riscv32imac-unknown-none-elf`), for bare-metal Xtensa
+targets (such as `xtensa-esp32-none-elf`), for MSP430 (`msp430-none-elf`) and
+for bare-metal x86_64 (`x86_64-unknown-none`, for kernels, hypervisors and
+bootloaders).  Only the functions that take a caller-provided stack (such as
+[`run_then_erase_with_stack`]), [`erase`] and [`wipe_registers`] are available
+there.  On Cortex-M, when called from privileged thread mode, the stack is
+installed as the process stack (PSP) for the duration of the run, so that
+interrupts keep running on the main stack.  On RISC-V, the stack pointer is
+switched directly, and `mscratch` is left alone, so trap handlers that use it
+keep working.  On Xtensa, the register windows of the callers are spilled to
+their stacks before the stack pointer is switched, and the windows that the
+user function left behind in the register file are wiped afterwards.  On
+MSP430, the stack pointer is switched directly, like on RISC-V, and so it is
+on bare-metal x86_64, where no thread-local storage is used either: everything
+that the run needs is passed to the ephemeral stack explicitly.  Panics are
+not caught: a panic in the user function goes straight to the panic handler,
+and the stack is not erased.  To keep the stack in a specific memory region,
+like CCM or TCM RAM, declare it with [`static_erased_stack!`].
+
+From an async task (for example, in an Embassy application), use
+[`run_then_erase_blocking_in_place`], which yields to the executor once and
+then runs the user function within a single poll.
+
+With the `mask_interrupts` feature, the interrupts are masked for the duration
+of the run, until the stack has been erased and the registers have been wiped,
+so that an interrupt handler cannot observe the ephemeral stack or the
+registers of the user function (or spill them somewhere else).  The interrupt
+mask is restored afterwards.  On RISC-V, this requires the caller to run in
+machine mode, and on x86_64 in ring 0.
+
+With the `defmt` feature, every run logs `defmt` events when it switches to
+the ephemeral stack, and once the stack has been erased and the registers have
+been wiped.  The run also checks a canary at the bottom of the stack, and logs
+an error if the user function overflowed it.  [`EraserError`] implements
+`defmt::Format` as well.
+
+On ARMv8-M (e.g. Cortex-M23 and Cortex-M33), enable the `trustzone` feature.
+It sets up the stack limit registers (`MSPLIM` and `PSPLIM`) for the run, and
+restores them afterwards.  When called from the secure state, the run then
+uses the secure banked stack pointers and limits, and the registers are wiped
+before control returns to the (non-secure) caller.  The feature is only
+supported on `thumbv8m` targets.
+
+## WebAssembly
+
+On `wasm32`, code cannot switch stacks or get at the registers, so the user
+function runs on the current stack, and the part of the (shadow) stack below
+the caller that it may have used is erased afterwards.  Use [`capabilities`]
+to find out which guarantees hold on the current target.
+
+On WASI (`wasm32-wasip1` and `wasm32-wasip2`), the seeds for
+[`ErasePattern::Random`] are drawn from the host for every erase, so random
+patterns stay unpredictable in modules that are pre-initialized before they
+are instantiated.
+
+## Other Linux targets
+
+On the glibc-based Linux targets that eraser has no assembly for (such as
+`powerpc64-unknown-linux-gnu` and `riscv32gc-unknown-linux-gnu`), the stack is
+switched with `makecontext` and `swapcontext`.  The stack is still erased, but
+the registers are not wiped: only the registers that the calling convention
+preserves are restored to the values of the caller, and the other registers
+may keep whatever the user function left in them.
+
+Inline assembly is not stable yet on MIPS, MIPS64 and SPARC64, so on these
+targets eraser needs a nightly compiler.  With the `cc_shim` feature, the build
+script assembles the same code with the C compiler for the target instead, so
+that eraser builds with a stable compiler as well (wherever that compiler ships
+the standard library for the target, such as for `sparc64-unknown-linux-gnu`).
 */
 
-// TODO: Support for Cortex-M4
+#[cfg(not(any(
+    feature = "std",
+    all(target_arch = "arm", target_os = "none"),
+    all(target_arch = "riscv32", target_os = "none"),
+    all(target_arch = "xtensa", target_os = "none"),
+    all(target_arch = "msp430", target_os = "none"),
+    all(target_arch = "x86_64", target_os = "none")
+)))]
+compile_error!(
+    "without the `std` feature, eraser only supports Cortex-M, RISC-V 32, Xtensa, MSP430 and \
+     bare-metal x86_64 targets"
+);
 
-use std::{alloc, arch, cell, panic, ptr};
+use core::{mem::MaybeUninit, ptr};
+#[cfg(feature = "std")]
+use std::{cell, panic};
+
+mod blocking;
+#[cfg(feature = "std")]
+mod builder;
+#[cfg(feature = "std")]
+mod capabilities;
+#[cfg(feature = "std")]
+mod collections;
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+mod cortex_m;
+pub mod ct;
+mod custom_wipe;
+mod error;
+#[cfg(target_os = "none")]
+mod events;
+#[cfg(feature = "std")]
+mod fiber;
+mod fill;
+#[cfg(feature = "std")]
+mod fork;
+#[cfg(feature = "std")]
+mod harden;
+#[cfg(feature = "std")]
+mod isolate;
+#[cfg(feature = "std")]
+mod memory;
+#[cfg(all(target_arch = "msp430", target_os = "none"))]
+mod msp430;
+#[cfg(feature = "std")]
+mod mte;
+#[cfg(feature = "std")]
+mod overflow;
+#[cfg(feature = "std")]
+mod pkey;
+#[cfg(feature = "std")]
+mod pool;
+#[cfg(all(target_arch = "riscv32", target_os = "none"))]
+mod riscv32;
+#[cfg(feature = "std")]
+mod secret;
+#[cfg(feature = "std")]
+mod session;
+#[cfg(feature = "std")]
+mod stack;
+mod static_stack;
+#[cfg(feature = "std")]
+mod sys;
+#[cfg(feature = "std")]
+mod thread;
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    target_env = "gnu",
+    not(any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "arm",
+        target_arch = "riscv64",
+        target_arch = "x86",
+        all(target_arch = "powerpc64", target_endian = "little"),
+        target_arch = "s390x",
+        target_arch = "loongarch64",
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "sparc",
+        target_arch = "sparc64"
+    ))
+))]
+mod ucontext;
+#[cfg(target_arch = "wasm32")]
+mod wasm;
+#[cfg(all(target_arch = "x86_64", target_os = "none"))]
+mod x86_64_none;
+#[cfg(all(feature = "std", target_arch = "x86_64"))]
+mod xsave;
+#[cfg(all(target_arch = "xtensa", target_os = "none"))]
+mod xtensa;
+
+pub use blocking::{run_then_erase_blocking_in_place, BlockingInPlace};
+#[cfg(feature = "std")]
+pub use builder::{memlock_limit, smt_siblings, Eraser, EraserBuilder, DEFAULT_STACK_SIZE};
+#[cfg(feature = "std")]
+pub use capabilities::{capabilities, Capabilities};
+#[cfg(feature = "std")]
+pub use collections::{SecretString, SecretVec};
+#[cfg(feature = "macros")]
+pub use eraser_macros::protected;
+pub use error::EraserError;
+#[cfg(feature = "std")]
+pub use harden::{harden_process, HardenReport};
+#[cfg(feature = "std")]
+pub use isolate::run_isolated;
+#[cfg(feature = "std")]
+pub use pool::{PooledStack, StackPool};
+#[cfg(feature = "std")]
+pub use secret::{Secret, SecretBox};
+#[cfg(feature = "std")]
+pub use session::EraserSession;
+#[cfg(feature = "std")]
+pub use stack::{AlignedStack, ErasedStack, Session};
+#[doc(hidden)]
+pub use static_stack::{StaticStack, StaticStackFlag};
 
 const STACK_ALIGN: usize = 32;
-const ERASE_VALUE: usize = 0xDEADBEEF_DEADBEEF;
+/// The smallest stack that we are willing to switch to.  The trampoline and
+/// the `catch_unwind` frame alone already need a few hundred bytes.
+const MIN_STACK_SIZE: usize = 1024;
+/// On 32-bit targets, this is truncated to `0xDEADBEEF`.
+const ERASE_VALUE: usize = 0xDEADBEEF_DEADBEEF_u64 as usize;
+
+/// The value that is used to overwrite the stack after the user function has
+/// run.
+///
+/// ## Example
+/// ```
+/// use eraser::ErasePattern;
+///
+/// let eraser = eraser::Eraser::builder()
+///     .erase_pattern(ErasePattern::Zeros)
+///     .build()
+///     .unwrap();
+/// assert_eq!(eraser.run(|| 6 * 7), 42);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[non_exhaustive]
+pub enum ErasePattern {
+    /// Overwrite every byte with `0x00`.
+    Zeros,
+    /// Overwrite every byte with `0xFF`.
+    Ones,
+    /// Overwrite the stack with random bytes, which are different for every
+    /// erase.
+    ///
+    /// The bytes come from a fast generator that is seeded from the operating
+    /// system; they are not suitable for cryptographic use.
+    Random,
+    /// Overwrite every word with `0xDEADBEEF_DEADBEEF`, which is easy to
+    /// recognize in a debugger or a core dump.
+    #[default]
+    Poison,
+    /// Overwrite every word with the given word.
+    Word(usize),
+}
+
+impl From<usize> for ErasePattern {
+    fn from(word: usize) -> Self {
+        ErasePattern::Word(word)
+    }
+}
+
+impl ErasePattern {
+    fn filler(self) -> Filler {
+        match self {
+            ErasePattern::Zeros => Filler::Fixed(0),
+            ErasePattern::Ones => Filler::Fixed(usize::MAX),
+            ErasePattern::Random => Filler::Random(random_seed()),
+            ErasePattern::Poison => Filler::Fixed(ERASE_VALUE),
+            ErasePattern::Word(word) => Filler::Fixed(word),
+        }
+    }
+}
+
+/// Return a new seed for `Filler::Random`.
+#[cfg(all(feature = "std", not(target_os = "wasi")))]
+fn random_seed() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+    std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish()
+}
+
+/// Return a new seed for `Filler::Random`.
+///
+/// On WASI, every seed is drawn from the host.  The keys of `RandomState` are
+/// only drawn once per thread, so a module that is pre-initialized (and
+/// snapshotted) before it is instantiated would share them with all of its
+/// instances.  If the host does not provide randomness, the time on the
+/// host's clock is mixed into `counter_seed` instead.
+#[cfg(all(feature = "std", target_os = "wasi"))]
+fn random_seed() -> u64 {
+    let mut seed = [0u8; 8];
+    if sys::fill_random(&mut seed).is_ok() {
+        return u64::from_ne_bytes(seed);
+    }
+    let time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos() as u64);
+    time ^ counter_seed()
+}
+
+/// Return a new seed for `Filler::Random`.
+///
+/// Without `std` there is no entropy source, so `counter_seed` is used.
+#[cfg(not(feature = "std"))]
+fn random_seed() -> u64 {
+    counter_seed()
+}
+
+/// Return a seed that mixes a counter into the address of a local, which
+/// still makes every erase different when there is no entropy source.
+#[cfg(any(not(feature = "std"), target_os = "wasi"))]
+fn counter_seed() -> u64 {
+    #[cfg(target_arch = "msp430")]
+    use crate::msp430::AtomicUsize;
+    #[cfg(not(target_arch = "msp430"))]
+    use core::sync::atomic::AtomicUsize;
+    use core::sync::atomic::Ordering;
+    // Only loads and stores are atomic on every Cortex-M
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let count = COUNTER.load(Ordering::Relaxed).wrapping_add(1);
+    COUNTER.store(count, Ordering::Relaxed);
+    let marker = 0u8;
+    let addr = core::hint::black_box(&marker) as *const u8 as u64;
+    addr ^ (count as u64).rotate_left(32)
+}
+
+/// Produces the words that are written by a single erase pass.
+#[derive(Clone)]
+enum Filler {
+    Fixed(usize),
+    /// splitmix64 state
+    Random(u64),
+}
+
+impl Filler {
+    fn next_word(&mut self) -> usize {
+        match self {
+            Filler::Fixed(word) => *word,
+            Filler::Random(state) => {
+                *state = state.wrapping_add(0x9E3779B9_7F4A7C15);
+                let mut z = *state;
+                z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D_1CE4E5B9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB_133111EB);
+                (z ^ (z >> 31)) as usize
+            }
+        }
+    }
+}
+
+/// The sequence of passes that is used to overwrite the stack after the user
+/// function has run.
+///
+/// Every pass overwrites the complete stack with one [`ErasePattern`].
+///
+/// ## Example
+/// ```
+/// use eraser::ErasePolicy;
+///
+/// let eraser = eraser::Eraser::builder()
+///     .erase_policy(ErasePolicy::ZerosOnesRandom)
+///     .build()
+///     .unwrap();
+/// assert_eq!(eraser.run(|| 6 * 7), 42);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErasePolicy {
+    /// Overwrite the stack the given number of times with the same pattern.
+    Repeat(ErasePattern, usize),
+    /// Overwrite the stack three times: with zeros, with ones, and finally
+    /// with random bytes.
+    ZerosOnesRandom,
+    /// Overwrite the stack once with every pattern in the sequence, in order.
+    Sequence(&'static [ErasePattern]),
+}
+
+impl Default for ErasePolicy {
+    fn default() -> Self {
+        ErasePolicy::Repeat(ErasePattern::Poison, 1)
+    }
+}
+
+impl ErasePolicy {
+    /// Return the number of passes.
+    pub(crate) fn passes(&self) -> usize {
+        match self {
+            ErasePolicy::Repeat(_, passes) => *passes,
+            ErasePolicy::ZerosOnesRandom => 3,
+            ErasePolicy::Sequence(patterns) => patterns.len(),
+        }
+    }
+
+    /// Return the pattern that is used in pass `index`.
+    fn pattern(&self, index: usize) -> ErasePattern {
+        match self {
+            ErasePolicy::Repeat(pattern, _) => *pattern,
+            ErasePolicy::ZerosOnesRandom => [
+                ErasePattern::Zeros,
+                ErasePattern::Ones,
+                ErasePattern::Random,
+            ][index],
+            ErasePolicy::Sequence(patterns) => patterns[index],
+        }
+    }
+}
+
+/// How the stack is switched to the ephemeral stack before the user function
+/// runs.
+///
+/// ## Example
+/// ```
+/// use eraser::{EraserError, SwitchBackend};
+///
+/// let eraser = eraser::Eraser::builder()
+///     .switch_backend(SwitchBackend::Fiber)
+///     .guard_page(false)
+///     .build();
+/// if cfg!(windows) {
+///     assert_eq!(eraser.unwrap().run(|| 6 * 7), 42);
+/// } else {
+///     assert_eq!(eraser.unwrap_err(), EraserError::Unsupported);
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[non_exhaustive]
+pub enum SwitchBackend {
+    /// Switch the stack pointer with inline assembly, on a stack that is set
+    /// up by eraser (with all the configured protections).
+    #[default]
+    Inline,
+    /// Run the user function on a new fiber (with `CreateFiberEx` and
+    /// `SwitchToFiber`), whose stack is erased before the fiber is deleted.
+    ///
+    /// Windows keeps track of the stack of a fiber itself, which plays nicer
+    /// with structured exception handling and debuggers.  The stack is set up
+    /// by Windows, though, so it cannot have any of the protections of
+    /// [`EraserBuilder`], and it cannot be reused (see
+    /// [`Eraser::new_stack`]).  Only supported on Windows.
+    Fiber,
+}
+
+/// Describes how the stack is erased after the user function has run.
+#[derive(Debug, Clone)]
+pub(crate) struct EraseConfig {
+    pub(crate) policy: ErasePolicy,
+    /// Only erase the part of a reused stack that was used, see
+    /// `StackErase::AboveWatermark`.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) high_water_mark: bool,
+    /// Read the memory back after erasing it, and panic if it does not hold
+    /// the pattern of the last pass.
+    pub(crate) verify: bool,
+    /// Flush the erased memory from the CPU caches.
+    pub(crate) flush: bool,
+    /// Zero the frame record at the top of the stack while the user function
+    /// runs, see `FRAME_RECORD`.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) redact_backtraces: bool,
+    /// Also put the extended register state in its initial configuration after
+    /// a run, see the `xsave` module.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) deep_wipe: bool,
+}
+
+impl Default for EraseConfig {
+    fn default() -> Self {
+        EraseConfig {
+            policy: ErasePolicy::default(),
+            high_water_mark: true,
+            verify: false,
+            flush: false,
+            redact_backtraces: false,
+            deep_wipe: false,
+        }
+    }
+}
+
+impl EraseConfig {
+    fn patterns(&self) -> impl Iterator<Item = ErasePattern> + '_ {
+        (0..self.policy.passes()).map(|index| self.policy.pattern(index))
+    }
+
+    /// Return the word that every word of the memory holds after an erase,
+    /// or `None` if the last pass is random.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn final_word(&self) -> Option<usize> {
+        let last = self.policy.passes().checked_sub(1)?;
+        match self.policy.pattern(last).filler() {
+            Filler::Fixed(word) => Some(word),
+            Filler::Random(_) => None,
+        }
+    }
+}
+
+/// Describes which part of the stack is erased by `switch_and_run`.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum StackErase {
+    /// Do not erase the stack; the caller is responsible for erasing it.
+    Skip,
+    /// Erase the whole stack.
+    Full,
+    /// Only erase the part of the stack that was used by the user function.
+    ///
+    /// Before the run, every word of the stack must hold the given sentinel.
+    /// Since the stack grows down, the used part is found by scanning up from
+    /// the bottom for the first word that no longer holds the sentinel (the
+    /// high-water mark).  All the words below the mark still hold the
+    /// sentinel, so they do not contain any secrets.
+    AboveWatermark(usize),
+}
+
+/// The word that the stack is filled with before a run by
+/// [`measure_stack_usage`].  Unlike zero, it is unlikely to be written by the
+/// user function, so that the high-water mark is not underestimated.
+#[cfg(feature = "std")]
+const MEASURE_PATTERN: usize = 0xa5c3_5a3c_a5c3_5a3c_u64 as usize;
+
+/// The size of the stack that [`measure_stack_usage`] runs the user function
+/// on, which is the usual size of the stack of the main thread on Linux.
+#[cfg(feature = "std")]
+const MEASURE_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Describes what `switch_and_run` does when the user function overflows the
+/// stack.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OnOverflow {
+    /// The stack has no guard page, so an overflow is not detected.
+    Undetected,
+    /// Erase the stack, and abort the process.
+    Abort,
+    /// Erase the stack, and return `EraserError::StackOverflow`.  The frames
+    /// of the user function are abandoned without being unwound.
+    Return,
+}
+
+/// Return the offset (in bytes) of the first word in `stack` that does not
+/// hold `sentinel`.
+#[cfg(feature = "std")]
+fn high_water_mark(stack: &[MaybeUninit<u8>], sentinel: usize) -> usize {
+    // The stack is aligned to `STACK_ALIGN` and its length is a multiple of
+    // it, and every byte is initialized because it holds the sentinel.
+    let words = unsafe {
+        core::slice::from_raw_parts(
+            stack.as_ptr() as *const usize,
+            stack.len() / core::mem::size_of::<usize>(),
+        )
+    };
+    let index = words
+        .iter()
+        .position(|&word| word != sentinel)
+        .unwrap_or(words.len());
+    index * core::mem::size_of::<usize>()
+}
 
 /// EraserContext contains any information that needs to be passed across the
 /// stack switch barrier from `run_then_erase_asm`.
+#[cfg(feature = "std")]
 #[derive(Debug, Default)]
 struct EraserContext {
     /// Function specified by the user that should be run in the separate stack.
-    user_fn: Option<fn()>,
+    user_fn: Option<UserFn>,
     /// Panic result describes whether the user's function panicked.  If a
     /// panic occurred, `panic_result` will encapsulate the error;  if the
     /// user function succeeded without panic, `panic_result` will be equal
     /// to `Some(Ok(()))`.
     panic_result: Option<std::thread::Result<()>>,
+    /// The frame record that `do_run_user_fn` zeroes before the user function
+    /// is called, if the backtraces are redacted.
+    frame_record: Option<*mut usize>,
+}
+
+/// Type-erased handle to the user's closure.
+///
+/// The closure itself stays on the caller's stack; only a pointer to it and a
+/// monomorphized trampoline that knows its concrete type are passed through
+/// `CTX`.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy)]
+struct UserFn {
+    /// Trampoline that moves the closure out of `data` and calls it.
+    call: unsafe fn(*mut u8),
+    /// Pointer to the `Payload<F, R>` that holds the closure.
+    data: *mut u8,
+}
+
+/// The user's closure and its return value.
+///
+/// The payload lives on the caller's stack.  The closure is moved from here
+/// onto the ephemeral stack before it is called, and its return value is
+/// moved back here afterwards.  Both slots are erased before control is
+/// returned to the user.
+struct Payload<F, R> {
+    f: MaybeUninit<F>,
+    result: MaybeUninit<R>,
 }
 
+#[cfg(feature = "std")]
 thread_local! {
     static CTX: cell::RefCell<EraserContext> = Default::default();
+    /// Stack that is reused by `run_then_erase_cached`.  It is `None` before
+    /// the first call, and while the stack is in use.
+    static CACHED_STACK: cell::RefCell<Option<ErasedStack>> = const { cell::RefCell::new(None) };
 }
 
-unsafe fn erase(ptr_mut: *mut u8, len: usize) {
+pub(crate) unsafe fn erase_words(ptr_mut: *mut u8, len: usize, config: &EraseConfig) {
     assert_eq!(ptr_mut.align_offset(core::mem::size_of::<usize>()), 0);
-    for offset in (0..len).step_by(core::mem::size_of::<usize>()) {
-        let cur = ptr_mut.add(offset) as *mut usize;
-        ptr::write_volatile(cur, ERASE_VALUE);
+    #[cfg(test)]
+    tests::ERASES.with(|count| count.set(count.get() + 1));
+    let mut last = None;
+    for pattern in config.patterns() {
+        let mut filler = pattern.filler();
+        last = Some(filler.clone());
+        if let Filler::Fixed(word) = filler {
+            fill::fill_words(ptr_mut, len, word);
+            continue;
+        }
+        for offset in (0..len).step_by(core::mem::size_of::<usize>()) {
+            let cur = ptr_mut.add(offset) as *mut usize;
+            ptr::write_volatile(cur, filler.next_word());
+        }
+    }
+    if config.flush {
+        fill::flush(ptr_mut, len);
+    }
+    if let (true, Some(mut filler)) = (config.verify, last) {
+        for offset in (0..len).step_by(core::mem::size_of::<usize>()) {
+            let cur = ptr_mut.add(offset) as *const usize;
+            let expected = filler.next_word();
+            assert!(
+                ptr::read_volatile(cur) == expected,
+                "erase verification failed at offset {}",
+                offset
+            );
+        }
+    }
+}
+
+/// Erase a slot outside of the ephemeral stack that (may have) held secret
+/// data, such as the closure that was moved onto the ephemeral stack.
+unsafe fn erase_slot<T>(slot: &mut MaybeUninit<T>, config: &EraseConfig) {
+    erase_bytes(
+        slot.as_mut_ptr() as *mut u8,
+        core::mem::size_of::<T>(),
+        config,
+    );
+}
+
+/// Erase `len` bytes at `ptr_mut`.
+///
+/// The memory may have any alignment, so it is erased byte by byte.
+pub(crate) unsafe fn erase_bytes(ptr_mut: *mut u8, len: usize, config: &EraseConfig) {
+    let mut last = None;
+    for pattern in config.patterns() {
+        let mut filler = pattern.filler();
+        last = Some(filler.clone());
+        for offset in 0..len {
+            ptr::write_volatile(ptr_mut.add(offset), filler.next_word() as u8);
+        }
+    }
+    if let (true, Some(mut filler)) = (config.verify, last) {
+        for offset in 0..len {
+            let expected = filler.next_word() as u8;
+            assert!(
+                ptr::read_volatile(ptr_mut.add(offset)) == expected,
+                "erase verification failed at offset {}",
+                offset
+            );
+        }
+    }
+}
+
+/// Erase the contents of `buf`.
+///
+/// The buffer is overwritten with the same volatile writes that are used to
+/// erase the ephemeral stacks, so the compiler cannot optimize the erase away.
+/// The aligned middle of the buffer is erased word by word, and the unaligned
+/// head and tail byte by byte, such that the whole buffer is filled with the
+/// same pattern as an erased stack.
+///
+/// ## Example
+/// ```
+/// let mut key = [0x42u8; 37];
+/// eraser::erase(&mut key[1..]);
+/// assert_eq!(key[0], 0x42);
+/// assert!(key[1..].iter().all(|&x| x != 0x42));
+/// ```
+pub fn erase(buf: &mut [u8]) {
+    let config = EraseConfig::default();
+    let pattern = ErasePattern::default().filler().next_word().to_ne_bytes();
+    // Any bit pattern is a valid `usize`, so this is sound
+    let (head, middle, tail) = unsafe { buf.align_to_mut::<usize>() };
+    for byte in head.iter_mut().chain(tail.iter_mut()) {
+        let index = (byte as *mut u8 as usize) % pattern.len();
+        unsafe { ptr::write_volatile(byte, pattern[index]) };
+    }
+    unsafe {
+        erase_words(
+            middle.as_mut_ptr() as *mut u8,
+            core::mem::size_of_val(middle),
+            &config,
+        )
+    };
+}
+
+/// Move the closure of type `F` out of the `Payload<F, R>` at `data`, run it
+/// and store its return value in the payload.
+///
+/// After this function returns (or unwinds), the closure slot in the payload
+/// is logically uninitialized and must not be read again.  The result slot is
+/// only initialized if this function returns normally.
+unsafe fn call_user_fn<F: FnOnce() -> R, R>(data: *mut u8) {
+    let payload = &mut *(data as *mut Payload<F, R>);
+    let f = payload.f.as_ptr().read();
+    payload.result.write(f());
+}
+
+/// Check whether the erased context is supported on this target.
+pub(crate) fn check_supported() -> Result<(), EraserError> {
+    if cfg!(any(
+        target_arch = "x86_64",
+        all(
+            target_arch = "aarch64",
+            any(target_os = "linux", target_os = "macos", windows)
+        ),
+        all(
+            target_arch = "arm",
+            any(target_os = "linux", target_os = "none")
+        ),
+        all(target_arch = "riscv64", target_os = "linux"),
+        all(target_arch = "riscv32", target_os = "none"),
+        all(target_arch = "xtensa", target_os = "none"),
+        all(target_arch = "msp430", target_os = "none"),
+        all(target_arch = "x86", any(target_os = "linux", windows)),
+        all(
+            target_arch = "powerpc64",
+            target_endian = "little",
+            target_os = "linux"
+        ),
+        all(target_arch = "s390x", target_os = "linux"),
+        all(target_arch = "loongarch64", target_os = "linux"),
+        all(target_arch = "mips", target_os = "linux"),
+        all(target_arch = "mips64", target_os = "linux"),
+        all(target_arch = "sparc64", target_os = "linux"),
+        all(
+            target_os = "linux",
+            target_env = "gnu",
+            not(target_arch = "sparc")
+        ),
+        target_arch = "wasm32"
+    )) {
+        Ok(())
+    } else {
+        Err(EraserError::Unsupported)
+    }
+}
+
+/// Check whether a stack buffer that is provided by the caller can be used on
+/// this target.  This is not the case if the kernel only accepts stacks that
+/// are mapped specially (see `sys::MAP_STACKS`).
+fn check_buffer_supported() -> Result<(), EraserError> {
+    check_supported()?;
+    #[cfg(feature = "std")]
+    if sys::MAP_STACKS {
+        return Err(EraserError::Unsupported);
+    }
+    Ok(())
+}
+
+/// View an initialized buffer as a possibly uninitialized one.
+pub(crate) fn as_uninit(slice: &mut [u8]) -> &mut [MaybeUninit<u8>] {
+    // We never write uninitialized bytes into the slice, so the caller can
+    // still soundly use it as `[u8]` afterwards.
+    unsafe {
+        core::slice::from_raw_parts_mut(slice.as_mut_ptr() as *mut MaybeUninit<u8>, slice.len())
+    }
+}
+
+/// Return the offset and length of the largest aligned sub-range of a stack
+/// buffer at `ptr` with length `len`.
+fn aligned_range(ptr: *const u8, len: usize) -> (usize, usize) {
+    let offset = ptr.align_offset(STACK_ALIGN).min(len);
+    let aligned_len = (len - offset) / STACK_ALIGN * STACK_ALIGN;
+    (offset, aligned_len)
+}
+
+/// Round the stack buffer to the largest sub-range that is aligned to
+/// `STACK_ALIGN`, and check that it is large enough.
+fn align_stack(stack: &mut [MaybeUninit<u8>]) -> Result<&mut [MaybeUninit<u8>], EraserError> {
+    let (offset, len) = aligned_range(stack.as_ptr() as *const u8, stack.len());
+    if len < MIN_STACK_SIZE {
+        return Err(EraserError::StackTooSmall);
     }
+    Ok(&mut stack[offset..offset + len])
+}
+
+/// Return the number of bytes of `stack` that will actually be used as the
+/// ephemeral stack by [`run_then_erase_with_stack`].
+///
+/// Stack buffers do not need to be aligned.  Instead, eraser uses the largest
+/// sub-range of the buffer that starts and ends on a 32-byte boundary.  The
+/// bytes outside of that sub-range are left untouched.
+///
+/// ## Example
+/// ```
+/// let mut stack = eraser::AlignedStack::<8192>::new();
+/// assert_eq!(eraser::usable_stack_size(stack.as_mut_slice()), 8192);
+/// assert_eq!(eraser::usable_stack_size(&stack.as_mut_slice()[1..]), 8192 - 32);
+/// ```
+pub fn usable_stack_size(stack: &[u8]) -> usize {
+    aligned_range(stack.as_ptr(), stack.len()).1
 }
 
 /// Run a function on a ephemeral stack and immediately erase the stack
@@ -47,168 +824,1634 @@ unsafe fn erase(ptr_mut: *mut u8, len: usize) {
 /// allocator present, or when the internal stack can be small enough such
 /// that it can be stored on the caller stack.
 ///
+/// The buffer does not need to be aligned: only the largest sub-range that is
+/// aligned to 32 bytes is used (see [`usable_stack_size`]).
+///
+/// The user function may capture its environment; the closure is moved onto
+/// the ephemeral stack before it is run, and the slot that held it on the
+/// caller's stack is erased afterwards.  Likewise, the value returned by the
+/// user function is moved off the ephemeral stack, and the intermediate slot
+/// is erased before the value is returned to the caller.
+///
+/// ## Panics
+///
+/// This function panics if the stack buffer is not valid.  See
+/// [`try_run_then_erase_with_stack`] for a non-panicking version.
+///
 /// ## Safety
 ///
-/// * The proviced stack buffer must have a length divisible by 32.
-/// * The provided stack buffer must be aligned to 32 bytes.
 /// * The stack buffer must be large enough for the user function.
 ///
+/// A buffer that is provided by the caller has no guard page, so there is no
+/// way to check this: an overflow silently overwrites the memory below the
+/// buffer.  For a safe alternative, run the user function on a stack from
+/// [`Eraser::new_stack`] with [`EraserBuilder::guard_page`] enabled.
+///
 /// ## Example
 /// ```
-/// use core::cell::RefCell;
+/// let mut stack = eraser::AlignedStack::<4096>::new();
+/// let result = unsafe {
+///     eraser::run_then_erase_with_stack(|| 42, stack.as_mut_slice())
+/// };
 ///
-/// thread_local! {
-///     static RESULT: RefCell<i32> = RefCell::default();
-/// }
+/// assert_eq!(result, 42);
+/// ```
+pub unsafe fn run_then_erase_with_stack<F, R>(f: F, stack: &mut [u8]) -> R
+where
+    F: FnOnce() -> R,
+{
+    try_run_then_erase_with_stack(f, stack).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Run a function on a ephemeral stack and immediately erase the stack,
+/// returning an error if the stack is not valid.
+///
+/// This function is the fallible version of [`run_then_erase_with_stack`].
+/// It returns [`EraserError::StackTooSmall`] if the usable part of the buffer
+/// is unreasonably small, and [`EraserError::Unsupported`] on targets where the
+/// stack switch is not implemented, or where the kernel only accepts stacks
+/// that it mapped itself (OpenBSD).  A panic in the user function is still
+/// propagated to the caller.
+///
+/// ## Safety
+///
+/// * The stack buffer must be large enough for the user function.
+pub unsafe fn try_run_then_erase_with_stack<F, R>(f: F, stack: &mut [u8]) -> Result<R, EraserError>
+where
+    F: FnOnce() -> R,
+{
+    try_run_then_erase_with_uninit_stack(f, as_uninit(stack))
+}
+
+/// Run a function on a ephemeral stack and immediately erase the stack,
+/// using an uninitialized stack buffer.
+///
+/// This function is the same as [`run_then_erase_with_stack`], but accepts a
+/// stack buffer that does not need to be initialized.  This saves the caller
+/// from zeroing a large buffer only for it to be overwritten.
+///
+/// ## Panics
+///
+/// This function panics if the stack buffer is not valid.  See
+/// [`try_run_then_erase_with_uninit_stack`] for a non-panicking version.
+///
+/// ## Safety
+///
+/// * The stack buffer must be large enough for the user function.
+///
+/// ## Example
+/// ```
+/// use core::mem::MaybeUninit;
 ///
 /// #[repr(C, align(32))]
-/// struct AlignedStack { buf: [u8; 4096] };
+/// struct UninitStack([MaybeUninit<u8>; 64 * 1024]);
 ///
-/// let mut stack = AlignedStack { buf: [0; 4096] };
-/// unsafe {
-///     eraser::run_then_erase_with_stack(|| {
-///         RESULT.with(|x| x.replace(42));
-///     }, &mut stack.buf);
-/// }
+/// let mut stack = UninitStack([MaybeUninit::uninit(); 64 * 1024]);
+/// let result = unsafe {
+///     eraser::run_then_erase_with_uninit_stack(|| 42, &mut stack.0)
+/// };
 ///
-/// RESULT.with(|x| assert_eq!(*x.borrow(), 42));
+/// assert_eq!(result, 42);
 /// ```
-pub unsafe fn run_then_erase_with_stack(f: fn(), stack: &mut [u8]) {
-    let stack_ptr = stack.as_mut_ptr();
-    let stack_top = stack_ptr.add(stack.len());
+pub unsafe fn run_then_erase_with_uninit_stack<F, R>(f: F, stack: &mut [MaybeUninit<u8>]) -> R
+where
+    F: FnOnce() -> R,
+{
+    try_run_then_erase_with_uninit_stack(f, stack).unwrap_or_else(|err| panic!("{}", err))
+}
 
-    // Check if the stack meets all our criteria
-    assert_eq!(
-        stack_ptr as usize % STACK_ALIGN,
-        0,
-        "stack buffer @ {:p} is not aligned to {}",
-        stack_ptr,
-        STACK_ALIGN
-    );
-    assert_eq!(
-        stack_top as usize % STACK_ALIGN,
-        0,
-        "stack top @ {:p} is not aligned to {} (is the buffer length divisible by {}?)",
-        stack_ptr,
-        STACK_ALIGN,
-        STACK_ALIGN
-    );
+/// Run a function on a ephemeral stack and immediately erase the stack,
+/// using an uninitialized stack buffer and returning an error if the stack is
+/// not valid.
+///
+/// This function is the fallible version of
+/// [`run_then_erase_with_uninit_stack`]; see [`try_run_then_erase_with_stack`]
+/// for the errors that it returns.
+///
+/// ## Safety
+///
+/// * The stack buffer must be large enough for the user function.
+pub unsafe fn try_run_then_erase_with_uninit_stack<F, R>(
+    f: F,
+    stack: &mut [MaybeUninit<u8>],
+) -> Result<R, EraserError>
+where
+    F: FnOnce() -> R,
+{
+    check_buffer_supported()?;
+    let stack = align_stack(stack)?;
+    Ok(run_on_stack(f, stack, &EraseConfig::default()))
+}
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub(crate) use cortex_m::run_on_stack;
+#[cfg(all(target_arch = "msp430", target_os = "none"))]
+pub(crate) use msp430::run_on_stack;
+#[cfg(all(target_arch = "riscv32", target_os = "none"))]
+pub(crate) use riscv32::run_on_stack;
+#[cfg(all(target_arch = "x86_64", target_os = "none"))]
+pub(crate) use x86_64_none::run_on_stack;
+#[cfg(all(target_arch = "xtensa", target_os = "none"))]
+pub(crate) use xtensa::run_on_stack;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+#[cfg_attr(target_has_atomic = "ptr", allow(unused_imports))]
+use cortex_m::without_interrupts;
+#[cfg(all(target_arch = "msp430", target_os = "none"))]
+use msp430::without_interrupts;
+
+/// Run the user function on `stack`, which has already been aligned by
+/// `align_stack`.
+#[cfg(feature = "std")]
+pub(crate) unsafe fn run_on_stack<F, R>(
+    f: F,
+    stack: &mut [MaybeUninit<u8>],
+    config: &EraseConfig,
+) -> R
+where
+    F: FnOnce() -> R,
+{
+    // Overflows are not caught on stacks without a guard page
+    match switch_and_run(f, stack, config, StackErase::Full, OnOverflow::Undetected) {
+        Ok(result) => result,
+        Err(err) => unreachable!("{}", err),
+    }
+}
+
+/// Run the user function on `stack`, like `run_on_stack`.
+///
+/// The `erase` argument determines which part of the stack is erased
+/// afterwards.  The slots that hold the closure and its result are always
+/// erased, and the registers are always wiped.
+///
+/// Unless `on_overflow` is `OnOverflow::Undetected`, the page below `stack`
+/// must be a guard page, and `on_overflow` determines what happens when the
+/// user function overflows the stack.
+#[cfg(feature = "std")]
+pub(crate) unsafe fn switch_and_run<F, R>(
+    f: F,
+    stack: &mut [MaybeUninit<u8>],
+    config: &EraseConfig,
+    erase: StackErase,
+    on_overflow: OnOverflow,
+) -> Result<R, EraserError>
+where
+    F: FnOnce() -> R,
+{
+    let stack_bottom = stack.as_mut_ptr() as *mut u8;
+    let stack_top = stack_bottom.add(stack.len());
+    let overflow_guard = match on_overflow {
+        OnOverflow::Undetected => None,
+        OnOverflow::Abort | OnOverflow::Return => Some(overflow::OverflowGuard::new(stack)?),
+    };
 
     // Initialize EraserContext
+    let mut payload = Payload {
+        f: MaybeUninit::new(f),
+        result: MaybeUninit::<R>::uninit(),
+    };
     CTX.with(|cell| {
         cell.replace(EraserContext {
-            user_fn: Some(f),
+            user_fn: Some(UserFn {
+                call: call_user_fn::<F, R>,
+                data: &mut payload as *mut Payload<F, R> as *mut u8,
+            }),
             panic_result: None,
+            frame_record: (config.redact_backtraces && FRAME_RECORD)
+                .then(|| unsafe { (stack_top as *mut usize).sub(2) }),
         })
     });
 
-    // Switch the location of the stack and call the wrapper function
+    // Switch the location of the stack and call the wrapper function.  The
+    // stack is erased exactly once, right after switching back, so that it is
+    // erased on every exit path (both a normal return and a panic).
     unsafe {
-        stack_switch(stack_top);
-        erase(stack_ptr, stack.len());
-    };
-
-    CTX.with(|cell| {
-        // Double-check that the user function did indeed finish
-        assert!(cell.borrow().panic_result.is_some());
+        stack_switch(stack_bottom, stack_top);
+        erase_stack(stack, erase, config);
+        erase_slot(&mut payload.f, config);
+    }
 
-        // If the user function panicked, resume that panic now
-        let ctx = cell.take();
-        if let Some(Err(err)) = ctx.panic_result {
-            panic::resume_unwind(err);
+    // If the user function was abandoned after a stack overflow, it did not
+    // store a result
+    if overflow_guard.is_some_and(|guard| guard.overflowed()) {
+        CTX.with(|cell| cell.take());
+        unsafe {
+            erase_slot(&mut payload.result, config);
+            wipe_registers_after_run(config);
         }
-    });
+        if on_overflow == OnOverflow::Abort {
+            // With `StackErase::Skip`, the stack has not been erased yet
+            unsafe { erase_stack(stack, StackErase::Full, config) };
+            overflow::abort();
+        }
+        return Err(EraserError::StackOverflow);
+    }
+
+    // Double-check that the user function did indeed finish
+    let panic_result = CTX
+        .with(|cell| cell.take().panic_result)
+        .expect("EraserContext.panic_result is None");
+
+    // If the user function panicked, resume that panic now
+    if let Err(err) = panic_result {
+        unsafe { wipe_registers_after_run(config) };
+        panic::resume_unwind(err);
+    }
+
+    // The user function returned normally, so the result is initialized
+    let result = unsafe { payload.result.as_ptr().read() };
 
-    // Erase the stack and wipe all the registers
+    // Erase the result slot and wipe all the registers
     unsafe {
-        erase(stack_ptr, stack.len());
-        wipe_all_registers();
+        erase_slot(&mut payload.result, config);
+        wipe_registers_after_run(config);
     }
+    Ok(result)
+}
+
+/// Wipe the registers after a run, and the rest of the extended register
+/// state too if `config` asks for a deep wipe.
+#[cfg(feature = "std")]
+pub(crate) unsafe fn wipe_registers_after_run(config: &EraseConfig) {
+    custom_wipe::run();
+    wipe_all_registers();
+    #[cfg(target_arch = "x86_64")]
+    if config.deep_wipe {
+        xsave::wipe();
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = config;
+}
+
+/// Erase the part of `stack` that is selected by `erase`.
+#[cfg(feature = "std")]
+unsafe fn erase_stack(stack: &mut [MaybeUninit<u8>], erase: StackErase, config: &EraseConfig) {
+    let offset = match erase {
+        StackErase::Skip => return,
+        StackErase::Full => 0,
+        StackErase::AboveWatermark(sentinel) => high_water_mark(stack, sentinel),
+    };
+    let stack_ptr = stack.as_mut_ptr() as *mut u8;
+    erase_words(stack_ptr.add(offset), stack.len() - offset, config);
 }
 
 /// Run a function on an ephemeral stack and immediately erase the stack.
 ///
 /// The `stack_size` specifies the size of the stack that will be provided to
 /// the user function.  It must be a multiple of 32 bytes, or otherwise this
-/// function will panic.
-pub fn run_then_erase(f: fn(), stack_size: usize) {
-    let layout =
-        alloc::Layout::from_size_align(stack_size, STACK_ALIGN).expect("incorrect alignment");
-    let ptr_opt = ptr::NonNull::new(unsafe { alloc::alloc_zeroed(layout) });
-    let mut ptr = ptr_opt.expect("alloc::alloc_zeroed returned null pointer");
-
-    if cfg!(feature = "guard_page") {
-        // TODO: Set up a guard page to catch overflows
-        unimplemented!("guard pages not implemented")
-    }
+/// function will panic.  See [`try_run_then_erase`] for a non-panicking
+/// version.
+///
+/// The user function may capture its environment, so secrets can be passed in
+/// by reference instead of through `static` values.  The value returned by the
+/// user function is returned to the caller.
+///
+/// ## Example
+/// ```
+/// let secret = [0x2Au8; 32];
+/// let sum = eraser::run_then_erase(|| secret.iter().map(|&x| x as u32).sum::<u32>(), 4096);
+/// assert_eq!(sum, 32 * 42);
+/// ```
+#[cfg(feature = "std")]
+pub fn run_then_erase<F, R>(f: F, stack_size: usize) -> R
+where
+    F: FnOnce() -> R,
+{
+    try_run_then_erase(f, stack_size).unwrap_or_else(|err| panic!("{}", err))
+}
 
-    unsafe {
-        let stack = core::slice::from_raw_parts_mut(ptr.as_mut(), layout.size());
-        run_then_erase_with_stack(f, stack);
-    }
+/// Run a function on an ephemeral stack and immediately erase the stack,
+/// returning an error if the stack could not be set up.
+///
+/// This function is the fallible version of [`run_then_erase`].  It returns
+/// [`EraserError::BadAlignment`] if `stack_size` is not a multiple of 32
+/// bytes, [`EraserError::StackTooSmall`] if `stack_size` is unreasonably small,
+/// [`EraserError::AllocFailed`] if the stack could not be allocated, and
+/// [`EraserError::Unsupported`] on targets (or with features) that are not
+/// supported.  A panic in the user function is still propagated to the
+/// caller.
+///
+/// ## Example
+/// ```
+/// use eraser::EraserError;
+///
+/// assert_eq!(eraser::try_run_then_erase(|| 42, 4096), Ok(42));
+/// assert_eq!(eraser::try_run_then_erase(|| 42, 4095), Err(EraserError::BadAlignment));
+/// ```
+#[cfg(feature = "std")]
+pub fn try_run_then_erase<F, R>(f: F, stack_size: usize) -> Result<R, EraserError>
+where
+    F: FnOnce() -> R,
+{
+    Eraser::builder().stack_size(stack_size).build()?.try_run(f)
 }
 
-/// Run the "assembly" part of the `run_then_erase` wrapper.
+/// Evaluate a block on an ephemeral stack and immediately erase the stack.
 ///
-/// This function is separate, because the user function might clobber any kind
-/// of register, even avx2 or x87 registers.  Instead of clobbering *all* of
-/// them in the asm! directive, we enter a separate function that explicitly
-/// uses the C ABI.  This way, we know with reasonably certainty that the
-/// calling function has saved any reasonable register that it needs to stay
-/// intact.
+/// `erased!(stack_size, { ... })` is shorthand for
+/// `run_then_erase(|| { ... }, stack_size)`.  The block may use the local
+/// variables that are in scope, and evaluates to the value of the block.  If
+/// the stack size is omitted, [`DEFAULT_STACK_SIZE`] is used.
 ///
-/// The API allows the user function to capture from its environment, but this
-/// prevents it from being compatible with the C ABI.  Therefore, we cannot
-/// store and pass it directly through calls that use the C calling convention.
-/// So, instead of directly passing them through the layer, we bypass the layer
-/// and stash the user function thread local storage static value `CTX`.
-/// `do_run_user_fn` will read back the user function out from `CTX` and
-/// execute it using the (unstable) Rust ABI convention (but on the other
-/// stack).
-#[inline(never)]
-unsafe fn stack_switch(stack_top: *mut u8) {
-    // TODO: Go through and guarantee the inline assembly rules listed at
-    // https://doc.rust-lang.org/reference/inline-assembly.html
+/// Because the block is run as a closure, `return` and `?` inside the block
+/// return from the block, not from the surrounding function.
+///
+/// ## Example
+/// ```
+/// let key = [0x01u8, 0x02, 0x04, 0x08];
+/// let msg = b"hello";
+///
+/// let tag = eraser::erased!(4096, {
+///     msg.iter().zip(key.iter().cycle()).fold(0u8, |acc, (m, k)| acc ^ (m ^ k))
+/// });
+/// assert_eq!(tag, 0x6c);
+///
+/// let sum = eraser::erased!({ key.iter().sum::<u8>() });
+/// assert_eq!(sum, 15);
+/// ```
+#[macro_export]
+macro_rules! erased {
+    ($stack_size:expr, $body:block) => {
+        $crate::run_then_erase(|| $body, $stack_size)
+    };
+    ($body:block) => {
+        $crate::run_then_erase(|| $body, $crate::DEFAULT_STACK_SIZE)
+    };
+}
 
-    arch::asm!(
-        // Stash the old rsp
+/// Run a function with an input on an ephemeral stack and immediately erase
+/// the stack.
+///
+/// This function is similar to [`run_then_erase`], but moves `input` across
+/// the stack-switch barrier and passes it to `f`.  This way, key material does
+/// not need to live in a `static` value to be visible inside the erased
+/// context.  The slot that carried the input across the barrier is erased
+/// together with the rest of the closure state.
+///
+/// Note that any copies of `input` that the caller made *before* calling this
+/// function are not erased.
+///
+/// ## Example
+/// ```
+/// fn xor_key(key: [u8; 4]) -> u8 {
+///     key.iter().fold(0, |acc, &x| acc ^ x)
+/// }
+///
+/// let key = [0x01, 0x02, 0x04, 0x08];
+/// assert_eq!(eraser::run_then_erase_with_input(xor_key, key, 4096), 0x0F);
+/// ```
+#[cfg(feature = "std")]
+pub fn run_then_erase_with_input<I, R>(f: fn(I) -> R, input: I, stack_size: usize) -> R {
+    run_then_erase(move || f(input), stack_size)
+}
+
+/// Run a function that writes its output into a caller-provided buffer on an
+/// ephemeral stack, and immediately erase the stack.
+///
+/// The user function receives `out` and returns the number of bytes that it
+/// has written to it, which is returned to the caller.  This way, a derived
+/// key or signature is written exactly once, directly into a buffer that is
+/// controlled by the caller (and that may, for example, already be locked into
+/// memory), instead of being copied around through a return value.
+///
+/// This function panics if the user function reports that it has written more
+/// bytes than `out` can hold.
+///
+/// ## Example
+/// ```
+/// let key = [0x5Au8; 16];
+/// let mut out = [0u8; 32];
+/// let len = eraser::run_then_erase_into(
+///     |out| {
+///         for (o, k) in out.iter_mut().zip(key.iter()) {
+///             *o = k ^ 0xFF;
+///         }
+///         key.len()
+///     },
+///     &mut out,
+///     4096,
+/// );
+/// assert_eq!(&out[..len], &[0xA5; 16]);
+/// ```
+#[cfg(feature = "std")]
+pub fn run_then_erase_into<F>(f: F, out: &mut [u8], stack_size: usize) -> usize
+where
+    F: FnOnce(&mut [u8]) -> usize,
+{
+    let out_len = out.len();
+    let written = run_then_erase(|| f(out), stack_size);
+    assert!(
+        written <= out_len,
+        "user function wrote {} bytes to a buffer of {} bytes",
+        written,
+        out_len
+    );
+    written
+}
+
+/// Run a function on an ephemeral stack, immediately erase the stack, and
+/// return the result wrapped in a [`Secret`].
+///
+/// The result is moved into the `Secret` inside the erased context, before the
+/// stack is erased.  Only the (heap) pointer to the secret crosses the
+/// stack-switch barrier, so no copy of the result is left behind outside of
+/// the `Secret`.
+///
+/// ## Example
+/// ```
+/// let key = eraser::run_then_erase_secret(|| [0x42u8; 32], 4096);
+/// key.expose(|key| assert_eq!(key[0], 0x42));
+/// ```
+#[cfg(feature = "std")]
+pub fn run_then_erase_secret<F, R>(f: F, stack_size: usize) -> Secret<R>
+where
+    F: FnOnce() -> R,
+{
+    run_then_erase(move || Secret::new(f()), stack_size)
+}
+
+/// Run a function on an ephemeral stack, immediately erase the stack, and
+/// return the result wrapped in [`zeroize::Zeroizing`].
+///
+/// This function is the same as [`run_then_erase`], but for return types that
+/// implement [`zeroize::Zeroize`].  The intermediate copies of the result are
+/// erased by eraser, and the final copy is zeroized when the returned value is
+/// dropped.
+///
+/// This function is only available with the `zeroize` feature.
+///
+/// ## Example
+/// ```
+/// let key = eraser::run_then_erase_zeroizing(|| vec![0x42u8; 32], 4096);
+/// assert_eq!(key[0], 0x42);
+/// ```
+#[cfg(feature = "zeroize")]
+pub fn run_then_erase_zeroizing<F, Z>(f: F, stack_size: usize) -> zeroize::Zeroizing<Z>
+where
+    F: FnOnce() -> Z,
+    Z: zeroize::Zeroize,
+{
+    run_then_erase(move || zeroize::Zeroizing::new(f()), stack_size)
+}
+
+/// Expose a secret to a function that runs on an ephemeral stack, and
+/// immediately erase the stack.
+///
+/// The secret is only exposed inside the erased context, so any copies of (or
+/// values derived from) the secret that the function leaves on the stack are
+/// erased.  Only the reference to the secret crosses the stack-switch
+/// barrier.
+///
+/// This function is only available with the `secrecy` feature.
+///
+/// ## Example
+/// ```
+/// use secrecy::SecretBox;
+///
+/// let key = SecretBox::new(Box::new([0x42u8; 32]));
+/// let sum = eraser::run_then_erase_exposed(&key, |key| key.iter().map(|&x| x as u32).sum::<u32>(), 4096);
+/// assert_eq!(sum, 32 * 0x42);
+/// ```
+#[cfg(feature = "secrecy")]
+pub fn run_then_erase_exposed<S, X, F, R>(secret: &X, f: F, stack_size: usize) -> R
+where
+    S: ?Sized,
+    X: secrecy::ExposeSecret<S>,
+    F: FnOnce(&S) -> R,
+{
+    run_then_erase(move || f(secret.expose_secret()), stack_size)
+}
+
+/// Initialize a new [`secrecy::SecretBox`] in place on an ephemeral stack, and
+/// immediately erase the stack.
+///
+/// The box is allocated with the default value of `S`, and `f` fills it in
+/// place inside the erased context.  Only the pointer to the box crosses the
+/// stack-switch barrier, so no copy of the secret remains outside of the box.
+///
+/// This function is only available with the `secrecy` feature.
+///
+/// ## Example
+/// ```
+/// use secrecy::ExposeSecret;
+///
+/// let key = eraser::run_then_erase_init_secret_box(|key: &mut [u8; 32]| key.fill(0x42), 4096);
+/// assert_eq!(key.expose_secret()[0], 0x42);
+/// ```
+#[cfg(feature = "secrecy")]
+pub fn run_then_erase_init_secret_box<S, F>(f: F, stack_size: usize) -> secrecy::SecretBox<S>
+where
+    S: zeroize::Zeroize + Default,
+    F: FnOnce(&mut S),
+{
+    run_then_erase(move || secrecy::SecretBox::init_with_mut(f), stack_size)
+}
+
+/// Run a function on a cached ephemeral stack and immediately erase the stack.
+///
+/// This function is similar to [`run_then_erase`], but instead of allocating
+/// a new stack for every call, it lazily allocates one stack of
+/// [`DEFAULT_STACK_SIZE`] bytes per thread and reuses it for every call on that
+/// thread.  The stack is erased after every call, and once more when the
+/// thread exits.
+///
+/// If the cached stack is already in use (i.e., when this function is called
+/// from inside the user function), a new stack is allocated for the nested
+/// call.
+///
+/// ## Example
+/// ```
+/// for i in 0..1000 {
+///     assert_eq!(eraser::run_then_erase_cached(|| i + 1), i + 1);
+/// }
+/// ```
+#[cfg(feature = "std")]
+pub fn run_then_erase_cached<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let cached = CACHED_STACK.with(|cell| cell.borrow_mut().take());
+    let mut stack = match cached {
+        Some(stack) => stack,
+        None => ErasedStack::new(DEFAULT_STACK_SIZE).unwrap_or_else(|err| panic!("{}", err)),
+    };
+    let result = stack.run(f);
+    CACHED_STACK.with(|cell| {
+        let mut cached = cell.borrow_mut();
+        if cached.is_none() {
+            *cached = Some(stack);
+        }
+    });
+    result
+}
+
+/// Run a function on a large ephemeral stack, and return its result along
+/// with the number of bytes of the stack that it used.
+///
+/// This is meant for picking a `stack_size` for the other functions of this
+/// crate based on a measurement, instead of on a guess.  The function is run
+/// on a stack of 8 MiB that is filled with a fixed pattern beforehand, and
+/// the usage is found by scanning up from the bottom of the stack for the
+/// first word that no longer holds the pattern (the high-water mark).  The
+/// stack is erased afterwards, like after any other run.
+///
+/// The usage includes the few hundred bytes that eraser itself needs on the
+/// ephemeral stack, but it only covers the code path that was taken in this
+/// run, so measure with representative (or worst-case) inputs, and leave some
+/// margin.
+///
+/// ## Panics
+///
+/// This function panics if the stack could not be set up.  A panic in the
+/// user function is propagated to the caller.  On targets that support guard
+/// pages, the stack always has one, regardless of the `guard_page` feature,
+/// so an overflow of the stack crashes the process instead of overwriting
+/// other memory (see [`EraserBuilder::guard_page`]).
+///
+/// ## Example
+/// ```
+/// let (sum, used) = eraser::measure_stack_usage(|| {
+///     let buf = core::hint::black_box([1u8; 2048]);
+///     buf.iter().map(|&x| x as u32).sum::<u32>()
+/// });
+/// assert_eq!(sum, 2048);
+/// assert!(used >= 2048);
+///
+/// let stack_size = (used + 4095) / 4096 * 4096 + 4096;
+/// assert_eq!(eraser::run_then_erase(|| 42, stack_size), 42);
+/// ```
+#[cfg(feature = "std")]
+pub fn measure_stack_usage<F, R>(f: F) -> (R, usize)
+where
+    F: FnOnce() -> R,
+{
+    Eraser::builder()
+        .stack_size(MEASURE_STACK_SIZE)
+        .guard_page(sys::CAN_MAP)
+        .build()
+        .and_then(|eraser| eraser.new_stack())
+        .and_then(|mut stack| stack.try_measure(f))
+        .unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Whether `stack_switch` sets up a frame record (the old frame pointer, next
+/// to a copy of the return address) in the two words at the top of the new
+/// stack, which the unwinders read the return address from, and which
+/// `stack_switch` does not restore anything from.
+#[cfg(feature = "std")]
+const FRAME_RECORD: bool = cfg!(any(
+    target_arch = "x86_64",
+    all(target_arch = "x86", any(target_os = "linux", windows)),
+    all(
+        target_arch = "aarch64",
+        any(target_os = "linux", target_os = "macos", windows)
+    ),
+    all(target_arch = "riscv64", target_os = "linux"),
+    all(target_arch = "loongarch64", target_os = "linux"),
+));
+
+/// The padding that `stack_switch` puts below the saved registers, which
+/// keeps the stack aligned to 16 bytes at the call.  The Windows x64 calling
+/// convention also requires 32 bytes of shadow space above the return
+/// address.
+#[cfg(all(target_arch = "x86_64", not(windows), not(target_os = "none")))]
+const FRAME_PADDING: usize = 0;
+#[cfg(all(target_arch = "x86_64", windows))]
+const FRAME_PADDING: usize = 40;
+/// The padding that the 32-bit x86 `stack_switch` puts below the saved
+/// registers, which keeps the stack aligned to 16 bytes at the call.
+#[cfg(all(target_arch = "x86", not(windows)))]
+const FRAME_PADDING: usize = 0;
+#[cfg(all(target_arch = "x86", windows))]
+const FRAME_PADDING: usize = 4;
+
+/// The number of bytes that the x86_64 `stack_switch` pushes on the new stack
+/// above `FRAME_PADDING`: the frame record, the saved stack and frame
+/// pointers, the other callee-saved registers and the shadow stack pointer,
+/// and on Windows the stack bounds in the TEB.  The `overflow` module resumes
+/// an abandoned run right below these.
+#[cfg(all(target_arch = "x86_64", not(target_os = "none")))]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+const SAVED_FRAME_SIZE: usize = if cfg!(windows) { 13 * 8 } else { 10 * 8 };
+
+/// Run the "assembly" part of the `run_then_erase` wrapper.
+///
+/// This function is separate, because the user function might clobber any kind
+/// of register, even avx2 or x87 registers.  Instead of clobbering *all* of
+/// them in the asm! directive, we enter a separate function that explicitly
+/// uses the C ABI.  This way, we know with reasonably certainty that the
+/// calling function has saved any reasonable register that it needs to stay
+/// intact.
+///
+/// The API allows the user function to capture from its environment, but this
+/// prevents it from being compatible with the C ABI.  Therefore, we cannot
+/// store and pass it directly through calls that use the C calling convention.
+/// So, instead of directly passing them through the layer, we bypass the layer
+/// and stash the user function thread local storage static value `CTX`.
+/// `do_run_user_fn` will read back the user function out from `CTX` and
+/// execute it using the (unstable) Rust ABI convention (but on the other
+/// stack).
+///
+/// `stack_switch` itself is a naked function that uses the C ABI as well, so
+/// the compiler does not emit a prologue or epilogue for it: no register of
+/// the caller is ever spilled by the compiler around the switch, and every
+/// instruction that runs between the call and the return is listed below.
+/// The caller treats all the caller-saved registers as clobbered by the call.
+///
+/// The switch is annotated with call frame information (`.cfi_*` directives),
+/// which tells unwinders where the old stack pointer and the return address
+/// were saved on the new stack.  This way, backtraces, profilers and debuggers
+/// can walk from the user function back to the caller of `run_then_erase`.
+/// Windows has its own unwind codes, which cannot describe the switch, so there
+/// is no unwind information for it there, and neither on MIPS, where
+/// `stack_switch` is not a naked function.
+///
+/// For the unwinders that follow the frame pointer chain instead, the top of
+/// the new stack holds a frame record (the old frame pointer, next to a copy
+/// of the return address), which the frame pointer points to while the user
+/// function runs.  On powerpc64le and s390x, the back chain of the new frame
+/// serves the same purpose.  32-bit ARM and MIPS do not get a frame record.
+/// The call frame information reads the return address from the frame record
+/// too, and the registers are restored from separate slots, so zeroing the
+/// frame record (see [`EraserBuilder::redact_backtraces`]) stops both kinds of
+/// unwinders at the switch.
+///
+/// The switch only ever returns to the addresses that were pushed by a
+/// matching `call`, so it works with the shadow stacks of Intel CET, which
+/// are not switched: the frames on the new stack push their return addresses
+/// on the thread's shadow stack, just like the frames on the old stack.  The
+/// only exception is a run that is abandoned after a stack overflow (see the
+/// `overflow` module), which leaves the entries of the abandoned frames on the
+/// shadow stack.  On x86_64, `stack_switch` saves the shadow stack pointer
+/// before the call, and pops any leftover entries (with `incsspq`) before it
+/// returns.  The retired entries cannot be zeroed, because only `wrss` can
+/// write to a shadow stack, but they only hold return addresses.
+///
+/// With the indirect branch tracking of Intel CET, every indirect call or jump
+/// has to land on an `endbr64` instruction.  The compiler puts one at the start
+/// of every function that may be called indirectly (with `-Z cf-protection`),
+/// but not in naked functions, so `stack_switch` starts with one itself, in
+/// case it is reached through a PLT stub or a function pointer.  The user
+/// function is called directly.
+///
+/// On Windows, the bounds of the current stack are also recorded in the
+/// thread environment block (`StackBase`, `StackLimit` and
+/// `DeallocationStack`), and structured exception handling, `__chkstk` and
+/// the stack overflow handling check the stack pointer against them.  These
+/// are pointed at the new stack for the duration of the switch, and restored
+/// afterwards.
+#[cfg(all(target_arch = "x86_64", not(target_os = "none")))]
+#[unsafe(naked)]
+unsafe extern "C" fn stack_switch(stack_bottom: *mut u8, stack_top: *mut u8) {
+    core::arch::naked_asm!(
+        #[cfg(not(windows))]
+        ".cfi_startproc",
+        // Land an indirect branch (this is a no-op without IBT)
+        "endbr64",
+        // Stash the old rsp
         "mov rax, rsp",
         // Switch stacks
-        "mov rsp, {stack_top}",
-        // Save the frame pointer and stack pointer values
+        #[cfg(not(windows))]
+        "mov rsp, rsi",
+        #[cfg(windows)]
+        "mov rsp, rdx",
+        #[cfg(not(windows))]
+        ".cfi_def_cfa rax, 8",
+        // Set up a frame record at the top of the new stack, with a copy of
+        // the return address, which links the frame pointer chain back to the
+        // caller
+        "push qword ptr [rax]",
         "push rbp",
+        // Save the stack pointer value, and the frame pointer once more, which
+        // is where it is restored from, so that the frame record can be
+        // redacted (see `redact_frame_record`)
         "push rax",
-        // Put the return address on the top of the stack
-        "lea rax, [9999f + rip]",
-        "push rax",
+        "push rbp",
+        "lea rbp, [rsp + 16]",
+        // The caller's frame is at the old rsp, which is now at `[rbp - 8]`,
+        // the old rbp is at `[rbp - 16]`, and the return address is read from
+        // the frame record at `[rbp + 8]` (DW_CFA_def_cfa_expression:
+        // DW_OP_breg6 -8, DW_OP_deref, DW_OP_plus_uconst 8; DW_CFA_expression
+        // rbp: DW_OP_breg6 -16; DW_CFA_expression rip: DW_OP_breg6 8)
+        #[cfg(not(windows))]
+        ".cfi_escape 0x0f, 5, 0x76, 0x78, 0x06, 0x23, 8",
+        #[cfg(not(windows))]
+        ".cfi_escape 0x10, 6, 2, 0x76, 0x70",
+        #[cfg(not(windows))]
+        ".cfi_escape 0x10, 16, 2, 0x76, 8",
+        // Save the other callee-saved registers, which do not survive if the
+        // user function is abandoned after a stack overflow (see the
+        // `overflow` module, which relies on this layout; every push in front
+        // of the padding is counted in `SAVED_FRAME_SIZE`)
+        "push rbx",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        // Save the shadow stack pointer, if shadow stacks are enabled
+        // (`rdsspq` does nothing otherwise)
+        "xor r11d, r11d",
+        "rdsspq r11",
+        "push r11",
+        // Save the stack bounds in the TEB and point them at the new stack
+        #[cfg(windows)]
+        "push qword ptr gs:[0x08]",
+        #[cfg(windows)]
+        "push qword ptr gs:[0x10]",
+        #[cfg(windows)]
+        "push qword ptr gs:[0x1478]",
+        #[cfg(windows)]
+        "mov qword ptr gs:[0x08], rdx",
+        #[cfg(windows)]
+        "mov qword ptr gs:[0x10], rcx",
+        #[cfg(windows)]
+        "mov qword ptr gs:[0x1478], rcx",
+        // Keep the stack aligned to 16 bytes at the call (and reserve the
+        // shadow space on Windows)
+        "sub rsp, {frame}",
         // Call the running function using the new stack
-        "jmp {user_fn}",
-        // Wrapped function will return to here
-        "9999:",
+        "call {user_fn}",
+        "add rsp, {frame}",
+        #[cfg(windows)]
+        "pop qword ptr gs:[0x1478]",
+        #[cfg(windows)]
+        "pop qword ptr gs:[0x10]",
+        #[cfg(windows)]
+        "pop qword ptr gs:[0x08]",
+        // Pop the entries of the frames that were abandoned after a stack
+        // overflow off the shadow stack, 255 at a time at most
+        "pop r11",
+        "test r11, r11",
+        "jz 3f",
+        "rdsspq rcx",
+        "sub r11, rcx",
+        "shr r11, 3",
+        "jz 3f",
+        "2:",
+        "mov ecx, 255",
+        "cmp r11, rcx",
+        "cmovb rcx, r11",
+        "incsspq rcx",
+        "sub r11, rcx",
+        "jnz 2b",
+        "3:",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbx",
         // Restore the original stack and frame pointer values
-        "pop rax",
+        "mov rax, [rsp + 8]",
+        #[cfg(not(windows))]
+        ".cfi_def_cfa rax, 8",
+        #[cfg(not(windows))]
+        ".cfi_restore 16",
         "pop rbp",
+        #[cfg(not(windows))]
+        ".cfi_restore rbp",
         "mov rsp, rax",
+        #[cfg(not(windows))]
+        ".cfi_def_cfa rsp, 8",
+        "ret",
+        #[cfg(not(windows))]
+        ".cfi_endproc",
+        user_fn = sym do_run_user_fn,
+        frame = const FRAME_PADDING,
+    );
+}
+
+/// The 32-bit x86 version of `stack_switch`.
+///
+/// The arguments are passed on the old stack.  The user function is called
+/// with the C ABI, which preserves `ebx`, `esi`, `edi` and `ebp`, so only the
+/// frame record, the frame pointer and the old stack pointer are saved on the
+/// new stack.  On Windows, the stack bounds in the thread information block
+/// (`fs:[0x04]`, `fs:[0x08]` and `DeallocationStack` at `fs:[0xE0C]`) are
+/// switched as well, just like on x86_64.  Structured exception handling only
+/// accepts handler records within those bounds, so an exception that is not
+/// handled on the new stack (a panic always is) cannot reach the handlers of
+/// the caller.
+#[cfg(all(target_arch = "x86", any(target_os = "linux", windows)))]
+#[unsafe(naked)]
+unsafe extern "C" fn stack_switch(stack_bottom: *mut u8, stack_top: *mut u8) {
+    core::arch::naked_asm!(
+        #[cfg(not(windows))]
+        ".cfi_startproc",
+        // Land an indirect branch (this is a no-op without IBT)
+        "endbr32",
+        // Load the arguments and stash the old esp
+        "mov ecx, [esp + 8]",
+        "mov edx, [esp + 4]",
+        "mov eax, esp",
+        // Switch stacks
+        "mov esp, ecx",
+        #[cfg(not(windows))]
+        ".cfi_def_cfa eax, 4",
+        // Set up a frame record at the top of the new stack, with a copy of
+        // the return address, which links the frame pointer chain back to the
+        // caller
+        "push dword ptr [eax]",
+        "push ebp",
+        // Save the stack pointer value, and the frame pointer once more, which
+        // is where it is restored from
+        "push eax",
+        "push ebp",
+        "lea ebp, [esp + 8]",
+        // The caller's frame is at the old esp, which is now at `[ebp - 4]`,
+        // the old ebp is at `[ebp - 8]`, and the return address is read from
+        // the frame record at `[ebp + 4]` (DW_CFA_def_cfa_expression:
+        // DW_OP_breg5 -4, DW_OP_deref, DW_OP_plus_uconst 4; DW_CFA_expression
+        // ebp: DW_OP_breg5 -8; DW_CFA_expression eip: DW_OP_breg5 4)
+        #[cfg(not(windows))]
+        ".cfi_escape 0x0f, 5, 0x75, 0x7c, 0x06, 0x23, 4",
+        #[cfg(not(windows))]
+        ".cfi_escape 0x10, 5, 2, 0x75, 0x78",
+        #[cfg(not(windows))]
+        ".cfi_escape 0x10, 8, 2, 0x75, 4",
+        // Save the stack bounds in the TIB and point them at the new stack
+        #[cfg(windows)]
+        "push dword ptr fs:[0x04]",
+        #[cfg(windows)]
+        "push dword ptr fs:[0x08]",
+        #[cfg(windows)]
+        "push dword ptr fs:[0xE0C]",
+        #[cfg(windows)]
+        "mov dword ptr fs:[0x04], ecx",
+        #[cfg(windows)]
+        "mov dword ptr fs:[0x08], edx",
+        #[cfg(windows)]
+        "mov dword ptr fs:[0xE0C], edx",
+        // Keep the stack aligned to 16 bytes at the call
+        "sub esp, {frame}",
+        // Call the running function using the new stack
+        "call {user_fn}",
+        "add esp, {frame}",
+        #[cfg(windows)]
+        "pop dword ptr fs:[0xE0C]",
+        #[cfg(windows)]
+        "pop dword ptr fs:[0x08]",
+        #[cfg(windows)]
+        "pop dword ptr fs:[0x04]",
+        // Restore the original stack and frame pointer values
+        "mov eax, [esp + 4]",
+        #[cfg(not(windows))]
+        ".cfi_def_cfa eax, 4",
+        #[cfg(not(windows))]
+        ".cfi_restore 8",
+        "pop ebp",
+        #[cfg(not(windows))]
+        ".cfi_restore ebp",
+        "mov esp, eax",
+        #[cfg(not(windows))]
+        ".cfi_def_cfa esp, 4",
+        "ret",
+        #[cfg(not(windows))]
+        ".cfi_endproc",
         user_fn = sym do_run_user_fn,
-        stack_top = in(reg) stack_top,
-        out("rax") _,
+        frame = const FRAME_PADDING,
     );
 }
 
+/// The aarch64 version of `stack_switch`.
+///
+/// The user function is called with the C ABI, which already preserves the
+/// callee-saved registers (`x19`–`x28` and the lower halves of `v8`–`v15`), so
+/// only the frame record, the frame pointer, the link register and the old
+/// stack pointer are saved on the new stack.  Nothing is stored below the old
+/// stack pointer, so the red zone of the Apple ABI (the 128 bytes below `sp`)
+/// is left alone, and `sp` stays aligned to 16 bytes, as all the aarch64 ABIs
+/// require.
+///
+/// On Windows, the stack bounds in the thread environment block (which `x18`
+/// points to) are switched as well, just like on x86_64.
+#[cfg(all(
+    target_arch = "aarch64",
+    any(target_os = "linux", target_os = "macos", windows)
+))]
+#[unsafe(naked)]
+unsafe extern "C" fn stack_switch(stack_bottom: *mut u8, stack_top: *mut u8) {
+    core::arch::naked_asm!(
+        #[cfg(not(windows))]
+        ".cfi_startproc",
+        // Stash the old sp
+        "mov x10, sp",
+        // Switch stacks
+        "mov sp, x1",
+        #[cfg(not(windows))]
+        ".cfi_def_cfa x10, 0",
+        // Set up a frame record at the top of the new stack, which links the
+        // frame pointer chain back to the caller, and save the frame pointer,
+        // link register and stack pointer values below it, which is where they
+        // are restored from
+        "stp x29, x30, [sp, #-16]!",
+        "stp x29, x30, [sp, #-16]!",
+        "str x10, [sp, #-16]!",
+        "add x29, sp, #32",
+        // The caller's frame is at the old sp, which is now at `[sp]`, the
+        // frame pointer is at `[sp + 16]`, and the link register is read from
+        // the frame record at `[sp + 40]` (DW_CFA_def_cfa_expression:
+        // DW_OP_breg31 0, DW_OP_deref; DW_CFA_expression x29: DW_OP_breg31 16;
+        // DW_CFA_expression x30: DW_OP_breg31 40)
+        #[cfg(not(windows))]
+        ".cfi_escape 0x0f, 3, 0x8f, 0, 0x06",
+        #[cfg(not(windows))]
+        ".cfi_escape 0x10, 29, 2, 0x8f, 16",
+        #[cfg(not(windows))]
+        ".cfi_escape 0x10, 30, 2, 0x8f, 40",
+        // Save the stack bounds in the TEB and point them at the new stack
+        #[cfg(windows)]
+        "ldr x11, [x18, #0x08]",
+        #[cfg(windows)]
+        "ldr x12, [x18, #0x10]",
+        #[cfg(windows)]
+        "ldr x13, [x18, #0x1478]",
+        #[cfg(windows)]
+        "stp x11, x12, [sp, #-16]!",
+        #[cfg(windows)]
+        "str x13, [sp, #-16]!",
+        #[cfg(windows)]
+        "str x1, [x18, #0x08]",
+        #[cfg(windows)]
+        "str x0, [x18, #0x10]",
+        #[cfg(windows)]
+        "str x0, [x18, #0x1478]",
+        // Call the running function using the new stack
+        "bl {user_fn}",
+        #[cfg(windows)]
+        "ldr x13, [sp], #16",
+        #[cfg(windows)]
+        "ldp x11, x12, [sp], #16",
+        #[cfg(windows)]
+        "str x13, [x18, #0x1478]",
+        #[cfg(windows)]
+        "str x12, [x18, #0x10]",
+        #[cfg(windows)]
+        "str x11, [x18, #0x08]",
+        // Restore the original stack, frame pointer and link register values
+        "ldr x10, [sp]",
+        #[cfg(not(windows))]
+        ".cfi_def_cfa x10, 0",
+        "ldp x29, x30, [sp, #16]",
+        #[cfg(not(windows))]
+        ".cfi_restore x29",
+        #[cfg(not(windows))]
+        ".cfi_restore x30",
+        "mov sp, x10",
+        #[cfg(not(windows))]
+        ".cfi_def_cfa sp, 0",
+        "ret",
+        #[cfg(not(windows))]
+        ".cfi_endproc",
+        user_fn = sym do_run_user_fn,
+    );
+}
+
+/// The 32-bit ARM version of `stack_switch`.
+///
+/// Like on aarch64, the C ABI preserves the callee-saved registers (`r4`–`r11`
+/// and `d8`–`d15`).  The frame pointer is `r11` in ARM mode and `r7` in Thumb
+/// mode, so both are saved, together with the old stack pointer and the link
+/// register, which keeps `sp` aligned to 8 bytes.
+#[cfg(all(target_arch = "arm", target_os = "linux"))]
+#[unsafe(naked)]
+unsafe extern "C" fn stack_switch(stack_bottom: *mut u8, stack_top: *mut u8) {
+    core::arch::naked_asm!(
+        ".cfi_startproc",
+        // Stash the old sp
+        "mov r2, sp",
+        // Switch stacks
+        "mov sp, r1",
+        ".cfi_def_cfa r2, 0",
+        // Save the frame pointer, stack pointer and link register values
+        "push {{r2, r7, r11, lr}}",
+        // The caller's frame is at the old sp, which is now at `[sp]`, and the
+        // link register is at `[sp + 12]` (DW_CFA_def_cfa_expression:
+        // DW_OP_breg13 0, DW_OP_deref; DW_CFA_expression lr: DW_OP_breg13 12)
+        ".cfi_escape 0x0f, 3, 0x7d, 0, 0x06",
+        ".cfi_escape 0x10, 14, 2, 0x7d, 12",
+        // Call the running function using the new stack
+        "bl {user_fn}",
+        // Restore the original stack, frame pointer and link register values
+        "pop {{r2, r7, r11, lr}}",
+        ".cfi_def_cfa r2, 0",
+        ".cfi_restore lr",
+        "mov sp, r2",
+        ".cfi_def_cfa sp, 0",
+        "bx lr",
+        ".cfi_endproc",
+        user_fn = sym do_run_user_fn,
+    );
+}
+
+/// The RISC-V version of `stack_switch`.
+///
+/// The C ABI preserves `s0`–`s11` and `fs0`–`fs11`, so only the frame record,
+/// the return address, the frame pointer (`s0`) and the old stack pointer are
+/// saved, in a 48-byte frame that keeps `sp` aligned as the ABI requires.
+#[cfg(all(target_arch = "riscv64", target_os = "linux"))]
+#[unsafe(naked)]
+unsafe extern "C" fn stack_switch(stack_bottom: *mut u8, stack_top: *mut u8) {
+    core::arch::naked_asm!(
+        ".cfi_startproc",
+        // Stash the old sp
+        "mv t1, sp",
+        // Switch stacks
+        "addi sp, a1, -48",
+        ".cfi_def_cfa t1, 0",
+        // Set up a frame record at the top of the new stack, and save the
+        // return address, frame pointer and stack pointer values below it,
+        // which is where they are restored from
+        "sd ra, 40(sp)",
+        "sd s0, 32(sp)",
+        "sd ra, 24(sp)",
+        "sd s0, 16(sp)",
+        "sd t1, 8(sp)",
+        // Point the frame pointer right above the frame record, which links
+        // the frame pointer chain back to the caller
+        "addi s0, sp, 48",
+        // The caller's frame is at the old sp, which is now at `8(sp)`, the
+        // frame pointer is at `16(sp)`, and the return address is read from
+        // the frame record at `40(sp)` (DW_CFA_def_cfa_expression: DW_OP_breg2
+        // 8, DW_OP_deref; DW_CFA_expression ra: DW_OP_breg2 40;
+        // DW_CFA_expression s0: DW_OP_breg2 16)
+        ".cfi_escape 0x0f, 3, 0x72, 8, 0x06",
+        ".cfi_escape 0x10, 1, 2, 0x72, 40",
+        ".cfi_escape 0x10, 8, 2, 0x72, 16",
+        // Call the running function using the new stack
+        "call {user_fn}",
+        // Restore the original stack, frame pointer and return address
+        "ld ra, 24(sp)",
+        ".cfi_restore ra",
+        "ld s0, 16(sp)",
+        ".cfi_restore s0",
+        "ld t1, 8(sp)",
+        ".cfi_def_cfa t1, 0",
+        "mv sp, t1",
+        ".cfi_def_cfa sp, 0",
+        "ret",
+        ".cfi_endproc",
+        user_fn = sym do_run_user_fn,
+    );
+}
+
+/// The powerpc64le version of `stack_switch`.
+///
+/// The ELFv2 ABI preserves `r14`–`r31`, `f14`–`f31` and `v20`–`v31`.  The link
+/// register is saved in the LR save slot of the caller's frame, as usual, and
+/// the TOC pointer (`r2`) in the TOC save slot of a minimal 32-byte frame on
+/// the new stack.  The back chain word of that frame points at the old stack,
+/// which is where the old stack pointer is restored from, and which lets
+/// debuggers walk back to the caller.
+#[cfg(all(
+    target_arch = "powerpc64",
+    target_endian = "little",
+    target_os = "linux"
+))]
+#[unsafe(naked)]
+unsafe extern "C" fn stack_switch(stack_bottom: *mut u8, stack_top: *mut u8) {
+    core::arch::naked_asm!(
+        ".cfi_startproc",
+        // Save the link register
+        "mflr 0",
+        "std 0, 16(1)",
+        ".cfi_offset lr, 16",
+        // Stash the old stack pointer
+        "mr 5, 1",
+        // Switch stacks, and store the back chain in the new frame
+        "mr 1, 4",
+        ".cfi_def_cfa 5, 0",
+        "stdu 5, -32(1)",
+        // The caller's frame is at the back chain (DW_CFA_def_cfa_expression:
+        // DW_OP_breg1 0, DW_OP_deref)
+        ".cfi_escape 0x0f, 3, 0x71, 0, 0x06",
+        // Save the TOC pointer
+        "std 2, 24(1)",
+        // Call the running function using the new stack
+        "bl {user_fn}",
+        "nop",
+        // Restore the TOC pointer, the original stack and the link register
+        "ld 2, 24(1)",
+        "ld 1, 0(1)",
+        ".cfi_def_cfa 1, 0",
+        "ld 0, 16(1)",
+        "mtlr 0",
+        ".cfi_restore lr",
+        "blr",
+        ".cfi_endproc",
+        user_fn = sym do_run_user_fn,
+    );
+}
+
+/// The s390x version of `stack_switch`.
+///
+/// The ELF ABI preserves `r6`–`r15` and `f8`–`f15`, and the stack pointer is
+/// `r15`.  Every caller has to provide a 160-byte register save area at the
+/// bottom of its frame, for the function that it calls, so that is where the
+/// return address (`r14`) is saved, and that is the frame that is set up on
+/// the new stack.  The old stack pointer is stored in its back chain word,
+/// which is where it is restored from.
+#[cfg(all(target_arch = "s390x", target_os = "linux"))]
+#[unsafe(naked)]
+unsafe extern "C" fn stack_switch(stack_bottom: *mut u8, stack_top: *mut u8) {
+    core::arch::naked_asm!(
+        ".cfi_startproc",
+        // Save the return address in the register save area of the caller
+        "stg %r14, 112(%r15)",
+        ".cfi_offset %r14, -48",
+        // Stash the old stack pointer
+        "lgr %r1, %r15",
+        // Switch stacks, and store the back chain in the new frame
+        "lay %r15, -160(%r3)",
+        ".cfi_def_cfa %r1, 160",
+        "stg %r1, 0(%r15)",
+        // The caller's frame is at the back chain (DW_CFA_def_cfa_expression:
+        // DW_OP_breg15 0, DW_OP_deref, DW_OP_plus_uconst 160)
+        ".cfi_escape 0x0f, 6, 0x7f, 0, 0x06, 0x23, 0xa0, 0x01",
+        // Call the running function using the new stack
+        "brasl %r14, {user_fn}",
+        // Restore the original stack and the return address
+        "lg %r15, 0(%r15)",
+        ".cfi_def_cfa %r15, 160",
+        "lg %r14, 112(%r15)",
+        ".cfi_restore %r14",
+        "br %r14",
+        ".cfi_endproc",
+        user_fn = sym do_run_user_fn,
+    );
+}
+
+/// The LoongArch version of `stack_switch`.
+///
+/// The C ABI preserves `$s0`–`$s8`, `$fp` and `$fs0`–`$fs7`, so only the
+/// frame record, the return address, the frame pointer and the old stack
+/// pointer are saved, in a 48-byte frame that keeps `$sp` aligned as the ABI
+/// requires.
+#[cfg(all(target_arch = "loongarch64", target_os = "linux"))]
+#[unsafe(naked)]
+unsafe extern "C" fn stack_switch(stack_bottom: *mut u8, stack_top: *mut u8) {
+    core::arch::naked_asm!(
+        ".cfi_startproc",
+        // Stash the old sp
+        "move $t1, $sp",
+        // Switch stacks
+        "addi.d $sp, $a1, -48",
+        ".cfi_def_cfa 13, 0",
+        // Set up a frame record at the top of the new stack, and save the
+        // return address, frame pointer and stack pointer values below it,
+        // which is where they are restored from
+        "st.d $ra, $sp, 40",
+        "st.d $fp, $sp, 32",
+        "st.d $ra, $sp, 24",
+        "st.d $fp, $sp, 16",
+        "st.d $t1, $sp, 8",
+        // Point the frame pointer right above the frame record, which links
+        // the frame pointer chain back to the caller
+        "addi.d $fp, $sp, 48",
+        // The caller's frame is at the old sp, which is now at `8($sp)`, the
+        // frame pointer is at `16($sp)`, and the return address is read from
+        // the frame record at `40($sp)` (DW_CFA_def_cfa_expression:
+        // DW_OP_breg3 8, DW_OP_deref; DW_CFA_expression $ra: DW_OP_breg3 40;
+        // DW_CFA_expression $fp: DW_OP_breg3 16)
+        ".cfi_escape 0x0f, 3, 0x73, 8, 0x06",
+        ".cfi_escape 0x10, 1, 2, 0x73, 40",
+        ".cfi_escape 0x10, 22, 2, 0x73, 16",
+        // Call the running function using the new stack
+        "bl {user_fn}",
+        // Restore the original stack, frame pointer and return address
+        "ld.d $ra, $sp, 24",
+        ".cfi_restore 1",
+        "ld.d $fp, $sp, 16",
+        ".cfi_restore 22",
+        "ld.d $t1, $sp, 8",
+        ".cfi_def_cfa 13, 0",
+        "move $sp, $t1",
+        ".cfi_def_cfa 3, 0",
+        "jr $ra",
+        ".cfi_endproc",
+        user_fn = sym do_run_user_fn,
+    );
+}
+
+/// The MIPS (o32) version of `stack_switch`.
+///
+/// MIPS has no `clobber_abi`, and the return address and global pointer
+/// registers cannot be named as clobbers, so this saves `$ra` and `$gp` (which
+/// the callee recomputes from `$t9` under o32) itself, and lists the
+/// caller-saved registers explicitly.  The callee may store its arguments in
+/// the 16 bytes at the bottom of the frame.  Unlike on the other targets, this
+/// is not a naked function: the callee expects its own address in `$t9`, and
+/// the compiler knows how to load that in position-independent code.
+#[cfg(all(target_arch = "mips", target_os = "linux", not(eraser_shim)))]
+#[inline(never)]
+unsafe fn stack_switch(_stack_bottom: *mut u8, stack_top: *mut u8) {
+    core::arch::asm!(
+        // Stash the old sp
+        "move $9, $sp",
+        // Switch stacks
+        "move $sp, $8",
+        // Save the return address, global pointer and stack pointer values
+        "addiu $sp, $sp, -32",
+        "sw $31, 16($sp)",
+        "sw $28, 20($sp)",
+        "sw $9, 24($sp)",
+        // Call the running function using the new stack
+        ".set noreorder",
+        "jalr $25",
+        "nop",
+        ".set reorder",
+        // Restore the original stack, global pointer and return address
+        "lw $31, 16($sp)",
+        "lw $28, 20($sp)",
+        "lw $9, 24($sp)",
+        "move $sp, $9",
+        inout("$8") stack_top => _,
+        inout("$25") do_run_user_fn as *const () => _,
+        lateout("$2") _,
+        lateout("$3") _,
+        lateout("$4") _,
+        lateout("$5") _,
+        lateout("$6") _,
+        lateout("$7") _,
+        lateout("$9") _,
+        lateout("$10") _,
+        lateout("$11") _,
+        lateout("$12") _,
+        lateout("$13") _,
+        lateout("$14") _,
+        lateout("$15") _,
+        lateout("$24") _,
+        lateout("$f0") _,
+        lateout("$f1") _,
+        lateout("$f2") _,
+        lateout("$f3") _,
+        lateout("$f4") _,
+        lateout("$f5") _,
+        lateout("$f6") _,
+        lateout("$f7") _,
+        lateout("$f8") _,
+        lateout("$f9") _,
+        lateout("$f10") _,
+        lateout("$f11") _,
+        lateout("$f12") _,
+        lateout("$f13") _,
+        lateout("$f14") _,
+        lateout("$f15") _,
+        lateout("$f16") _,
+        lateout("$f17") _,
+        lateout("$f18") _,
+        lateout("$f19") _,
+    );
+}
+
+/// The MIPS64 (n64) version of `stack_switch`.
+///
+/// Like on 32-bit MIPS, `$ra` and `$gp` are saved by hand and the
+/// caller-saved registers are listed explicitly.
+#[cfg(all(target_arch = "mips64", target_os = "linux", not(eraser_shim)))]
+#[inline(never)]
+unsafe fn stack_switch(_stack_bottom: *mut u8, stack_top: *mut u8) {
+    core::arch::asm!(
+        // Stash the old sp
+        "move $9, $sp",
+        // Switch stacks
+        "move $sp, $8",
+        // Save the return address, global pointer and stack pointer values
+        "daddiu $sp, $sp, -32",
+        "sd $31, 0($sp)",
+        "sd $28, 8($sp)",
+        "sd $9, 16($sp)",
+        // Call the running function using the new stack
+        ".set noreorder",
+        "jalr $25",
+        "nop",
+        ".set reorder",
+        // Restore the original stack, global pointer and return address
+        "ld $31, 0($sp)",
+        "ld $28, 8($sp)",
+        "ld $9, 16($sp)",
+        "move $sp, $9",
+        inout("$8") stack_top => _,
+        inout("$25") do_run_user_fn as *const () => _,
+        lateout("$2") _,
+        lateout("$3") _,
+        lateout("$4") _,
+        lateout("$5") _,
+        lateout("$6") _,
+        lateout("$7") _,
+        lateout("$9") _,
+        lateout("$10") _,
+        lateout("$11") _,
+        lateout("$12") _,
+        lateout("$13") _,
+        lateout("$14") _,
+        lateout("$15") _,
+        lateout("$24") _,
+        lateout("$f0") _,
+        lateout("$f1") _,
+        lateout("$f2") _,
+        lateout("$f3") _,
+        lateout("$f4") _,
+        lateout("$f5") _,
+        lateout("$f6") _,
+        lateout("$f7") _,
+        lateout("$f8") _,
+        lateout("$f9") _,
+        lateout("$f10") _,
+        lateout("$f11") _,
+        lateout("$f12") _,
+        lateout("$f13") _,
+        lateout("$f14") _,
+        lateout("$f15") _,
+        lateout("$f16") _,
+        lateout("$f17") _,
+        lateout("$f18") _,
+        lateout("$f19") _,
+        lateout("$f20") _,
+        lateout("$f21") _,
+        lateout("$f22") _,
+        lateout("$f23") _,
+    );
+}
+
+/// The SPARC V9 version of `stack_switch`.
+///
+/// This function gets a register window of its own, and the register windows
+/// of all the callers are flushed to their stack frames (with `flushw`, the V9
+/// counterpart of the `ta 3` trap), so that none of them is spilled while the
+/// ephemeral stack is in use.  The windows that the user function spills end
+/// up on the ephemeral stack, where they are erased with the rest.  The stack
+/// pointer is biased by 2047 bytes, and the callee may store its window in the
+/// 176 bytes above it.  The old stack pointer is still in `%fp`, and `restore`
+/// returns to it.
+#[cfg(all(target_arch = "sparc64", target_os = "linux", not(eraser_shim)))]
+#[unsafe(naked)]
+unsafe extern "C" fn stack_switch(stack_bottom: *mut u8, stack_top: *mut u8) {
+    core::arch::naked_asm!(
+        ".cfi_startproc",
+        "save %sp, -176, %sp",
+        // The caller's frame is at `%fp`, in the window of this function
+        ".cfi_def_cfa_register %fp",
+        ".cfi_window_save",
+        ".cfi_register %o7, %i7",
+        // Flush the register windows of all the callers to their stacks
+        "flushw",
+        // Switch stacks
+        "add %i1, -2223, %sp",
+        // Call the running function using the new stack
+        "call {user_fn}",
+        "nop",
+        // Return, which restores the original stack
+        "ret",
+        "restore",
+        ".cfi_endproc",
+        user_fn = sym do_run_user_fn,
+    );
+}
+
+/// The version of `stack_switch` that calls into the assembly shim (see
+/// `src/shim`), which the build script assembles with the `cc_shim` feature on
+/// targets where inline assembly is not stable yet.
+#[cfg(eraser_shim)]
+unsafe fn stack_switch(_stack_bottom: *mut u8, stack_top: *mut u8) {
+    extern "C" {
+        fn eraser_stack_switch(stack_top: *mut u8, user_fn: extern "C" fn());
+    }
+    eraser_stack_switch(stack_top, do_run_user_fn);
+}
+
+#[cfg(all(
+    feature = "std",
+    not(any(
+        target_arch = "x86_64",
+        all(
+            target_arch = "aarch64",
+            any(target_os = "linux", target_os = "macos", windows)
+        ),
+        all(target_arch = "arm", target_os = "linux"),
+        all(target_arch = "riscv64", target_os = "linux"),
+        all(target_arch = "x86", any(target_os = "linux", windows)),
+        all(
+            target_arch = "powerpc64",
+            target_endian = "little",
+            target_os = "linux"
+        ),
+        all(target_arch = "s390x", target_os = "linux"),
+        all(target_arch = "loongarch64", target_os = "linux"),
+        all(target_arch = "mips", target_os = "linux"),
+        all(target_arch = "mips64", target_os = "linux"),
+        all(target_arch = "sparc64", target_os = "linux"),
+        all(target_os = "linux", target_env = "gnu", not(target_arch = "sparc")),
+        target_arch = "wasm32"
+    ))
+))]
+unsafe fn stack_switch(_stack_bottom: *mut u8, _stack_top: *mut u8) {
+    unreachable!("stack switching is not supported on this target")
+}
+
+#[cfg(target_arch = "wasm32")]
+use wasm::stack_switch;
+
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    target_env = "gnu",
+    not(any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "arm",
+        target_arch = "riscv64",
+        target_arch = "x86",
+        all(target_arch = "powerpc64", target_endian = "little"),
+        target_arch = "s390x",
+        target_arch = "loongarch64",
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "sparc",
+        target_arch = "sparc64"
+    ))
+))]
+use ucontext::stack_switch;
+
+#[cfg(feature = "std")]
 extern "C" fn do_run_user_fn() {
-    CTX.with(|cell| {
+    // Do not keep `CTX` borrowed while the user function runs, so that the
+    // user function is allowed to call into eraser again.
+    let (user_fn, frame_record) = CTX.with(|cell| {
         let mut ctx = cell.borrow_mut();
-        let user_fn_opt = ctx.user_fn;
-        ctx.panic_result = Some(panic::catch_unwind(|| {
-            let user_fn = user_fn_opt.expect("EraserContext.user_fn is None");
-            user_fn()
-        }));
+        (ctx.user_fn.take(), ctx.frame_record)
     });
+    let user_fn = user_fn.expect("EraserContext.user_fn is None");
+    // Unlink the ephemeral stack from the caller's stack, for the unwinders
+    if let Some(record) = frame_record {
+        unsafe { ptr::write_bytes(record, 0, 2) };
+    }
+    let panic_result = panic::catch_unwind(|| unsafe { (user_fn.call)(user_fn.data) });
+    CTX.with(|cell| cell.borrow_mut().panic_result = Some(panic_result));
+}
+
+/// Wipe the general purpose and SIMD registers.
+///
+/// This is the register wipe that is done after every run on an ephemeral
+/// stack, for code that cannot afford a stack switch, e.g. right before
+/// returning from a hand-written scalar multiplication routine.  Note that the
+/// registers that the calling convention requires to be preserved (on x86_64:
+/// `rbx`, `rbp`, `rsp` and `r12`–`r15`; on aarch64: `x19`–`x29`, `sp` and the
+/// lower halves of `v8`–`v15`) still hold the values of the caller
+/// afterwards, and that secrets that were spilled to the stack are not
+/// erased.
+///
+/// On x86_64, the vector registers `xmm0`–`xmm15` are wiped as well, at their
+/// full length (with `vzeroall`, on CPUs with AVX), and on CPUs with AVX-512,
+/// so are `zmm16`–`zmm31` and the mask registers `k0`–`k7`.  On CPUs with AMX,
+/// the tiles are released, and on CPUs with APX, the extended registers
+/// `r16`–`r31` are wiped.  The x87 (and MMX) registers are wiped too, and
+/// `MXCSR` is reset to its default, which also clears its exception flags.  On
+/// aarch64, the NEON registers `v0`–`v31` are wiped as well, and the
+/// floating-point status and control registers (`FPSR` and `FPCR`) are reset.
+/// On CPUs with SVE, the scalable vector and predicate registers are wiped at
+/// their full length too.  On 32-bit ARM Linux, the VFP registers (`d0`–`d31`,
+/// or `d0`–`d15` on targets without `d32`) are wiped and `FPSCR` is reset.  On
+/// Cortex-M, `r0`–`r5`, `r8`–`r12` and `lr` are wiped, and so are `s0`–`s31`
+/// and `FPSCR` when the target has an FPU.  On RISC-V 64 Linux, the integer
+/// registers other than `s0`, `s1`, `gp` and `tp` are wiped, as well as
+/// `f0`–`f31`, and `fcsr` is reset.  When the CPU has the vector extension (and
+/// the kernel lets the process use it), `v0`–`v31` are wiped as well, and `vl`
+/// and `vtype` are reset.  On bare-metal RISC-V 32, the same integer registers
+/// are wiped, and so are the floating-point registers and `fcsr` when the
+/// target has the F extension.  On bare-metal Xtensa, the address registers of
+/// all the windows are wiped, except for `a0`, `a1` and `a7` of the current
+/// one, as well as the floating-point registers when the target has an FPU.  On
+/// MSP430, `r5`–`r15` are wiped.  On bare-metal x86_64, the general-purpose
+/// registers other than `rbx`, `rbp` and `rsp` are wiped, and so are
+/// `xmm0`–`xmm15` (and `MXCSR` is reset) only when the target has SSE, since a
+/// kernel may not have enabled the FPU.  On 32-bit x86 (Linux and Windows),
+/// `eax`, `ebx`, `ecx`, `edx`, `edi`, `xmm0`–`xmm7` and the x87 (and MMX)
+/// registers are wiped, and `MXCSR` is reset.  On powerpc64le Linux, the
+/// general-purpose registers other than `r1`, `r2`, `r13`, `r30` and `r31` are
+/// wiped, as well as the floating-point and vector registers (through
+/// `vs0`–`vs63`), and `FPSCR` is reset.  On s390x Linux, the general-purpose
+/// registers other than `r11` and `r15` are wiped, as well as `f0`–`f15` (and
+/// `v0`–`v31` when the target has the `vector` feature), and the floating-point
+/// control register is reset.  On LoongArch64 Linux, the general-purpose
+/// registers other than `$tp`, `$sp`, `$r21`, `$fp` and `$s8` are wiped, as
+/// well as the floating-point registers and the vector registers (when the
+/// target has `lsx` or `lasx`), and `fcsr0` is reset.  On MIPS and MIPS64
+/// Linux, `$v0`, `$v1`, the argument, temporary and saved registers are wiped,
+/// as well as `hi`, `lo` and the floating-point registers, and `FCSR` is reset.
+/// On SPARC64 Linux, the register windows are flushed to the stack, the local
+/// and out registers of all the other windows are wiped, and so are the
+/// registers of the current window other than `%sp`, `%fp`, `%i7`, `%g1`, `%g6`
+/// and `%g7`, the floating-point registers and `FSR`.  On other targets
+/// (including the Linux targets that switch stacks with `swapcontext`) this
+/// function does nothing.
+///
+/// ## Example
+/// ```
+/// let mut acc = 0u64;
+/// for x in [3u64, 5, 7] {
+///     acc = acc.wrapping_mul(31).wrapping_add(x);
+/// }
+/// eraser::wipe_registers();
+/// assert_eq!(acc, 3 * 31 * 31 + 5 * 31 + 7);
+/// ```
+#[inline(never)]
+pub fn wipe_registers() {
+    custom_wipe::run();
+    // The asm block declares all the registers that it touches as clobbered,
+    // so the compiler does not expect any of them to survive the call.
+    unsafe { wipe_all_registers() }
+}
+
+/// Register a routine that wipes additional registers, e.g. those of a
+/// vendor-specific accelerator or DSP on a system on a chip.
+///
+/// The registered routines are called, in the order in which they were
+/// registered, whenever eraser wipes the registers: after every run on an
+/// ephemeral stack, and in [`wipe_registers`].  They run before the built-in
+/// wipe, so the general-purpose registers that they use are wiped afterwards.
+/// A routine cannot be unregistered, and it must not panic.
+///
+/// At most 8 routines can be registered; after that,
+/// [`EraserError::TooManyWipes`] is returned.  On targets without atomic
+/// compare-and-swap (other than Cortex-M and MSP430),
+/// [`EraserError::Unsupported`] is returned.
+///
+/// ## Example
+/// ```
+/// fn wipe_dsp_registers() {
+///     // e.g. write zeros to the memory-mapped accumulators of a DSP
+/// }
+///
+/// eraser::register_wipe(wipe_dsp_registers).unwrap();
+/// ```
+pub fn register_wipe(wipe: fn()) -> Result<(), EraserError> {
+    custom_wipe::register(wipe)
 }
 
-#[cfg(target_arch = "x86_64")]
+/// Like on 32-bit x86, the x87 registers are overwritten with zeros before
+/// `fninit` marks them as empty (which also leaves the MMX state empty, like
+/// `emms`), and the x87 control word is saved and restored.  `MXCSR` is the
+/// same on every platform by default, so it is simply reset to that, which
+/// clears the exception flags as well.
+#[cfg(all(target_arch = "x86_64", not(target_os = "none")))]
 unsafe fn wipe_all_registers() {
-    arch::asm!(
+    // Only wipe the register classes that the CPU has: `vzeroall` needs AVX,
+    // and it does not touch the registers that AVX-512 adds
+    if std::is_x86_feature_detected!("avx") {
+        wipe_avx_registers();
+    } else {
+        wipe_sse_registers();
+    }
+    if std::is_x86_feature_detected!("avx512f") {
+        wipe_avx512_registers();
+    }
+    if has_amx() {
+        wipe_amx_registers();
+    }
+    if has_apx() {
+        wipe_apx_registers();
+    }
+    core::arch::asm!(
         "xor rax, rax",
         "xor rcx, rcx",
         "xor rdx, rdx",
@@ -222,7 +2465,23 @@ unsafe fn wipe_all_registers() {
         "xor r13, r13",
         "xor r14, r14",
         "xor r15, r15",
-        "vzeroall",
+        // Zero the x87 (and MMX) registers, and reset `MXCSR`
+        "sub rsp, 8",
+        "fnstcw word ptr [rsp]",
+        "fninit",
+        "fldz",
+        "fldz",
+        "fldz",
+        "fldz",
+        "fldz",
+        "fldz",
+        "fldz",
+        "fldz",
+        "fninit",
+        "fldcw word ptr [rsp]",
+        "mov dword ptr [rsp], 0x1f80",
+        "ldmxcsr dword ptr [rsp]",
+        "add rsp, 8",
         lateout("rax") _,
         lateout("rcx") _,
         lateout("rdx") _,
@@ -236,6 +2495,37 @@ unsafe fn wipe_all_registers() {
         lateout("r13") _,
         lateout("r14") _,
         lateout("r15") _,
+        lateout("st(0)") _,
+        lateout("st(1)") _,
+        lateout("st(2)") _,
+        lateout("st(3)") _,
+        lateout("st(4)") _,
+        lateout("st(5)") _,
+        lateout("st(6)") _,
+        lateout("st(7)") _,
+    )
+}
+
+/// Wipe the vector registers `xmm0`–`xmm15` on CPUs without AVX.
+#[cfg(all(target_arch = "x86_64", not(target_os = "none")))]
+unsafe fn wipe_sse_registers() {
+    core::arch::asm!(
+        "xorps xmm0, xmm0",
+        "xorps xmm1, xmm1",
+        "xorps xmm2, xmm2",
+        "xorps xmm3, xmm3",
+        "xorps xmm4, xmm4",
+        "xorps xmm5, xmm5",
+        "xorps xmm6, xmm6",
+        "xorps xmm7, xmm7",
+        "xorps xmm8, xmm8",
+        "xorps xmm9, xmm9",
+        "xorps xmm10, xmm10",
+        "xorps xmm11, xmm11",
+        "xorps xmm12, xmm12",
+        "xorps xmm13, xmm13",
+        "xorps xmm14, xmm14",
+        "xorps xmm15, xmm15",
         lateout("xmm0") _,
         lateout("xmm1") _,
         lateout("xmm2") _,
@@ -252,16 +2542,1965 @@ unsafe fn wipe_all_registers() {
         lateout("xmm13") _,
         lateout("xmm14") _,
         lateout("xmm15") _,
+        options(nomem, nostack, preserves_flags),
+    )
+}
+
+/// Wipe the vector registers `ymm0`–`ymm15`, which `vzeroall` does at their
+/// full length, so including the upper halves of `zmm0`–`zmm15` on CPUs with
+/// AVX-512.
+#[cfg(all(target_arch = "x86_64", not(target_os = "none")))]
+#[target_feature(enable = "avx")]
+unsafe fn wipe_avx_registers() {
+    core::arch::asm!(
+        "vzeroall",
+        lateout("ymm0") _,
+        lateout("ymm1") _,
+        lateout("ymm2") _,
+        lateout("ymm3") _,
+        lateout("ymm4") _,
+        lateout("ymm5") _,
+        lateout("ymm6") _,
+        lateout("ymm7") _,
+        lateout("ymm8") _,
+        lateout("ymm9") _,
+        lateout("ymm10") _,
+        lateout("ymm11") _,
+        lateout("ymm12") _,
+        lateout("ymm13") _,
+        lateout("ymm14") _,
+        lateout("ymm15") _,
+        options(nomem, nostack, preserves_flags),
+    )
+}
+
+/// Wipe the vector registers `zmm16`–`zmm31` and the mask registers `k0`–`k7`
+/// that AVX-512 adds.
+///
+/// An EVEX-encoded instruction zeroes the upper bits of its destination, and so
+/// does a VEX-encoded mask instruction, so the registers are wiped at their
+/// full length.
+#[cfg(all(target_arch = "x86_64", not(target_os = "none")))]
+#[target_feature(enable = "avx512f")]
+unsafe fn wipe_avx512_registers() {
+    core::arch::asm!(
+        "vpxord xmm16, xmm16, xmm16",
+        "vpxord xmm17, xmm17, xmm17",
+        "vpxord xmm18, xmm18, xmm18",
+        "vpxord xmm19, xmm19, xmm19",
+        "vpxord xmm20, xmm20, xmm20",
+        "vpxord xmm21, xmm21, xmm21",
+        "vpxord xmm22, xmm22, xmm22",
+        "vpxord xmm23, xmm23, xmm23",
+        "vpxord xmm24, xmm24, xmm24",
+        "vpxord xmm25, xmm25, xmm25",
+        "vpxord xmm26, xmm26, xmm26",
+        "vpxord xmm27, xmm27, xmm27",
+        "vpxord xmm28, xmm28, xmm28",
+        "vpxord xmm29, xmm29, xmm29",
+        "vpxord xmm30, xmm30, xmm30",
+        "vpxord xmm31, xmm31, xmm31",
+        // `k0` cannot be declared as clobbered, because the compiler treats it
+        // as a constant zero, which is what it is set to here
+        "kxorw k0, k0, k0",
+        "kxorw k1, k1, k1",
+        "kxorw k2, k2, k2",
+        "kxorw k3, k3, k3",
+        "kxorw k4, k4, k4",
+        "kxorw k5, k5, k5",
+        "kxorw k6, k6, k6",
+        "kxorw k7, k7, k7",
+        lateout("zmm16") _,
+        lateout("zmm17") _,
+        lateout("zmm18") _,
+        lateout("zmm19") _,
+        lateout("zmm20") _,
+        lateout("zmm21") _,
+        lateout("zmm22") _,
+        lateout("zmm23") _,
+        lateout("zmm24") _,
+        lateout("zmm25") _,
+        lateout("zmm26") _,
+        lateout("zmm27") _,
+        lateout("zmm28") _,
+        lateout("zmm29") _,
+        lateout("zmm30") _,
+        lateout("zmm31") _,
+        lateout("k1") _,
+        lateout("k2") _,
+        lateout("k3") _,
+        lateout("k4") _,
+        lateout("k5") _,
+        lateout("k6") _,
+        lateout("k7") _,
+    )
+}
+
+/// `is_x86_feature_detected!` cannot detect AMX on stable Rust, so ask CPUID
+/// directly (leaf 7, EDX bit 24), together with whether the OS enabled `xgetbv`
+/// (leaf 1, ECX bit 27) and whether `xgetbv` can tell which state components
+/// are in use (leaf 13, subleaf 1, EAX bit 2), and cache the answer.
+#[cfg(all(target_arch = "x86_64", not(target_os = "none")))]
+fn has_amx() -> bool {
+    use core::arch::x86_64::{__cpuid, __cpuid_count};
+    static HAS_AMX: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+    *HAS_AMX.get_or_init(|| {
+        __cpuid(0).eax >= 13
+            && __cpuid(1).ecx & (1 << 27) != 0
+            && __cpuid_count(7, 0).edx & (1 << 24) != 0
+            && __cpuid_count(13, 1).eax & (1 << 2) != 0
+    })
+}
+
+/// Release the AMX tiles, which returns the tile data and the tile
+/// configuration to their initial state, i.e. zero.
+///
+/// The tiles are only released if they are in use (the `XINUSE` bits 17 and
+/// 18, which `xgetbv` reports for `ecx` = 1).  Linux only lets a process use
+/// the tiles after it has asked for permission, and `tilerelease` would fault
+/// before that.  The compiler never keeps values in the tiles by itself, so
+/// none of them has to be declared as clobbered.
+#[cfg(all(target_arch = "x86_64", not(target_os = "none")))]
+unsafe fn wipe_amx_registers() {
+    core::arch::asm!(
+        "mov ecx, 1",
+        "xgetbv",
+        "test eax, 0x60000",
+        "jz 2f",
+        "tilerelease",
+        "2:",
+        out("eax") _,
+        out("ecx") _,
+        out("edx") _,
+        options(nostack),
+    )
+}
+
+/// Like AMX, APX cannot be detected with `is_x86_feature_detected!` on stable
+/// Rust, so ask CPUID (leaf 7, subleaf 1, EDX bit 21) and whether the OS
+/// enabled the extended registers in `XCR0` (bit 19), and cache the answer.
+#[cfg(all(target_arch = "x86_64", not(target_os = "none")))]
+fn has_apx() -> bool {
+    use core::arch::x86_64::{__cpuid, __cpuid_count};
+    static HAS_APX: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+    *HAS_APX.get_or_init(|| {
+        if __cpuid(0).eax < 7
+            || __cpuid(1).ecx & (1 << 27) == 0
+            || __cpuid_count(7, 1).edx & (1 << 21) == 0
+        {
+            return false;
+        }
+        let xcr0: u32;
+        unsafe {
+            core::arch::asm!(
+                "xgetbv",
+                in("ecx") 0,
+                out("eax") xcr0,
+                out("edx") _,
+                options(nomem, nostack, preserves_flags),
+            )
+        };
+        xcr0 & (1 << 19) != 0
+    })
+}
+
+/// Wipe the extended general-purpose registers `r16`–`r31` that APX adds.
+///
+/// These registers cannot be declared as clobbered in `asm!`, but the calling
+/// conventions make all of them caller-saved, so this is a naked function with
+/// the C ABI: code that was compiled to use them (with the `apxf` target
+/// feature) does not expect them to survive the call.
+#[cfg(all(target_arch = "x86_64", not(target_os = "none")))]
+#[unsafe(naked)]
+unsafe extern "C" fn wipe_apx_registers() {
+    core::arch::naked_asm!(
+        "xor r16d, r16d",
+        "xor r17d, r17d",
+        "xor r18d, r18d",
+        "xor r19d, r19d",
+        "xor r20d, r20d",
+        "xor r21d, r21d",
+        "xor r22d, r22d",
+        "xor r23d, r23d",
+        "xor r24d, r24d",
+        "xor r25d, r25d",
+        "xor r26d, r26d",
+        "xor r27d, r27d",
+        "xor r28d, r28d",
+        "xor r29d, r29d",
+        "xor r30d, r30d",
+        "xor r31d, r31d",
+        "ret",
+    )
+}
+
+/// `esi` is reserved by the compiler and `ebp` is the frame pointer, so
+/// neither can be wiped here.  `fninit` only marks the x87 registers as empty,
+/// so they are overwritten with zeros first.  It also resets the x87 control
+/// word, which is not the same on every platform, so that is saved and
+/// restored.  `MXCSR` is reset to its default, on targets with SSE.
+#[cfg(all(target_arch = "x86", any(target_os = "linux", windows)))]
+unsafe fn wipe_all_registers() {
+    core::arch::asm!(
+        "xor eax, eax",
+        "xor ebx, ebx",
+        "xor ecx, ecx",
+        "xor edx, edx",
+        "xor edi, edi",
+        #[cfg(target_feature = "sse")]
+        "xorps xmm0, xmm0",
+        #[cfg(target_feature = "sse")]
+        "xorps xmm1, xmm1",
+        #[cfg(target_feature = "sse")]
+        "xorps xmm2, xmm2",
+        #[cfg(target_feature = "sse")]
+        "xorps xmm3, xmm3",
+        #[cfg(target_feature = "sse")]
+        "xorps xmm4, xmm4",
+        #[cfg(target_feature = "sse")]
+        "xorps xmm5, xmm5",
+        #[cfg(target_feature = "sse")]
+        "xorps xmm6, xmm6",
+        #[cfg(target_feature = "sse")]
+        "xorps xmm7, xmm7",
+        // Zero the x87 (and MMX) registers
+        "sub esp, 4",
+        "fnstcw word ptr [esp]",
+        "fninit",
+        "fldz",
+        "fldz",
+        "fldz",
+        "fldz",
+        "fldz",
+        "fldz",
+        "fldz",
+        "fldz",
+        "fninit",
+        "fldcw word ptr [esp]",
+        #[cfg(target_feature = "sse")]
+        "mov dword ptr [esp], 0x1f80",
+        #[cfg(target_feature = "sse")]
+        "ldmxcsr dword ptr [esp]",
+        "add esp, 4",
+        lateout("eax") _,
+        lateout("ebx") _,
+        lateout("ecx") _,
+        lateout("edx") _,
+        lateout("edi") _,
+        #[cfg(target_feature = "sse")]
+        lateout("xmm0") _,
+        #[cfg(target_feature = "sse")]
+        lateout("xmm1") _,
+        #[cfg(target_feature = "sse")]
+        lateout("xmm2") _,
+        #[cfg(target_feature = "sse")]
+        lateout("xmm3") _,
+        #[cfg(target_feature = "sse")]
+        lateout("xmm4") _,
+        #[cfg(target_feature = "sse")]
+        lateout("xmm5") _,
+        #[cfg(target_feature = "sse")]
+        lateout("xmm6") _,
+        #[cfg(target_feature = "sse")]
+        lateout("xmm7") _,
+        lateout("st(0)") _,
+        lateout("st(1)") _,
+        lateout("st(2)") _,
+        lateout("st(3)") _,
+        lateout("st(4)") _,
+        lateout("st(5)") _,
+        lateout("st(6)") _,
+        lateout("st(7)") _,
+    )
+}
+
+/// `x19` is reserved by the compiler and `x29` is the frame pointer, so
+/// neither can be wiped here.  On macOS and Windows, `x18` is reserved for the
+/// platform as well.
+#[cfg(all(
+    target_arch = "aarch64",
+    any(target_os = "linux", target_os = "macos", windows)
+))]
+unsafe fn wipe_all_registers() {
+    // The SVE registers overlap the NEON registers, so wipe them first
+    if std::arch::is_aarch64_feature_detected!("sve") {
+        wipe_sve_registers();
+    }
+    core::arch::asm!(
+        "mov x0, xzr",
+        "mov x1, xzr",
+        "mov x2, xzr",
+        "mov x3, xzr",
+        "mov x4, xzr",
+        "mov x5, xzr",
+        "mov x6, xzr",
+        "mov x7, xzr",
+        "mov x8, xzr",
+        "mov x9, xzr",
+        "mov x10, xzr",
+        "mov x11, xzr",
+        "mov x12, xzr",
+        "mov x13, xzr",
+        "mov x14, xzr",
+        "mov x15, xzr",
+        "mov x16, xzr",
+        "mov x17, xzr",
+        #[cfg(target_os = "linux")]
+        "mov x18, xzr",
+        "mov x20, xzr",
+        "mov x21, xzr",
+        "mov x22, xzr",
+        "mov x23, xzr",
+        "mov x24, xzr",
+        "mov x25, xzr",
+        "mov x26, xzr",
+        "mov x27, xzr",
+        "mov x28, xzr",
+        "mov x30, xzr",
+        "movi v0.2d, #0",
+        "movi v1.2d, #0",
+        "movi v2.2d, #0",
+        "movi v3.2d, #0",
+        "movi v4.2d, #0",
+        "movi v5.2d, #0",
+        "movi v6.2d, #0",
+        "movi v7.2d, #0",
+        "movi v8.2d, #0",
+        "movi v9.2d, #0",
+        "movi v10.2d, #0",
+        "movi v11.2d, #0",
+        "movi v12.2d, #0",
+        "movi v13.2d, #0",
+        "movi v14.2d, #0",
+        "movi v15.2d, #0",
+        "movi v16.2d, #0",
+        "movi v17.2d, #0",
+        "movi v18.2d, #0",
+        "movi v19.2d, #0",
+        "movi v20.2d, #0",
+        "movi v21.2d, #0",
+        "movi v22.2d, #0",
+        "movi v23.2d, #0",
+        "movi v24.2d, #0",
+        "movi v25.2d, #0",
+        "movi v26.2d, #0",
+        "movi v27.2d, #0",
+        "movi v28.2d, #0",
+        "movi v29.2d, #0",
+        "movi v30.2d, #0",
+        "movi v31.2d, #0",
+        // Reset the floating-point status (the cumulative exception flags)
+        // and control registers, which Rust expects at their default of zero
+        "msr fpsr, xzr",
+        "msr fpcr, xzr",
+        lateout("x0") _,
+        lateout("x1") _,
+        lateout("x2") _,
+        lateout("x3") _,
+        lateout("x4") _,
+        lateout("x5") _,
+        lateout("x6") _,
+        lateout("x7") _,
+        lateout("x8") _,
+        lateout("x9") _,
+        lateout("x10") _,
+        lateout("x11") _,
+        lateout("x12") _,
+        lateout("x13") _,
+        lateout("x14") _,
+        lateout("x15") _,
+        lateout("x16") _,
+        lateout("x17") _,
+        #[cfg(target_os = "linux")]
+        lateout("x18") _,
+        lateout("x20") _,
+        lateout("x21") _,
+        lateout("x22") _,
+        lateout("x23") _,
+        lateout("x24") _,
+        lateout("x25") _,
+        lateout("x26") _,
+        lateout("x27") _,
+        lateout("x28") _,
+        lateout("x30") _,
+        lateout("v0") _,
+        lateout("v1") _,
+        lateout("v2") _,
+        lateout("v3") _,
+        lateout("v4") _,
+        lateout("v5") _,
+        lateout("v6") _,
+        lateout("v7") _,
+        lateout("v8") _,
+        lateout("v9") _,
+        lateout("v10") _,
+        lateout("v11") _,
+        lateout("v12") _,
+        lateout("v13") _,
+        lateout("v14") _,
+        lateout("v15") _,
+        lateout("v16") _,
+        lateout("v17") _,
+        lateout("v18") _,
+        lateout("v19") _,
+        lateout("v20") _,
+        lateout("v21") _,
+        lateout("v22") _,
+        lateout("v23") _,
+        lateout("v24") _,
+        lateout("v25") _,
+        lateout("v26") _,
+        lateout("v27") _,
+        lateout("v28") _,
+        lateout("v29") _,
+        lateout("v30") _,
+        lateout("v31") _,
+    )
+}
+
+/// Wipe the scalable vector registers `z0`–`z31`, the predicate registers
+/// `p0`–`p15` and the first-fault register, at whatever vector length the CPU
+/// runs at.
+#[cfg(all(
+    target_arch = "aarch64",
+    any(target_os = "linux", target_os = "macos", windows)
+))]
+#[target_feature(enable = "sve")]
+unsafe fn wipe_sve_registers() {
+    core::arch::asm!(
+        "mov z0.d, #0",
+        "mov z1.d, #0",
+        "mov z2.d, #0",
+        "mov z3.d, #0",
+        "mov z4.d, #0",
+        "mov z5.d, #0",
+        "mov z6.d, #0",
+        "mov z7.d, #0",
+        "mov z8.d, #0",
+        "mov z9.d, #0",
+        "mov z10.d, #0",
+        "mov z11.d, #0",
+        "mov z12.d, #0",
+        "mov z13.d, #0",
+        "mov z14.d, #0",
+        "mov z15.d, #0",
+        "mov z16.d, #0",
+        "mov z17.d, #0",
+        "mov z18.d, #0",
+        "mov z19.d, #0",
+        "mov z20.d, #0",
+        "mov z21.d, #0",
+        "mov z22.d, #0",
+        "mov z23.d, #0",
+        "mov z24.d, #0",
+        "mov z25.d, #0",
+        "mov z26.d, #0",
+        "mov z27.d, #0",
+        "mov z28.d, #0",
+        "mov z29.d, #0",
+        "mov z30.d, #0",
+        "mov z31.d, #0",
+        "pfalse p0.b",
+        "pfalse p1.b",
+        "pfalse p2.b",
+        "pfalse p3.b",
+        "pfalse p4.b",
+        "pfalse p5.b",
+        "pfalse p6.b",
+        "pfalse p7.b",
+        "pfalse p8.b",
+        "pfalse p9.b",
+        "pfalse p10.b",
+        "pfalse p11.b",
+        "pfalse p12.b",
+        "pfalse p13.b",
+        "pfalse p14.b",
+        "pfalse p15.b",
+        // The first-fault register cannot be declared as clobbered, but the
+        // compiler never keeps a value in it across a function call
+        "wrffr p0.b",
+        lateout("v0") _,
+        lateout("v1") _,
+        lateout("v2") _,
+        lateout("v3") _,
+        lateout("v4") _,
+        lateout("v5") _,
+        lateout("v6") _,
+        lateout("v7") _,
+        lateout("v8") _,
+        lateout("v9") _,
+        lateout("v10") _,
+        lateout("v11") _,
+        lateout("v12") _,
+        lateout("v13") _,
+        lateout("v14") _,
+        lateout("v15") _,
+        lateout("v16") _,
+        lateout("v17") _,
+        lateout("v18") _,
+        lateout("v19") _,
+        lateout("v20") _,
+        lateout("v21") _,
+        lateout("v22") _,
+        lateout("v23") _,
+        lateout("v24") _,
+        lateout("v25") _,
+        lateout("v26") _,
+        lateout("v27") _,
+        lateout("v28") _,
+        lateout("v29") _,
+        lateout("v30") _,
+        lateout("v31") _,
+        lateout("p0") _,
+        lateout("p1") _,
+        lateout("p2") _,
+        lateout("p3") _,
+        lateout("p4") _,
+        lateout("p5") _,
+        lateout("p6") _,
+        lateout("p7") _,
+        lateout("p8") _,
+        lateout("p9") _,
+        lateout("p10") _,
+        lateout("p11") _,
+        lateout("p12") _,
+        lateout("p13") _,
+        lateout("p14") _,
+        lateout("p15") _,
     )
 }
 
-#[cfg(not(target_arch = "x86_64"))]
+/// `r6` is reserved by the compiler, and the frame pointer (`r11` in ARM mode
+/// and `r7` in Thumb mode) cannot be wiped either.  The VFP registers are
+/// cleared from the (zeroed) `r0`, which also works without NEON.
+#[cfg(all(target_arch = "arm", target_os = "linux"))]
+unsafe fn wipe_all_registers() {
+    core::arch::asm!(
+        "mov r0, #0",
+        "mov r1, #0",
+        "mov r2, #0",
+        "mov r3, #0",
+        "mov r4, #0",
+        "mov r5, #0",
+        #[cfg(not(target_feature = "thumb-mode"))]
+        "mov r7, #0",
+        "mov r8, #0",
+        "mov r9, #0",
+        "mov r10, #0",
+        #[cfg(target_feature = "thumb-mode")]
+        "mov r11, #0",
+        "mov r12, #0",
+        "mov lr, #0",
+        "vmov d0, r0, r0",
+        "vmov d1, r0, r0",
+        "vmov d2, r0, r0",
+        "vmov d3, r0, r0",
+        "vmov d4, r0, r0",
+        "vmov d5, r0, r0",
+        "vmov d6, r0, r0",
+        "vmov d7, r0, r0",
+        "vmov d8, r0, r0",
+        "vmov d9, r0, r0",
+        "vmov d10, r0, r0",
+        "vmov d11, r0, r0",
+        "vmov d12, r0, r0",
+        "vmov d13, r0, r0",
+        "vmov d14, r0, r0",
+        "vmov d15, r0, r0",
+        #[cfg(target_feature = "d32")]
+        "vmov d16, r0, r0",
+        #[cfg(target_feature = "d32")]
+        "vmov d17, r0, r0",
+        #[cfg(target_feature = "d32")]
+        "vmov d18, r0, r0",
+        #[cfg(target_feature = "d32")]
+        "vmov d19, r0, r0",
+        #[cfg(target_feature = "d32")]
+        "vmov d20, r0, r0",
+        #[cfg(target_feature = "d32")]
+        "vmov d21, r0, r0",
+        #[cfg(target_feature = "d32")]
+        "vmov d22, r0, r0",
+        #[cfg(target_feature = "d32")]
+        "vmov d23, r0, r0",
+        #[cfg(target_feature = "d32")]
+        "vmov d24, r0, r0",
+        #[cfg(target_feature = "d32")]
+        "vmov d25, r0, r0",
+        #[cfg(target_feature = "d32")]
+        "vmov d26, r0, r0",
+        #[cfg(target_feature = "d32")]
+        "vmov d27, r0, r0",
+        #[cfg(target_feature = "d32")]
+        "vmov d28, r0, r0",
+        #[cfg(target_feature = "d32")]
+        "vmov d29, r0, r0",
+        #[cfg(target_feature = "d32")]
+        "vmov d30, r0, r0",
+        #[cfg(target_feature = "d32")]
+        "vmov d31, r0, r0",
+        // Reset the floating-point status and control register
+        "vmsr fpscr, r0",
+        lateout("r0") _,
+        lateout("r1") _,
+        lateout("r2") _,
+        lateout("r3") _,
+        lateout("r4") _,
+        lateout("r5") _,
+        #[cfg(not(target_feature = "thumb-mode"))]
+        lateout("r7") _,
+        lateout("r8") _,
+        lateout("r9") _,
+        lateout("r10") _,
+        #[cfg(target_feature = "thumb-mode")]
+        lateout("r11") _,
+        lateout("r12") _,
+        lateout("lr") _,
+        lateout("d0") _,
+        lateout("d1") _,
+        lateout("d2") _,
+        lateout("d3") _,
+        lateout("d4") _,
+        lateout("d5") _,
+        lateout("d6") _,
+        lateout("d7") _,
+        lateout("d8") _,
+        lateout("d9") _,
+        lateout("d10") _,
+        lateout("d11") _,
+        lateout("d12") _,
+        lateout("d13") _,
+        lateout("d14") _,
+        lateout("d15") _,
+        #[cfg(target_feature = "d32")]
+        lateout("d16") _,
+        #[cfg(target_feature = "d32")]
+        lateout("d17") _,
+        #[cfg(target_feature = "d32")]
+        lateout("d18") _,
+        #[cfg(target_feature = "d32")]
+        lateout("d19") _,
+        #[cfg(target_feature = "d32")]
+        lateout("d20") _,
+        #[cfg(target_feature = "d32")]
+        lateout("d21") _,
+        #[cfg(target_feature = "d32")]
+        lateout("d22") _,
+        #[cfg(target_feature = "d32")]
+        lateout("d23") _,
+        #[cfg(target_feature = "d32")]
+        lateout("d24") _,
+        #[cfg(target_feature = "d32")]
+        lateout("d25") _,
+        #[cfg(target_feature = "d32")]
+        lateout("d26") _,
+        #[cfg(target_feature = "d32")]
+        lateout("d27") _,
+        #[cfg(target_feature = "d32")]
+        lateout("d28") _,
+        #[cfg(target_feature = "d32")]
+        lateout("d29") _,
+        #[cfg(target_feature = "d32")]
+        lateout("d30") _,
+        #[cfg(target_feature = "d32")]
+        lateout("d31") _,
+    )
+}
+
+/// `s0` is the frame pointer and `s1` is reserved by the compiler, so neither
+/// can be wiped here, and neither can `gp` and `tp`, which belong to the
+/// platform.
+#[cfg(all(target_arch = "riscv64", target_os = "linux"))]
+unsafe fn wipe_all_registers() {
+    if sys::has_vector() {
+        wipe_vector_registers();
+    }
+    core::arch::asm!(
+        "mv ra, zero",
+        "mv t0, zero",
+        "mv t1, zero",
+        "mv t2, zero",
+        "mv t3, zero",
+        "mv t4, zero",
+        "mv t5, zero",
+        "mv t6, zero",
+        "mv a0, zero",
+        "mv a1, zero",
+        "mv a2, zero",
+        "mv a3, zero",
+        "mv a4, zero",
+        "mv a5, zero",
+        "mv a6, zero",
+        "mv a7, zero",
+        "mv s2, zero",
+        "mv s3, zero",
+        "mv s4, zero",
+        "mv s5, zero",
+        "mv s6, zero",
+        "mv s7, zero",
+        "mv s8, zero",
+        "mv s9, zero",
+        "mv s10, zero",
+        "mv s11, zero",
+        "fmv.d.x f0, zero",
+        "fmv.d.x f1, zero",
+        "fmv.d.x f2, zero",
+        "fmv.d.x f3, zero",
+        "fmv.d.x f4, zero",
+        "fmv.d.x f5, zero",
+        "fmv.d.x f6, zero",
+        "fmv.d.x f7, zero",
+        "fmv.d.x f8, zero",
+        "fmv.d.x f9, zero",
+        "fmv.d.x f10, zero",
+        "fmv.d.x f11, zero",
+        "fmv.d.x f12, zero",
+        "fmv.d.x f13, zero",
+        "fmv.d.x f14, zero",
+        "fmv.d.x f15, zero",
+        "fmv.d.x f16, zero",
+        "fmv.d.x f17, zero",
+        "fmv.d.x f18, zero",
+        "fmv.d.x f19, zero",
+        "fmv.d.x f20, zero",
+        "fmv.d.x f21, zero",
+        "fmv.d.x f22, zero",
+        "fmv.d.x f23, zero",
+        "fmv.d.x f24, zero",
+        "fmv.d.x f25, zero",
+        "fmv.d.x f26, zero",
+        "fmv.d.x f27, zero",
+        "fmv.d.x f28, zero",
+        "fmv.d.x f29, zero",
+        "fmv.d.x f30, zero",
+        "fmv.d.x f31, zero",
+        // Reset the floating-point control and status register
+        "csrw fcsr, zero",
+        lateout("ra") _,
+        lateout("t0") _,
+        lateout("t1") _,
+        lateout("t2") _,
+        lateout("t3") _,
+        lateout("t4") _,
+        lateout("t5") _,
+        lateout("t6") _,
+        lateout("a0") _,
+        lateout("a1") _,
+        lateout("a2") _,
+        lateout("a3") _,
+        lateout("a4") _,
+        lateout("a5") _,
+        lateout("a6") _,
+        lateout("a7") _,
+        lateout("s2") _,
+        lateout("s3") _,
+        lateout("s4") _,
+        lateout("s5") _,
+        lateout("s6") _,
+        lateout("s7") _,
+        lateout("s8") _,
+        lateout("s9") _,
+        lateout("s10") _,
+        lateout("s11") _,
+        lateout("f0") _,
+        lateout("f1") _,
+        lateout("f2") _,
+        lateout("f3") _,
+        lateout("f4") _,
+        lateout("f5") _,
+        lateout("f6") _,
+        lateout("f7") _,
+        lateout("f8") _,
+        lateout("f9") _,
+        lateout("f10") _,
+        lateout("f11") _,
+        lateout("f12") _,
+        lateout("f13") _,
+        lateout("f14") _,
+        lateout("f15") _,
+        lateout("f16") _,
+        lateout("f17") _,
+        lateout("f18") _,
+        lateout("f19") _,
+        lateout("f20") _,
+        lateout("f21") _,
+        lateout("f22") _,
+        lateout("f23") _,
+        lateout("f24") _,
+        lateout("f25") _,
+        lateout("f26") _,
+        lateout("f27") _,
+        lateout("f28") _,
+        lateout("f29") _,
+        lateout("f30") _,
+        lateout("f31") _,
+    )
+}
+
+/// Wipe the vector registers `v0`–`v31`, at whatever vector length the CPU
+/// has, and reset `vl`, `vtype` and the fixed-point state in `vcsr`.
+///
+/// Enabling the `v` target feature for a single function is not stable yet, so
+/// the vector instructions are enabled for the assembler instead.  The vector
+/// registers only have to be declared as clobbered when the compiler may use
+/// them itself.  This function is never inlined, so that the compiler does not
+/// expect `vl` and `vtype` to survive it.
+#[cfg(all(target_arch = "riscv64", target_os = "linux"))]
+#[inline(never)]
+unsafe fn wipe_vector_registers() {
+    core::arch::asm!(
+        ".option push",
+        ".option arch, +v",
+        // Use all the registers of each group of eight, at the maximum length
+        "vsetvli t0, zero, e8, m8, ta, ma",
+        "vmv.v.i v0, 0",
+        "vmv.v.i v8, 0",
+        "vmv.v.i v16, 0",
+        "vmv.v.i v24, 0",
+        // Leave an empty vector length and a fixed vector type behind
+        "vsetivli zero, 0, e8, m1, ta, ma",
+        "csrw vcsr, zero",
+        ".option pop",
+        lateout("t0") _,
+        #[cfg(target_feature = "v")]
+        lateout("v0") _,
+        #[cfg(target_feature = "v")]
+        lateout("v1") _,
+        #[cfg(target_feature = "v")]
+        lateout("v2") _,
+        #[cfg(target_feature = "v")]
+        lateout("v3") _,
+        #[cfg(target_feature = "v")]
+        lateout("v4") _,
+        #[cfg(target_feature = "v")]
+        lateout("v5") _,
+        #[cfg(target_feature = "v")]
+        lateout("v6") _,
+        #[cfg(target_feature = "v")]
+        lateout("v7") _,
+        #[cfg(target_feature = "v")]
+        lateout("v8") _,
+        #[cfg(target_feature = "v")]
+        lateout("v9") _,
+        #[cfg(target_feature = "v")]
+        lateout("v10") _,
+        #[cfg(target_feature = "v")]
+        lateout("v11") _,
+        #[cfg(target_feature = "v")]
+        lateout("v12") _,
+        #[cfg(target_feature = "v")]
+        lateout("v13") _,
+        #[cfg(target_feature = "v")]
+        lateout("v14") _,
+        #[cfg(target_feature = "v")]
+        lateout("v15") _,
+        #[cfg(target_feature = "v")]
+        lateout("v16") _,
+        #[cfg(target_feature = "v")]
+        lateout("v17") _,
+        #[cfg(target_feature = "v")]
+        lateout("v18") _,
+        #[cfg(target_feature = "v")]
+        lateout("v19") _,
+        #[cfg(target_feature = "v")]
+        lateout("v20") _,
+        #[cfg(target_feature = "v")]
+        lateout("v21") _,
+        #[cfg(target_feature = "v")]
+        lateout("v22") _,
+        #[cfg(target_feature = "v")]
+        lateout("v23") _,
+        #[cfg(target_feature = "v")]
+        lateout("v24") _,
+        #[cfg(target_feature = "v")]
+        lateout("v25") _,
+        #[cfg(target_feature = "v")]
+        lateout("v26") _,
+        #[cfg(target_feature = "v")]
+        lateout("v27") _,
+        #[cfg(target_feature = "v")]
+        lateout("v28") _,
+        #[cfg(target_feature = "v")]
+        lateout("v29") _,
+        #[cfg(target_feature = "v")]
+        lateout("v30") _,
+        #[cfg(target_feature = "v")]
+        lateout("v31") _,
+    )
+}
+
+/// `r1` is the stack pointer, `r2` the TOC pointer and `r13` the thread
+/// pointer, and `r30` and `r31` are reserved by the compiler, so none of these
+/// can be wiped here.  The ELFv2 ABI requires POWER8, so VSX is always there:
+/// `vs0`–`vs63` cover both the floating-point registers (`f0`–`f31`) and the
+/// vector registers (`v0`–`v31`).  `VSCR` is left alone, because its
+/// non-Java mode bit is set by the kernel.
+#[cfg(all(
+    target_arch = "powerpc64",
+    target_endian = "little",
+    target_os = "linux"
+))]
+unsafe fn wipe_all_registers() {
+    core::arch::asm!(
+        "li 0, 0",
+        "li 3, 0",
+        "li 4, 0",
+        "li 5, 0",
+        "li 6, 0",
+        "li 7, 0",
+        "li 8, 0",
+        "li 9, 0",
+        "li 10, 0",
+        "li 11, 0",
+        "li 12, 0",
+        "li 14, 0",
+        "li 15, 0",
+        "li 16, 0",
+        "li 17, 0",
+        "li 18, 0",
+        "li 19, 0",
+        "li 20, 0",
+        "li 21, 0",
+        "li 22, 0",
+        "li 23, 0",
+        "li 24, 0",
+        "li 25, 0",
+        "li 26, 0",
+        "li 27, 0",
+        "li 28, 0",
+        "li 29, 0",
+        "mtctr 0",
+        "mtxer 0",
+        "xxlxor 0, 0, 0",
+        "xxlxor 1, 1, 1",
+        "xxlxor 2, 2, 2",
+        "xxlxor 3, 3, 3",
+        "xxlxor 4, 4, 4",
+        "xxlxor 5, 5, 5",
+        "xxlxor 6, 6, 6",
+        "xxlxor 7, 7, 7",
+        "xxlxor 8, 8, 8",
+        "xxlxor 9, 9, 9",
+        "xxlxor 10, 10, 10",
+        "xxlxor 11, 11, 11",
+        "xxlxor 12, 12, 12",
+        "xxlxor 13, 13, 13",
+        "xxlxor 14, 14, 14",
+        "xxlxor 15, 15, 15",
+        "xxlxor 16, 16, 16",
+        "xxlxor 17, 17, 17",
+        "xxlxor 18, 18, 18",
+        "xxlxor 19, 19, 19",
+        "xxlxor 20, 20, 20",
+        "xxlxor 21, 21, 21",
+        "xxlxor 22, 22, 22",
+        "xxlxor 23, 23, 23",
+        "xxlxor 24, 24, 24",
+        "xxlxor 25, 25, 25",
+        "xxlxor 26, 26, 26",
+        "xxlxor 27, 27, 27",
+        "xxlxor 28, 28, 28",
+        "xxlxor 29, 29, 29",
+        "xxlxor 30, 30, 30",
+        "xxlxor 31, 31, 31",
+        "xxlxor 32, 32, 32",
+        "xxlxor 33, 33, 33",
+        "xxlxor 34, 34, 34",
+        "xxlxor 35, 35, 35",
+        "xxlxor 36, 36, 36",
+        "xxlxor 37, 37, 37",
+        "xxlxor 38, 38, 38",
+        "xxlxor 39, 39, 39",
+        "xxlxor 40, 40, 40",
+        "xxlxor 41, 41, 41",
+        "xxlxor 42, 42, 42",
+        "xxlxor 43, 43, 43",
+        "xxlxor 44, 44, 44",
+        "xxlxor 45, 45, 45",
+        "xxlxor 46, 46, 46",
+        "xxlxor 47, 47, 47",
+        "xxlxor 48, 48, 48",
+        "xxlxor 49, 49, 49",
+        "xxlxor 50, 50, 50",
+        "xxlxor 51, 51, 51",
+        "xxlxor 52, 52, 52",
+        "xxlxor 53, 53, 53",
+        "xxlxor 54, 54, 54",
+        "xxlxor 55, 55, 55",
+        "xxlxor 56, 56, 56",
+        "xxlxor 57, 57, 57",
+        "xxlxor 58, 58, 58",
+        "xxlxor 59, 59, 59",
+        "xxlxor 60, 60, 60",
+        "xxlxor 61, 61, 61",
+        "xxlxor 62, 62, 62",
+        "xxlxor 63, 63, 63",
+        // Reset the floating-point status and control register
+        "mtfsf 0xff, 0",
+        lateout("r0") _,
+        lateout("r3") _,
+        lateout("r4") _,
+        lateout("r5") _,
+        lateout("r6") _,
+        lateout("r7") _,
+        lateout("r8") _,
+        lateout("r9") _,
+        lateout("r10") _,
+        lateout("r11") _,
+        lateout("r12") _,
+        lateout("r14") _,
+        lateout("r15") _,
+        lateout("r16") _,
+        lateout("r17") _,
+        lateout("r18") _,
+        lateout("r19") _,
+        lateout("r20") _,
+        lateout("r21") _,
+        lateout("r22") _,
+        lateout("r23") _,
+        lateout("r24") _,
+        lateout("r25") _,
+        lateout("r26") _,
+        lateout("r27") _,
+        lateout("r28") _,
+        lateout("r29") _,
+        lateout("ctr") _,
+        lateout("xer") _,
+        lateout("vs0") _,
+        lateout("vs1") _,
+        lateout("vs2") _,
+        lateout("vs3") _,
+        lateout("vs4") _,
+        lateout("vs5") _,
+        lateout("vs6") _,
+        lateout("vs7") _,
+        lateout("vs8") _,
+        lateout("vs9") _,
+        lateout("vs10") _,
+        lateout("vs11") _,
+        lateout("vs12") _,
+        lateout("vs13") _,
+        lateout("vs14") _,
+        lateout("vs15") _,
+        lateout("vs16") _,
+        lateout("vs17") _,
+        lateout("vs18") _,
+        lateout("vs19") _,
+        lateout("vs20") _,
+        lateout("vs21") _,
+        lateout("vs22") _,
+        lateout("vs23") _,
+        lateout("vs24") _,
+        lateout("vs25") _,
+        lateout("vs26") _,
+        lateout("vs27") _,
+        lateout("vs28") _,
+        lateout("vs29") _,
+        lateout("vs30") _,
+        lateout("vs31") _,
+        lateout("vs32") _,
+        lateout("vs33") _,
+        lateout("vs34") _,
+        lateout("vs35") _,
+        lateout("vs36") _,
+        lateout("vs37") _,
+        lateout("vs38") _,
+        lateout("vs39") _,
+        lateout("vs40") _,
+        lateout("vs41") _,
+        lateout("vs42") _,
+        lateout("vs43") _,
+        lateout("vs44") _,
+        lateout("vs45") _,
+        lateout("vs46") _,
+        lateout("vs47") _,
+        lateout("vs48") _,
+        lateout("vs49") _,
+        lateout("vs50") _,
+        lateout("vs51") _,
+        lateout("vs52") _,
+        lateout("vs53") _,
+        lateout("vs54") _,
+        lateout("vs55") _,
+        lateout("vs56") _,
+        lateout("vs57") _,
+        lateout("vs58") _,
+        lateout("vs59") _,
+        lateout("vs60") _,
+        lateout("vs61") _,
+        lateout("vs62") _,
+        lateout("vs63") _,
+    )
+}
+
+/// `r11` is the frame pointer and `r15` the stack pointer, so neither can be
+/// wiped here, and neither can the access registers, which hold the thread
+/// pointer.  The vector registers are only wiped when the target has the
+/// `vector` feature (z13 and later), because the compiler does not use them
+/// otherwise.
+#[cfg(all(target_arch = "s390x", target_os = "linux"))]
+unsafe fn wipe_all_registers() {
+    core::arch::asm!(
+        "lghi %r0, 0",
+        "lghi %r1, 0",
+        "lghi %r2, 0",
+        "lghi %r3, 0",
+        "lghi %r4, 0",
+        "lghi %r5, 0",
+        "lghi %r6, 0",
+        "lghi %r7, 0",
+        "lghi %r8, 0",
+        "lghi %r9, 0",
+        "lghi %r10, 0",
+        "lghi %r12, 0",
+        "lghi %r13, 0",
+        "lghi %r14, 0",
+        "lzdr %f0",
+        "lzdr %f1",
+        "lzdr %f2",
+        "lzdr %f3",
+        "lzdr %f4",
+        "lzdr %f5",
+        "lzdr %f6",
+        "lzdr %f7",
+        "lzdr %f8",
+        "lzdr %f9",
+        "lzdr %f10",
+        "lzdr %f11",
+        "lzdr %f12",
+        "lzdr %f13",
+        "lzdr %f14",
+        "lzdr %f15",
+        // Reset the floating-point control register
+        "sfpc %r0",
+        lateout("r0") _,
+        lateout("r1") _,
+        lateout("r2") _,
+        lateout("r3") _,
+        lateout("r4") _,
+        lateout("r5") _,
+        lateout("r6") _,
+        lateout("r7") _,
+        lateout("r8") _,
+        lateout("r9") _,
+        lateout("r10") _,
+        lateout("r12") _,
+        lateout("r13") _,
+        lateout("r14") _,
+        lateout("f0") _,
+        lateout("f1") _,
+        lateout("f2") _,
+        lateout("f3") _,
+        lateout("f4") _,
+        lateout("f5") _,
+        lateout("f6") _,
+        lateout("f7") _,
+        lateout("f8") _,
+        lateout("f9") _,
+        lateout("f10") _,
+        lateout("f11") _,
+        lateout("f12") _,
+        lateout("f13") _,
+        lateout("f14") _,
+        lateout("f15") _,
+    );
+    #[cfg(target_feature = "vector")]
+    core::arch::asm!(
+        "vzero %v0",
+        "vzero %v1",
+        "vzero %v2",
+        "vzero %v3",
+        "vzero %v4",
+        "vzero %v5",
+        "vzero %v6",
+        "vzero %v7",
+        "vzero %v8",
+        "vzero %v9",
+        "vzero %v10",
+        "vzero %v11",
+        "vzero %v12",
+        "vzero %v13",
+        "vzero %v14",
+        "vzero %v15",
+        "vzero %v16",
+        "vzero %v17",
+        "vzero %v18",
+        "vzero %v19",
+        "vzero %v20",
+        "vzero %v21",
+        "vzero %v22",
+        "vzero %v23",
+        "vzero %v24",
+        "vzero %v25",
+        "vzero %v26",
+        "vzero %v27",
+        "vzero %v28",
+        "vzero %v29",
+        "vzero %v30",
+        "vzero %v31",
+        lateout("v0") _,
+        lateout("v1") _,
+        lateout("v2") _,
+        lateout("v3") _,
+        lateout("v4") _,
+        lateout("v5") _,
+        lateout("v6") _,
+        lateout("v7") _,
+        lateout("v8") _,
+        lateout("v9") _,
+        lateout("v10") _,
+        lateout("v11") _,
+        lateout("v12") _,
+        lateout("v13") _,
+        lateout("v14") _,
+        lateout("v15") _,
+        lateout("v16") _,
+        lateout("v17") _,
+        lateout("v18") _,
+        lateout("v19") _,
+        lateout("v20") _,
+        lateout("v21") _,
+        lateout("v22") _,
+        lateout("v23") _,
+        lateout("v24") _,
+        lateout("v25") _,
+        lateout("v26") _,
+        lateout("v27") _,
+        lateout("v28") _,
+        lateout("v29") _,
+        lateout("v30") _,
+        lateout("v31") _,
+    );
+}
+
+/// `$tp` is the thread pointer, `$r21` is reserved by the ABI, `$fp` is the
+/// frame pointer and `$s8` is reserved by the compiler, so none of these can
+/// be wiped here.  The vector registers extend the floating-point registers,
+/// so the compiler does not keep anything in them across the asm that wipes
+/// them, even though they cannot be named as clobbers themselves.  They are
+/// wiped at their full length when the target has the `lasx` feature, and
+/// their low 128 bits when it has `lsx`.
+#[cfg(all(target_arch = "loongarch64", target_os = "linux"))]
+unsafe fn wipe_all_registers() {
+    core::arch::asm!(
+        "move $ra, $zero",
+        "move $a0, $zero",
+        "move $a1, $zero",
+        "move $a2, $zero",
+        "move $a3, $zero",
+        "move $a4, $zero",
+        "move $a5, $zero",
+        "move $a6, $zero",
+        "move $a7, $zero",
+        "move $t0, $zero",
+        "move $t1, $zero",
+        "move $t2, $zero",
+        "move $t3, $zero",
+        "move $t4, $zero",
+        "move $t5, $zero",
+        "move $t6, $zero",
+        "move $t7, $zero",
+        "move $t8, $zero",
+        "move $s0, $zero",
+        "move $s1, $zero",
+        "move $s2, $zero",
+        "move $s3, $zero",
+        "move $s4, $zero",
+        "move $s5, $zero",
+        "move $s6, $zero",
+        "move $s7, $zero",
+        "movgr2fr.d $f0, $zero",
+        "movgr2fr.d $f1, $zero",
+        "movgr2fr.d $f2, $zero",
+        "movgr2fr.d $f3, $zero",
+        "movgr2fr.d $f4, $zero",
+        "movgr2fr.d $f5, $zero",
+        "movgr2fr.d $f6, $zero",
+        "movgr2fr.d $f7, $zero",
+        "movgr2fr.d $f8, $zero",
+        "movgr2fr.d $f9, $zero",
+        "movgr2fr.d $f10, $zero",
+        "movgr2fr.d $f11, $zero",
+        "movgr2fr.d $f12, $zero",
+        "movgr2fr.d $f13, $zero",
+        "movgr2fr.d $f14, $zero",
+        "movgr2fr.d $f15, $zero",
+        "movgr2fr.d $f16, $zero",
+        "movgr2fr.d $f17, $zero",
+        "movgr2fr.d $f18, $zero",
+        "movgr2fr.d $f19, $zero",
+        "movgr2fr.d $f20, $zero",
+        "movgr2fr.d $f21, $zero",
+        "movgr2fr.d $f22, $zero",
+        "movgr2fr.d $f23, $zero",
+        "movgr2fr.d $f24, $zero",
+        "movgr2fr.d $f25, $zero",
+        "movgr2fr.d $f26, $zero",
+        "movgr2fr.d $f27, $zero",
+        "movgr2fr.d $f28, $zero",
+        "movgr2fr.d $f29, $zero",
+        "movgr2fr.d $f30, $zero",
+        "movgr2fr.d $f31, $zero",
+        // Reset the floating-point control and status register
+        "movgr2fcsr $fcsr0, $zero",
+        lateout("$ra") _,
+        lateout("$a0") _,
+        lateout("$a1") _,
+        lateout("$a2") _,
+        lateout("$a3") _,
+        lateout("$a4") _,
+        lateout("$a5") _,
+        lateout("$a6") _,
+        lateout("$a7") _,
+        lateout("$t0") _,
+        lateout("$t1") _,
+        lateout("$t2") _,
+        lateout("$t3") _,
+        lateout("$t4") _,
+        lateout("$t5") _,
+        lateout("$t6") _,
+        lateout("$t7") _,
+        lateout("$t8") _,
+        lateout("$s0") _,
+        lateout("$s1") _,
+        lateout("$s2") _,
+        lateout("$s3") _,
+        lateout("$s4") _,
+        lateout("$s5") _,
+        lateout("$s6") _,
+        lateout("$s7") _,
+        lateout("$f0") _,
+        lateout("$f1") _,
+        lateout("$f2") _,
+        lateout("$f3") _,
+        lateout("$f4") _,
+        lateout("$f5") _,
+        lateout("$f6") _,
+        lateout("$f7") _,
+        lateout("$f8") _,
+        lateout("$f9") _,
+        lateout("$f10") _,
+        lateout("$f11") _,
+        lateout("$f12") _,
+        lateout("$f13") _,
+        lateout("$f14") _,
+        lateout("$f15") _,
+        lateout("$f16") _,
+        lateout("$f17") _,
+        lateout("$f18") _,
+        lateout("$f19") _,
+        lateout("$f20") _,
+        lateout("$f21") _,
+        lateout("$f22") _,
+        lateout("$f23") _,
+        lateout("$f24") _,
+        lateout("$f25") _,
+        lateout("$f26") _,
+        lateout("$f27") _,
+        lateout("$f28") _,
+        lateout("$f29") _,
+        lateout("$f30") _,
+        lateout("$f31") _,
+    );
+    #[cfg(all(target_feature = "lsx", not(target_feature = "lasx")))]
+    core::arch::asm!(
+        "vxor.v $vr0, $vr0, $vr0",
+        "vxor.v $vr1, $vr1, $vr1",
+        "vxor.v $vr2, $vr2, $vr2",
+        "vxor.v $vr3, $vr3, $vr3",
+        "vxor.v $vr4, $vr4, $vr4",
+        "vxor.v $vr5, $vr5, $vr5",
+        "vxor.v $vr6, $vr6, $vr6",
+        "vxor.v $vr7, $vr7, $vr7",
+        "vxor.v $vr8, $vr8, $vr8",
+        "vxor.v $vr9, $vr9, $vr9",
+        "vxor.v $vr10, $vr10, $vr10",
+        "vxor.v $vr11, $vr11, $vr11",
+        "vxor.v $vr12, $vr12, $vr12",
+        "vxor.v $vr13, $vr13, $vr13",
+        "vxor.v $vr14, $vr14, $vr14",
+        "vxor.v $vr15, $vr15, $vr15",
+        "vxor.v $vr16, $vr16, $vr16",
+        "vxor.v $vr17, $vr17, $vr17",
+        "vxor.v $vr18, $vr18, $vr18",
+        "vxor.v $vr19, $vr19, $vr19",
+        "vxor.v $vr20, $vr20, $vr20",
+        "vxor.v $vr21, $vr21, $vr21",
+        "vxor.v $vr22, $vr22, $vr22",
+        "vxor.v $vr23, $vr23, $vr23",
+        "vxor.v $vr24, $vr24, $vr24",
+        "vxor.v $vr25, $vr25, $vr25",
+        "vxor.v $vr26, $vr26, $vr26",
+        "vxor.v $vr27, $vr27, $vr27",
+        "vxor.v $vr28, $vr28, $vr28",
+        "vxor.v $vr29, $vr29, $vr29",
+        "vxor.v $vr30, $vr30, $vr30",
+        "vxor.v $vr31, $vr31, $vr31",
+        lateout("$f0") _,
+        lateout("$f1") _,
+        lateout("$f2") _,
+        lateout("$f3") _,
+        lateout("$f4") _,
+        lateout("$f5") _,
+        lateout("$f6") _,
+        lateout("$f7") _,
+        lateout("$f8") _,
+        lateout("$f9") _,
+        lateout("$f10") _,
+        lateout("$f11") _,
+        lateout("$f12") _,
+        lateout("$f13") _,
+        lateout("$f14") _,
+        lateout("$f15") _,
+        lateout("$f16") _,
+        lateout("$f17") _,
+        lateout("$f18") _,
+        lateout("$f19") _,
+        lateout("$f20") _,
+        lateout("$f21") _,
+        lateout("$f22") _,
+        lateout("$f23") _,
+        lateout("$f24") _,
+        lateout("$f25") _,
+        lateout("$f26") _,
+        lateout("$f27") _,
+        lateout("$f28") _,
+        lateout("$f29") _,
+        lateout("$f30") _,
+        lateout("$f31") _,
+    );
+    #[cfg(target_feature = "lasx")]
+    core::arch::asm!(
+        "xvxor.v $xr0, $xr0, $xr0",
+        "xvxor.v $xr1, $xr1, $xr1",
+        "xvxor.v $xr2, $xr2, $xr2",
+        "xvxor.v $xr3, $xr3, $xr3",
+        "xvxor.v $xr4, $xr4, $xr4",
+        "xvxor.v $xr5, $xr5, $xr5",
+        "xvxor.v $xr6, $xr6, $xr6",
+        "xvxor.v $xr7, $xr7, $xr7",
+        "xvxor.v $xr8, $xr8, $xr8",
+        "xvxor.v $xr9, $xr9, $xr9",
+        "xvxor.v $xr10, $xr10, $xr10",
+        "xvxor.v $xr11, $xr11, $xr11",
+        "xvxor.v $xr12, $xr12, $xr12",
+        "xvxor.v $xr13, $xr13, $xr13",
+        "xvxor.v $xr14, $xr14, $xr14",
+        "xvxor.v $xr15, $xr15, $xr15",
+        "xvxor.v $xr16, $xr16, $xr16",
+        "xvxor.v $xr17, $xr17, $xr17",
+        "xvxor.v $xr18, $xr18, $xr18",
+        "xvxor.v $xr19, $xr19, $xr19",
+        "xvxor.v $xr20, $xr20, $xr20",
+        "xvxor.v $xr21, $xr21, $xr21",
+        "xvxor.v $xr22, $xr22, $xr22",
+        "xvxor.v $xr23, $xr23, $xr23",
+        "xvxor.v $xr24, $xr24, $xr24",
+        "xvxor.v $xr25, $xr25, $xr25",
+        "xvxor.v $xr26, $xr26, $xr26",
+        "xvxor.v $xr27, $xr27, $xr27",
+        "xvxor.v $xr28, $xr28, $xr28",
+        "xvxor.v $xr29, $xr29, $xr29",
+        "xvxor.v $xr30, $xr30, $xr30",
+        "xvxor.v $xr31, $xr31, $xr31",
+        lateout("$f0") _,
+        lateout("$f1") _,
+        lateout("$f2") _,
+        lateout("$f3") _,
+        lateout("$f4") _,
+        lateout("$f5") _,
+        lateout("$f6") _,
+        lateout("$f7") _,
+        lateout("$f8") _,
+        lateout("$f9") _,
+        lateout("$f10") _,
+        lateout("$f11") _,
+        lateout("$f12") _,
+        lateout("$f13") _,
+        lateout("$f14") _,
+        lateout("$f15") _,
+        lateout("$f16") _,
+        lateout("$f17") _,
+        lateout("$f18") _,
+        lateout("$f19") _,
+        lateout("$f20") _,
+        lateout("$f21") _,
+        lateout("$f22") _,
+        lateout("$f23") _,
+        lateout("$f24") _,
+        lateout("$f25") _,
+        lateout("$f26") _,
+        lateout("$f27") _,
+        lateout("$f28") _,
+        lateout("$f29") _,
+        lateout("$f30") _,
+        lateout("$f31") _,
+    );
+}
+
+/// `$at` belongs to the assembler, `$k0` and `$k1` to the kernel, and `$gp`,
+/// `$sp`, `$fp` and `$ra` cannot be named as clobbers, so none of these are
+/// wiped here.  Under o32, the floating-point registers are 32 bits wide, and
+/// the odd ones cannot be written on their own, so each pair is wiped by
+/// converting an integer zero to a double.
+#[cfg(all(target_arch = "mips", target_os = "linux", not(eraser_shim)))]
+unsafe fn wipe_all_registers() {
+    core::arch::asm!(
+        "move $2, $zero",
+        "move $3, $zero",
+        "move $4, $zero",
+        "move $5, $zero",
+        "move $6, $zero",
+        "move $7, $zero",
+        "move $8, $zero",
+        "move $9, $zero",
+        "move $10, $zero",
+        "move $11, $zero",
+        "move $12, $zero",
+        "move $13, $zero",
+        "move $14, $zero",
+        "move $15, $zero",
+        "move $16, $zero",
+        "move $17, $zero",
+        "move $18, $zero",
+        "move $19, $zero",
+        "move $20, $zero",
+        "move $21, $zero",
+        "move $22, $zero",
+        "move $23, $zero",
+        "move $24, $zero",
+        "move $25, $zero",
+        "mthi $zero",
+        "mtlo $zero",
+        "mtc1 $zero, $f0",
+        "cvt.d.w $f0, $f0",
+        "mtc1 $zero, $f2",
+        "cvt.d.w $f2, $f2",
+        "mtc1 $zero, $f4",
+        "cvt.d.w $f4, $f4",
+        "mtc1 $zero, $f6",
+        "cvt.d.w $f6, $f6",
+        "mtc1 $zero, $f8",
+        "cvt.d.w $f8, $f8",
+        "mtc1 $zero, $f10",
+        "cvt.d.w $f10, $f10",
+        "mtc1 $zero, $f12",
+        "cvt.d.w $f12, $f12",
+        "mtc1 $zero, $f14",
+        "cvt.d.w $f14, $f14",
+        "mtc1 $zero, $f16",
+        "cvt.d.w $f16, $f16",
+        "mtc1 $zero, $f18",
+        "cvt.d.w $f18, $f18",
+        "mtc1 $zero, $f20",
+        "cvt.d.w $f20, $f20",
+        "mtc1 $zero, $f22",
+        "cvt.d.w $f22, $f22",
+        "mtc1 $zero, $f24",
+        "cvt.d.w $f24, $f24",
+        "mtc1 $zero, $f26",
+        "cvt.d.w $f26, $f26",
+        "mtc1 $zero, $f28",
+        "cvt.d.w $f28, $f28",
+        "mtc1 $zero, $f30",
+        "cvt.d.w $f30, $f30",
+        // Reset the floating-point control and status register
+        "ctc1 $zero, $31",
+        lateout("$2") _,
+        lateout("$3") _,
+        lateout("$4") _,
+        lateout("$5") _,
+        lateout("$6") _,
+        lateout("$7") _,
+        lateout("$8") _,
+        lateout("$9") _,
+        lateout("$10") _,
+        lateout("$11") _,
+        lateout("$12") _,
+        lateout("$13") _,
+        lateout("$14") _,
+        lateout("$15") _,
+        lateout("$16") _,
+        lateout("$17") _,
+        lateout("$18") _,
+        lateout("$19") _,
+        lateout("$20") _,
+        lateout("$21") _,
+        lateout("$22") _,
+        lateout("$23") _,
+        lateout("$24") _,
+        lateout("$25") _,
+        lateout("$f0") _,
+        lateout("$f1") _,
+        lateout("$f2") _,
+        lateout("$f3") _,
+        lateout("$f4") _,
+        lateout("$f5") _,
+        lateout("$f6") _,
+        lateout("$f7") _,
+        lateout("$f8") _,
+        lateout("$f9") _,
+        lateout("$f10") _,
+        lateout("$f11") _,
+        lateout("$f12") _,
+        lateout("$f13") _,
+        lateout("$f14") _,
+        lateout("$f15") _,
+        lateout("$f16") _,
+        lateout("$f17") _,
+        lateout("$f18") _,
+        lateout("$f19") _,
+        lateout("$f20") _,
+        lateout("$f21") _,
+        lateout("$f22") _,
+        lateout("$f23") _,
+        lateout("$f24") _,
+        lateout("$f25") _,
+        lateout("$f26") _,
+        lateout("$f27") _,
+        lateout("$f28") _,
+        lateout("$f29") _,
+        lateout("$f30") _,
+        lateout("$f31") _,
+    )
+}
+
+/// Under n64, the floating-point registers are 64 bits wide.
+#[cfg(all(target_arch = "mips64", target_os = "linux", not(eraser_shim)))]
+unsafe fn wipe_all_registers() {
+    core::arch::asm!(
+        "move $2, $zero",
+        "move $3, $zero",
+        "move $4, $zero",
+        "move $5, $zero",
+        "move $6, $zero",
+        "move $7, $zero",
+        "move $8, $zero",
+        "move $9, $zero",
+        "move $10, $zero",
+        "move $11, $zero",
+        "move $12, $zero",
+        "move $13, $zero",
+        "move $14, $zero",
+        "move $15, $zero",
+        "move $16, $zero",
+        "move $17, $zero",
+        "move $18, $zero",
+        "move $19, $zero",
+        "move $20, $zero",
+        "move $21, $zero",
+        "move $22, $zero",
+        "move $23, $zero",
+        "move $24, $zero",
+        "move $25, $zero",
+        "mthi $zero",
+        "mtlo $zero",
+        "dmtc1 $zero, $f0",
+        "dmtc1 $zero, $f1",
+        "dmtc1 $zero, $f2",
+        "dmtc1 $zero, $f3",
+        "dmtc1 $zero, $f4",
+        "dmtc1 $zero, $f5",
+        "dmtc1 $zero, $f6",
+        "dmtc1 $zero, $f7",
+        "dmtc1 $zero, $f8",
+        "dmtc1 $zero, $f9",
+        "dmtc1 $zero, $f10",
+        "dmtc1 $zero, $f11",
+        "dmtc1 $zero, $f12",
+        "dmtc1 $zero, $f13",
+        "dmtc1 $zero, $f14",
+        "dmtc1 $zero, $f15",
+        "dmtc1 $zero, $f16",
+        "dmtc1 $zero, $f17",
+        "dmtc1 $zero, $f18",
+        "dmtc1 $zero, $f19",
+        "dmtc1 $zero, $f20",
+        "dmtc1 $zero, $f21",
+        "dmtc1 $zero, $f22",
+        "dmtc1 $zero, $f23",
+        "dmtc1 $zero, $f24",
+        "dmtc1 $zero, $f25",
+        "dmtc1 $zero, $f26",
+        "dmtc1 $zero, $f27",
+        "dmtc1 $zero, $f28",
+        "dmtc1 $zero, $f29",
+        "dmtc1 $zero, $f30",
+        "dmtc1 $zero, $f31",
+        // Reset the floating-point control and status register
+        "ctc1 $zero, $31",
+        lateout("$2") _,
+        lateout("$3") _,
+        lateout("$4") _,
+        lateout("$5") _,
+        lateout("$6") _,
+        lateout("$7") _,
+        lateout("$8") _,
+        lateout("$9") _,
+        lateout("$10") _,
+        lateout("$11") _,
+        lateout("$12") _,
+        lateout("$13") _,
+        lateout("$14") _,
+        lateout("$15") _,
+        lateout("$16") _,
+        lateout("$17") _,
+        lateout("$18") _,
+        lateout("$19") _,
+        lateout("$20") _,
+        lateout("$21") _,
+        lateout("$22") _,
+        lateout("$23") _,
+        lateout("$24") _,
+        lateout("$25") _,
+        lateout("$f0") _,
+        lateout("$f1") _,
+        lateout("$f2") _,
+        lateout("$f3") _,
+        lateout("$f4") _,
+        lateout("$f5") _,
+        lateout("$f6") _,
+        lateout("$f7") _,
+        lateout("$f8") _,
+        lateout("$f9") _,
+        lateout("$f10") _,
+        lateout("$f11") _,
+        lateout("$f12") _,
+        lateout("$f13") _,
+        lateout("$f14") _,
+        lateout("$f15") _,
+        lateout("$f16") _,
+        lateout("$f17") _,
+        lateout("$f18") _,
+        lateout("$f19") _,
+        lateout("$f20") _,
+        lateout("$f21") _,
+        lateout("$f22") _,
+        lateout("$f23") _,
+        lateout("$f24") _,
+        lateout("$f25") _,
+        lateout("$f26") _,
+        lateout("$f27") _,
+        lateout("$f28") _,
+        lateout("$f29") _,
+        lateout("$f30") _,
+        lateout("$f31") _,
+    )
+}
+
+/// Every SPARC V9 CPU that runs Linux has eight register windows.  After the
+/// windows of the callers have been flushed, the other seven windows belong to
+/// no frame, so they are wiped by going through them with `save` (and back with
+/// `restore`), which spills and fills nothing but the current window.  Then the
+/// local, out and in registers of the current window are wiped, except for the
+/// stack and frame pointers and the return address.  `%g1`, `%g6` and `%g7`
+/// belong to the compiler and the system.
+///
+/// The floating-point registers cannot be named as clobbers, so this function
+/// is never inlined: they are all caller-saved, so the compiler does not
+/// expect them to survive it.
+#[cfg(all(target_arch = "sparc64", target_os = "linux", not(eraser_shim)))]
+#[inline(never)]
+unsafe fn wipe_all_registers() {
+    static ZERO: u64 = 0;
+    core::arch::asm!(
+        // Wipe the floating-point registers and reset the floating-point
+        // state register
+        "ldd [{zero}], %f0",
+        "ldd [{zero}], %f2",
+        "ldd [{zero}], %f4",
+        "ldd [{zero}], %f6",
+        "ldd [{zero}], %f8",
+        "ldd [{zero}], %f10",
+        "ldd [{zero}], %f12",
+        "ldd [{zero}], %f14",
+        "ldd [{zero}], %f16",
+        "ldd [{zero}], %f18",
+        "ldd [{zero}], %f20",
+        "ldd [{zero}], %f22",
+        "ldd [{zero}], %f24",
+        "ldd [{zero}], %f26",
+        "ldd [{zero}], %f28",
+        "ldd [{zero}], %f30",
+        "ldd [{zero}], %f32",
+        "ldd [{zero}], %f34",
+        "ldd [{zero}], %f36",
+        "ldd [{zero}], %f38",
+        "ldd [{zero}], %f40",
+        "ldd [{zero}], %f42",
+        "ldd [{zero}], %f44",
+        "ldd [{zero}], %f46",
+        "ldd [{zero}], %f48",
+        "ldd [{zero}], %f50",
+        "ldd [{zero}], %f52",
+        "ldd [{zero}], %f54",
+        "ldd [{zero}], %f56",
+        "ldd [{zero}], %f58",
+        "ldd [{zero}], %f60",
+        "ldd [{zero}], %f62",
+        "ldx [{zero}], %fsr",
+        // Flush the register windows of all the callers to their stacks
+        "flushw",
+        // Wipe the local and out registers of the other windows
+        "mov 7, %g4",
+        "2:",
+        "save %sp, -176, %sp",
+        "clr %l0",
+        "clr %l1",
+        "clr %l2",
+        "clr %l3",
+        "clr %l4",
+        "clr %l5",
+        "clr %l6",
+        "clr %l7",
+        "clr %o0",
+        "clr %o1",
+        "clr %o2",
+        "clr %o3",
+        "clr %o4",
+        "clr %o5",
+        "clr %o7",
+        "subcc %g4, 1, %g4",
+        "bne %xcc, 2b",
+        "nop",
+        "mov 7, %g4",
+        "3:",
+        "subcc %g4, 1, %g4",
+        "bne %xcc, 3b",
+        "restore",
+        // Wipe the registers of the current window
+        "clr %l0",
+        "clr %l1",
+        "clr %l2",
+        "clr %l3",
+        "clr %l4",
+        "clr %l5",
+        "clr %l6",
+        "clr %l7",
+        "clr %o0",
+        "clr %o1",
+        "clr %o2",
+        "clr %o3",
+        "clr %o4",
+        "clr %o5",
+        "clr %o7",
+        "clr %i0",
+        "clr %i1",
+        "clr %i2",
+        "clr %i3",
+        "clr %i4",
+        "clr %i5",
+        "clr %g2",
+        "clr %g3",
+        "clr %g5",
+        zero = in(reg) &ZERO,
+        lateout("g4") _,
+        lateout("g2") _,
+        lateout("g3") _,
+        lateout("g5") _,
+        lateout("l0") _,
+        lateout("l1") _,
+        lateout("l2") _,
+        lateout("l3") _,
+        lateout("l4") _,
+        lateout("l5") _,
+        lateout("l6") _,
+        lateout("l7") _,
+        lateout("o0") _,
+        lateout("o1") _,
+        lateout("o2") _,
+        lateout("o3") _,
+        lateout("o4") _,
+        lateout("o5") _,
+        lateout("o7") _,
+        lateout("i0") _,
+        lateout("i1") _,
+        lateout("i2") _,
+        lateout("i3") _,
+        lateout("i4") _,
+        lateout("i5") _,
+    )
+}
+
+/// The shim's version only wipes the registers that the calling convention
+/// does not preserve, but the compiler restores the others after the inline
+/// assembly versions as well.
+#[cfg(eraser_shim)]
+unsafe fn wipe_all_registers() {
+    extern "C" {
+        fn eraser_wipe_registers();
+    }
+    eraser_wipe_registers();
+}
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use cortex_m::wipe_all_registers;
+#[cfg(all(target_arch = "msp430", target_os = "none"))]
+use msp430::wipe_all_registers;
+#[cfg(all(target_arch = "riscv32", target_os = "none"))]
+use riscv32::wipe_all_registers;
+#[cfg(all(target_arch = "x86_64", target_os = "none"))]
+use x86_64_none::wipe_all_registers;
+#[cfg(all(target_arch = "xtensa", target_os = "none"))]
+use xtensa::wipe_all_registers;
+
+/// Whether `wipe_all_registers` wipes the registers on this target.
+#[cfg(feature = "std")]
+const WIPES_REGISTERS: bool = cfg!(any(
+    target_arch = "x86_64",
+    all(
+        target_arch = "aarch64",
+        any(target_os = "linux", target_os = "macos", windows)
+    ),
+    all(target_arch = "arm", target_os = "linux"),
+    all(target_arch = "riscv64", target_os = "linux"),
+    all(target_arch = "x86", any(target_os = "linux", windows)),
+    all(
+        target_arch = "powerpc64",
+        target_endian = "little",
+        target_os = "linux"
+    ),
+    all(target_arch = "s390x", target_os = "linux"),
+    all(target_arch = "loongarch64", target_os = "linux"),
+    all(target_arch = "mips", target_os = "linux"),
+    all(target_arch = "mips64", target_os = "linux"),
+    all(target_arch = "sparc64", target_os = "linux")
+));
+
+#[cfg(not(any(
+    target_arch = "x86_64",
+    all(
+        target_arch = "aarch64",
+        any(target_os = "linux", target_os = "macos", windows)
+    ),
+    all(target_arch = "arm", any(target_os = "linux", target_os = "none")),
+    all(target_arch = "riscv64", target_os = "linux"),
+    all(target_arch = "riscv32", target_os = "none"),
+    all(target_arch = "xtensa", target_os = "none"),
+    all(target_arch = "msp430", target_os = "none"),
+    all(target_arch = "x86", any(target_os = "linux", windows)),
+    all(
+        target_arch = "powerpc64",
+        target_endian = "little",
+        target_os = "linux"
+    ),
+    all(target_arch = "s390x", target_os = "linux"),
+    all(target_arch = "loongarch64", target_os = "linux"),
+    all(target_arch = "mips", target_os = "linux"),
+    all(target_arch = "mips64", target_os = "linux"),
+    all(target_arch = "sparc64", target_os = "linux")
+)))]
 unsafe fn wipe_all_registers() {}
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::cell::RefCell;
+    use std::cell::{Cell, RefCell};
+
+    thread_local! {
+        /// The number of times `erase_words` was called on this thread.
+        pub(super) static ERASES: Cell<usize> = const { Cell::new(0) };
+    }
 
     #[derive(Debug, Clone, Copy, Default)]
     struct CryptoSimulInfo {
@@ -274,19 +4513,19 @@ mod tests {
 
     fn bump_ctr() {
         INFO.with(|cell| {
-            (*cell.borrow_mut()).ctr += 1;
+            cell.borrow_mut().ctr += 1;
         });
     }
 
     #[test]
     fn functional() {
         INFO.with(|cell| {
-            (*cell.borrow_mut()).ctr = 0;
+            cell.borrow_mut().ctr = 0;
         });
         run_then_erase(bump_ctr, 4096);
         let mut ctr = 0;
         INFO.with(|cell| {
-            ctr = (*cell.borrow()).ctr;
+            ctr = cell.borrow().ctr;
         });
         assert_eq!(ctr, 1);
     }
@@ -316,6 +4555,632 @@ mod tests {
     #[test]
     #[should_panic]
     fn explicit_panic() {
-        run_then_erase(do_panic, 4096);
+        // Unwinding needs quite a bit of stack space (especially in debug
+        // builds), so 4096 bytes is not enough here.
+        run_then_erase(do_panic, 64 * 1024);
+    }
+
+    #[test]
+    fn capturing_closure() {
+        let key = [1u8, 2, 3, 4];
+        let mut sum = 0;
+        run_then_erase(
+            || {
+                sum = key.iter().map(|&x| x as i32).sum();
+            },
+            4096,
+        );
+        assert_eq!(sum, 10);
+    }
+
+    #[test]
+    fn return_value() {
+        let key = [0x55u8; 64];
+        let out = run_then_erase(
+            move || key.iter().map(|&x| x ^ 0xFF).collect::<Vec<_>>(),
+            4096,
+        );
+        assert_eq!(out, vec![0xAAu8; 64]);
+    }
+
+    #[test]
+    fn with_input() {
+        fn sum(input: (Vec<u8>, u32)) -> u32 {
+            let (key, offset) = input;
+            key.iter().map(|&x| x as u32).sum::<u32>() + offset
+        }
+        let result = run_then_erase_with_input(sum, (vec![1, 2, 3], 10), 4096);
+        assert_eq!(result, 16);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            try_run_then_erase(|| (), 4100),
+            Err(EraserError::BadAlignment)
+        );
+        assert_eq!(
+            try_run_then_erase(|| (), 32),
+            Err(EraserError::StackTooSmall)
+        );
+        assert_eq!(
+            try_run_then_erase(|| (), usize::MAX - 31),
+            Err(EraserError::AllocFailed)
+        );
+
+        let mut stack = AlignedStack::<1024>::new();
+        let buf = &mut stack.as_mut_slice()[1..];
+        let result = unsafe { try_run_then_erase_with_stack(|| (), buf) };
+        assert_eq!(result, Err(EraserError::StackTooSmall));
+
+        let builder = Eraser::builder().erase_policy(ErasePolicy::Sequence(&[]));
+        assert_eq!(builder.build().unwrap_err(), EraserError::NoErasePasses);
+        let builder = Eraser::builder().erase_passes(0);
+        assert_eq!(builder.build().unwrap_err(), EraserError::NoErasePasses);
+        let builder = unsafe { Eraser::builder().recover_from_overflow(true) };
+        let overflow_handler = cfg!(all(target_os = "linux", target_arch = "x86_64"));
+        assert_eq!(builder.build().is_ok(), overflow_handler);
+    }
+
+    #[test]
+    fn unaligned_stack() {
+        let mut stack = AlignedStack::<{ 4096 + 32 }>::new();
+        let buf = &mut stack.as_mut_slice()[1..4097];
+        assert_eq!(usable_stack_size(buf), 4096 - 32);
+        let result = unsafe { try_run_then_erase_with_stack(|| 42, buf) };
+        assert_eq!(result, Ok(42));
+        // The bytes outside the aligned sub-range are not touched
+        assert_eq!(stack.as_mut_slice()[1..32], [0; 31]);
+    }
+
+    #[test]
+    fn builder() {
+        let eraser = Eraser::builder()
+            .stack_size(16 * 1024)
+            .guard_page(true)
+            .mlock(true)
+            .dont_dump(cfg!(target_os = "linux"))
+            .erase_passes(3)
+            .erase_pattern(0)
+            .build()
+            .unwrap();
+        for i in 0..4 {
+            assert_eq!(eraser.run(|| i * 2), i * 2);
+        }
+        let zeroed = Eraser::builder().zero_stack(true).build().unwrap();
+        assert_eq!(zeroed.run(|| 7), 7);
+        assert_eq!(
+            Eraser::builder().stack_size(100).build().unwrap_err(),
+            EraserError::StackTooSmall
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn paranoid() {
+        assert!(memlock_limit().is_some());
+        let mut stack = Eraser::paranoid()
+            .stack_size(16 * 1024)
+            .mlock_fallback(true)
+            .build()
+            .unwrap()
+            .new_stack()
+            .unwrap();
+        assert_eq!(stack.run(|| 3 * 3), 9);
+        if memlock_limit() == Some(usize::MAX) {
+            assert!(stack.is_locked());
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn restrict_speculation() {
+        extern "C" {
+            fn prctl(option: i32, ...) -> i32;
+        }
+        const PR_GET_SPECULATION_CTRL: i32 = 52;
+        const PR_SPEC_DISABLE: i32 = 1 << 2;
+        let state = || -> [i32; 2] {
+            [0, 1]
+                .map(|ctrl: i64| unsafe { prctl(PR_GET_SPECULATION_CTRL, ctrl, 0i64, 0i64, 0i64) })
+        };
+
+        let before = state();
+        let eraser = Eraser::builder()
+            .restrict_speculation(true)
+            .stack_size(16 * 1024)
+            .build()
+            .unwrap();
+        match eraser.try_run(state) {
+            Err(EraserError::Unsupported) => {}
+            result => {
+                for (during, before) in result.unwrap().into_iter().zip(before) {
+                    assert!(before == 0 || during & PR_SPEC_DISABLE != 0);
+                }
+            }
+        }
+        assert_eq!(state(), before);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn pin_cpu() {
+        extern "C" {
+            fn sched_getcpu() -> i32;
+        }
+
+        // The current CPU is always in the thread's cpuset
+        let cpu = unsafe { sched_getcpu() } as usize;
+        assert!(!smt_siblings(cpu).unwrap().contains(&cpu));
+        let eraser = Eraser::builder()
+            .pin_cpu(Some(cpu))
+            .stack_size(16 * 1024)
+            .build()
+            .unwrap();
+        for _ in 0..10 {
+            let result = eraser.try_run(|| unsafe { sched_getcpu() } as usize);
+            assert_eq!(result, Ok(cpu));
+        }
+        let eraser = Eraser::builder().pin_cpu(Some(100_000)).build().unwrap();
+        assert_eq!(eraser.try_run(|| ()), Err(EraserError::PinFailed));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn secret_memory() {
+        let eraser = Eraser::builder()
+            .secret_memory(true)
+            .stack_size(16 * 1024)
+            .build()
+            .unwrap();
+        match eraser.try_run(|| 3 * 3) {
+            Err(EraserError::Unsupported) => {}
+            result => assert_eq!(result, Ok(9)),
+        }
+    }
+
+    #[test]
+    fn verified_erase() {
+        for policy in [ErasePolicy::default(), ErasePolicy::ZerosOnesRandom] {
+            let eraser = Eraser::builder()
+                .stack_size(16 * 1024)
+                .erase_policy(policy)
+                .verify_erase(true)
+                .flush_cache(true)
+                .build()
+                .unwrap();
+            let mut stack = eraser.new_stack().unwrap();
+            for i in 0..4 {
+                assert_eq!(eraser.run(|| i * 2), i * 2);
+                assert_eq!(stack.run(|| [i; 64])[63], i);
+            }
+        }
+    }
+
+    #[test]
+    fn cached() {
+        for i in 0..100 {
+            assert_eq!(run_then_erase_cached(|| i * 3), i * 3);
+        }
+        assert!(CACHED_STACK.with(|cell| cell.borrow().is_some()));
+
+        let nested = run_then_erase_cached(|| run_then_erase_cached(|| 21) * 2);
+        assert_eq!(nested, 42);
+    }
+
+    #[test]
+    fn erased_macro() {
+        let key = [3u8; 16];
+        let mut calls = 0;
+        let sum = erased!(4096, {
+            calls += 1;
+            key.iter().map(|&x| x as u32).sum::<u32>()
+        });
+        assert_eq!((sum, calls), (48, 1));
+    }
+
+    #[test]
+    fn into() {
+        let mut out = [0u8; 8];
+        let written = run_then_erase_into(
+            |out| {
+                out[..3].copy_from_slice(b"key");
+                3
+            },
+            &mut out,
+            4096,
+        );
+        assert_eq!(&out[..written], b"key");
+    }
+
+    #[test]
+    fn secret() {
+        let key = run_then_erase_secret(|| [7u8; 64], 4096);
+        assert_eq!(
+            key.expose(|key| key.iter().map(|&x| x as u32).sum::<u32>()),
+            64 * 7
+        );
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn zeroize() {
+        use zeroize::Zeroize;
+
+        let mut secret = run_then_erase(|| Secret::new([7u8; 4]), 4096);
+        secret.zeroize();
+        assert_eq!(secret.expose(|v| *v), [0; 4]);
+
+        let mut vec = SecretVec::from(&[1u8, 2, 3][..]);
+        vec.zeroize();
+        assert!(vec.is_empty());
+
+        let mut stack = ErasedStack::new(4096).unwrap();
+        assert_eq!(stack.run(|| 3), 3);
+        stack.zeroize();
+
+        let key = run_then_erase_zeroizing(|| vec![1u8; 16], 4096);
+        assert_eq!(key.len(), 16);
+    }
+
+    #[cfg(feature = "secrecy")]
+    #[test]
+    fn secrecy() {
+        use secrecy::ExposeSecret;
+
+        let key = run_then_erase_init_secret_box(|key: &mut Vec<u8>| key.resize(16, 7), 4096);
+        let sum = run_then_erase_exposed(&key, |key: &Vec<u8>| key.iter().sum::<u8>(), 4096);
+        assert_eq!(sum, 16 * 7);
+        assert_eq!(key.expose_secret().len(), 16);
+    }
+
+    #[test]
+    fn erase_unaligned() {
+        let mut buf = [0u8; 64];
+        erase(&mut buf[3..61]);
+        assert_eq!(buf[..3], [0; 3]);
+        assert_eq!(buf[61..], [0; 3]);
+        let pattern = ERASE_VALUE.to_ne_bytes();
+        for byte in &buf[3..61] {
+            let index = byte as *const u8 as usize % pattern.len();
+            assert_eq!(*byte, pattern[index]);
+        }
+    }
+
+    #[test]
+    fn watermark() {
+        let mut stack = AlignedStack::<1024>::new();
+        let buf = as_uninit(stack.as_mut_slice());
+        assert_eq!(high_water_mark(buf, 0), 1024);
+        buf[1000] = MaybeUninit::new(1);
+        buf[600] = MaybeUninit::new(1);
+        assert_eq!(high_water_mark(buf, 0), 600);
+        assert_eq!(high_water_mark(buf, 1), 0);
+    }
+
+    #[test]
+    fn stack_usage() {
+        let (result, small) = measure_stack_usage(|| 5);
+        assert_eq!(result, 5);
+        assert!(small > 0);
+
+        let (sum, large) = measure_stack_usage(|| {
+            let buf = core::hint::black_box([1u8; 64 * 1024]);
+            buf.iter().map(|&x| x as u32).sum::<u32>()
+        });
+        assert_eq!(sum, 64 * 1024);
+        assert!(large >= small + 64 * 1024);
+        assert!(large < MEASURE_STACK_SIZE);
+
+        let result = panic::catch_unwind(|| measure_stack_usage(|| panic!("oops")));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn single_erase() {
+        let mut stack = AlignedStack::<{ 64 * 1024 }>::new();
+        let before = ERASES.with(Cell::get);
+        let result = unsafe { run_then_erase_with_stack(|| 5, stack.as_mut_slice()) };
+        assert_eq!(result, 5);
+        assert_eq!(ERASES.with(Cell::get), before + 1);
+
+        let before = ERASES.with(Cell::get);
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| unsafe {
+            run_then_erase_with_stack(|| panic!("oops"), stack.as_mut_slice())
+        }));
+        assert!(result.is_err());
+        assert_eq!(ERASES.with(Cell::get), before + 1);
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    #[test]
+    fn stack_overflow() {
+        fn recurse(depth: u64) -> u64 {
+            let buf = core::hint::black_box([depth; 32]);
+            if buf[0] == u64::MAX {
+                return 0;
+            }
+            recurse(depth + 1) + buf[7]
+        }
+
+        let builder = Eraser::builder().stack_size(64 * 1024).guard_page(true);
+        let eraser = unsafe { builder.recover_from_overflow(true) }
+            .build()
+            .unwrap();
+        assert_eq!(
+            eraser.try_run(|| recurse(0)),
+            Err(EraserError::StackOverflow)
+        );
+        assert_eq!(eraser.try_run(|| 7), Ok(7));
+
+        let mut stack = eraser.new_stack().unwrap();
+        for _ in 0..3 {
+            assert_eq!(
+                stack.try_run(|| recurse(0)),
+                Err(EraserError::StackOverflow)
+            );
+            assert_eq!(stack.run(|| 8), 8);
+        }
+
+        let mut session = stack.session(recurse);
+        assert_eq!(session.try_call(0), Err(EraserError::StackOverflow));
+        assert_eq!(session.try_call(u64::MAX), Ok(0));
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    #[test]
+    fn stack_overflow_aborts() {
+        extern "C" {
+            fn fork() -> i32;
+            fn waitpid(pid: i32, status: *mut i32, options: i32) -> i32;
+            fn _exit(status: i32) -> !;
+        }
+        const SIGABRT: i32 = 6;
+
+        fn recurse(depth: u64) -> u64 {
+            let buf = core::hint::black_box([depth; 32]);
+            if buf[0] == u64::MAX {
+                return 0;
+            }
+            recurse(depth + 1) + buf[7]
+        }
+
+        // Set up everything before the fork, so that the child does not
+        // allocate while another thread of the test harness may hold a lock
+        let mut stack = Eraser::builder()
+            .stack_size(64 * 1024)
+            .guard_page(true)
+            .build()
+            .unwrap()
+            .new_stack()
+            .unwrap();
+        assert_eq!(stack.run(|| 7), 7);
+        match unsafe { fork() } {
+            0 => unsafe {
+                let _ = stack.try_run(|| recurse(0));
+                _exit(0)
+            },
+            pid => {
+                assert!(pid > 0);
+                let mut status = 0;
+                assert_eq!(unsafe { waitpid(pid, &mut status, 0) }, pid);
+                assert_eq!(status & 0x7f, SIGABRT);
+            }
+        }
+    }
+
+    #[test]
+    fn nested() {
+        let mut ctr = 0;
+        run_then_erase(
+            || {
+                ctr += 1;
+                run_then_erase(|| ctr += 1, 4096);
+            },
+            16 * 1024,
+        );
+        assert_eq!(ctr, 2);
+    }
+
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    #[test]
+    fn backtrace() {
+        let trace = run_then_erase(
+            || std::backtrace::Backtrace::force_capture().to_string(),
+            64 * 1024,
+        );
+        // The unwinder has to get past the stack switch to reach this frame
+        assert!(
+            trace
+                .lines()
+                .any(|line| line.ends_with(": eraser::tests::backtrace")),
+            "{trace}"
+        );
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn avx512_wipe() {
+        #[target_feature(enable = "avx512f")]
+        unsafe fn registers_after_wipe() -> ([u64; 8], u32) {
+            core::arch::asm!(
+                "vpternlogd zmm16, zmm16, zmm16, 0xff",
+                "kxnorw k1, k1, k1",
+                out("zmm16") _,
+                out("k1") _,
+            );
+            wipe_registers();
+            let mut zmm = [0u64; 8];
+            let k: u32;
+            core::arch::asm!(
+                "vmovdqu64 [{zmm}], zmm16",
+                "kmovw {k:e}, k1",
+                zmm = in(reg) zmm.as_mut_ptr(),
+                k = out(reg) k,
+            );
+            (zmm, k)
+        }
+
+        if !std::is_x86_feature_detected!("avx512f") {
+            return;
+        }
+        assert_eq!(unsafe { registers_after_wipe() }, ([0; 8], 0));
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn sse_wipe() {
+        let xmm8: u64;
+        unsafe {
+            core::arch::asm!("pcmpeqd xmm8, xmm8", out("xmm8") _);
+            wipe_sse_registers();
+            core::arch::asm!("movq {}, xmm8", out(reg) xmm8);
+        }
+        assert_eq!(xmm8, 0);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn mxcsr_reset() {
+        // Raise the divide-by-zero flag
+        let _ = core::hint::black_box(1.0f64) / core::hint::black_box(0.0);
+        wipe_registers();
+        let mut mxcsr = 0u32;
+        unsafe { core::arch::asm!("stmxcsr [{}]", in(reg) &mut mxcsr) };
+        assert_eq!(mxcsr, 0x1f80);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn deep_register_wipe() {
+        let eraser = Eraser::builder().deep_register_wipe(true).build().unwrap();
+        let (mut before, mut after, mut mxcsr) = (0u16, 0u16, 0u32);
+        unsafe { core::arch::asm!("fnstcw [{}]", in(reg) &mut before) };
+        assert_eq!(eraser.run(|| 6 * 7), 42);
+        unsafe {
+            core::arch::asm!(
+                "fnstcw [{}]",
+                "stmxcsr [{}]",
+                in(reg) &mut after,
+                in(reg) &mut mxcsr,
+            )
+        };
+        // The x87 control word survives, and `MXCSR` is at its default
+        assert_eq!(after, before);
+        assert_eq!(mxcsr, 0x1f80);
+    }
+
+    #[test]
+    fn blocking_in_place() {
+        use core::future::Future;
+        use core::pin::Pin;
+        use core::task::{Context, Poll, Waker};
+        use std::rc::Rc;
+
+        let mut stack = [MaybeUninit::uninit(); 4096];
+        let mut cx = Context::from_waker(Waker::noop());
+        let secret = [0x5au8; 16];
+        let mut run = unsafe {
+            run_then_erase_blocking_in_place(
+                move || secret.iter().fold(0, |acc, x| acc ^ x),
+                &mut stack,
+            )
+        };
+        // The first poll yields to the executor
+        assert_eq!(Pin::new(&mut run).poll(&mut cx), Poll::Pending);
+        assert_eq!(Pin::new(&mut run).poll(&mut cx), Poll::Ready(Ok(0)));
+        drop(run);
+
+        // A future that is dropped before the run drops the closure
+        let captured = Rc::new(());
+        let held = captured.clone();
+        let mut run = unsafe { run_then_erase_blocking_in_place(move || drop(held), &mut stack) };
+        assert_eq!(Pin::new(&mut run).poll(&mut cx), Poll::Pending);
+        assert_eq!(Rc::strong_count(&captured), 2);
+        drop(run);
+        assert_eq!(Rc::strong_count(&captured), 1);
+    }
+
+    #[test]
+    fn static_erased_stack() {
+        fn stack() -> Option<&'static mut [MaybeUninit<u8>]> {
+            static_erased_stack!(4096, ".data.eraser_test_stack")
+        }
+
+        let buf = stack().unwrap();
+        assert_eq!(buf.len(), 4096);
+        assert_eq!(buf.as_ptr() as usize % STACK_ALIGN, 0);
+        assert_eq!(
+            unsafe { run_then_erase_with_uninit_stack(|| 6 * 7, buf) },
+            42
+        );
+        // Every invocation hands out its stack only once
+        assert!(stack().is_none());
+        assert!(static_erased_stack!(1024).is_some());
+    }
+
+    #[test]
+    fn custom_register_wipe() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        static WIPES: AtomicUsize = AtomicUsize::new(0);
+        fn count_wipe() {
+            WIPES.fetch_add(1, Ordering::Relaxed);
+        }
+
+        register_wipe(count_wipe).unwrap();
+        let before = WIPES.load(Ordering::Relaxed);
+        assert_eq!(run_then_erase(|| 6 * 7, 4096), 42);
+        assert!(WIPES.load(Ordering::Relaxed) > before);
+        let before = WIPES.load(Ordering::Relaxed);
+        wipe_registers();
+        assert!(WIPES.load(Ordering::Relaxed) > before);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn stack_switch_saved_frame() {
+        // The return address of the call to the user function is right below
+        // the saved frame and the padding
+        let mut stack = AlignedStack::<8192>::new();
+        let top = stack.as_mut_slice().as_ptr_range().end as usize;
+        let slot = top - SAVED_FRAME_SIZE - FRAME_PADDING - 8;
+        let ret = unsafe {
+            run_then_erase_with_stack(|| (slot as *const usize).read(), stack.as_mut_slice())
+        };
+        let start = stack_switch as *const () as usize;
+        assert!((start..start + 512).contains(&ret), "{ret:#x}");
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn stack_switch_landing_pad() {
+        let code = stack_switch as *const [u8; 4];
+        // `endbr64`
+        assert_eq!(unsafe { *code }, [0xf3, 0x0f, 0x1e, 0xfa]);
+    }
+
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    #[test]
+    fn backtrace_redacted() {
+        let eraser = Eraser::builder().redact_backtraces(true).build().unwrap();
+        let trace = eraser.run(|| std::backtrace::Backtrace::force_capture().to_string());
+        assert!(
+            trace
+                .lines()
+                .any(|line| line.contains(": eraser::tests::backtrace_redacted::{")),
+            "{trace}"
+        );
+        assert!(
+            !trace
+                .lines()
+                .any(|line| line.ends_with(": eraser::tests::backtrace_redacted")),
+            "{trace}"
+        );
     }
 }
@@ -0,0 +1,482 @@
+use crate::{mte, pkey, sys, EraserError};
+use std::{alloc, mem::MaybeUninit, ptr};
+
+/// The protections that are applied to a `ProtectedMemory` allocation.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Protections {
+    /// Put an inaccessible guard page below the memory.
+    pub(crate) guard_page: bool,
+    /// Lock the memory into RAM.
+    pub(crate) mlock: bool,
+    /// Continue without locking if the memory cannot be locked.
+    pub(crate) mlock_fallback: bool,
+    /// Exclude the memory from core dumps.
+    pub(crate) dont_dump: bool,
+    /// Replace the memory with zeros in the child after a `fork()`.
+    pub(crate) wipe_on_fork: bool,
+    /// Seal the guard page with `mseal`, so that it cannot be unmapped or made
+    /// accessible anymore.
+    pub(crate) seal_guard_page: bool,
+    /// Tag the usable memory with the shared protection key, so that it can
+    /// only be accessed while access is enabled with `ProtectedMemory::access`.
+    pub(crate) protection_key: bool,
+    /// Map the usable memory with memory tags, so that it can be retagged
+    /// with `ProtectedMemory::tagged`.
+    pub(crate) memory_tagging: bool,
+    /// Back the memory with `memfd_secret`, which removes it from the kernel
+    /// direct map.
+    pub(crate) secret_memory: bool,
+}
+
+impl Protections {
+    /// Whether the memory has to span whole pages.  This is also the case for
+    /// `mlock`, because unlocking the memory would otherwise also unlock the
+    /// neighbouring allocations that share its pages.
+    pub(crate) fn needs_pages(&self) -> bool {
+        self.guard_page
+            || self.mlock
+            || self.dont_dump
+            || self.wipe_on_fork
+            || self.protection_key
+            || self.memory_tagging
+            || self.secret_memory
+    }
+}
+
+/// Allocations of at least this many bytes are mapped directly from the
+/// operating system (where supported), even if no protections are requested.
+const MAP_THRESHOLD: usize = 64 * 1024;
+
+/// Memory for stacks and secret values, with optional protections.
+///
+/// This is used for ephemeral stacks and for secret values.  On Linux, macOS
+/// and Windows, memory that needs protections and large allocations are
+/// mapped with `mmap` (or `VirtualAlloc`) instead of taken from the global
+/// allocator, so that the protections apply to a region of their own and
+/// large stacks do not fragment the heap.  If requested, the memory is
+/// preceded by an inaccessible guard page, so that a stack overflow results
+/// in a segmentation fault instead of silently corrupting the heap, it is
+/// locked into memory so that it is never swapped out, it is excluded from
+/// core dumps, and it can be backed by `memfd_secret` so that not even the
+/// kernel can read it through its direct map.  The allocation is released on
+/// drop, also when the user function panics.
+#[derive(Debug)]
+pub(crate) struct ProtectedMemory {
+    ptr: ptr::NonNull<u8>,
+    layout: alloc::Layout,
+    /// Whether the allocation was mapped with `sys::map` instead of taken
+    /// from the global allocator.
+    mapped: bool,
+    /// Length of the usable part of the allocation, after the guard region.
+    len: usize,
+    /// Length of the guard region at the start of the allocation.
+    guard_len: usize,
+    /// Whether the usable part of the allocation has been locked.
+    locked: bool,
+    /// Whether the allocation has been excluded from core dumps.
+    dont_dump: bool,
+    /// Whether the kernel keeps the contents of the allocation from forked
+    /// child processes.
+    wiped_on_fork: bool,
+    /// Whether the guard region has been sealed.  A sealed region can never
+    /// be unmapped, so it is left behind when the memory is dropped.
+    sealed: bool,
+    /// The protection key that the usable memory is tagged with, if any.
+    protection_key: Option<i32>,
+    /// Whether the usable memory carries memory tags.
+    memory_tagging: bool,
+}
+
+// The allocation is exclusively owned by the `ProtectedMemory`.
+unsafe impl Send for ProtectedMemory {}
+unsafe impl Sync for ProtectedMemory {}
+
+impl ProtectedMemory {
+    /// Allocate (at least) `len` zeroed usable bytes, aligned to `align`
+    /// bytes, and apply the requested protections.
+    ///
+    /// If any protection is requested, the usable part is rounded up to a
+    /// whole number of pages.  `len` must not be zero.
+    pub(crate) fn new(
+        len: usize,
+        align: usize,
+        protections: &Protections,
+    ) -> Result<Self, EraserError> {
+        Self::allocate(len, align, protections, true)
+    }
+
+    /// Like `new`, but do not zero the memory.  The memory can then only be
+    /// accessed through `as_uninit_slice` until it has been written.
+    pub(crate) fn new_uninit(
+        len: usize,
+        align: usize,
+        protections: &Protections,
+    ) -> Result<Self, EraserError> {
+        Self::allocate(len, align, protections, false)
+    }
+
+    fn allocate(
+        len: usize,
+        align: usize,
+        protections: &Protections,
+        zeroed: bool,
+    ) -> Result<Self, EraserError> {
+        assert_ne!(len, 0, "cannot allocate zero bytes");
+
+        // Memory protections only work on whole pages
+        let page_size = sys::page_size();
+        let (size, align, guard_len) = if protections.needs_pages() {
+            let guard_len = if protections.guard_page { page_size } else { 0 };
+            let size = len
+                .checked_next_multiple_of(page_size)
+                .and_then(|size| size.checked_add(guard_len))
+                .ok_or(EraserError::AllocFailed)?;
+            (size, align.max(page_size), guard_len)
+        } else {
+            (len, align, 0)
+        };
+        let usable_len = size - guard_len;
+
+        if protections.secret_memory && align > page_size {
+            return Err(EraserError::Unsupported);
+        }
+        let mapped = sys::CAN_MAP
+            && (protections.needs_pages() || len >= MAP_THRESHOLD || sys::MAP_STACKS)
+            && align <= page_size;
+        let (ptr, layout) = if mapped {
+            // Mappings are always zeroed, and span whole pages
+            let size = size
+                .checked_next_multiple_of(page_size)
+                .ok_or(EraserError::AllocFailed)?;
+            let layout = alloc::Layout::from_size_align(size, page_size)
+                .map_err(|_| EraserError::AllocFailed)?;
+            let ptr = if protections.secret_memory {
+                sys::map_secret(size)?
+            } else {
+                sys::map(size)?
+            };
+            (ptr, layout)
+        } else {
+            let layout = alloc::Layout::from_size_align(size, align)
+                .map_err(|_| EraserError::AllocFailed)?;
+            let ptr = if zeroed {
+                unsafe { alloc::alloc_zeroed(layout) }
+            } else {
+                unsafe { alloc::alloc(layout) }
+            };
+            (ptr, layout)
+        };
+        let ptr = ptr::NonNull::new(ptr).ok_or(EraserError::AllocFailed)?;
+        let mut memory = ProtectedMemory {
+            ptr,
+            layout,
+            mapped,
+            len: usable_len,
+            guard_len: 0,
+            locked: false,
+            dont_dump: false,
+            wiped_on_fork: false,
+            sealed: false,
+            protection_key: None,
+            memory_tagging: false,
+        };
+
+        if protections.dont_dump {
+            unsafe { sys::dont_dump(memory.ptr.as_ptr(), size)? };
+            memory.dont_dump = true;
+        }
+        if protections.wipe_on_fork {
+            // Only supported on private anonymous mappings.  A secret memory
+            // mapping is shared with the child instead, so leave it out of the
+            // child altogether.
+            if !memory.mapped {
+                return Err(EraserError::Unsupported);
+            }
+            let (ptr, size) = (memory.ptr.as_ptr(), memory.layout.size());
+            if protections.secret_memory {
+                unsafe { sys::dont_fork(ptr, size)? };
+            } else {
+                unsafe { sys::wipe_on_fork(ptr, size)? };
+            }
+            memory.wiped_on_fork = true;
+        }
+        if protections.guard_page {
+            unsafe { sys::protect_none(memory.ptr.as_ptr(), guard_len)? };
+            memory.guard_len = guard_len;
+            if protections.seal_guard_page {
+                // Sealing only works on mappings
+                if !memory.mapped {
+                    return Err(EraserError::Unsupported);
+                }
+                unsafe { sys::seal(memory.ptr.as_ptr(), guard_len)? };
+                memory.sealed = true;
+            }
+        }
+        if protections.mlock {
+            let slice = memory.as_uninit_slice();
+            match unsafe { sys::lock(slice.as_mut_ptr() as *mut u8, slice.len()) } {
+                Ok(()) => memory.locked = true,
+                Err(_) if protections.mlock_fallback => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if protections.memory_tagging {
+            if !memory.mapped {
+                return Err(EraserError::Unsupported);
+            }
+            let slice = memory.as_uninit_slice();
+            unsafe { mte::protect(slice.as_mut_ptr() as *mut u8, slice.len())? };
+            memory.memory_tagging = true;
+        }
+        if protections.protection_key {
+            // Tag the memory last, so that setting it up is not affected by
+            // the key
+            if !memory.mapped {
+                return Err(EraserError::Unsupported);
+            }
+            let key = pkey::key()?;
+            let slice = memory.as_uninit_slice();
+            unsafe { sys::pkey_mprotect(slice.as_mut_ptr() as *mut u8, slice.len(), key)? };
+            memory.protection_key = Some(key);
+        }
+        Ok(memory)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Return whether the memory is locked into RAM.
+    pub(crate) fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Return whether forked child processes never see the contents of the
+    /// memory.
+    pub(crate) fn is_wiped_on_fork(&self) -> bool {
+        self.wiped_on_fork
+    }
+
+    /// Return the protection key that the usable memory is tagged with, if
+    /// any.
+    pub(crate) fn protection_key(&self) -> Option<i32> {
+        self.protection_key
+    }
+
+    /// Return whether the usable memory carries memory tags.
+    pub(crate) fn has_memory_tags(&self) -> bool {
+        self.memory_tagging
+    }
+
+    /// Return whether the usable memory is preceded by a guard page.
+    pub(crate) fn has_guard_page(&self) -> bool {
+        self.guard_len != 0
+    }
+
+    /// Allow the current thread to access the memory until the returned
+    /// value is dropped.  This is only needed if the memory is tagged with a
+    /// protection key.
+    pub(crate) fn access(&self) -> pkey::Access {
+        pkey::Access::new(self.protection_key)
+    }
+
+    /// Tag the usable memory with a fresh random tag, until the returned
+    /// value is dropped.  This does nothing if the memory does not carry
+    /// memory tags.
+    pub(crate) fn tagged(&mut self) -> Tagged<'_> {
+        let slice = self.as_uninit_slice();
+        let (ptr, len) = (slice.as_mut_ptr() as *mut u8, slice.len());
+        let ptr = if self.memory_tagging {
+            unsafe { mte::tag(ptr, len) }
+        } else {
+            ptr
+        };
+        Tagged { memory: self, ptr }
+    }
+
+    pub(crate) fn as_ptr(&self) -> *const u8 {
+        unsafe { self.ptr.as_ptr().add(self.guard_len) }
+    }
+
+    /// Return the usable memory.  The memory must have been zeroed on
+    /// allocation, or have been written since.
+    pub(crate) fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe {
+            core::slice::from_raw_parts_mut(self.ptr.as_ptr().add(self.guard_len), self.len())
+        }
+    }
+
+    /// Return the usable memory, which may be uninitialized.
+    pub(crate) fn as_uninit_slice(&mut self) -> &mut [MaybeUninit<u8>] {
+        unsafe {
+            core::slice::from_raw_parts_mut(
+                self.ptr.as_ptr().add(self.guard_len) as *mut MaybeUninit<u8>,
+                self.len(),
+            )
+        }
+    }
+}
+
+/// The usable memory of a [`ProtectedMemory`], tagged with a random tag.
+///
+/// When this is dropped, the tags of the memory are reset, so that the
+/// pointers that are derived from this value cannot access the memory
+/// anymore.
+pub(crate) struct Tagged<'a> {
+    memory: &'a mut ProtectedMemory,
+    /// The start of the usable memory, with the tag.
+    ptr: *mut u8,
+}
+
+impl Tagged<'_> {
+    /// Return the usable memory, which may be uninitialized, through a
+    /// pointer with the tag.
+    pub(crate) fn as_uninit_slice(&mut self) -> &mut [MaybeUninit<u8>] {
+        unsafe {
+            core::slice::from_raw_parts_mut(self.ptr as *mut MaybeUninit<u8>, self.memory.len())
+        }
+    }
+}
+
+impl Drop for Tagged<'_> {
+    fn drop(&mut self) {
+        if self.memory.memory_tagging {
+            unsafe { mte::untag(self.ptr, self.memory.len()) };
+        }
+    }
+}
+
+impl Drop for ProtectedMemory {
+    fn drop(&mut self) {
+        if self.mapped {
+            // Unmapping also drops the protections.  A sealed guard page
+            // cannot be unmapped, so it stays reserved (but without any memory
+            // behind it) until the process exits.
+            let skip = if self.sealed { self.guard_len } else { 0 };
+            unsafe { sys::unmap(self.ptr.as_ptr().add(skip), self.layout.size() - skip) };
+            return;
+        }
+        if self.locked {
+            let slice = self.as_uninit_slice();
+            unsafe { sys::unlock(slice.as_mut_ptr() as *mut u8, slice.len()) };
+        }
+        if self.guard_len != 0 {
+            // The allocator will want to write to this page again.  If it
+            // cannot be unprotected, the allocation is leaked instead, because
+            // panicking here would abort the process during an unwind.
+            let result = unsafe { sys::protect_read_write(self.ptr.as_ptr(), self.guard_len) };
+            if result.is_err() {
+                return;
+            }
+        }
+        if self.dont_dump {
+            unsafe { sys::do_dump(self.ptr.as_ptr(), self.layout.size()) };
+        }
+        unsafe { alloc::dealloc(self.ptr.as_ptr(), self.layout) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn large_allocation() {
+        let len = 2 * MAP_THRESHOLD + 32;
+        let mut memory = ProtectedMemory::new(len, 32, &Protections::default()).unwrap();
+        assert_eq!(memory.mapped, sys::CAN_MAP);
+        assert_eq!(memory.len(), len);
+        let slice = memory.as_mut_slice();
+        assert!(slice.iter().all(|&x| x == 0));
+        slice.fill(0xAA);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn wipe_on_fork() {
+        extern "C" {
+            fn fork() -> i32;
+            fn waitpid(pid: i32, status: *mut i32, options: i32) -> i32;
+            fn _exit(status: i32) -> !;
+        }
+
+        let protections = Protections {
+            wipe_on_fork: true,
+            ..Protections::default()
+        };
+        let mut memory = ProtectedMemory::new(4096, 32, &protections).unwrap();
+        memory.as_mut_slice().fill(0xAA);
+        match unsafe { fork() } {
+            0 => {
+                // Only async-signal-safe operations are allowed in the child
+                let wiped = memory.as_mut_slice().iter().all(|&x| x == 0);
+                unsafe { _exit(if wiped { 0 } else { 1 }) }
+            }
+            pid => {
+                assert!(pid > 0);
+                let mut status = 0;
+                assert_eq!(unsafe { waitpid(pid, &mut status, 0) }, pid);
+                assert_eq!(status, 0);
+                assert!(memory.as_mut_slice().iter().all(|&x| x == 0xAA));
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn secret_memory() {
+        let protections = Protections {
+            secret_memory: true,
+            guard_page: true,
+            wipe_on_fork: true,
+            ..Protections::default()
+        };
+        // `memfd_secret` is not available on every kernel
+        let mut memory = match ProtectedMemory::new(3 * 4096, 32, &protections) {
+            Err(EraserError::Unsupported) => return,
+            memory => memory.unwrap(),
+        };
+        assert!(memory.has_guard_page());
+        let slice = memory.as_mut_slice();
+        assert!(slice.iter().all(|&x| x == 0));
+        slice.fill(0xAA);
+        assert!(slice.iter().all(|&x| x == 0xAA));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn sealed_guard_page() {
+        let protections = Protections {
+            guard_page: true,
+            seal_guard_page: true,
+            ..Protections::default()
+        };
+        // `mseal` is not available on every kernel
+        let mut memory = match ProtectedMemory::new(4096, 32, &protections) {
+            Err(EraserError::Unsupported) => return,
+            memory => memory.unwrap(),
+        };
+        assert!(memory.sealed);
+        let result = unsafe { sys::protect_read_write(memory.ptr.as_ptr(), memory.guard_len) };
+        assert_eq!(result, Err(EraserError::ProtectFailed));
+        memory.as_mut_slice().fill(0xAA);
+    }
+
+    #[test]
+    fn memory_tagging() {
+        let protections = Protections {
+            memory_tagging: true,
+            ..Protections::default()
+        };
+        // MTE is not available on every CPU
+        let mut memory = match ProtectedMemory::new(4096, 32, &protections) {
+            Err(EraserError::Unsupported) => return,
+            memory => memory.unwrap(),
+        };
+        memory
+            .tagged()
+            .as_uninit_slice()
+            .fill(MaybeUninit::new(0xAA));
+        assert!(memory.as_mut_slice().iter().all(|&x| x == 0xAA));
+    }
+}
@@ -0,0 +1,197 @@
+//! Running the user function on MSP430, without `std`.
+//!
+//! Like on the other bare-metal targets, there are no threads and no
+//! unwinding, so the closure is passed to the ephemeral stack in a register,
+//! and a panic in the user function goes straight to the panic handler of the
+//! application.
+//!
+//! The stack pointer (`r1`) is switched directly.  An interrupt that is taken
+//! during the run pushes `PC` and `SR` onto the ephemeral stack (where they are
+//! erased with the rest), and so does its handler with the registers that it
+//! saves.  With the `mask_interrupts` feature, the interrupts are masked (by
+//! clearing `SR.GIE`) from before the stack switch until the stack has been
+//! erased and the registers have been wiped instead.
+//!
+//! The hardware multiplier is a peripheral, so its operand and result
+//! registers are not wiped here.  Code that multiplies secrets on a device
+//! with a multiplier can clear them with a wipe that is registered with
+//! [`register_wipe`](crate::register_wipe).
+
+use crate::{EraseConfig, Payload};
+use core::arch::asm;
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::Ordering;
+
+/// Run the user function on `stack`, which has already been aligned by
+/// `align_stack`.
+pub(crate) unsafe fn run_on_stack<F, R>(
+    f: F,
+    stack: &mut [MaybeUninit<u8>],
+    config: &EraseConfig,
+) -> R
+where
+    F: FnOnce() -> R,
+{
+    let stack_bottom = stack.as_mut_ptr() as *mut u8;
+    let stack_top = stack_bottom.add(stack.len());
+    let mut payload = Payload {
+        f: MaybeUninit::new(f),
+        result: MaybeUninit::<R>::uninit(),
+    };
+    let data = &mut payload as *mut Payload<F, R> as *mut u8;
+
+    crate::events::enter(stack_bottom, stack.len());
+    #[cfg(feature = "mask_interrupts")]
+    let sr = mask_interrupts();
+    stack_switch::<F, R>(data, stack_top);
+    crate::events::check_overflow(stack_bottom);
+    crate::erase_words(stack_bottom, stack.len(), config);
+    crate::erase_slot(&mut payload.f, config);
+
+    let result = payload.result.as_ptr().read();
+    crate::erase_slot(&mut payload.result, config);
+    crate::custom_wipe::run();
+    wipe_all_registers();
+    #[cfg(feature = "mask_interrupts")]
+    restore_interrupts(sr);
+    crate::events::erased(stack.len());
+    result
+}
+
+extern "C" fn trampoline<F: FnOnce() -> R, R>(data: *mut u8) {
+    unsafe { crate::call_user_fn::<F, R>(data) }
+}
+
+/// Run `f` with the interrupts masked, and restore the mask afterwards.
+pub(crate) fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let sr = mask_interrupts();
+    let result = f();
+    restore_interrupts(sr);
+    result
+}
+
+/// Mask the interrupts, and return the old value of `SR`.
+fn mask_interrupts() -> u16 {
+    let sr: u16;
+    unsafe {
+        asm!(
+            "mov r2, {}",
+            "dint",
+            // `dint` only takes effect after the next instruction
+            "nop",
+            out(reg) sr,
+            options(nostack, preserves_flags),
+        );
+    }
+    sr
+}
+
+/// Unmask the interrupts, if they were enabled in `SR` (as returned by
+/// `mask_interrupts`).
+fn restore_interrupts(sr: u16) {
+    unsafe {
+        asm!(
+            "nop",
+            // GIE is bit 3
+            "bis {}, r2",
+            "nop",
+            in(reg) sr & 0x8,
+            options(nostack, preserves_flags),
+        );
+    }
+}
+
+/// A stand-in for the atomic types of `core`, which MSP430 does not have.
+///
+/// MSP430 has a single core, and a load or store of up to 16 bits is a single
+/// instruction, so it cannot be torn by an interrupt.  Sequences that read and
+/// then write must run under `without_interrupts`.
+pub struct Atomic<T>(UnsafeCell<T>);
+
+unsafe impl<T> Sync for Atomic<T> {}
+
+pub(crate) type AtomicBool = Atomic<bool>;
+pub(crate) type AtomicPtr<T> = Atomic<*mut T>;
+pub(crate) type AtomicUsize = Atomic<usize>;
+
+impl<T: Copy> Atomic<T> {
+    pub const fn new(value: T) -> Self {
+        Atomic(UnsafeCell::new(value))
+    }
+
+    pub fn load(&self, _order: Ordering) -> T {
+        let value = unsafe { self.0.get().read_volatile() };
+        compiler_barrier();
+        value
+    }
+
+    pub fn store(&self, value: T, _order: Ordering) {
+        compiler_barrier();
+        unsafe { self.0.get().write_volatile(value) };
+    }
+}
+
+/// Keep the compiler from moving memory accesses across this point.
+/// `compiler_fence` cannot be used, because LLVM cannot lower it on MSP430.
+fn compiler_barrier() {
+    unsafe { asm!("", options(nostack, preserves_flags)) };
+}
+
+/// Call `trampoline::<F, R>(data)` on the stack that ends at `stack_top`.
+///
+/// The old stack pointer is pushed onto the new stack, and popped straight
+/// back into `r1` afterwards.  The frame pointer (`r4`) and `r5`–`r10` are
+/// preserved by the trampoline.
+#[inline(never)]
+unsafe fn stack_switch<F: FnOnce() -> R, R>(data: *mut u8, stack_top: *mut u8) {
+    asm!(
+        // Switch stacks, and save the old stack pointer on the new one
+        "mov r1, r14",
+        "mov r13, r1",
+        "push r14",
+        // Call the running function using the new stack
+        "call #{user_fn}",
+        // Restore the original stack pointer
+        "mov @r1, r1",
+        user_fn = sym trampoline::<F, R>,
+        inout("r12") data => _,
+        inout("r13") stack_top => _,
+        // The caller-saved registers of the MSP430 EABI
+        lateout("r11") _,
+        lateout("r14") _,
+        lateout("r15") _,
+    );
+}
+
+/// Wipe the general-purpose registers.
+///
+/// `r0`–`r3` are `PC`, `SP`, `SR` and the constant generator, and `r4` is the
+/// frame pointer, so only `r5`–`r15` can be wiped here.
+pub(crate) unsafe fn wipe_all_registers() {
+    asm!(
+        "clr r5",
+        "clr r6",
+        "clr r7",
+        "clr r8",
+        "clr r9",
+        "clr r10",
+        "clr r11",
+        "clr r12",
+        "clr r13",
+        "clr r14",
+        "clr r15",
+        lateout("r5") _,
+        lateout("r6") _,
+        lateout("r7") _,
+        lateout("r8") _,
+        lateout("r9") _,
+        lateout("r10") _,
+        lateout("r11") _,
+        lateout("r12") _,
+        lateout("r13") _,
+        lateout("r14") _,
+        lateout("r15") _,
+        options(nostack, preserves_flags),
+    )
+}
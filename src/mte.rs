@@ -0,0 +1,172 @@
+//! Tagging of the ephemeral stacks with the Arm Memory Tagging Extension.
+//!
+//! Memory that is mapped with `PROT_MTE` carries a 4-bit allocation tag for
+//! every 16-byte granule, and every pointer carries a 4-bit logical tag in its
+//! top byte.  With tag checking enabled, an access through a pointer whose tag
+//! does not match the tag of the memory traps.
+//!
+//! Before a run, the granules of a stack with
+//! [`EraserBuilder::memory_tagging`](crate::EraserBuilder::memory_tagging)
+//! are tagged with a fresh random (non-zero) tag, and the run uses a stack
+//! pointer with that tag.  After the run, the stack is erased and its tags
+//! are reset to zero.  A pointer into the stack that leaks out of a run, or an
+//! untagged pointer that strays into the stack during a run, then no longer
+//! matches the tag of the memory, and using it traps.
+//!
+//! Tag checking is enabled (in synchronous mode) for every thread that runs
+//! on a tagged stack, unless the thread already had tag checking enabled.
+//!
+//! This is only supported on aarch64 Linux, on CPUs with MTE.
+
+use crate::EraserError;
+
+/// Map the pages in `[addr, addr + len)` with `PROT_MTE`, so that they carry
+/// allocation tags.
+pub(crate) unsafe fn protect(addr: *mut u8, len: usize) -> Result<(), EraserError> {
+    #[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+    {
+        if !std::arch::is_aarch64_feature_detected!("mte") {
+            return Err(EraserError::Unsupported);
+        }
+        crate::sys::protect_tagged(addr, len)
+    }
+    #[cfg(not(all(target_os = "linux", target_arch = "aarch64")))]
+    {
+        let _ = (addr, len);
+        Err(EraserError::Unsupported)
+    }
+}
+
+/// Tag the `len` bytes at `ptr` with a fresh random tag, and return `ptr`
+/// with that tag.
+///
+/// The memory must have been set up with [`protect`], and `ptr` and `len`
+/// must be aligned to 32 bytes.
+pub(crate) unsafe fn tag(ptr: *mut u8, len: usize) -> *mut u8 {
+    #[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+    {
+        aarch64::enable_checking();
+        aarch64::tag(ptr, len)
+    }
+    #[cfg(not(all(target_os = "linux", target_arch = "aarch64")))]
+    {
+        let _ = len;
+        ptr
+    }
+}
+
+/// Reset the tags of the `len` bytes at `ptr` to zero, and return `ptr`
+/// without a tag.
+pub(crate) unsafe fn untag(ptr: *mut u8, len: usize) -> *mut u8 {
+    #[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+    {
+        aarch64::untag(ptr, len)
+    }
+    #[cfg(not(all(target_os = "linux", target_arch = "aarch64")))]
+    {
+        let _ = len;
+        ptr
+    }
+}
+
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+mod aarch64 {
+    use core::arch::asm;
+    use core::ffi::{c_int, c_long};
+    use std::cell::Cell;
+
+    const PR_SET_TAGGED_ADDR_CTRL: c_int = 55;
+    const PR_GET_TAGGED_ADDR_CTRL: c_int = 56;
+    const PR_TAGGED_ADDR_ENABLE: c_long = 1 << 0;
+    const PR_MTE_TCF_SYNC: c_long = 1 << 1;
+    const PR_MTE_TCF_MASK: c_long = 3 << 1;
+    /// Let `irg` generate all tags except for zero.
+    const PR_MTE_TAG_INCLUDE: c_long = 0xfffe << 3;
+
+    /// The bits of a pointer that hold its tag.
+    const TAG_MASK: usize = 0xf << 56;
+    const GRANULE_PAIR: usize = 32;
+
+    extern "C" {
+        fn prctl(option: c_int, ...) -> c_int;
+    }
+
+    thread_local! {
+        static CHECKING: Cell<bool> = const { Cell::new(false) };
+    }
+
+    /// Enable synchronous tag checking for the current thread, unless some
+    /// tag checking mode was already enabled.
+    pub(super) fn enable_checking() {
+        if CHECKING.with(Cell::get) {
+            return;
+        }
+        let ctrl = unsafe {
+            prctl(
+                PR_GET_TAGGED_ADDR_CTRL,
+                0 as c_long,
+                0 as c_long,
+                0 as c_long,
+                0 as c_long,
+            )
+        };
+        if ctrl >= 0 && ctrl as c_long & PR_MTE_TCF_MASK == 0 {
+            let ctrl = PR_TAGGED_ADDR_ENABLE | PR_MTE_TCF_SYNC | PR_MTE_TAG_INCLUDE;
+            unsafe {
+                prctl(
+                    PR_SET_TAGGED_ADDR_CTRL,
+                    ctrl,
+                    0 as c_long,
+                    0 as c_long,
+                    0 as c_long,
+                )
+            };
+        }
+        CHECKING.with(|cell| cell.set(true));
+    }
+
+    #[target_feature(enable = "mte")]
+    pub(super) unsafe fn tag(ptr: *mut u8, len: usize) -> *mut u8 {
+        let tagged: *mut u8;
+        asm!(
+            "irg {tagged}, {ptr}, {exclude}",
+            tagged = out(reg) tagged,
+            // Only the address is used, the memory is not accessed
+            ptr = in(reg) ptr as usize,
+            exclude = in(reg) 1usize,
+            options(nomem, nostack, preserves_flags),
+        );
+        set_tags(tagged, len);
+        tagged
+    }
+
+    #[target_feature(enable = "mte")]
+    pub(super) unsafe fn untag(ptr: *mut u8, len: usize) -> *mut u8 {
+        let untagged = (ptr as usize & !TAG_MASK) as *mut u8;
+        set_tags(untagged, len);
+        untagged
+    }
+
+    /// Set the allocation tags of the `len` bytes at `ptr` to the tag of
+    /// `ptr`.
+    #[target_feature(enable = "mte")]
+    unsafe fn set_tags(ptr: *mut u8, len: usize) {
+        debug_assert_eq!(ptr.align_offset(GRANULE_PAIR), 0);
+        debug_assert_eq!(len % GRANULE_PAIR, 0);
+        if len == 0 {
+            return;
+        }
+        // `st2g` takes the tag from its first operand, and tags the two
+        // granules at its second operand
+        asm!(
+            "2:",
+            "st2g {tag}, [{addr}], #32",
+            "subs {len}, {len}, #32",
+            "b.ne 2b",
+            tag = in(reg) ptr,
+            addr = inout(reg) ptr => _,
+            len = inout(reg) len => _,
+            options(nostack),
+        );
+    }
+}
@@ -0,0 +1,319 @@
+//! Detection of stack overflows in the user function.
+//!
+//! When an ephemeral stack has a guard page, an overflow of the stack faults
+//! on that guard page.  While such a run is active, a `SIGSEGV`/`SIGBUS`
+//! handler checks whether a fault hit the page right below the active stack.
+//! If so, the handler abandons the user function: it rewrites the interrupted
+//! context such that execution continues right after the stack switch in
+//! `stack_switch`, with the stack pointer at the values that were saved there.
+//! The frames of the user function are never unwound (so no destructors run),
+//! but the stack is still erased afterwards.  Then the process is aborted,
+//! because whatever the abandoned frames held (locks, borrows by scoped
+//! threads, pinned values) can never be released; only a stack that was set
+//! up with `EraserBuilder::recover_from_overflow`, whose caller promised that
+//! this is sound, returns
+//! [`EraserError::StackOverflow`](crate::EraserError::StackOverflow) instead.
+//!
+//! Only a fault on the guard page itself is recognized.  Code that is compiled
+//! by rustc for x86_64 probes every page of a frame that is larger than a page
+//! before it uses the frame, so it always hits the guard page first, but
+//! foreign code without stack probes can skip over it.  Such an overflow is
+//! not detected: it either faults elsewhere (and is passed on as below) or
+//! overwrites the memory below the guard page.
+//!
+//! Faults anywhere else are passed on to the handler that was installed
+//! before.  The handler runs on an alternate signal stack, because the
+//! overflowed stack cannot be used anymore; if the thread does not have one,
+//! one is set up for the duration of the run.
+//!
+//! This is only supported on x86_64 Linux.  On other targets an overflow still
+//! crashes the process (on the guard page).
+
+use crate::EraserError;
+use std::mem::MaybeUninit;
+
+/// Report an overflow of the stack, and abort the process.
+///
+/// The abandoned frames of the user function may still hold the lock on
+/// `stderr`, so the message is written to the file descriptor directly.
+pub(crate) fn abort() -> ! {
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    linux::write_stderr(b"eraser: the user function overflowed the ephemeral stack, aborting\n");
+    std::process::abort()
+}
+
+/// Registers an ephemeral stack for overflow detection for the duration of a
+/// run.
+pub(crate) struct OverflowGuard {
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    inner: linux::Guard,
+}
+
+impl OverflowGuard {
+    /// Start catching overflows of `stack`.
+    pub(crate) fn new(stack: &[MaybeUninit<u8>]) -> Result<Self, EraserError> {
+        #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+        {
+            Ok(OverflowGuard {
+                inner: linux::Guard::new(stack)?,
+            })
+        }
+        #[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+        {
+            let _ = stack;
+            Ok(OverflowGuard {})
+        }
+    }
+
+    /// Return whether the user function overflowed the stack.
+    pub(crate) fn overflowed(&self) -> bool {
+        #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+        {
+            self.inner.overflowed()
+        }
+        #[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+        {
+            false
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod linux {
+    use crate::EraserError;
+    use core::ffi::{c_int, c_void};
+    use std::cell::{Cell, UnsafeCell};
+    use std::mem::MaybeUninit;
+    use std::sync::OnceLock;
+
+    const SIGBUS: c_int = 7;
+    const SIGSEGV: c_int = 11;
+    const SA_SIGINFO: c_int = 0x4;
+    const SA_ONSTACK: c_int = 0x0800_0000;
+    const SS_DISABLE: c_int = 2;
+    const SIG_DFL: usize = 0;
+    const SIG_IGN: usize = 1;
+    /// Size of the alternate signal stack that is set up if the thread does
+    /// not have one.
+    const ALT_STACK_SIZE: usize = 64 * 1024;
+
+    /// Offset of `uc_mcontext.gregs` in `ucontext_t`.
+    const GREGS_OFFSET: usize = 40;
+    const REG_RSP: usize = 15;
+    const REG_RIP: usize = 16;
+
+    #[repr(C)]
+    struct SigAction {
+        sa_sigaction: usize,
+        sa_mask: [u64; 16],
+        sa_flags: c_int,
+        sa_restorer: usize,
+    }
+
+    #[repr(C)]
+    struct SigInfo {
+        si_signo: c_int,
+        si_errno: c_int,
+        si_code: c_int,
+        si_addr: *mut c_void,
+    }
+
+    #[repr(C)]
+    struct StackT {
+        ss_sp: *mut c_void,
+        ss_flags: c_int,
+        ss_size: usize,
+    }
+
+    extern "C" {
+        fn sigaction(signum: c_int, act: *const SigAction, oldact: *mut SigAction) -> c_int;
+        fn sigaltstack(ss: *const StackT, old_ss: *mut StackT) -> c_int;
+        fn write(fd: c_int, buf: *const c_void, count: usize) -> isize;
+    }
+
+    /// Write `msg` to `stderr`, without taking the lock of `std::io::stderr`.
+    pub(super) fn write_stderr(msg: &[u8]) {
+        unsafe { write(2, msg.as_ptr() as *const c_void, msg.len()) };
+    }
+
+    /// The stack that is currently being run on by this thread.
+    #[derive(Clone, Copy)]
+    struct Active {
+        /// Lowest address of the stack.
+        bottom: usize,
+        /// Address one past the highest address of the stack.
+        top: usize,
+        page_size: usize,
+    }
+
+    thread_local! {
+        static ACTIVE: Cell<Option<Active>> = const { Cell::new(None) };
+        static OVERFLOWED: Cell<bool> = const { Cell::new(false) };
+        static ALT_STACK: UnsafeCell<Option<Box<[u8]>>> = const { UnsafeCell::new(None) };
+    }
+
+    /// The handlers that were installed before ours, for `SIGSEGV` and
+    /// `SIGBUS`.
+    struct Previous(UnsafeCell<[MaybeUninit<SigAction>; 2]>);
+
+    // Only written once, under `INSTALL`, before our handler is installed.
+    unsafe impl Sync for Previous {}
+
+    static PREVIOUS: Previous = Previous(UnsafeCell::new([
+        MaybeUninit::uninit(),
+        MaybeUninit::uninit(),
+    ]));
+    static INSTALL: OnceLock<Result<(), EraserError>> = OnceLock::new();
+
+    /// Install our handlers, once; every call returns the outcome of that
+    /// installation.
+    fn install_handlers() -> Result<(), EraserError> {
+        *INSTALL.get_or_init(|| {
+            let mut result = Ok(());
+            for (index, signum) in [SIGSEGV, SIGBUS].into_iter().enumerate() {
+                let action = SigAction {
+                    sa_sigaction: handler as *const () as usize,
+                    sa_mask: [0; 16],
+                    sa_flags: SA_SIGINFO | SA_ONSTACK,
+                    sa_restorer: 0,
+                };
+                let previous = unsafe { (*PREVIOUS.0.get())[index].as_mut_ptr() };
+                if unsafe { sigaction(signum, &action, previous) } != 0 {
+                    result = Err(EraserError::Unsupported);
+                }
+            }
+            result
+        })
+    }
+
+    unsafe extern "C" fn handler(signum: c_int, info: *mut SigInfo, context: *mut c_void) {
+        let addr = (*info).si_addr as usize;
+        let active = ACTIVE.with(Cell::get);
+        match active {
+            Some(active) if addr < active.bottom && addr >= active.bottom - active.page_size => {
+                // Continue at the return address that `stack_switch` pushed,
+                // with the stack pointer right above it.  The handler runs
+                // without access to a stack with a protection key.
+                crate::pkey::allow_all();
+                let gregs = (context as *mut u8).add(GREGS_OFFSET) as *mut usize;
+                let resume_sp = active.top - crate::SAVED_FRAME_SIZE - crate::FRAME_PADDING;
+                *gregs.add(REG_RIP) = *((resume_sp - 8) as *const usize);
+                *gregs.add(REG_RSP) = resume_sp;
+                OVERFLOWED.with(|cell| cell.set(true));
+            }
+            _ => call_previous(signum, info, context),
+        }
+    }
+
+    unsafe fn call_previous(signum: c_int, info: *mut SigInfo, context: *mut c_void) {
+        let index = if signum == SIGSEGV { 0 } else { 1 };
+        let previous = (*PREVIOUS.0.get())[index].as_ptr();
+        match (*previous).sa_sigaction {
+            SIG_DFL | SIG_IGN => {
+                // Restore the previous disposition; the faulting instruction
+                // then faults again and is handled by it.
+                sigaction(signum, previous, core::ptr::null_mut());
+            }
+            f if (*previous).sa_flags & SA_SIGINFO != 0 => {
+                let f: unsafe extern "C" fn(c_int, *mut SigInfo, *mut c_void) =
+                    core::mem::transmute(f);
+                f(signum, info, context);
+            }
+            f => {
+                let f: unsafe extern "C" fn(c_int) = core::mem::transmute(f);
+                f(signum);
+            }
+        }
+    }
+
+    pub(super) struct Guard {
+        previous: Option<Active>,
+        own_alt_stack: bool,
+    }
+
+    impl Guard {
+        pub(super) fn new(stack: &[MaybeUninit<u8>]) -> Result<Self, EraserError> {
+            install_handlers()?;
+
+            let mut old = StackT {
+                ss_sp: core::ptr::null_mut(),
+                ss_flags: 0,
+                ss_size: 0,
+            };
+            if unsafe { sigaltstack(core::ptr::null(), &mut old) } != 0 {
+                return Err(EraserError::Unsupported);
+            }
+            let own_alt_stack = old.ss_flags & SS_DISABLE != 0;
+            if own_alt_stack {
+                let ss_sp = ALT_STACK.with(|cell| unsafe {
+                    (*cell.get())
+                        .get_or_insert_with(|| vec![0; ALT_STACK_SIZE].into_boxed_slice())
+                        .as_mut_ptr()
+                });
+                let new = StackT {
+                    ss_sp: ss_sp as *mut c_void,
+                    ss_flags: 0,
+                    ss_size: ALT_STACK_SIZE,
+                };
+                if unsafe { sigaltstack(&new, core::ptr::null_mut()) } != 0 {
+                    return Err(EraserError::Unsupported);
+                }
+            }
+
+            let bottom = stack.as_ptr() as usize;
+            let active = Active {
+                bottom,
+                top: bottom + stack.len(),
+                page_size: crate::sys::page_size(),
+            };
+            OVERFLOWED.with(|cell| cell.set(false));
+            Ok(Guard {
+                previous: ACTIVE.with(|cell| cell.replace(Some(active))),
+                own_alt_stack,
+            })
+        }
+
+        pub(super) fn overflowed(&self) -> bool {
+            OVERFLOWED.with(Cell::get)
+        }
+    }
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            ACTIVE.with(|cell| cell.set(self.previous));
+            OVERFLOWED.with(|cell| cell.set(false));
+            if self.own_alt_stack {
+                let disable = StackT {
+                    ss_sp: core::ptr::null_mut(),
+                    ss_flags: SS_DISABLE,
+                    ss_size: 0,
+                };
+                unsafe { sigaltstack(&disable, core::ptr::null_mut()) };
+            }
+        }
+    }
+
+    #[cfg(all(test, target_env = "gnu"))]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn ucontext_layout() {
+            extern "C" {
+                fn getcontext(ucp: *mut c_void) -> c_int;
+            }
+            let mut context = [0usize; 256];
+            let local = core::hint::black_box(0u8);
+            assert_eq!(
+                unsafe { getcontext(context.as_mut_ptr() as *mut c_void) },
+                0
+            );
+            // The saved registers point into this frame and into this function
+            let gregs = &context[GREGS_OFFSET / 8..];
+            let sp = &local as *const u8 as usize;
+            assert!(gregs[REG_RSP].abs_diff(sp) < 4096, "{:#x}", gregs[REG_RSP]);
+            let start = ucontext_layout as *const () as usize;
+            assert!((start..start + 4096).contains(&gregs[REG_RIP]));
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! Isolation of the ephemeral stacks with memory protection keys (Intel MPK).
+//!
+//! All stacks that are configured with
+//! [`EraserBuilder::protection_key`](crate::EraserBuilder::protection_key)
+//! share a single protection key, which is allocated on first use.  Access to
+//! the memory tagged with that key is disabled by default, and is only
+//! enabled (by writing the thread's `PKRU` register) while the current thread
+//! is inside the erased context.  Any access to the stack from outside the
+//! erased context, including from other threads, faults.
+//!
+//! This is only supported on x86_64 Linux, on CPUs with protection keys.
+
+use crate::EraserError;
+
+/// Return the protection key that is shared by all protected stacks,
+/// allocating it if necessary.
+pub(crate) fn key() -> Result<i32, EraserError> {
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    {
+        x86_64::key()
+    }
+    #[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+    {
+        Err(EraserError::Unsupported)
+    }
+}
+
+/// Allow the current thread to access the memory tagged with a protection key
+/// until this is dropped.
+pub(crate) struct Access {
+    /// The value of `PKRU` before access was enabled.
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    saved: Option<u32>,
+}
+
+impl Access {
+    /// Enable access to the memory tagged with `key`, if any.
+    pub(crate) fn new(key: Option<i32>) -> Self {
+        #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+        {
+            Access {
+                saved: key.map(x86_64::enable),
+            }
+        }
+        #[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+        {
+            let _ = key;
+            Access {}
+        }
+    }
+}
+
+impl Drop for Access {
+    fn drop(&mut self) {
+        #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+        if let Some(pkru) = self.saved {
+            unsafe { x86_64::wrpkru(pkru) };
+        }
+    }
+}
+
+/// Enable access to all protection keys for the current thread.
+///
+/// This is used by the overflow handler, which runs with the default `PKRU`
+/// value, but needs to read the overflowed stack.  The kernel restores the
+/// interrupted `PKRU` value when the handler returns.  Does nothing if no
+/// protection key has been allocated.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) fn allow_all() {
+    if x86_64::allocated() {
+        unsafe { x86_64::wrpkru(0) };
+    }
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod x86_64 {
+    use crate::{sys, EraserError};
+    use core::arch::asm;
+    use std::sync::OnceLock;
+
+    static KEY: OnceLock<Option<i32>> = OnceLock::new();
+
+    pub(super) fn key() -> Result<i32, EraserError> {
+        KEY.get_or_init(|| sys::pkey_alloc().ok())
+            .ok_or(EraserError::Unsupported)
+    }
+
+    /// Return whether the shared key has been allocated.  This only reads the
+    /// cell, so it is safe to call from a signal handler.
+    pub(super) fn allocated() -> bool {
+        matches!(KEY.get(), Some(Some(_)))
+    }
+
+    /// Clear the access-disable and write-disable bits of `key`, and return
+    /// the previous value of `PKRU`.
+    pub(super) fn enable(key: i32) -> u32 {
+        let pkru = unsafe { rdpkru() };
+        unsafe { wrpkru(pkru & !(0b11 << (2 * key))) };
+        pkru
+    }
+
+    /// Only called after a key has been allocated, so the CPU and the kernel
+    /// support protection keys.
+    unsafe fn rdpkru() -> u32 {
+        let pkru: u32;
+        asm!(
+            "rdpkru",
+            in("ecx") 0,
+            out("eax") pkru,
+            out("edx") _,
+            options(nomem, nostack, preserves_flags),
+        );
+        pkru
+    }
+
+    pub(super) unsafe fn wrpkru(pkru: u32) {
+        // Not `nomem`: memory accesses must not be moved across this
+        asm!(
+            "wrpkru",
+            in("eax") pkru,
+            in("ecx") 0,
+            in("edx") 0,
+            options(nostack, preserves_flags),
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use crate::{ErasedStack, Eraser, EraserError};
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// A pool of pre-allocated stacks that can be shared between threads.
+///
+/// All the stacks in the pool are set up up front with the protections of the
+/// [`Eraser`] that created them, so worker threads do not have to go through
+/// the allocator (or repeat expensive system calls) for every run.  Checking
+/// out a stack is lock-free: every stack has an atomic flag that marks
+/// whether it is in use.
+///
+/// ## Example
+/// ```
+/// let eraser = eraser::Eraser::builder().stack_size(16 * 1024).build().unwrap();
+/// let pool = eraser::StackPool::new(&eraser, 4).unwrap();
+///
+/// std::thread::scope(|s| {
+///     for i in 0..8 {
+///         let pool = &pool;
+///         s.spawn(move || assert_eq!(pool.run(|| i * 2), i * 2));
+///     }
+/// });
+/// ```
+#[derive(Debug)]
+pub struct StackPool {
+    eraser: Eraser,
+    slots: Box<[Slot]>,
+}
+
+#[derive(Debug)]
+struct Slot {
+    in_use: AtomicBool,
+    stack: UnsafeCell<ErasedStack>,
+}
+
+// The `in_use` flag guarantees that only one thread at a time can access the
+// stack in a slot.
+unsafe impl Sync for StackPool {}
+
+impl StackPool {
+    /// Set up a pool of `count` stacks with the protections that are
+    /// configured in `eraser`.
+    ///
+    /// The stacks in the pool live as long as the pool, so they are also wiped
+    /// in forked child processes: on Linux by the kernel (see
+    /// [`EraserBuilder::wipe_on_fork`]), and elsewhere by a `pthread_atfork`
+    /// handler that erases them right after the fork.
+    ///
+    /// On Windows, the stacks are also encrypted with `CryptProtectMemory`
+    /// while they are not checked out, so that any residue of an earlier run
+    /// is only readable in plaintext during a run.
+    ///
+    /// [`EraserBuilder::wipe_on_fork`]: crate::EraserBuilder::wipe_on_fork
+    pub fn new(eraser: &Eraser, count: usize) -> Result<Self, EraserError> {
+        let eraser = &eraser.with_wipe_on_fork();
+        let slots = (0..count)
+            .map(|_| {
+                Ok(Slot {
+                    in_use: AtomicBool::new(false),
+                    stack: UnsafeCell::new({
+                        let mut stack = eraser.new_stack()?;
+                        stack.erase_on_fork();
+                        stack.encrypt_when_idle();
+                        stack
+                    }),
+                })
+            })
+            .collect::<Result<_, EraserError>>()?;
+        Ok(StackPool {
+            eraser: eraser.clone(),
+            slots,
+        })
+    }
+
+    /// Return the number of stacks in the pool.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Check out a stack from the pool.
+    ///
+    /// Returns `None` if all the stacks are in use.  The stack is returned to
+    /// the pool when the [`PooledStack`] is dropped.
+    pub fn checkout(&self) -> Option<PooledStack<'_>> {
+        self.slots.iter().find_map(|slot| {
+            slot.in_use
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .ok()
+                .map(|_| PooledStack { slot })
+        })
+    }
+
+    /// Run a function on a stack from the pool and immediately erase the
+    /// stack.
+    ///
+    /// If all the stacks in the pool are in use, a temporary stack is set up
+    /// for this call.  This function panics if that temporary stack could not
+    /// be set up.
+    pub fn run<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        match self.checkout() {
+            Some(mut stack) => stack.run(f),
+            None => self.eraser.run(f),
+        }
+    }
+}
+
+/// A stack that is checked out from a [`StackPool`].
+///
+/// The stack is returned to the pool on drop.
+#[derive(Debug)]
+pub struct PooledStack<'a> {
+    slot: &'a Slot,
+}
+
+impl PooledStack<'_> {
+    /// Run a function on this stack and immediately erase the stack.
+    pub fn run<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        unsafe { (*self.slot.stack.get()).run(f) }
+    }
+}
+
+impl Drop for PooledStack<'_> {
+    fn drop(&mut self) {
+        self.slot.in_use.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkout() {
+        let eraser = Eraser::builder().stack_size(8 * 1024).build().unwrap();
+        let pool = StackPool::new(&eraser, 2).unwrap();
+        assert_eq!(pool.capacity(), 2);
+
+        let mut a = pool.checkout().unwrap();
+        let b = pool.checkout().unwrap();
+        assert!(pool.checkout().is_none());
+        assert_eq!(pool.run(|| 5), 5);
+        assert_eq!(a.run(|| 6), 6);
+        drop(b);
+        assert!(pool.checkout().is_some());
+    }
+
+    #[test]
+    fn threads() {
+        let eraser = Eraser::builder().stack_size(16 * 1024).build().unwrap();
+        let pool = StackPool::new(&eraser, 3).unwrap();
+        std::thread::scope(|s| {
+            for i in 0..8 {
+                let pool = &pool;
+                s.spawn(move || {
+                    for j in 0..100 {
+                        assert_eq!(pool.run(|| i * j), i * j);
+                    }
+                });
+            }
+        });
+    }
+}
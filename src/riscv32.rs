@@ -0,0 +1,399 @@
+//! Running the user function on bare-metal 32-bit RISC-V, without `std`.
+//!
+//! Like on Cortex-M, there are no threads and no unwinding, so the closure is
+//! passed to the ephemeral stack in a register, and a panic in the user
+//! function goes straight to the panic handler of the application.
+//!
+//! The stack pointer is switched directly.  An interrupt or exception that is
+//! taken during the run stores its frame on the ephemeral stack (where it is
+//! erased with the rest), unless the trap handler switches to a stack of its
+//! own.  `mscratch` is never touched: trap handlers that swap `sp` with
+//! `mscratch` on entry (to find their own stack, or to tell traps from a lower
+//! privilege mode apart) keep working, and swap the ephemeral stack pointer
+//! back in on their way out.
+//!
+//! With the `mask_interrupts` feature, the machine-mode interrupts are masked
+//! (by clearing `mstatus.MIE`) from before the stack switch until the stack
+//! has been erased and the registers have been wiped, so that no trap handler
+//! runs on the ephemeral stack, or sees the registers of the user function.
+//! This requires the caller to run in machine mode.  Exceptions are not
+//! masked.
+
+use crate::{EraseConfig, Payload};
+use core::arch::asm;
+use core::mem::MaybeUninit;
+
+/// Run the user function on `stack`, which has already been aligned by
+/// `align_stack`.
+pub(crate) unsafe fn run_on_stack<F, R>(
+    f: F,
+    stack: &mut [MaybeUninit<u8>],
+    config: &EraseConfig,
+) -> R
+where
+    F: FnOnce() -> R,
+{
+    let stack_bottom = stack.as_mut_ptr() as *mut u8;
+    let stack_top = stack_bottom.add(stack.len());
+    let mut payload = Payload {
+        f: MaybeUninit::new(f),
+        result: MaybeUninit::<R>::uninit(),
+    };
+    let data = &mut payload as *mut Payload<F, R> as *mut u8;
+
+    crate::events::enter(stack_bottom, stack.len());
+    #[cfg(feature = "mask_interrupts")]
+    let mstatus = mask_interrupts();
+    stack_switch::<F, R>(data, stack_top);
+    crate::events::check_overflow(stack_bottom);
+    crate::erase_words(stack_bottom, stack.len(), config);
+    crate::erase_slot(&mut payload.f, config);
+
+    let result = payload.result.as_ptr().read();
+    crate::erase_slot(&mut payload.result, config);
+    crate::custom_wipe::run();
+    wipe_all_registers();
+    #[cfg(feature = "mask_interrupts")]
+    restore_interrupts(mstatus);
+    crate::events::erased(stack.len());
+    result
+}
+
+extern "C" fn trampoline<F: FnOnce() -> R, R>(data: *mut u8) {
+    unsafe { crate::call_user_fn::<F, R>(data) }
+}
+
+/// Mask the machine-mode interrupts, and return the old value of `mstatus`.
+#[cfg(feature = "mask_interrupts")]
+unsafe fn mask_interrupts() -> usize {
+    let mstatus: usize;
+    // MIE is bit 3
+    asm!("csrrci {}, mstatus, 8", out(reg) mstatus, options(nostack, preserves_flags));
+    mstatus
+}
+
+/// Unmask the machine-mode interrupts, if they were enabled in `mstatus` (as
+/// returned by `mask_interrupts`).
+#[cfg(feature = "mask_interrupts")]
+unsafe fn restore_interrupts(mstatus: usize) {
+    asm!("csrs mstatus, {}", in(reg) mstatus & 8, options(nostack, preserves_flags));
+}
+
+/// Call `trampoline::<F, R>(data)` on the stack that ends at `stack_top`.
+///
+/// The frame pointer (`s0`) and the old stack pointer are saved in a 16-byte
+/// frame on the new stack, which keeps `sp` aligned as the ABI requires.
+#[inline(never)]
+unsafe fn stack_switch<F: FnOnce() -> R, R>(data: *mut u8, stack_top: *mut u8) {
+    asm!(
+        // Stash the old sp
+        "mv t1, sp",
+        // Switch stacks
+        "mv sp, t0",
+        // Save the frame pointer and stack pointer values
+        "addi sp, sp, -16",
+        "sw s0, 0(sp)",
+        "sw t1, 4(sp)",
+        // Call the running function using the new stack
+        "call {user_fn}",
+        // Restore the original stack and frame pointer values
+        "lw s0, 0(sp)",
+        "lw t1, 4(sp)",
+        "mv sp, t1",
+        user_fn = sym trampoline::<F, R>,
+        in("a0") data,
+        in("t0") stack_top,
+        clobber_abi("C"),
+    );
+}
+
+/// Wipe the integer registers and (with the F or D extension) the
+/// floating-point registers.
+///
+/// `s0` is the frame pointer and `s1` is reserved by the compiler, so neither
+/// can be wiped here, and neither can `gp` and `tp`, which belong to the
+/// platform.  On RV32E, only `x0`–`x15` exist.
+pub(crate) unsafe fn wipe_all_registers() {
+    asm!(
+        "mv ra, zero",
+        "mv t0, zero",
+        "mv t1, zero",
+        "mv t2, zero",
+        "mv a0, zero",
+        "mv a1, zero",
+        "mv a2, zero",
+        "mv a3, zero",
+        "mv a4, zero",
+        "mv a5, zero",
+        #[cfg(not(target_feature = "e"))]
+        "mv a6, zero",
+        #[cfg(not(target_feature = "e"))]
+        "mv a7, zero",
+        #[cfg(not(target_feature = "e"))]
+        "mv s2, zero",
+        #[cfg(not(target_feature = "e"))]
+        "mv s3, zero",
+        #[cfg(not(target_feature = "e"))]
+        "mv s4, zero",
+        #[cfg(not(target_feature = "e"))]
+        "mv s5, zero",
+        #[cfg(not(target_feature = "e"))]
+        "mv s6, zero",
+        #[cfg(not(target_feature = "e"))]
+        "mv s7, zero",
+        #[cfg(not(target_feature = "e"))]
+        "mv s8, zero",
+        #[cfg(not(target_feature = "e"))]
+        "mv s9, zero",
+        #[cfg(not(target_feature = "e"))]
+        "mv s10, zero",
+        #[cfg(not(target_feature = "e"))]
+        "mv s11, zero",
+        #[cfg(not(target_feature = "e"))]
+        "mv t3, zero",
+        #[cfg(not(target_feature = "e"))]
+        "mv t4, zero",
+        #[cfg(not(target_feature = "e"))]
+        "mv t5, zero",
+        #[cfg(not(target_feature = "e"))]
+        "mv t6, zero",
+        // Without the D extension, `fcvt.d.w` does not exist
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f0, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f1, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f2, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f3, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f4, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f5, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f6, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f7, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f8, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f9, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f10, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f11, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f12, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f13, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f14, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f15, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f16, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f17, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f18, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f19, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f20, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f21, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f22, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f23, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f24, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f25, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f26, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f27, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f28, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f29, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f30, zero",
+        #[cfg(target_feature = "d")]
+        "fcvt.d.w f31, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f0, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f1, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f2, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f3, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f4, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f5, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f6, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f7, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f8, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f9, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f10, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f11, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f12, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f13, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f14, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f15, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f16, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f17, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f18, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f19, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f20, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f21, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f22, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f23, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f24, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f25, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f26, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f27, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f28, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f29, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f30, zero",
+        #[cfg(all(target_feature = "f", not(target_feature = "d")))]
+        "fmv.w.x f31, zero",
+        // Reset the floating-point control and status register
+        #[cfg(target_feature = "f")]
+        "csrw fcsr, zero",
+        lateout("ra") _,
+        lateout("t0") _,
+        lateout("t1") _,
+        lateout("t2") _,
+        lateout("a0") _,
+        lateout("a1") _,
+        lateout("a2") _,
+        lateout("a3") _,
+        lateout("a4") _,
+        lateout("a5") _,
+        #[cfg(not(target_feature = "e"))]
+        lateout("a6") _,
+        #[cfg(not(target_feature = "e"))]
+        lateout("a7") _,
+        #[cfg(not(target_feature = "e"))]
+        lateout("s2") _,
+        #[cfg(not(target_feature = "e"))]
+        lateout("s3") _,
+        #[cfg(not(target_feature = "e"))]
+        lateout("s4") _,
+        #[cfg(not(target_feature = "e"))]
+        lateout("s5") _,
+        #[cfg(not(target_feature = "e"))]
+        lateout("s6") _,
+        #[cfg(not(target_feature = "e"))]
+        lateout("s7") _,
+        #[cfg(not(target_feature = "e"))]
+        lateout("s8") _,
+        #[cfg(not(target_feature = "e"))]
+        lateout("s9") _,
+        #[cfg(not(target_feature = "e"))]
+        lateout("s10") _,
+        #[cfg(not(target_feature = "e"))]
+        lateout("s11") _,
+        #[cfg(not(target_feature = "e"))]
+        lateout("t3") _,
+        #[cfg(not(target_feature = "e"))]
+        lateout("t4") _,
+        #[cfg(not(target_feature = "e"))]
+        lateout("t5") _,
+        #[cfg(not(target_feature = "e"))]
+        lateout("t6") _,
+        #[cfg(target_feature = "f")]
+        lateout("f0") _,
+        #[cfg(target_feature = "f")]
+        lateout("f1") _,
+        #[cfg(target_feature = "f")]
+        lateout("f2") _,
+        #[cfg(target_feature = "f")]
+        lateout("f3") _,
+        #[cfg(target_feature = "f")]
+        lateout("f4") _,
+        #[cfg(target_feature = "f")]
+        lateout("f5") _,
+        #[cfg(target_feature = "f")]
+        lateout("f6") _,
+        #[cfg(target_feature = "f")]
+        lateout("f7") _,
+        #[cfg(target_feature = "f")]
+        lateout("f8") _,
+        #[cfg(target_feature = "f")]
+        lateout("f9") _,
+        #[cfg(target_feature = "f")]
+        lateout("f10") _,
+        #[cfg(target_feature = "f")]
+        lateout("f11") _,
+        #[cfg(target_feature = "f")]
+        lateout("f12") _,
+        #[cfg(target_feature = "f")]
+        lateout("f13") _,
+        #[cfg(target_feature = "f")]
+        lateout("f14") _,
+        #[cfg(target_feature = "f")]
+        lateout("f15") _,
+        #[cfg(target_feature = "f")]
+        lateout("f16") _,
+        #[cfg(target_feature = "f")]
+        lateout("f17") _,
+        #[cfg(target_feature = "f")]
+        lateout("f18") _,
+        #[cfg(target_feature = "f")]
+        lateout("f19") _,
+        #[cfg(target_feature = "f")]
+        lateout("f20") _,
+        #[cfg(target_feature = "f")]
+        lateout("f21") _,
+        #[cfg(target_feature = "f")]
+        lateout("f22") _,
+        #[cfg(target_feature = "f")]
+        lateout("f23") _,
+        #[cfg(target_feature = "f")]
+        lateout("f24") _,
+        #[cfg(target_feature = "f")]
+        lateout("f25") _,
+        #[cfg(target_feature = "f")]
+        lateout("f26") _,
+        #[cfg(target_feature = "f")]
+        lateout("f27") _,
+        #[cfg(target_feature = "f")]
+        lateout("f28") _,
+        #[cfg(target_feature = "f")]
+        lateout("f29") _,
+        #[cfg(target_feature = "f")]
+        lateout("f30") _,
+        #[cfg(target_feature = "f")]
+        lateout("f31") _,
+    )
+}
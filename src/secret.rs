@@ -0,0 +1,248 @@
+use crate::memory::{ProtectedMemory, Protections};
+use crate::{EraseConfig, EraserError};
+use core::{fmt, mem::ManuallyDrop};
+
+/// A secret value that is erased when it is dropped.
+///
+/// The value is stored on the heap, so that moving a `Secret` around does not
+/// leave copies of the value behind.  When the `Secret` is dropped, the value
+/// is dropped in place, after which its backing storage is overwritten with
+/// volatile writes before it is deallocated.
+///
+/// The value can only be accessed through [`Secret::expose`] and
+/// [`Secret::expose_mut`], which makes it easy to spot (and review) the places
+/// where the secret is used.  The `Debug` implementation does not print the
+/// value.
+///
+/// ## Example
+/// ```
+/// use eraser::Secret;
+///
+/// let key = eraser::run_then_erase(|| Secret::new([0x42u8; 32]), 4096);
+/// let sum = key.expose(|key| key.iter().map(|&x| x as u32).sum::<u32>());
+/// assert_eq!(sum, 32 * 0x42);
+/// ```
+pub struct Secret<T> {
+    inner: Box<ManuallyDrop<T>>,
+}
+
+impl<T> Secret<T> {
+    /// Move `value` into a new `Secret`.
+    ///
+    /// Note that this cannot erase the place where `value` was stored before
+    /// it was moved.  To avoid such copies, create the `Secret` inside the
+    /// erased context.
+    pub fn new(value: T) -> Self {
+        Secret {
+            inner: Box::new(ManuallyDrop::new(value)),
+        }
+    }
+
+    /// Call `f` with a reference to the secret value.
+    pub fn expose<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        f(&self.inner)
+    }
+
+    /// Call `f` with a mutable reference to the secret value.
+    pub fn expose_mut<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.inner)
+    }
+}
+
+impl<T> Drop for Secret<T> {
+    fn drop(&mut self) {
+        unsafe {
+            ManuallyDrop::drop(&mut self.inner);
+            let ptr_mut = &mut *self.inner as *mut ManuallyDrop<T> as *mut u8;
+            crate::erase_bytes(ptr_mut, core::mem::size_of::<T>(), &EraseConfig::default());
+        }
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<T: zeroize::Zeroize> zeroize::Zeroize for Secret<T> {
+    fn zeroize(&mut self) {
+        self.inner.zeroize();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<T> zeroize::ZeroizeOnDrop for Secret<T> {}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret<{}>(..)", core::any::type_name::<T>())
+    }
+}
+
+/// A secret value in locked heap memory that is erased when it is dropped.
+///
+/// Like [`Secret`], but the value is stored in its own pages, which are
+/// locked into memory (so that they are never written to swap).  On Linux,
+/// FreeBSD and OpenBSD, the pages are also excluded from core dumps; on
+/// macOS, Windows, illumos, QNX and Fuchsia, this is not supported, so there
+/// the value can end up in a core dump of the process.  Because a `SecretBox`
+/// is only a pointer, it can be moved into and out of the erased context
+/// without copying the value.
+///
+/// To avoid copies of the value altogether, create the box with
+/// [`SecretBox::new_default`] and fill it in place with
+/// [`SecretBox::expose_mut`] inside the erased context.
+///
+/// Locking memory is subject to `RLIMIT_MEMLOCK`; if the limit is too low,
+/// the constructors return [`EraserError::LockFailed`].  On targets that
+/// cannot lock memory at all, they return [`EraserError::Unsupported`].
+///
+/// ## Example
+/// ```no_run
+/// use eraser::SecretBox;
+///
+/// let mut key = SecretBox::<[u8; 32]>::new_default().unwrap();
+/// let key = eraser::run_then_erase(
+///     move || {
+///         key.expose_mut(|key| key.fill(0x42));
+///         key
+///     },
+///     4096,
+/// );
+/// assert_eq!(key.expose(|key| key[0]), 0x42);
+/// ```
+pub struct SecretBox<T> {
+    memory: ProtectedMemory,
+    _marker: core::marker::PhantomData<T>,
+}
+
+// `SecretBox<T>` owns a `T`, just like `Box<T>`.
+unsafe impl<T: Send> Send for SecretBox<T> {}
+unsafe impl<T: Sync> Sync for SecretBox<T> {}
+
+impl<T> SecretBox<T> {
+    /// Move `value` into a new `SecretBox`.
+    ///
+    /// Note that this cannot erase the place where `value` was stored before
+    /// it was moved.
+    pub fn new(value: T) -> Result<Self, EraserError> {
+        let mut memory = Self::alloc()?;
+        unsafe { (memory.as_mut_slice().as_mut_ptr() as *mut T).write(value) };
+        Ok(SecretBox {
+            memory,
+            _marker: core::marker::PhantomData,
+        })
+    }
+
+    /// Create a new `SecretBox` that holds the default value of `T`.
+    pub fn new_default() -> Result<Self, EraserError>
+    where
+        T: Default,
+    {
+        Self::new(T::default())
+    }
+
+    fn alloc() -> Result<ProtectedMemory, EraserError> {
+        let protections = Protections {
+            mlock: true,
+            dont_dump: crate::sys::CAN_DONT_DUMP,
+            ..Protections::default()
+        };
+        let len = core::mem::size_of::<T>().max(1);
+        ProtectedMemory::new(len, core::mem::align_of::<T>(), &protections)
+    }
+
+    fn as_ptr(&self) -> *const T {
+        self.memory.as_ptr() as *const T
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut T {
+        self.memory.as_mut_slice().as_mut_ptr() as *mut T
+    }
+
+    /// Call `f` with a reference to the secret value.
+    pub fn expose<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        f(unsafe { &*self.as_ptr() })
+    }
+
+    /// Call `f` with a mutable reference to the secret value.
+    pub fn expose_mut<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(unsafe { &mut *self.as_mut_ptr() })
+    }
+}
+
+impl<T> Drop for SecretBox<T> {
+    fn drop(&mut self) {
+        unsafe {
+            core::ptr::drop_in_place(self.as_mut_ptr());
+            let memory = self.memory.as_mut_slice();
+            crate::erase_bytes(memory.as_mut_ptr(), memory.len(), &EraseConfig::default());
+        }
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<T: zeroize::Zeroize> zeroize::Zeroize for SecretBox<T> {
+    fn zeroize(&mut self) {
+        self.expose_mut(|value| value.zeroize());
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<T> zeroize::ZeroizeOnDrop for SecretBox<T> {}
+
+impl<T> fmt::Debug for SecretBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretBox<{}>(..)", core::any::type_name::<T>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expose() {
+        let mut secret = Secret::new(vec![1u8, 2, 3]);
+        secret.expose_mut(|v| v.push(4));
+        assert_eq!(secret.expose(|v| v.len()), 4);
+        assert_eq!(format!("{:?}", secret), "Secret<alloc::vec::Vec<u8>>(..)");
+    }
+
+    #[test]
+    fn drops_value() {
+        use std::rc::Rc;
+        let rc = Rc::new(());
+        let secret = Secret::new(rc.clone());
+        assert_eq!(Rc::strong_count(&rc), 2);
+        drop(secret);
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+
+    /// Create a `SecretBox`, or return `None` if the sandbox that runs the
+    /// tests does not allow us to lock memory, or if this target cannot.
+    fn new_box<T>(value: T) -> Option<SecretBox<T>> {
+        match SecretBox::new(value) {
+            Ok(secret) => Some(secret),
+            Err(EraserError::LockFailed) => None,
+            Err(EraserError::Unsupported) if !crate::sys::CAN_MAP => None,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    #[test]
+    fn secret_box() {
+        let Some(mut secret) = new_box(vec![1u8, 2, 3]) else {
+            return;
+        };
+        secret.expose_mut(|v| v.push(4));
+        let secret = crate::run_then_erase(
+            move || {
+                assert_eq!(secret.expose(|v| v.len()), 4);
+                secret
+            },
+            16 * 1024,
+        );
+        assert_eq!(secret.expose(|v| v.iter().sum::<u8>()), 10);
+
+        if let Some(unit) = new_box(()) {
+            unit.expose(|_| ());
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use crate::{ErasedStack, Eraser, EraserError};
+
+/// A long-lived erased context with a hardened stack.
+///
+/// An `EraserSession` owns a single stack that is set up with all the
+/// protections that are available: the stack is preceded by a guard page and
+/// locked into memory.  On Linux, FreeBSD and OpenBSD, it is also excluded
+/// from core dumps, and wiped by the kernel in forked child processes; on
+/// other Unix targets, it is erased right after a fork instead (see
+/// [`StackPool::new`](crate::StackPool::new)).  On Windows, the stack is
+/// encrypted with `CryptProtectMemory` in between calls.  The stack is erased
+/// after every call, and when the session is dropped, it is erased once more
+/// before the protections are removed and the memory is released.
+///
+/// This is the natural API for daemons that do many signing operations with
+/// the same key.
+///
+/// Not to be confused with [`Session`](crate::Session), which calls one
+/// stateful function repeatedly on an existing [`ErasedStack`] and only
+/// erases the stack when it is dropped.  An `EraserSession` runs arbitrary
+/// closures, and erases its stack after each of them.
+///
+/// ## Example
+/// ```no_run
+/// let mut session = eraser::EraserSession::new(64 * 1024).unwrap();
+/// for msg in [b"first", b"other"] {
+///     let tag = session.run(|| msg.iter().fold(0u8, |acc, &x| acc ^ x));
+///     println!("{:02x}", tag);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct EraserSession {
+    stack: ErasedStack,
+}
+
+impl EraserSession {
+    /// Set up a session with a hardened stack of `stack_size` bytes.
+    ///
+    /// Only the protections that this target supports are requested, but
+    /// setting up the stack fails if any of those cannot be applied; for
+    /// example, [`EraserError::LockFailed`] is returned when `RLIMIT_MEMLOCK`
+    /// is too low to lock the stack into memory.  Returns
+    /// [`EraserError::Unsupported`] on targets without guard pages.
+    pub fn new(stack_size: usize) -> Result<Self, EraserError> {
+        let mut stack = Eraser::builder()
+            .stack_size(stack_size)
+            .guard_page(true)
+            .mlock(true)
+            .dont_dump(crate::sys::CAN_DONT_DUMP)
+            .build()?
+            .with_wipe_on_fork()
+            .new_stack()?;
+        stack.erase_on_fork();
+        stack.encrypt_when_idle();
+        Ok(EraserSession { stack })
+    }
+
+    /// Return the usable size of the session's stack in bytes.
+    pub fn stack_size(&self) -> usize {
+        self.stack.size()
+    }
+
+    /// Run a function on the session's stack and immediately erase the stack.
+    pub fn run<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        self.stack.run(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_runs() {
+        let mut session = match EraserSession::new(16 * 1024) {
+            Ok(session) => session,
+            // The sandbox that runs the tests might not allow us to lock memory
+            Err(EraserError::LockFailed) => return,
+            Err(EraserError::Unsupported) if !crate::sys::CAN_MAP => return,
+            Err(err) => panic!("{}", err),
+        };
+        assert_eq!(session.stack_size(), 16 * 1024);
+        for i in 0..10 {
+            assert_eq!(session.run(|| i * 7), i * 7);
+        }
+    }
+}
@@ -0,0 +1,669 @@
+use crate::fork::Registration;
+use crate::memory::ProtectedMemory;
+use crate::thread::ThreadProtections;
+use crate::{
+    EraseConfig, Eraser, EraserError, OnOverflow, StackErase, MEASURE_PATTERN, MIN_STACK_SIZE,
+    STACK_ALIGN,
+};
+use std::mem::MaybeUninit;
+use std::panic;
+
+/// A reusable ephemeral stack.
+///
+/// Setting up a stack for every call is wasteful when a function is run many
+/// times, for example in a hot loop.  An `ErasedStack` is allocated (and, if
+/// configured, locked into memory) only once, and can then be used for any
+/// number of runs.  The stack is erased after every run, and once more when
+/// the `ErasedStack` is dropped.
+///
+/// Use [`Eraser::new_stack`] to create a stack with other protections than
+/// the default ones.
+///
+/// ## Example
+/// ```
+/// let mut stack = eraser::ErasedStack::new(16 * 1024).unwrap();
+/// let mut total = 0;
+/// for i in 0..100 {
+///     total += stack.run(|| i * i);
+/// }
+/// assert_eq!(total, 328350);
+/// ```
+pub struct ErasedStack {
+    stack: ProtectedMemory,
+    erase: EraseConfig,
+    thread: ThreadProtections,
+    /// The word that every word of the stack holds, if known.
+    sentinel: Option<usize>,
+    /// Keeps the stack registered for erasure in forked child processes.
+    fork_registration: Option<Registration>,
+    /// Whether the stack is encrypted while no run is in progress.
+    encrypted: bool,
+    /// Whether an overflow of the stack is returned as an error instead of
+    /// aborting the process, see `EraserBuilder::recover_from_overflow`.
+    recover_overflow: bool,
+}
+
+impl ErasedStack {
+    /// Allocate a new stack of `stack_size` bytes with the default
+    /// protections.
+    ///
+    /// The size must be a multiple of 32 bytes.
+    pub fn new(stack_size: usize) -> Result<Self, EraserError> {
+        Eraser::builder()
+            .stack_size(stack_size)
+            .build()?
+            .new_stack()
+    }
+
+    /// Wrap `stack`; `zeroed` tells whether it was zeroed on allocation.
+    pub(crate) fn from_parts(
+        stack: ProtectedMemory,
+        erase: EraseConfig,
+        thread: ThreadProtections,
+        zeroed: bool,
+        recover_overflow: bool,
+    ) -> Self {
+        ErasedStack {
+            stack,
+            erase,
+            thread,
+            sentinel: zeroed.then_some(0),
+            fork_registration: None,
+            encrypted: false,
+            recover_overflow,
+        }
+    }
+
+    /// Return what a run does when the user function overflows this stack.
+    fn on_overflow(&self) -> OnOverflow {
+        match (self.stack.has_guard_page(), self.recover_overflow) {
+            (false, _) => OnOverflow::Undetected,
+            (true, false) => OnOverflow::Abort,
+            (true, true) => OnOverflow::Return,
+        }
+    }
+
+    /// Erase this stack in forked child processes, unless the kernel already
+    /// keeps its contents from them.
+    pub(crate) fn erase_on_fork(&mut self) {
+        if self.stack.is_wiped_on_fork() || self.fork_registration.is_some() {
+            return;
+        }
+        let key = self.stack.protection_key();
+        let tagged = self.stack.has_memory_tags();
+        let word = self.erase.final_word().unwrap_or(0);
+        let stack = self.stack.as_uninit_slice();
+        let (ptr, len) = (stack.as_mut_ptr() as *mut u8, stack.len());
+        let registration = crate::fork::register(ptr, len, word, key, tagged);
+        self.fork_registration = Some(registration);
+    }
+
+    /// Keep this stack encrypted (with `CryptProtectMemory`) while no run is
+    /// in progress, where this is supported.
+    ///
+    /// The stack is decrypted right before every run and encrypted again
+    /// right after, so any residue that survives the erase is only readable
+    /// in plaintext during a run.
+    pub(crate) fn encrypt_when_idle(&mut self) {
+        if self.encrypted {
+            return;
+        }
+        let stack = self.stack.as_uninit_slice();
+        let (ptr, len) = (stack.as_mut_ptr() as *mut u8, stack.len());
+        self.encrypted = unsafe { crate::sys::encrypt(ptr, len) }.is_ok();
+    }
+
+    /// Decrypt the stack if it is encrypted while idle, until the returned
+    /// value is dropped.
+    fn decrypted(&mut self) -> Option<Decrypted> {
+        if !self.encrypted {
+            return None;
+        }
+        let stack = self.stack.as_uninit_slice();
+        let (ptr, len) = (stack.as_mut_ptr() as *mut u8, stack.len());
+        unsafe { crate::sys::decrypt(ptr, len) }
+            .unwrap_or_else(|err| panic!("could not decrypt the stack: {}", err));
+        Some(Decrypted { ptr, len })
+    }
+
+    /// Return the usable size of the stack in bytes.
+    pub fn size(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Return whether the stack is locked into memory.
+    ///
+    /// This is only `false` for a stack that was configured with
+    /// [`EraserBuilder::mlock`] if locking failed and
+    /// [`EraserBuilder::mlock_fallback`] was enabled.
+    ///
+    /// [`EraserBuilder::mlock`]: crate::EraserBuilder::mlock
+    /// [`EraserBuilder::mlock_fallback`]: crate::EraserBuilder::mlock_fallback
+    pub fn is_locked(&self) -> bool {
+        self.stack.is_locked()
+    }
+
+    /// Run a function on this stack and immediately erase the stack.
+    ///
+    /// This function is similar to [`crate::run_then_erase`], but reuses the
+    /// already set up stack.
+    ///
+    /// Unless this is disabled with [`EraserBuilder::erase_high_water_mark`],
+    /// only the part of the stack that was actually used is erased.  For
+    /// large stacks this is a lot faster than erasing the whole stack.
+    ///
+    /// [`EraserBuilder::erase_high_water_mark`]: crate::EraserBuilder::erase_high_water_mark
+    pub fn run<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        self.try_run(f).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Run a function on this stack and immediately erase the stack,
+    /// returning an error if the run failed.
+    ///
+    /// If the stack has a guard page (see [`EraserBuilder::guard_page`]) and
+    /// was set up with [`EraserBuilder::recover_from_overflow`], an overflow
+    /// of the stack returns [`EraserError::StackOverflow`] instead of
+    /// aborting the process (on x86_64 Linux).
+    ///
+    /// [`EraserBuilder::guard_page`]: crate::EraserBuilder::guard_page
+    /// [`EraserBuilder::recover_from_overflow`]: crate::EraserBuilder::recover_from_overflow
+    pub fn try_run<F, R>(&mut self, f: F) -> Result<R, EraserError>
+    where
+        F: FnOnce() -> R,
+    {
+        let _applied = self.thread.apply()?;
+        let _decrypted = self.decrypted();
+        let final_word = self.erase.final_word();
+        let erase = match self.sentinel {
+            Some(sentinel) if self.erase.high_water_mark && final_word == Some(sentinel) => {
+                StackErase::AboveWatermark(sentinel)
+            }
+            _ => StackErase::Full,
+        };
+        // Either the whole stack is erased, or the part below the high-water
+        // mark already holds the final word.  This is also true when the user
+        // function panics, because the stack is erased before the panic is
+        // resumed.
+        self.sentinel = final_word;
+        let on_overflow = self.on_overflow();
+        let _access = self.stack.access();
+        let mut stack = self.stack.tagged();
+        let stack = stack.as_uninit_slice();
+        unsafe { crate::switch_and_run(f, stack, &self.erase, erase, on_overflow) }
+    }
+
+    /// Run a function on this stack like `try_run`, and also return the
+    /// number of bytes of the stack that it used.
+    ///
+    /// The stack is filled with `MEASURE_PATTERN` before the run, and erased
+    /// in full only after the high-water mark has been found.  A panic in the
+    /// user function is caught on the ephemeral stack, so that the stack is
+    /// erased before the panic is resumed.
+    pub(crate) fn try_measure<F, R>(&mut self, f: F) -> Result<(R, usize), EraserError>
+    where
+        F: FnOnce() -> R,
+    {
+        let _applied = self.thread.apply()?;
+        let _decrypted = self.decrypted();
+        self.sentinel = self.erase.final_word();
+        let on_overflow = self.on_overflow();
+        let _access = self.stack.access();
+        let mut stack = self.stack.tagged();
+        let stack = stack.as_uninit_slice();
+        let (ptr, len) = (stack.as_mut_ptr() as *mut u8, stack.len());
+        let f = || panic::catch_unwind(panic::AssertUnwindSafe(f));
+        let result = unsafe {
+            crate::fill::fill_words(ptr, len, MEASURE_PATTERN);
+            let result =
+                crate::switch_and_run(f, stack, &self.erase, StackErase::Skip, on_overflow);
+            let used = len - crate::high_water_mark(stack, MEASURE_PATTERN);
+            crate::erase_words(ptr, len, &self.erase);
+            result.map(|result| (result, used))
+        };
+        match result? {
+            (Ok(result), used) => Ok((result, used)),
+            (Err(err), _) => panic::resume_unwind(err),
+        }
+    }
+
+    /// Start a session in which the stateful function `f` can be called
+    /// repeatedly on this stack.
+    ///
+    /// Unlike [`ErasedStack::run`], the stack is *not* erased between the
+    /// calls in a session, but only when the session ends (i.e., when the
+    /// [`Session`] is dropped).  This is useful for multi-step operations, like
+    /// a streaming AEAD with init/update/final steps, that do not want to pay
+    /// for an erase after every step.  The registers are still wiped after
+    /// every call.  When the session ends, `f` is dropped, and the place
+    /// where it was stored (including any state that it captures by value)
+    /// is erased as well.
+    ///
+    /// ## Example
+    /// ```
+    /// let mut stack = eraser::ErasedStack::new(16 * 1024).unwrap();
+    /// let mut sum = 0u32;
+    /// let mut session = stack.session(|chunk: &[u8]| {
+    ///     sum += chunk.iter().map(|&x| x as u32).sum::<u32>();
+    ///     sum
+    /// });
+    /// session.call(b"abc");
+    /// assert_eq!(session.call(b"def"), 597);
+    /// ```
+    pub fn session<F>(&mut self, f: F) -> Session<'_, F> {
+        Session {
+            stack: self,
+            f: MaybeUninit::new(f),
+        }
+    }
+}
+
+/// A session in which a stateful function is called repeatedly on the same
+/// [`ErasedStack`].
+///
+/// The stack is erased when the session is dropped.  See
+/// [`ErasedStack::session`].
+///
+/// Not to be confused with [`EraserSession`](crate::EraserSession), which
+/// owns a hardened stack, runs arbitrary closures on it, and erases the stack
+/// after every call.
+pub struct Session<'a, F> {
+    stack: &'a mut ErasedStack,
+    /// Always initialized; it is only dropped (and erased) when the session
+    /// is dropped.
+    f: MaybeUninit<F>,
+}
+
+impl<F> Session<'_, F> {
+    /// Call the session's function with `arg` on the session's stack.
+    ///
+    /// This function panics if the call failed; see [`Session::try_call`] for
+    /// a non-panicking version.
+    pub fn call<A, R>(&mut self, arg: A) -> R
+    where
+        F: FnMut(A) -> R,
+    {
+        self.try_call(arg).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Call the session's function with `arg` on the session's stack,
+    /// returning an error if the call failed.
+    ///
+    /// This returns the same errors as [`ErasedStack::try_run`].
+    pub fn try_call<A, R>(&mut self, arg: A) -> Result<R, EraserError>
+    where
+        F: FnMut(A) -> R,
+    {
+        let _applied = self.stack.thread.apply()?;
+        let _decrypted = self.stack.decrypted();
+        let f = unsafe { self.f.assume_init_mut() };
+        let on_overflow = self.stack.on_overflow();
+        let _access = self.stack.stack.access();
+        let mut stack = self.stack.stack.tagged();
+        let stack = stack.as_uninit_slice();
+        let config = &self.stack.erase;
+        unsafe {
+            crate::switch_and_run(move || f(arg), stack, config, StackErase::Skip, on_overflow)
+        }
+    }
+}
+
+impl<F> Drop for Session<'_, F> {
+    fn drop(&mut self) {
+        let _decrypted = self.stack.decrypted();
+        let _access = self.stack.stack.access();
+        let stack = self.stack.stack.as_uninit_slice();
+        let stack_ptr = stack.as_mut_ptr() as *mut u8;
+        unsafe { crate::erase_words(stack_ptr, stack.len(), &self.stack.erase) };
+        self.stack.sentinel = self.stack.erase.final_word();
+        unsafe {
+            self.f.assume_init_drop();
+            crate::erase_slot(&mut self.f, &self.stack.erase);
+        }
+    }
+}
+
+impl<F> core::fmt::Debug for Session<'_, F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Session")
+            .field("stack", &self.stack)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Keeps an idle-encrypted stack decrypted, and encrypts it again on drop.
+struct Decrypted {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Drop for Decrypted {
+    fn drop(&mut self) {
+        unsafe { crate::sys::encrypt(self.ptr, self.len) }
+            .unwrap_or_else(|err| panic!("could not encrypt the stack: {}", err));
+    }
+}
+
+impl Drop for ErasedStack {
+    fn drop(&mut self) {
+        // The memory is released right after this
+        self.fork_registration.take();
+        let _access = self.stack.access();
+        let stack = self.stack.as_uninit_slice();
+        let stack_ptr = stack.as_mut_ptr() as *mut u8;
+        unsafe { crate::erase_words(stack_ptr, stack.len(), &self.erase) };
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl zeroize::Zeroize for ErasedStack {
+    fn zeroize(&mut self) {
+        let _decrypted = self.decrypted();
+        let _access = self.stack.access();
+        let stack = self.stack.as_uninit_slice();
+        let zeros = EraseConfig {
+            policy: crate::ErasePolicy::Repeat(crate::ErasePattern::Zeros, 1),
+            ..EraseConfig::default()
+        };
+        unsafe { crate::erase_words(stack.as_mut_ptr() as *mut u8, stack.len(), &zeros) };
+        self.sentinel = Some(0);
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl zeroize::ZeroizeOnDrop for ErasedStack {}
+
+impl core::fmt::Debug for ErasedStack {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ErasedStack")
+            .field("memory", &self.stack)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A stack buffer that is correctly aligned for [`run_then_erase_with_stack`].
+///
+/// The size `N` must be a multiple of 32 bytes, and at least 1024 bytes; this
+/// is checked at compile time.
+///
+/// ## Example
+/// ```
+/// let mut stack = eraser::AlignedStack::<4096>::new();
+/// let result = unsafe { eraser::run_then_erase_with_stack(|| 42, stack.as_mut_slice()) };
+/// assert_eq!(result, 42);
+/// ```
+///
+/// A stack with an invalid size is rejected at compile time:
+/// ```compile_fail
+/// let stack = eraser::AlignedStack::<4100>::new();
+/// ```
+///
+/// [`run_then_erase_with_stack`]: crate::run_then_erase_with_stack
+#[derive(Debug, Clone)]
+#[repr(C, align(32))]
+pub struct AlignedStack<const N: usize> {
+    buf: [u8; N],
+}
+
+// The `align` attribute on `AlignedStack` cannot refer to `STACK_ALIGN`
+const _: () = assert!(core::mem::align_of::<AlignedStack<0>>() == STACK_ALIGN);
+
+impl<const N: usize> AlignedStack<N> {
+    const VALID: () = assert!(
+        N.is_multiple_of(STACK_ALIGN) && N >= MIN_STACK_SIZE,
+        "AlignedStack size must be a multiple of 32 bytes and at least 1024 bytes"
+    );
+
+    /// Create a new (zeroed) stack buffer.
+    pub const fn new() -> Self {
+        let () = Self::VALID;
+        AlignedStack { buf: [0; N] }
+    }
+
+    /// Return the stack buffer as a mutable slice.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<const N: usize> zeroize::Zeroize for AlignedStack<N> {
+    fn zeroize(&mut self) {
+        self.buf.zeroize();
+    }
+}
+
+impl<const N: usize> Default for AlignedStack<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Return the memory of `stack` as words.
+    fn stack_words(stack: &mut ErasedStack) -> &[usize] {
+        let slice = stack.stack.as_mut_slice();
+        unsafe {
+            core::slice::from_raw_parts(
+                slice.as_ptr() as *const usize,
+                slice.len() / core::mem::size_of::<usize>(),
+            )
+        }
+    }
+
+    #[test]
+    fn reused_stack() {
+        let mut stack = Eraser::builder()
+            .stack_size(8 * 1024)
+            .erase_pattern(0x1111_1111_1111_1111_u64 as usize)
+            .build()
+            .unwrap()
+            .new_stack()
+            .unwrap();
+        assert_eq!(stack.size(), 8 * 1024);
+        for i in 0..16 {
+            assert_eq!(stack.run(|| i + 1), i + 1);
+        }
+        let words = stack_words(&mut stack);
+        assert!(words
+            .iter()
+            .all(|&w| w == 0x1111_1111_1111_1111_u64 as usize));
+    }
+
+    #[test]
+    fn encrypt_when_idle() {
+        let mut stack = Eraser::builder()
+            .stack_size(8 * 1024)
+            .erase_pattern(0x2222_2222_2222_2222_u64 as usize)
+            .build()
+            .unwrap()
+            .new_stack()
+            .unwrap();
+        stack.encrypt_when_idle();
+        assert_eq!(stack.encrypted, cfg!(windows));
+        for i in 0..4 {
+            assert_eq!(stack.run(|| i * 3), i * 3);
+        }
+        let mut session = stack.session(|x: usize| x + 1);
+        assert_eq!(session.call(1), 2);
+        drop(session);
+
+        // While idle, the erased stack only holds the pattern in plaintext if
+        // it is not encrypted
+        let erased = stack.stack.as_mut_slice().iter().all(|&x| x == 0x22);
+        assert_eq!(erased, !stack.encrypted);
+        let _decrypted = stack.decrypted();
+        assert!(stack.stack.as_mut_slice().iter().all(|&x| x == 0x22));
+    }
+
+    #[test]
+    fn random_pattern() {
+        let mut stack = Eraser::builder()
+            .stack_size(8 * 1024)
+            .erase_pattern(crate::ErasePattern::Random)
+            .build()
+            .unwrap()
+            .new_stack()
+            .unwrap();
+        stack.run(|| ());
+        let words = stack_words(&mut stack);
+        let distinct: std::collections::HashSet<_> = words.iter().collect();
+        assert!(distinct.len() > words.len() / 2);
+    }
+
+    #[test]
+    fn sequence_policy() {
+        use crate::{ErasePattern, ErasePolicy};
+        const PASSES: &[ErasePattern] = &[
+            ErasePattern::Random,
+            ErasePattern::Ones,
+            ErasePattern::Word(0x3333_3333_3333_3333_u64 as usize),
+        ];
+        let mut stack = Eraser::builder()
+            .stack_size(8 * 1024)
+            .erase_policy(ErasePolicy::Sequence(PASSES))
+            .build()
+            .unwrap()
+            .new_stack()
+            .unwrap();
+        stack.run(|| ());
+        let words = stack_words(&mut stack);
+        assert!(words
+            .iter()
+            .all(|&w| w == 0x3333_3333_3333_3333_u64 as usize));
+    }
+
+    #[test]
+    fn session() {
+        let mut stack = Eraser::builder()
+            .stack_size(8 * 1024)
+            .erase_pattern(0x2222_2222_2222_2222_u64 as usize)
+            .build()
+            .unwrap()
+            .new_stack()
+            .unwrap();
+        let mut state = Vec::new();
+        {
+            let mut session = stack.session(|x: u8| {
+                state.push(x);
+                state.len()
+            });
+            assert_eq!(session.call(1), 1);
+            assert_eq!(session.call(2), 2);
+        }
+        assert_eq!(state, [1, 2]);
+
+        // The captured state is dropped with the session
+        let rc = std::rc::Rc::new(3u8);
+        let captured = rc.clone();
+        assert_eq!(stack.session(move |x: u8| x + *captured).call(1), 4);
+        assert_eq!(std::rc::Rc::strong_count(&rc), 1);
+
+        let words = stack_words(&mut stack);
+        assert!(words
+            .iter()
+            .all(|&w| w == 0x2222_2222_2222_2222_u64 as usize));
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    #[test]
+    fn protection_key() {
+        extern "C" {
+            fn fork() -> i32;
+            fn waitpid(pid: i32, status: *mut i32, options: i32) -> i32;
+            fn _exit(status: i32) -> !;
+        }
+        const SIGSEGV: i32 = 11;
+
+        let builder = Eraser::builder()
+            .stack_size(64 * 1024)
+            .guard_page(true)
+            .protection_key(true);
+        let eraser = unsafe { builder.recover_from_overflow(true) }
+            .build()
+            .unwrap();
+        // Protection keys are not available on every CPU
+        let mut stack = match eraser.new_stack() {
+            Err(EraserError::Unsupported) => return,
+            stack => stack.unwrap(),
+        };
+        assert_eq!(stack.run(|| 6 * 7), 42);
+        assert_eq!(stack.session(|x: u32| x + 1).call(1), 2);
+
+        // The overflow handler can still read the stack
+        fn recurse(depth: u64) -> u64 {
+            let buf = core::hint::black_box([depth; 32]);
+            if buf[0] == u64::MAX {
+                return 0;
+            }
+            recurse(depth + 1) + buf[7]
+        }
+        assert_eq!(
+            stack.try_run(|| recurse(0)),
+            Err(EraserError::StackOverflow)
+        );
+        assert_eq!(stack.run(|| 8), 8);
+
+        // Outside of a run, even reading the stack faults
+        let ptr = stack.stack.as_ptr();
+        match unsafe { fork() } {
+            0 => unsafe {
+                core::ptr::read_volatile(ptr);
+                _exit(0)
+            },
+            pid => {
+                assert!(pid > 0);
+                let mut status = 0;
+                assert_eq!(unsafe { waitpid(pid, &mut status, 0) }, pid);
+                assert_eq!(status & 0x7f, SIGSEGV);
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn erase_on_fork() {
+        extern "C" {
+            fn fork() -> i32;
+            fn waitpid(pid: i32, status: *mut i32, options: i32) -> i32;
+            fn _exit(status: i32) -> !;
+        }
+        fn wait(pid: i32) -> i32 {
+            assert!(pid > 0);
+            let mut status = 0;
+            assert_eq!(unsafe { waitpid(pid, &mut status, 0) }, pid);
+            status
+        }
+
+        let mut stack = Eraser::builder()
+            .stack_size(8 * 1024)
+            .erase_pattern(0x4444_4444_4444_4444_u64 as usize)
+            .build()
+            .unwrap()
+            .new_stack()
+            .unwrap();
+        stack.erase_on_fork();
+        stack.stack.as_mut_slice().fill(0xAA);
+        match unsafe { fork() } {
+            0 => {
+                let slice = stack.stack.as_mut_slice();
+                let erased = slice.iter().all(|&x| x == 0x44);
+                unsafe { _exit(if erased { 0 } else { 1 }) }
+            }
+            pid => assert_eq!(wait(pid), 0),
+        }
+        assert!(stack.stack.as_mut_slice().iter().all(|&x| x == 0xAA));
+
+        // A child that is forked by the user function keeps its stack
+        let status = stack.run(|| match unsafe { fork() } {
+            0 => unsafe { _exit(core::hint::black_box(42)) },
+            pid => wait(pid),
+        });
+        assert_eq!(status, 42 << 8);
+    }
+}
@@ -0,0 +1,146 @@
+//! Stacks in `static` memory, declared with [`static_erased_stack!`].
+//!
+//! The buffer of the stack is kept apart from the flag that records whether
+//! it was handed out, so that the buffer can be placed in a section that the
+//! startup code does not initialize (like a `NOLOAD` section in TCM or CCM
+//! RAM), while the flag is still zeroed with the rest of `.bss`.
+
+#[cfg(target_arch = "msp430")]
+use crate::msp430::AtomicBool;
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+#[cfg(not(target_arch = "msp430"))]
+use core::sync::atomic::AtomicBool;
+#[cfg(any(
+    target_has_atomic = "8",
+    all(any(target_arch = "arm", target_arch = "msp430"), target_os = "none")
+))]
+use core::sync::atomic::Ordering;
+
+/// The buffer of a stack that was declared with [`static_erased_stack!`].
+#[doc(hidden)]
+#[repr(C, align(32))]
+pub struct StaticStack<const N: usize> {
+    buf: UnsafeCell<MaybeUninit<[u8; N]>>,
+}
+
+// The `align` attribute on `StaticStack` cannot refer to `STACK_ALIGN`
+const _: () = assert!(core::mem::align_of::<StaticStack<0>>() == crate::STACK_ALIGN);
+
+// The buffer is only ever accessed through the one reference that `take`
+// hands out.
+unsafe impl<const N: usize> Sync for StaticStack<N> {}
+
+/// Whether a stack that was declared with [`static_erased_stack!`] was handed
+/// out.
+#[doc(hidden)]
+pub struct StaticStackFlag(AtomicBool);
+
+impl StaticStackFlag {
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Self {
+        StaticStackFlag(AtomicBool::new(false))
+    }
+}
+
+impl<const N: usize> StaticStack<N> {
+    const VALID: () = assert!(
+        N.is_multiple_of(crate::STACK_ALIGN) && N >= crate::MIN_STACK_SIZE,
+        "static_erased_stack! size must be a multiple of 32 bytes and at least 1024 bytes"
+    );
+
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Self {
+        let () = Self::VALID;
+        StaticStack {
+            buf: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Return the buffer, unless it was already handed out (as recorded in
+    /// `taken`).
+    #[allow(clippy::mut_from_ref)]
+    pub fn take(
+        &'static self,
+        taken: &'static StaticStackFlag,
+    ) -> Option<&'static mut [MaybeUninit<u8>]> {
+        if !claim(&taken.0) {
+            return None;
+        }
+        let buf = self.buf.get() as *mut MaybeUninit<u8>;
+        Some(unsafe { core::slice::from_raw_parts_mut(buf, N) })
+    }
+}
+
+/// Set `taken`, and return whether it was clear.
+#[cfg(target_has_atomic = "8")]
+fn claim(taken: &AtomicBool) -> bool {
+    !taken.swap(true, Ordering::AcqRel)
+}
+
+#[cfg(all(
+    not(target_has_atomic = "8"),
+    any(target_arch = "arm", target_arch = "msp430"),
+    target_os = "none"
+))]
+fn claim(taken: &AtomicBool) -> bool {
+    crate::without_interrupts(|| {
+        if taken.load(Ordering::Acquire) {
+            return false;
+        }
+        taken.store(true, Ordering::Release);
+        true
+    })
+}
+
+#[cfg(not(any(
+    target_has_atomic = "8",
+    all(any(target_arch = "arm", target_arch = "msp430"), target_os = "none")
+)))]
+fn claim(_taken: &AtomicBool) -> bool {
+    false
+}
+
+/// Declare a stack in `static` memory, optionally in a specific link section,
+/// and evaluate to a `&'static mut [MaybeUninit<u8>]` for it.
+///
+/// This is meant for embedded targets, where the ephemeral stack can be kept
+/// in fast memory that DMA controllers cannot reach, like the CCM RAM of an
+/// STM32 or the TCM of a Cortex-M7, by placing it in the section that the
+/// linker script maps there.  The stack is aligned to 32 bytes, and its size
+/// must be a multiple of 32 bytes and at least 1024 bytes, which is checked at
+/// compile time.  The buffer is not initialized, so it can live in a `NOLOAD`
+/// section; use it with [`run_then_erase_with_uninit_stack`] or
+/// [`try_run_then_erase_with_uninit_stack`].
+///
+/// Every invocation declares its own stack, and evaluates to `Some` only the
+/// first time that it is executed; after that, it evaluates to `None`.  On
+/// targets without atomic swap (other than Cortex-M and MSP430), it always
+/// evaluates to `None`.
+///
+/// ## Example
+/// ```
+/// let stack = eraser::static_erased_stack!(4096).unwrap();
+/// let result = unsafe { eraser::run_then_erase_with_uninit_stack(|| 42, stack) };
+/// assert_eq!(result, 42);
+/// ```
+///
+/// With a `.ccmram` section in the linker script,
+/// `static_erased_stack!(8192, ".ccmram")` puts an 8 KiB stack in CCM RAM.
+///
+/// [`run_then_erase_with_uninit_stack`]: crate::run_then_erase_with_uninit_stack
+/// [`try_run_then_erase_with_uninit_stack`]: crate::try_run_then_erase_with_uninit_stack
+#[macro_export]
+macro_rules! static_erased_stack {
+    ($size:expr, $section:literal) => {{
+        #[link_section = $section]
+        static STACK: $crate::StaticStack<{ $size }> = $crate::StaticStack::new();
+        static TAKEN: $crate::StaticStackFlag = $crate::StaticStackFlag::new();
+        STACK.take(&TAKEN)
+    }};
+    ($size:expr) => {{
+        static STACK: $crate::StaticStack<{ $size }> = $crate::StaticStack::new();
+        static TAKEN: $crate::StaticStackFlag = $crate::StaticStackFlag::new();
+        STACK.take(&TAKEN)
+    }};
+}
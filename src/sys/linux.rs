@@ -0,0 +1,535 @@
+use super::{ChildExit, CpuSet, Speculation};
+use crate::EraserError;
+#[cfg(target_arch = "riscv64")]
+use core::ffi::c_ulong;
+use core::ffi::{c_int, c_long, c_void};
+
+const PROT_NONE: c_int = 0x0;
+const PROT_READ: c_int = 0x1;
+const PROT_WRITE: c_int = 0x2;
+#[cfg(target_arch = "aarch64")]
+const PROT_MTE: c_int = 0x20;
+const MAP_SHARED: c_int = 0x01;
+const MAP_PRIVATE: c_int = 0x02;
+// MIPS has its own values for most of the mapping flags
+#[cfg(not(any(target_arch = "mips", target_arch = "mips64")))]
+const MAP_ANONYMOUS: c_int = 0x20;
+#[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+const MAP_ANONYMOUS: c_int = 0x800;
+#[cfg(not(any(
+    target_arch = "powerpc64",
+    target_arch = "sparc64",
+    target_arch = "mips",
+    target_arch = "mips64"
+)))]
+const MAP_NORESERVE: c_int = 0x4000;
+#[cfg(any(target_arch = "powerpc64", target_arch = "sparc64"))]
+const MAP_NORESERVE: c_int = 0x40;
+#[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+const MAP_NORESERVE: c_int = 0x400;
+#[cfg(not(any(target_arch = "mips", target_arch = "mips64")))]
+const MAP_STACK: c_int = 0x20000;
+#[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+const MAP_STACK: c_int = 0x40000;
+const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+const SC_PAGESIZE: c_int = 30;
+const RLIMIT_CORE: c_int = 4;
+#[cfg(not(any(target_arch = "mips", target_arch = "mips64")))]
+const RLIMIT_MEMLOCK: c_int = 8;
+#[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+const RLIMIT_MEMLOCK: c_int = 9;
+const RLIM_INFINITY: u64 = !0;
+const MADV_DONTFORK: c_int = 10;
+const MADV_DONTDUMP: c_int = 16;
+const MADV_DODUMP: c_int = 17;
+const MADV_WIPEONFORK: c_int = 18;
+const PR_SET_DUMPABLE: c_int = 4;
+const PR_SET_NO_NEW_PRIVS: c_int = 38;
+const PR_GET_SPECULATION_CTRL: c_int = 52;
+const PR_SET_SPECULATION_CTRL: c_int = 53;
+const PR_SPEC_STORE_BYPASS: c_long = 0;
+const PR_SPEC_INDIRECT_BRANCH: c_long = 1;
+const PR_SPEC_PRCTL: c_int = 1 << 0;
+const PR_SPEC_ENABLE: c_long = 1 << 1;
+const PR_SPEC_DISABLE: c_long = 1 << 2;
+const PR_SPEC_FORCE_DISABLE: c_int = 1 << 3;
+#[cfg(not(target_arch = "sparc64"))]
+const O_CLOEXEC: c_long = 0o2000000;
+#[cfg(target_arch = "sparc64")]
+const O_CLOEXEC: c_long = 0x400000;
+/// The number of the `memfd_secret` system call, on the architectures where it
+/// exists.
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64",
+    target_arch = "s390x"
+))]
+const SYS_MEMFD_SECRET: Option<c_long> = Some(447);
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64",
+    target_arch = "s390x"
+)))]
+const SYS_MEMFD_SECRET: Option<c_long> = None;
+/// The number of the `mseal` system call, which only exists on 64-bit
+/// architectures.  The system calls of the MIPS n64 ABI start at 5000.
+#[cfg(all(target_pointer_width = "64", not(target_arch = "mips64")))]
+const SYS_MSEAL: Option<c_long> = Some(462);
+#[cfg(all(target_pointer_width = "64", target_arch = "mips64"))]
+const SYS_MSEAL: Option<c_long> = Some(5462);
+#[cfg(not(target_pointer_width = "64"))]
+const SYS_MSEAL: Option<c_long> = None;
+/// The numbers of the protection key system calls.
+#[cfg(target_arch = "x86_64")]
+const SYS_PKEY_MPROTECT: Option<c_long> = Some(329);
+#[cfg(not(target_arch = "x86_64"))]
+const SYS_PKEY_MPROTECT: Option<c_long> = None;
+#[cfg(target_arch = "x86_64")]
+const SYS_PKEY_ALLOC: c_long = 330;
+#[cfg(target_arch = "x86_64")]
+const PKEY_DISABLE_ACCESS: c_long = 0x1;
+#[cfg(target_arch = "riscv64")]
+const AT_HWCAP: c_ulong = 16;
+#[cfg(target_arch = "riscv64")]
+const HWCAP_ISA_V: c_ulong = 1 << (b'V' - b'A');
+#[cfg(target_arch = "riscv64")]
+const PR_RISCV_V_GET_CONTROL: c_int = 70;
+#[cfg(target_arch = "riscv64")]
+const PR_RISCV_V_VSTATE_CTRL_ON: c_int = 2;
+#[cfg(target_arch = "riscv64")]
+const PR_RISCV_V_VSTATE_CTRL_CUR_MASK: c_int = 0x3;
+
+// On 32-bit glibc, `off_t` and `rlim_t` are 32 bits wide, so the 64-bit
+// variants of the functions that take them are used instead.
+extern "C" {
+    fn sysconf(name: c_int) -> c_long;
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: c_long,
+    ) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    fn syscall(number: c_long, ...) -> c_long;
+    #[cfg_attr(
+        all(target_pointer_width = "32", target_env = "gnu"),
+        link_name = "ftruncate64"
+    )]
+    fn ftruncate(fd: c_int, length: i64) -> c_int;
+    fn close(fd: c_int) -> c_int;
+    #[cfg_attr(
+        all(target_pointer_width = "32", target_env = "gnu"),
+        link_name = "getrlimit64"
+    )]
+    fn getrlimit(resource: c_int, rlim: *mut [u64; 2]) -> c_int;
+    #[cfg_attr(
+        all(target_pointer_width = "32", target_env = "gnu"),
+        link_name = "setrlimit64"
+    )]
+    fn setrlimit(resource: c_int, rlim: *const [u64; 2]) -> c_int;
+    fn prctl(option: c_int, ...) -> c_int;
+    fn fork() -> c_int;
+    fn waitpid(pid: c_int, status: *mut c_int, options: c_int) -> c_int;
+    fn _exit(status: c_int) -> !;
+    fn sched_getaffinity(pid: c_int, size: usize, mask: *mut CpuSet) -> c_int;
+    fn sched_setaffinity(pid: c_int, size: usize, mask: *const CpuSet) -> c_int;
+    fn mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int;
+    fn mlock(addr: *const c_void, len: usize) -> c_int;
+    fn munlock(addr: *const c_void, len: usize) -> c_int;
+    fn madvise(addr: *mut c_void, len: usize, advice: c_int) -> c_int;
+    #[cfg(target_arch = "riscv64")]
+    fn getauxval(kind: c_ulong) -> c_ulong;
+}
+
+/// Return the size of a memory page.
+pub(crate) fn page_size() -> usize {
+    let size = unsafe { sysconf(SC_PAGESIZE) };
+    assert!(size > 0, "sysconf(_SC_PAGESIZE) failed");
+    size as usize
+}
+
+/// Return the maximum number of bytes that the process may lock into memory,
+/// or `None` if it is unknown.
+pub(crate) fn memlock_limit() -> Option<usize> {
+    // `struct rlimit { rlim_t rlim_cur; rlim_t rlim_max; }`
+    let mut limit = [0u64; 2];
+    if unsafe { getrlimit(RLIMIT_MEMLOCK, &mut limit) } != 0 {
+        return None;
+    }
+    match limit[0] {
+        RLIM_INFINITY => Some(usize::MAX),
+        cur => Some(usize::try_from(cur).unwrap_or(usize::MAX)),
+    }
+}
+
+/// Mark the process as not dumpable.
+pub(crate) fn set_not_dumpable() -> Result<(), EraserError> {
+    let zero = 0 as c_long;
+    match unsafe { prctl(PR_SET_DUMPABLE, zero, zero, zero, zero) } {
+        0 => Ok(()),
+        _ => Err(EraserError::ProtectFailed),
+    }
+}
+
+/// Set the core dump size limit of the process to zero.
+pub(crate) fn disable_core_dumps() -> Result<(), EraserError> {
+    match unsafe { setrlimit(RLIMIT_CORE, &[0, 0]) } {
+        0 => Ok(()),
+        _ => Err(EraserError::ProtectFailed),
+    }
+}
+
+/// Keep the process from gaining new privileges through `execve`.
+pub(crate) fn set_no_new_privs() -> Result<(), EraserError> {
+    let zero = 0 as c_long;
+    match unsafe { prctl(PR_SET_NO_NEW_PRIVS, 1 as c_long, zero, zero, zero) } {
+        0 => Ok(()),
+        _ => Err(EraserError::ProtectFailed),
+    }
+}
+
+fn speculation_ctrl(speculation: Speculation) -> c_long {
+    match speculation {
+        Speculation::StoreBypass => PR_SPEC_STORE_BYPASS,
+        Speculation::IndirectBranch => PR_SPEC_INDIRECT_BRANCH,
+    }
+}
+
+/// Restrict `speculation` for the current thread.
+///
+/// Returns whether the speculation was allowed before, i.e., whether it has
+/// to be allowed again with `allow_speculation`.  Fails with
+/// [`EraserError::Unsupported`] if the speculation is allowed, and the kernel
+/// does not let the thread restrict it.
+pub(crate) fn restrict_speculation(speculation: Speculation) -> Result<bool, EraserError> {
+    let ctrl = speculation_ctrl(speculation);
+    let zero = 0 as c_long;
+    let state = unsafe { prctl(PR_GET_SPECULATION_CTRL, ctrl, zero, zero, zero) };
+    if state < 0 {
+        return Err(EraserError::Unsupported);
+    }
+    // The CPU is not affected, or the speculation is already restricted
+    let disabled = PR_SPEC_DISABLE as c_int | PR_SPEC_FORCE_DISABLE;
+    if state == 0 || state & disabled != 0 {
+        return Ok(false);
+    }
+    if state & PR_SPEC_PRCTL == 0 {
+        return Err(EraserError::Unsupported);
+    }
+    match unsafe { prctl(PR_SET_SPECULATION_CTRL, ctrl, PR_SPEC_DISABLE, zero, zero) } {
+        0 => Ok(true),
+        _ => Err(EraserError::Unsupported),
+    }
+}
+
+/// Allow `speculation` for the current thread again, after it was restricted
+/// with `restrict_speculation`.
+pub(crate) fn allow_speculation(speculation: Speculation) {
+    let ctrl = speculation_ctrl(speculation);
+    let zero = 0 as c_long;
+    unsafe { prctl(PR_SET_SPECULATION_CTRL, ctrl, PR_SPEC_ENABLE, zero, zero) };
+}
+
+/// Restrict the current thread to run on `cpu` only, and return the set of
+/// CPUs that it was allowed to run on before.
+pub(crate) fn pin_to_cpu(cpu: usize) -> Result<CpuSet, EraserError> {
+    let mut previous = [0; 16];
+    let mut pinned = [0; 16];
+    *pinned.get_mut(cpu / 64).ok_or(EraserError::PinFailed)? = 1 << (cpu % 64);
+    let size = core::mem::size_of::<CpuSet>();
+    if unsafe { sched_getaffinity(0, size, &mut previous) } != 0 {
+        return Err(EraserError::PinFailed);
+    }
+    match unsafe { sched_setaffinity(0, size, &pinned) } {
+        0 => Ok(previous),
+        _ => Err(EraserError::PinFailed),
+    }
+}
+
+/// Let the current thread run on the CPUs in `cpus` again.
+pub(crate) fn set_affinity(cpus: &CpuSet) {
+    unsafe { sched_setaffinity(0, core::mem::size_of::<CpuSet>(), cpus) };
+}
+
+/// Return the other online hardware threads on the same core as `cpu`, or
+/// `None` if the topology of `cpu` is not known.
+pub(crate) fn smt_siblings(cpu: usize) -> Option<Vec<usize>> {
+    let path = format!(
+        "/sys/devices/system/cpu/cpu{}/topology/thread_siblings_list",
+        cpu
+    );
+    let list = std::fs::read_to_string(path).ok()?;
+    let mut siblings = Vec::new();
+    // The list looks like "0-1,4"
+    for range in list.trim().split(',') {
+        let (first, last) = range.split_once('-').unwrap_or((range, range));
+        let (first, last): (usize, usize) = (first.parse().ok()?, last.parse().ok()?);
+        siblings.extend((first..=last).filter(|&sibling| sibling != cpu));
+    }
+    Some(siblings)
+}
+
+/// Fork the process.  Returns the process ID of the child in the parent, and
+/// zero in the child.
+pub(crate) fn fork_process() -> Result<i32, EraserError> {
+    match unsafe { fork() } {
+        pid if pid >= 0 => Ok(pid),
+        _ => Err(EraserError::IsolationFailed),
+    }
+}
+
+/// Wait for the child process `pid` to end.
+pub(crate) fn wait_child(pid: i32) -> Result<ChildExit, EraserError> {
+    let mut status = 0;
+    while unsafe { waitpid(pid, &mut status, 0) } != pid {
+        if std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted {
+            return Err(EraserError::IsolationFailed);
+        }
+    }
+    // `WIFEXITED` and `WEXITSTATUS`
+    match status & 0x7f {
+        0 => Ok(ChildExit::Exited((status >> 8) & 0xff)),
+        _ => Ok(ChildExit::Killed),
+    }
+}
+
+/// End the current (child) process with `status`, without running any exit
+/// handlers.
+pub(crate) fn exit_child(status: i32) -> ! {
+    unsafe { _exit(status) }
+}
+
+/// Map `len` bytes of zeroed, readable and writable memory that is shared with
+/// child processes.
+///
+/// `len` must be a multiple of the page size.
+pub(crate) fn map_shared(len: usize) -> Result<*mut u8, EraserError> {
+    let flags = MAP_SHARED | MAP_ANONYMOUS;
+    let addr = unsafe {
+        mmap(
+            core::ptr::null_mut(),
+            len,
+            PROT_READ | PROT_WRITE,
+            flags,
+            -1,
+            0,
+        )
+    };
+    match addr {
+        MAP_FAILED => Err(EraserError::AllocFailed),
+        addr => Ok(addr as *mut u8),
+    }
+}
+
+/// Map `len` bytes of private, zeroed, readable and writable memory.
+///
+/// `len` must be a multiple of the page size.  No swap space is reserved for
+/// the mapping, so large stacks only cost memory for the pages that are
+/// actually used.
+pub(crate) fn map(len: usize) -> Result<*mut u8, EraserError> {
+    let flags = MAP_PRIVATE | MAP_ANONYMOUS | MAP_STACK | MAP_NORESERVE;
+    let addr = unsafe {
+        mmap(
+            core::ptr::null_mut(),
+            len,
+            PROT_READ | PROT_WRITE,
+            flags,
+            -1,
+            0,
+        )
+    };
+    match addr {
+        MAP_FAILED => Err(EraserError::AllocFailed),
+        addr => Ok(addr as *mut u8),
+    }
+}
+
+/// Map `len` bytes of zeroed, readable and writable memory that is backed by
+/// `memfd_secret`.
+///
+/// The pages of the mapping are removed from the kernel direct map, so they
+/// can only be accessed through this mapping.  This needs Linux 5.14 or later,
+/// with secret memory enabled; otherwise this fails with
+/// [`EraserError::Unsupported`].  `len` must be a multiple of the page size.
+pub(crate) fn map_secret(len: usize) -> Result<*mut u8, EraserError> {
+    let number = SYS_MEMFD_SECRET.ok_or(EraserError::Unsupported)?;
+    let fd = unsafe { syscall(number, O_CLOEXEC) };
+    if fd < 0 {
+        return Err(EraserError::Unsupported);
+    }
+    let fd = fd as c_int;
+    let length = i64::try_from(len).map_err(|_| EraserError::AllocFailed)?;
+    let addr = match unsafe { ftruncate(fd, length) } {
+        0 => unsafe {
+            mmap(
+                core::ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED,
+                fd,
+                0,
+            )
+        },
+        _ => MAP_FAILED,
+    };
+    // The mapping keeps the memory alive
+    unsafe { close(fd) };
+    match addr {
+        MAP_FAILED => Err(EraserError::AllocFailed),
+        addr => Ok(addr as *mut u8),
+    }
+}
+
+/// Unmap the memory in `[addr, addr + len)` that was mapped with `map`,
+/// `map_secret`, or `map_shared`.
+pub(crate) unsafe fn unmap(addr: *mut u8, len: usize) {
+    munmap(addr as *mut c_void, len);
+}
+
+/// Make the pages in `[addr, addr + len)` inaccessible.
+pub(crate) unsafe fn protect_none(addr: *mut u8, len: usize) -> Result<(), EraserError> {
+    match mprotect(addr as *mut c_void, len, PROT_NONE) {
+        0 => Ok(()),
+        _ => Err(EraserError::ProtectFailed),
+    }
+}
+
+/// Make the pages in `[addr, addr + len)` readable and writable again.
+pub(crate) unsafe fn protect_read_write(addr: *mut u8, len: usize) -> Result<(), EraserError> {
+    match mprotect(addr as *mut c_void, len, PROT_READ | PROT_WRITE) {
+        0 => Ok(()),
+        _ => Err(EraserError::ProtectFailed),
+    }
+}
+
+/// Seal the pages in `[addr, addr + len)`, so that they can never be
+/// unmapped, remapped, or have their protection changed.
+///
+/// This needs Linux 6.10 or later; otherwise this fails with
+/// [`EraserError::Unsupported`].
+pub(crate) unsafe fn seal(addr: *mut u8, len: usize) -> Result<(), EraserError> {
+    let number = SYS_MSEAL.ok_or(EraserError::Unsupported)?;
+    match syscall(number, addr, len, 0 as c_long) {
+        0 => Ok(()),
+        _ => Err(EraserError::Unsupported),
+    }
+}
+
+/// Allocate a protection key, with access to the memory that is tagged with it
+/// disabled.
+///
+/// This fails with [`EraserError::Unsupported`] if the CPU or the kernel does
+/// not support protection keys, or if all the keys are in use.
+#[cfg(target_arch = "x86_64")]
+pub(crate) fn pkey_alloc() -> Result<i32, EraserError> {
+    match unsafe { syscall(SYS_PKEY_ALLOC, 0 as c_long, PKEY_DISABLE_ACCESS) } {
+        key if key > 0 => Ok(key as i32),
+        _ => Err(EraserError::Unsupported),
+    }
+}
+
+/// Tag the readable and writable pages in `[addr, addr + len)` with the
+/// protection key `key`.
+pub(crate) unsafe fn pkey_mprotect(addr: *mut u8, len: usize, key: i32) -> Result<(), EraserError> {
+    let number = SYS_PKEY_MPROTECT.ok_or(EraserError::Unsupported)?;
+    let prot = (PROT_READ | PROT_WRITE) as c_long;
+    match syscall(number, addr, len, prot, key as c_long) {
+        0 => Ok(()),
+        _ => Err(EraserError::ProtectFailed),
+    }
+}
+
+/// Make the pages in `[addr, addr + len)` readable and writable, and let them
+/// carry memory tags.
+#[cfg(target_arch = "aarch64")]
+pub(crate) unsafe fn protect_tagged(addr: *mut u8, len: usize) -> Result<(), EraserError> {
+    match mprotect(addr as *mut c_void, len, PROT_READ | PROT_WRITE | PROT_MTE) {
+        0 => Ok(()),
+        _ => Err(EraserError::Unsupported),
+    }
+}
+
+/// Return whether the current process can use the RISC-V vector extension.
+///
+/// The CPU must have it (according to `AT_HWCAP`), and the kernel must let the
+/// process use it: otherwise, the first vector instruction raises `SIGILL`.
+/// Both are looked up once.
+#[cfg(target_arch = "riscv64")]
+pub(crate) fn has_vector() -> bool {
+    use core::sync::atomic::{AtomicU8, Ordering};
+    // 0 if not looked up yet, otherwise 1 + whether the extension is usable
+    static HAS_VECTOR: AtomicU8 = AtomicU8::new(0);
+    match HAS_VECTOR.load(Ordering::Relaxed) {
+        0 => {}
+        state => return state == 2,
+    }
+    let usable = unsafe { getauxval(AT_HWCAP) } & HWCAP_ISA_V != 0 && {
+        let zero = 0 as c_long;
+        let ctrl = unsafe { prctl(PR_RISCV_V_GET_CONTROL, zero, zero, zero, zero) };
+        ctrl >= 0 && ctrl & PR_RISCV_V_VSTATE_CTRL_CUR_MASK == PR_RISCV_V_VSTATE_CTRL_ON
+    };
+    HAS_VECTOR.store(1 + usable as u8, Ordering::Relaxed);
+    usable
+}
+
+/// Lock the pages in `[addr, addr + len)` into memory.
+pub(crate) unsafe fn lock(addr: *mut u8, len: usize) -> Result<(), EraserError> {
+    match mlock(addr as *const c_void, len) {
+        0 => Ok(()),
+        _ => Err(EraserError::LockFailed),
+    }
+}
+
+/// Unlock the pages in `[addr, addr + len)`.
+pub(crate) unsafe fn unlock(addr: *mut u8, len: usize) {
+    munlock(addr as *const c_void, len);
+}
+
+/// Exclude the pages in `[addr, addr + len)` from core dumps.
+pub(crate) unsafe fn dont_dump(addr: *mut u8, len: usize) -> Result<(), EraserError> {
+    match madvise(addr as *mut c_void, len, MADV_DONTDUMP) {
+        0 => Ok(()),
+        _ => Err(EraserError::ProtectFailed),
+    }
+}
+
+/// Replace the pages in `[addr, addr + len)` with zeros in the child process
+/// after a `fork()`.
+///
+/// This needs Linux 4.14 or later, and only works on private anonymous
+/// mappings.
+pub(crate) unsafe fn wipe_on_fork(addr: *mut u8, len: usize) -> Result<(), EraserError> {
+    match madvise(addr as *mut c_void, len, MADV_WIPEONFORK) {
+        0 => Ok(()),
+        _ => Err(EraserError::ProtectFailed),
+    }
+}
+
+/// Leave the pages in `[addr, addr + len)` out of the child process after a
+/// `fork()`.
+pub(crate) unsafe fn dont_fork(addr: *mut u8, len: usize) -> Result<(), EraserError> {
+    match madvise(addr as *mut c_void, len, MADV_DONTFORK) {
+        0 => Ok(()),
+        _ => Err(EraserError::ProtectFailed),
+    }
+}
+
+/// Include the pages in `[addr, addr + len)` in core dumps again.
+pub(crate) unsafe fn do_dump(addr: *mut u8, len: usize) {
+    madvise(addr as *mut c_void, len, MADV_DODUMP);
+}
+
+/// Encrypt the memory in `[addr, addr + len)` in place, with a key that only
+/// this process can use.
+pub(crate) unsafe fn encrypt(_addr: *mut u8, _len: usize) -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Decrypt memory that was encrypted with [`encrypt`].
+pub(crate) unsafe fn decrypt(_addr: *mut u8, _len: usize) -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
@@ -0,0 +1,129 @@
+//! Thin wrappers around the memory management functions of the operating
+//! system.
+//!
+//! Every backend exposes the same set of functions, and fails with
+//! [`EraserError::Unsupported`](crate::EraserError::Unsupported) for the
+//! functions that the operating system does not support.  On targets without a
+//! backend, all of them fail.
+
+/// A kind of speculative execution that can be restricted for a thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Speculation {
+    /// Speculative store bypass (Spectre variant 4).
+    StoreBypass,
+    /// Indirect branch speculation (Spectre variant 2).
+    IndirectBranch,
+}
+
+/// How a child process ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    not(any(
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "illumos",
+        target_os = "redox",
+        target_os = "nto"
+    )),
+    allow(dead_code)
+)]
+pub(crate) enum ChildExit {
+    /// The child exited with this status code.
+    Exited(i32),
+    /// The child was killed by a signal.
+    Killed,
+}
+
+/// A set of CPUs, as a bitmask of (up to) 1024 CPUs.
+pub(crate) type CpuSet = [u64; 16];
+
+/// Whether the backend can map memory (and protect whole pages of it).
+pub(crate) const CAN_MAP: bool = cfg!(any(
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "illumos",
+    target_os = "redox",
+    target_os = "nto",
+    target_os = "fuchsia",
+    windows
+));
+
+/// Whether the backend can exclude memory from core dumps.
+pub(crate) const CAN_DONT_DUMP: bool = cfg!(any(
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "redox"
+));
+
+/// Whether the kernel only accepts stacks that it mapped itself (like OpenBSD
+/// with `MAP_STACK`), so that every stack has to be mapped with `map`.
+pub(crate) const MAP_STACKS: bool = cfg!(target_os = "openbsd");
+
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "linux")]
+pub(crate) use linux::*;
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "illumos",
+    target_os = "redox",
+    target_os = "nto"
+))]
+mod posix;
+#[cfg(any(
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "illumos",
+    target_os = "redox",
+    target_os = "nto"
+))]
+pub(crate) use posix::*;
+
+#[cfg(target_os = "fuchsia")]
+mod fuchsia;
+#[cfg(target_os = "fuchsia")]
+pub(crate) use fuchsia::*;
+
+#[cfg(windows)]
+mod windows;
+#[cfg(windows)]
+pub(crate) use windows::*;
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "illumos",
+    target_os = "redox",
+    target_os = "nto",
+    target_os = "fuchsia",
+    windows
+)))]
+mod unsupported;
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "illumos",
+    target_os = "redox",
+    target_os = "nto",
+    target_os = "fuchsia",
+    windows
+)))]
+pub(crate) use unsupported::*;
+
+#[cfg(target_os = "wasi")]
+mod wasi;
+#[cfg(target_os = "wasi")]
+pub(crate) use wasi::*;
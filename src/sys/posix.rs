@@ -0,0 +1,450 @@
+//! The backend for the BSD-like systems (macOS, FreeBSD and OpenBSD),
+//! illumos, Redox and QNX, which share most of their memory management
+//! functions.  Only the constants and a few functions differ between them.
+
+use super::{ChildExit, CpuSet, Speculation};
+use crate::EraserError;
+use core::ffi::{c_int, c_long, c_void};
+
+const PROT_NONE: c_int = 0x0;
+#[cfg(not(any(target_os = "redox", target_os = "nto")))]
+const PROT_READ: c_int = 0x1;
+#[cfg(target_os = "redox")]
+const PROT_READ: c_int = 0x4;
+#[cfg(target_os = "nto")]
+const PROT_READ: c_int = 0x100;
+#[cfg(not(target_os = "nto"))]
+const PROT_WRITE: c_int = 0x2;
+#[cfg(target_os = "nto")]
+const PROT_WRITE: c_int = 0x200;
+const MAP_SHARED: c_int = 0x0001;
+const MAP_PRIVATE: c_int = 0x0002;
+const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+const RLIMIT_CORE: c_int = 4;
+#[cfg(not(target_os = "nto"))]
+const RLIM_INFINITY: u64 = (1 << 63) - 1;
+#[cfg(target_os = "nto")]
+const RLIM_INFINITY: u64 = !2;
+
+#[cfg(target_os = "macos")]
+mod os {
+    use core::ffi::c_int;
+
+    pub(super) const SC_PAGESIZE: c_int = 29;
+    pub(super) const MAP_ANON: c_int = 0x1000;
+    pub(super) const RLIMIT_MEMLOCK: Option<c_int> = Some(6);
+    /// Flags for private mappings, on top of `MAP_PRIVATE | MAP_ANON`.
+    pub(super) const MAP_FLAGS: c_int = 0;
+    /// Whether `MAP_FLAGS` excludes the mappings from core dumps.
+    pub(super) const CONCEALED: bool = false;
+    /// The `minherit` values that leave the pages out of the child, and that
+    /// zero the pages in the child, if supported.
+    pub(super) const INHERIT_NONE: Option<c_int> = Some(2);
+    pub(super) const INHERIT_ZERO: Option<c_int> = None;
+    pub(super) const MADV_ZERO_WIRED_PAGES: c_int = 6;
+    pub(super) const PT_DENY_ATTACH: c_int = 31;
+}
+
+#[cfg(target_os = "openbsd")]
+mod os {
+    use core::ffi::c_int;
+
+    pub(super) const SC_PAGESIZE: c_int = 28;
+    pub(super) const MAP_ANON: c_int = 0x1000;
+    pub(super) const RLIMIT_MEMLOCK: Option<c_int> = Some(6);
+    const MAP_STACK: c_int = 0x4000;
+    const MAP_CONCEAL: c_int = 0x8000;
+    /// Flags for private mappings, on top of `MAP_PRIVATE | MAP_ANON`.
+    ///
+    /// The kernel kills a process whose stack pointer is outside of a
+    /// `MAP_STACK` mapping on a system call or page fault, and `MAP_CONCEAL`
+    /// excludes the mapping from core dumps.
+    pub(super) const MAP_FLAGS: c_int = MAP_STACK | MAP_CONCEAL;
+    pub(super) const CONCEALED: bool = true;
+    pub(super) const INHERIT_NONE: Option<c_int> = Some(2);
+    pub(super) const INHERIT_ZERO: Option<c_int> = Some(3);
+}
+
+#[cfg(target_os = "freebsd")]
+mod os {
+    use core::ffi::c_int;
+
+    pub(super) const SC_PAGESIZE: c_int = 47;
+    pub(super) const MAP_ANON: c_int = 0x1000;
+    pub(super) const RLIMIT_MEMLOCK: Option<c_int> = Some(6);
+    const MAP_NOCORE: c_int = 0x20000;
+    /// Flags for private mappings, on top of `MAP_PRIVATE | MAP_ANON`.
+    ///
+    /// `MAP_NOCORE` excludes the mapping from core dumps.
+    pub(super) const MAP_FLAGS: c_int = MAP_NOCORE;
+    pub(super) const CONCEALED: bool = true;
+    pub(super) const INHERIT_NONE: Option<c_int> = Some(2);
+    pub(super) const INHERIT_ZERO: Option<c_int> = Some(3);
+    pub(super) const P_PID: c_int = 0;
+    pub(super) const PROC_TRACE_CTL: c_int = 7;
+    pub(super) const PROC_TRACE_CTL_DISABLE: c_int = 2;
+    pub(super) const PROC_NO_NEW_PRIVS_CTL: c_int = 19;
+    pub(super) const PROC_NO_NEW_PRIVS_ENABLE: c_int = 1;
+}
+
+#[cfg(target_os = "illumos")]
+mod os {
+    use core::ffi::c_int;
+
+    pub(super) const SC_PAGESIZE: c_int = 11;
+    pub(super) const MAP_ANON: c_int = 0x100;
+    /// illumos limits locked memory with the `proc_lock_memory` privilege and
+    /// the `project.max-locked-memory` resource control instead.
+    pub(super) const RLIMIT_MEMLOCK: Option<c_int> = None;
+    /// Flags for private mappings, on top of `MAP_PRIVATE | MAP_ANON`.
+    pub(super) const MAP_FLAGS: c_int = 0;
+    pub(super) const CONCEALED: bool = false;
+    /// illumos does not have `minherit`.
+    pub(super) const INHERIT_NONE: Option<c_int> = None;
+    pub(super) const INHERIT_ZERO: Option<c_int> = None;
+}
+
+#[cfg(target_os = "redox")]
+mod os {
+    use core::ffi::c_int;
+
+    pub(super) const SC_PAGESIZE: c_int = 30;
+    pub(super) const MAP_ANON: c_int = 0x20;
+    /// Redox does not limit locked memory.
+    pub(super) const RLIMIT_MEMLOCK: Option<c_int> = None;
+    /// Flags for private mappings, on top of `MAP_PRIVATE | MAP_ANON`.
+    pub(super) const MAP_FLAGS: c_int = 0;
+    /// Redox does not write core dumps at all.
+    pub(super) const CONCEALED: bool = true;
+    /// Redox does not have `minherit`.
+    pub(super) const INHERIT_NONE: Option<c_int> = None;
+    pub(super) const INHERIT_ZERO: Option<c_int> = None;
+}
+
+#[cfg(target_os = "nto")]
+mod os {
+    use core::ffi::c_int;
+
+    pub(super) const SC_PAGESIZE: c_int = 11;
+    pub(super) const MAP_ANON: c_int = 0x80000;
+    pub(super) const RLIMIT_MEMLOCK: Option<c_int> = Some(7);
+    /// Flags for private mappings, on top of `MAP_PRIVATE | MAP_ANON`.
+    pub(super) const MAP_FLAGS: c_int = 0;
+    pub(super) const CONCEALED: bool = false;
+    /// QNX does not have `minherit`.
+    pub(super) const INHERIT_NONE: Option<c_int> = None;
+    pub(super) const INHERIT_ZERO: Option<c_int> = None;
+}
+
+extern "C" {
+    fn sysconf(name: c_int) -> c_long;
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: i64,
+    ) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    fn getrlimit(resource: c_int, rlim: *mut [u64; 2]) -> c_int;
+    fn setrlimit(resource: c_int, rlim: *const [u64; 2]) -> c_int;
+    #[cfg(target_os = "freebsd")]
+    fn procctl(idtype: c_int, id: i64, cmd: c_int, data: *mut c_void) -> c_int;
+    #[cfg(target_os = "macos")]
+    fn ptrace(request: c_int, pid: c_int, addr: *mut c_void, data: c_int) -> c_int;
+    fn fork() -> c_int;
+    fn waitpid(pid: c_int, status: *mut c_int, options: c_int) -> c_int;
+    fn _exit(status: c_int) -> !;
+    fn mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int;
+    fn mlock(addr: *const c_void, len: usize) -> c_int;
+    fn munlock(addr: *const c_void, len: usize) -> c_int;
+    #[cfg(target_os = "macos")]
+    fn madvise(addr: *mut c_void, len: usize, advice: c_int) -> c_int;
+    #[cfg(not(any(target_os = "illumos", target_os = "redox", target_os = "nto")))]
+    fn minherit(addr: *mut c_void, len: usize, inherit: c_int) -> c_int;
+}
+
+/// illumos, Redox and QNX do not have `minherit`, and `os::INHERIT_NONE` and
+/// `os::INHERIT_ZERO` are `None` there, so this is never called.
+#[cfg(any(target_os = "illumos", target_os = "redox", target_os = "nto"))]
+unsafe fn minherit(_addr: *mut c_void, _len: usize, _inherit: c_int) -> c_int {
+    -1
+}
+
+/// Return the size of a memory page.
+pub(crate) fn page_size() -> usize {
+    let size = unsafe { sysconf(os::SC_PAGESIZE) };
+    assert!(size > 0, "sysconf(_SC_PAGESIZE) failed");
+    size as usize
+}
+
+/// Return the maximum number of bytes that the process may lock into memory,
+/// or `None` if it is unknown.
+pub(crate) fn memlock_limit() -> Option<usize> {
+    // `struct rlimit { rlim_t rlim_cur; rlim_t rlim_max; }`
+    let mut limit = [0u64; 2];
+    let resource = os::RLIMIT_MEMLOCK?;
+    if unsafe { getrlimit(resource, &mut limit) } != 0 {
+        return None;
+    }
+    match limit[0] {
+        RLIM_INFINITY => Some(usize::MAX),
+        cur => Some(usize::try_from(cur).unwrap_or(usize::MAX)),
+    }
+}
+
+/// Mark the process as not dumpable.
+///
+/// macOS and FreeBSD do not have a dumpable flag; instead, this keeps
+/// debuggers from attaching to the process (with `PT_DENY_ATTACH` and
+/// `PROC_TRACE_CTL`).  This is not supported on OpenBSD, illumos, Redox and
+/// QNX.
+pub(crate) fn set_not_dumpable() -> Result<(), EraserError> {
+    #[cfg(target_os = "macos")]
+    {
+        match unsafe { ptrace(os::PT_DENY_ATTACH, 0, core::ptr::null_mut(), 0) } {
+            0 => Ok(()),
+            _ => Err(EraserError::ProtectFailed),
+        }
+    }
+    #[cfg(target_os = "freebsd")]
+    {
+        proc_ctl(os::PROC_TRACE_CTL, os::PROC_TRACE_CTL_DISABLE)
+    }
+    #[cfg(not(any(target_os = "macos", target_os = "freebsd")))]
+    {
+        Err(EraserError::Unsupported)
+    }
+}
+
+/// Change the `cmd` setting of the current process to `value`.
+#[cfg(target_os = "freebsd")]
+fn proc_ctl(cmd: c_int, mut value: c_int) -> Result<(), EraserError> {
+    let data = &mut value as *mut c_int as *mut c_void;
+    match unsafe { procctl(os::P_PID, 0, cmd, data) } {
+        0 => Ok(()),
+        _ => Err(EraserError::ProtectFailed),
+    }
+}
+
+/// Set the core dump size limit of the process to zero.
+///
+/// Redox does not write core dumps, so there is nothing to disable there.
+pub(crate) fn disable_core_dumps() -> Result<(), EraserError> {
+    if cfg!(target_os = "redox") {
+        return Ok(());
+    }
+    match unsafe { setrlimit(RLIMIT_CORE, &[0, 0]) } {
+        0 => Ok(()),
+        _ => Err(EraserError::ProtectFailed),
+    }
+}
+
+/// Keep the process from gaining new privileges through `execve`.
+///
+/// This needs FreeBSD 14 or later, and is not supported on the other systems.
+pub(crate) fn set_no_new_privs() -> Result<(), EraserError> {
+    #[cfg(target_os = "freebsd")]
+    {
+        proc_ctl(os::PROC_NO_NEW_PRIVS_CTL, os::PROC_NO_NEW_PRIVS_ENABLE)
+    }
+    #[cfg(not(target_os = "freebsd"))]
+    {
+        Err(EraserError::Unsupported)
+    }
+}
+
+/// Restrict `speculation` for the current thread.
+pub(crate) fn restrict_speculation(_speculation: Speculation) -> Result<bool, EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Allow `speculation` for the current thread again.
+pub(crate) fn allow_speculation(_speculation: Speculation) {}
+
+/// Restrict the current thread to run on `cpu` only, and return the set of
+/// CPUs that it was allowed to run on before.
+pub(crate) fn pin_to_cpu(_cpu: usize) -> Result<CpuSet, EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Let the current thread run on the CPUs in `cpus` again.
+pub(crate) fn set_affinity(_cpus: &CpuSet) {}
+
+/// Return the other online hardware threads on the same core as `cpu`, or
+/// `None` if the topology of `cpu` is not known.
+pub(crate) fn smt_siblings(_cpu: usize) -> Option<Vec<usize>> {
+    None
+}
+
+/// Fork the process.  Returns the process ID of the child in the parent, and
+/// zero in the child.
+pub(crate) fn fork_process() -> Result<i32, EraserError> {
+    match unsafe { fork() } {
+        pid if pid >= 0 => Ok(pid),
+        _ => Err(EraserError::IsolationFailed),
+    }
+}
+
+/// Wait for the child process `pid` to end.
+pub(crate) fn wait_child(pid: i32) -> Result<ChildExit, EraserError> {
+    let mut status = 0;
+    while unsafe { waitpid(pid, &mut status, 0) } != pid {
+        if std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted {
+            return Err(EraserError::IsolationFailed);
+        }
+    }
+    // `WIFEXITED` and `WEXITSTATUS`
+    match status & 0x7f {
+        0 => Ok(ChildExit::Exited((status >> 8) & 0xff)),
+        _ => Ok(ChildExit::Killed),
+    }
+}
+
+/// End the current (child) process with `status`, without running any exit
+/// handlers.
+pub(crate) fn exit_child(status: i32) -> ! {
+    unsafe { _exit(status) }
+}
+
+fn map_anonymous(len: usize, flags: c_int) -> Result<*mut u8, EraserError> {
+    let prot = PROT_READ | PROT_WRITE;
+    let addr = unsafe {
+        mmap(
+            core::ptr::null_mut(),
+            len,
+            prot,
+            flags | os::MAP_ANON,
+            -1,
+            0,
+        )
+    };
+    match addr {
+        MAP_FAILED => Err(EraserError::AllocFailed),
+        addr => Ok(addr as *mut u8),
+    }
+}
+
+/// Map `len` bytes of zeroed, readable and writable memory that is shared with
+/// child processes.
+///
+/// `len` must be a multiple of the page size.
+pub(crate) fn map_shared(len: usize) -> Result<*mut u8, EraserError> {
+    map_anonymous(len, MAP_SHARED)
+}
+
+/// Map `len` bytes of private, zeroed, readable and writable memory.
+///
+/// `len` must be a multiple of the page size.  On OpenBSD, the mapping can be
+/// used as a stack.  On FreeBSD and OpenBSD, it is excluded from core dumps.
+pub(crate) fn map(len: usize) -> Result<*mut u8, EraserError> {
+    map_anonymous(len, MAP_PRIVATE | os::MAP_FLAGS)
+}
+
+/// Map `len` bytes of zeroed, readable and writable memory that is removed
+/// from the kernel direct map.
+pub(crate) fn map_secret(_len: usize) -> Result<*mut u8, EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Unmap the memory in `[addr, addr + len)` that was mapped with `map` or
+/// `map_shared`.
+pub(crate) unsafe fn unmap(addr: *mut u8, len: usize) {
+    munmap(addr as *mut c_void, len);
+}
+
+/// Make the pages in `[addr, addr + len)` inaccessible.
+pub(crate) unsafe fn protect_none(addr: *mut u8, len: usize) -> Result<(), EraserError> {
+    match mprotect(addr as *mut c_void, len, PROT_NONE) {
+        0 => Ok(()),
+        _ => Err(EraserError::ProtectFailed),
+    }
+}
+
+/// Make the pages in `[addr, addr + len)` readable and writable again.
+pub(crate) unsafe fn protect_read_write(addr: *mut u8, len: usize) -> Result<(), EraserError> {
+    match mprotect(addr as *mut c_void, len, PROT_READ | PROT_WRITE) {
+        0 => Ok(()),
+        _ => Err(EraserError::ProtectFailed),
+    }
+}
+
+/// Seal the pages in `[addr, addr + len)`.
+pub(crate) unsafe fn seal(_addr: *mut u8, _len: usize) -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Tag the pages in `[addr, addr + len)` with the protection key `key`.
+pub(crate) unsafe fn pkey_mprotect(
+    _addr: *mut u8,
+    _len: usize,
+    _key: i32,
+) -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Lock the pages in `[addr, addr + len)` into memory.
+///
+/// On macOS, the pages are also marked with `MADV_ZERO_WIRED_PAGES`, so that
+/// the kernel zeroes them if they are released while they are still locked
+/// (for example, when the process exits or crashes during a run).
+pub(crate) unsafe fn lock(addr: *mut u8, len: usize) -> Result<(), EraserError> {
+    // This is best effort: older kernels do not know the advice
+    #[cfg(target_os = "macos")]
+    madvise(addr as *mut c_void, len, os::MADV_ZERO_WIRED_PAGES);
+    match mlock(addr as *const c_void, len) {
+        0 => Ok(()),
+        _ => Err(EraserError::LockFailed),
+    }
+}
+
+/// Unlock the pages in `[addr, addr + len)`.
+pub(crate) unsafe fn unlock(addr: *mut u8, len: usize) {
+    munlock(addr as *const c_void, len);
+}
+
+/// Exclude the pages in `[addr, addr + len)` from core dumps.
+///
+/// On FreeBSD and OpenBSD, all the memory that is mapped with `map` is
+/// excluded from core dumps already, and Redox does not write core dumps.
+pub(crate) unsafe fn dont_dump(_addr: *mut u8, _len: usize) -> Result<(), EraserError> {
+    match os::CONCEALED {
+        true => Ok(()),
+        false => Err(EraserError::Unsupported),
+    }
+}
+
+unsafe fn inherit(addr: *mut u8, len: usize, inherit: Option<c_int>) -> Result<(), EraserError> {
+    let inherit = inherit.ok_or(EraserError::Unsupported)?;
+    match minherit(addr as *mut c_void, len, inherit) {
+        0 => Ok(()),
+        _ => Err(EraserError::ProtectFailed),
+    }
+}
+
+/// Replace the pages in `[addr, addr + len)` with zeros in the child process
+/// after a `fork()`.
+pub(crate) unsafe fn wipe_on_fork(addr: *mut u8, len: usize) -> Result<(), EraserError> {
+    inherit(addr, len, os::INHERIT_ZERO)
+}
+
+/// Leave the pages in `[addr, addr + len)` out of the child process after a
+/// `fork()`.
+pub(crate) unsafe fn dont_fork(addr: *mut u8, len: usize) -> Result<(), EraserError> {
+    inherit(addr, len, os::INHERIT_NONE)
+}
+
+/// Include the pages in `[addr, addr + len)` in core dumps again.
+pub(crate) unsafe fn do_dump(_addr: *mut u8, _len: usize) {}
+
+/// Encrypt the memory in `[addr, addr + len)` in place, with a key that only
+/// this process can use.
+pub(crate) unsafe fn encrypt(_addr: *mut u8, _len: usize) -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Decrypt memory that was encrypted with [`encrypt`].
+pub(crate) unsafe fn decrypt(_addr: *mut u8, _len: usize) -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
@@ -0,0 +1,155 @@
+use super::{ChildExit, CpuSet, Speculation};
+use crate::EraserError;
+
+/// Return the size of a memory page.
+pub(crate) fn page_size() -> usize {
+    4096
+}
+
+/// Return the maximum number of bytes that the process may lock into memory,
+/// or `None` if it is unknown.
+pub(crate) fn memlock_limit() -> Option<usize> {
+    None
+}
+
+/// Mark the process as not dumpable.
+pub(crate) fn set_not_dumpable() -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Set the core dump size limit of the process to zero.
+pub(crate) fn disable_core_dumps() -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Keep the process from gaining new privileges through `execve`.
+pub(crate) fn set_no_new_privs() -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Restrict `speculation` for the current thread.
+pub(crate) fn restrict_speculation(_speculation: Speculation) -> Result<bool, EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Allow `speculation` for the current thread again, after it was restricted
+/// with `restrict_speculation`.
+pub(crate) fn allow_speculation(_speculation: Speculation) {}
+
+/// Restrict the current thread to run on `cpu` only, and return the set of
+/// CPUs that it was allowed to run on before.
+pub(crate) fn pin_to_cpu(_cpu: usize) -> Result<CpuSet, EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Let the current thread run on the CPUs in `cpus` again.
+pub(crate) fn set_affinity(_cpus: &CpuSet) {}
+
+/// Return the other online hardware threads on the same core as `cpu`, or
+/// `None` if the topology of `cpu` is not known.
+pub(crate) fn smt_siblings(_cpu: usize) -> Option<Vec<usize>> {
+    None
+}
+
+/// Fork the process.  Returns the process ID of the child in the parent, and
+/// zero in the child.
+pub(crate) fn fork_process() -> Result<i32, EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Wait for the child process `pid` to end.
+pub(crate) fn wait_child(_pid: i32) -> Result<ChildExit, EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// End the current (child) process with `status`, without running any exit
+/// handlers.
+pub(crate) fn exit_child(status: i32) -> ! {
+    std::process::exit(status)
+}
+
+/// Map `len` bytes of zeroed, readable and writable memory that is shared with
+/// child processes.
+pub(crate) fn map_shared(_len: usize) -> Result<*mut u8, EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Map `len` bytes of private, zeroed, readable and writable memory.
+pub(crate) fn map(_len: usize) -> Result<*mut u8, EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Map `len` bytes of zeroed, readable and writable memory that is backed by
+/// `memfd_secret`.
+pub(crate) fn map_secret(_len: usize) -> Result<*mut u8, EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Unmap the memory in `[addr, addr + len)` that was mapped with `map`,
+/// `map_secret`, or `map_shared`.
+pub(crate) unsafe fn unmap(_addr: *mut u8, _len: usize) {}
+
+/// Make the pages in `[addr, addr + len)` inaccessible.
+pub(crate) unsafe fn protect_none(_addr: *mut u8, _len: usize) -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Make the pages in `[addr, addr + len)` readable and writable again.
+pub(crate) unsafe fn protect_read_write(_addr: *mut u8, _len: usize) -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Seal the pages in `[addr, addr + len)`, so that they can never be
+/// unmapped, remapped, or have their protection changed.
+pub(crate) unsafe fn seal(_addr: *mut u8, _len: usize) -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Tag the readable and writable pages in `[addr, addr + len)` with the
+/// protection key `key`.
+pub(crate) unsafe fn pkey_mprotect(
+    _addr: *mut u8,
+    _len: usize,
+    _key: i32,
+) -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Lock the pages in `[addr, addr + len)` into memory.
+pub(crate) unsafe fn lock(_addr: *mut u8, _len: usize) -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Unlock the pages in `[addr, addr + len)`.
+pub(crate) unsafe fn unlock(_addr: *mut u8, _len: usize) {}
+
+/// Exclude the pages in `[addr, addr + len)` from core dumps.
+pub(crate) unsafe fn dont_dump(_addr: *mut u8, _len: usize) -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Replace the pages in `[addr, addr + len)` with zeros in the child process
+/// after a `fork()`.
+pub(crate) unsafe fn wipe_on_fork(_addr: *mut u8, _len: usize) -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Leave the pages in `[addr, addr + len)` out of the child process after a
+/// `fork()`.
+pub(crate) unsafe fn dont_fork(_addr: *mut u8, _len: usize) -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Include the pages in `[addr, addr + len)` in core dumps again.
+pub(crate) unsafe fn do_dump(_addr: *mut u8, _len: usize) {}
+
+/// Encrypt the memory in `[addr, addr + len)` in place, with a key that only
+/// this process can use.
+pub(crate) unsafe fn encrypt(_addr: *mut u8, _len: usize) -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Decrypt memory that was encrypted with [`encrypt`].
+pub(crate) unsafe fn decrypt(_addr: *mut u8, _len: usize) -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
@@ -0,0 +1,18 @@
+//! WASI has no memory management beyond growing the linear memory, so all of
+//! the functions of the `unsupported` backend apply.  The host does provide
+//! randomness, through `random_get` (which wasi-libc wraps as `getentropy`,
+//! on both preview 1 and preview 2).
+
+use crate::EraserError;
+
+extern "C" {
+    fn getentropy(buf: *mut u8, len: usize) -> i32;
+}
+
+/// Fill `buf` (of at most 256 bytes) with random bytes from the host.
+pub(crate) fn fill_random(buf: &mut [u8]) -> Result<(), EraserError> {
+    match unsafe { getentropy(buf.as_mut_ptr(), buf.len()) } {
+        0 => Ok(()),
+        _ => Err(EraserError::Unsupported),
+    }
+}
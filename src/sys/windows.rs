@@ -0,0 +1,249 @@
+use super::{ChildExit, CpuSet, Speculation};
+use crate::EraserError;
+use core::ffi::c_void;
+
+const MEM_COMMIT: u32 = 0x1000;
+const MEM_RESERVE: u32 = 0x2000;
+const MEM_RELEASE: u32 = 0x8000;
+const PAGE_NOACCESS: u32 = 0x01;
+const PAGE_READWRITE: u32 = 0x04;
+const CRYPTPROTECTMEMORY_SAME_PROCESS: u32 = 0x00;
+
+#[repr(C)]
+struct SystemInfo {
+    processor_architecture: u16,
+    reserved: u16,
+    page_size: u32,
+    minimum_application_address: *mut c_void,
+    maximum_application_address: *mut c_void,
+    active_processor_mask: usize,
+    number_of_processors: u32,
+    processor_type: u32,
+    allocation_granularity: u32,
+    processor_level: u16,
+    processor_revision: u16,
+}
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn GetSystemInfo(info: *mut SystemInfo);
+    fn VirtualAlloc(
+        addr: *mut c_void,
+        size: usize,
+        allocation_type: u32,
+        protect: u32,
+    ) -> *mut c_void;
+    fn VirtualFree(addr: *mut c_void, size: usize, free_type: u32) -> i32;
+    fn VirtualProtect(addr: *mut c_void, size: usize, protect: u32, old: *mut u32) -> i32;
+    fn VirtualLock(addr: *mut c_void, size: usize) -> i32;
+    fn VirtualUnlock(addr: *mut c_void, size: usize) -> i32;
+}
+
+#[link(name = "crypt32")]
+extern "system" {
+    fn CryptProtectMemory(data: *mut c_void, len: u32, flags: u32) -> i32;
+    fn CryptUnprotectMemory(data: *mut c_void, len: u32, flags: u32) -> i32;
+}
+
+/// Return the size of a memory page.
+pub(crate) fn page_size() -> usize {
+    let mut info = core::mem::MaybeUninit::<SystemInfo>::uninit();
+    let info = unsafe {
+        GetSystemInfo(info.as_mut_ptr());
+        info.assume_init()
+    };
+    info.page_size as usize
+}
+
+/// Return the maximum number of bytes that the process may lock into memory,
+/// or `None` if it is unknown.
+///
+/// On Windows, locking is limited by the minimum working set size of the
+/// process instead.
+pub(crate) fn memlock_limit() -> Option<usize> {
+    None
+}
+
+/// Mark the process as not dumpable.
+pub(crate) fn set_not_dumpable() -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Set the core dump size limit of the process to zero.
+pub(crate) fn disable_core_dumps() -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Keep the process from gaining new privileges through `execve`.
+pub(crate) fn set_no_new_privs() -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Restrict `speculation` for the current thread.
+pub(crate) fn restrict_speculation(_speculation: Speculation) -> Result<bool, EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Allow `speculation` for the current thread again, after it was restricted
+/// with `restrict_speculation`.
+pub(crate) fn allow_speculation(_speculation: Speculation) {}
+
+/// Restrict the current thread to run on `cpu` only, and return the set of
+/// CPUs that it was allowed to run on before.
+pub(crate) fn pin_to_cpu(_cpu: usize) -> Result<CpuSet, EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Let the current thread run on the CPUs in `cpus` again.
+pub(crate) fn set_affinity(_cpus: &CpuSet) {}
+
+/// Return the other online hardware threads on the same core as `cpu`, or
+/// `None` if the topology of `cpu` is not known.
+pub(crate) fn smt_siblings(_cpu: usize) -> Option<Vec<usize>> {
+    None
+}
+
+/// Fork the process.  Returns the process ID of the child in the parent, and
+/// zero in the child.
+pub(crate) fn fork_process() -> Result<i32, EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Wait for the child process `pid` to end.
+pub(crate) fn wait_child(_pid: i32) -> Result<ChildExit, EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// End the current (child) process with `status`, without running any exit
+/// handlers.
+pub(crate) fn exit_child(status: i32) -> ! {
+    std::process::exit(status)
+}
+
+/// Map `len` bytes of zeroed, readable and writable memory that is shared with
+/// child processes.
+pub(crate) fn map_shared(_len: usize) -> Result<*mut u8, EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Map `len` bytes of private, zeroed, readable and writable memory.
+///
+/// `len` must be a multiple of the page size.
+pub(crate) fn map(len: usize) -> Result<*mut u8, EraserError> {
+    let flags = MEM_COMMIT | MEM_RESERVE;
+    let addr = unsafe { VirtualAlloc(core::ptr::null_mut(), len, flags, PAGE_READWRITE) };
+    match addr.is_null() {
+        true => Err(EraserError::AllocFailed),
+        false => Ok(addr as *mut u8),
+    }
+}
+
+/// Map `len` bytes of zeroed, readable and writable memory that is backed by
+/// `memfd_secret`.
+pub(crate) fn map_secret(_len: usize) -> Result<*mut u8, EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Unmap the memory in `[addr, addr + len)` that was mapped with `map`,
+/// `map_secret`, or `map_shared`.
+pub(crate) unsafe fn unmap(addr: *mut u8, _len: usize) {
+    // The whole reservation is always released at once
+    VirtualFree(addr as *mut c_void, 0, MEM_RELEASE);
+}
+
+unsafe fn protect(addr: *mut u8, len: usize, protection: u32) -> Result<(), EraserError> {
+    let mut old = 0;
+    match VirtualProtect(addr as *mut c_void, len, protection, &mut old) {
+        0 => Err(EraserError::ProtectFailed),
+        _ => Ok(()),
+    }
+}
+
+/// Make the pages in `[addr, addr + len)` inaccessible.
+///
+/// This uses `PAGE_NOACCESS` instead of `PAGE_GUARD`, because a `PAGE_GUARD`
+/// page only faults on the first access, and is accessible afterwards.
+pub(crate) unsafe fn protect_none(addr: *mut u8, len: usize) -> Result<(), EraserError> {
+    protect(addr, len, PAGE_NOACCESS)
+}
+
+/// Make the pages in `[addr, addr + len)` readable and writable again.
+pub(crate) unsafe fn protect_read_write(addr: *mut u8, len: usize) -> Result<(), EraserError> {
+    protect(addr, len, PAGE_READWRITE)
+}
+
+/// Seal the pages in `[addr, addr + len)`, so that they can never be
+/// unmapped, remapped, or have their protection changed.
+pub(crate) unsafe fn seal(_addr: *mut u8, _len: usize) -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Tag the readable and writable pages in `[addr, addr + len)` with the
+/// protection key `key`.
+pub(crate) unsafe fn pkey_mprotect(
+    _addr: *mut u8,
+    _len: usize,
+    _key: i32,
+) -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Lock the pages in `[addr, addr + len)` into memory.
+pub(crate) unsafe fn lock(addr: *mut u8, len: usize) -> Result<(), EraserError> {
+    match VirtualLock(addr as *mut c_void, len) {
+        0 => Err(EraserError::LockFailed),
+        _ => Ok(()),
+    }
+}
+
+/// Unlock the pages in `[addr, addr + len)`.
+pub(crate) unsafe fn unlock(addr: *mut u8, len: usize) {
+    VirtualUnlock(addr as *mut c_void, len);
+}
+
+/// Exclude the pages in `[addr, addr + len)` from core dumps.
+pub(crate) unsafe fn dont_dump(_addr: *mut u8, _len: usize) -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Replace the pages in `[addr, addr + len)` with zeros in the child process
+/// after a `fork()`.
+pub(crate) unsafe fn wipe_on_fork(_addr: *mut u8, _len: usize) -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Leave the pages in `[addr, addr + len)` out of the child process after a
+/// `fork()`.
+pub(crate) unsafe fn dont_fork(_addr: *mut u8, _len: usize) -> Result<(), EraserError> {
+    Err(EraserError::Unsupported)
+}
+
+/// Include the pages in `[addr, addr + len)` in core dumps again.
+pub(crate) unsafe fn do_dump(_addr: *mut u8, _len: usize) {}
+
+/// Encrypt the memory in `[addr, addr + len)` in place, with a key that only
+/// this process can use.
+///
+/// `len` must be a multiple of 16 bytes.
+pub(crate) unsafe fn encrypt(addr: *mut u8, len: usize) -> Result<(), EraserError> {
+    match CryptProtectMemory(
+        addr as *mut c_void,
+        len as u32,
+        CRYPTPROTECTMEMORY_SAME_PROCESS,
+    ) {
+        0 => Err(EraserError::ProtectFailed),
+        _ => Ok(()),
+    }
+}
+
+/// Decrypt memory that was encrypted with [`encrypt`].
+pub(crate) unsafe fn decrypt(addr: *mut u8, len: usize) -> Result<(), EraserError> {
+    match CryptUnprotectMemory(
+        addr as *mut c_void,
+        len as u32,
+        CRYPTPROTECTMEMORY_SAME_PROCESS,
+    ) {
+        0 => Err(EraserError::ProtectFailed),
+        _ => Ok(()),
+    }
+}
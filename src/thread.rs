@@ -0,0 +1,61 @@
+use crate::sys::{self, CpuSet, Speculation};
+use crate::EraserError;
+
+/// The protections that are applied to the thread that runs the user
+/// function, for the duration of a run.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ThreadProtections {
+    /// Restrict speculative execution (speculative store bypass and indirect
+    /// branch speculation).
+    pub(crate) restrict_speculation: bool,
+    /// Pin the thread to this CPU.
+    pub(crate) pin_cpu: Option<usize>,
+}
+
+impl ThreadProtections {
+    /// Whether any of the protections is requested.
+    pub(crate) fn any(&self) -> bool {
+        self.restrict_speculation || self.pin_cpu.is_some()
+    }
+
+    /// Apply the protections to the current thread, until the returned value
+    /// is dropped.
+    pub(crate) fn apply(&self) -> Result<Applied, EraserError> {
+        let mut applied = Applied::default();
+        if let Some(cpu) = self.pin_cpu {
+            applied.affinity = Some(sys::pin_to_cpu(cpu)?);
+        }
+        if self.restrict_speculation {
+            let all = [Speculation::StoreBypass, Speculation::IndirectBranch];
+            for (slot, speculation) in applied.restricted.iter_mut().zip(all) {
+                // If this fails, the speculation that was restricted already
+                // is allowed again when `applied` is dropped
+                if sys::restrict_speculation(speculation)? {
+                    *slot = Some(speculation);
+                }
+            }
+        }
+        Ok(applied)
+    }
+}
+
+/// The changes that `ThreadProtections::apply` made to the current thread,
+/// which are undone on drop.
+#[derive(Default)]
+pub(crate) struct Applied {
+    /// The speculation that was allowed before, and has been restricted.
+    restricted: [Option<Speculation>; 2],
+    /// The CPUs that the thread was allowed to run on before it was pinned.
+    affinity: Option<CpuSet>,
+}
+
+impl Drop for Applied {
+    fn drop(&mut self) {
+        for &speculation in self.restricted.iter().flatten() {
+            sys::allow_speculation(speculation);
+        }
+        if let Some(cpus) = &self.affinity {
+            sys::set_affinity(cpus);
+        }
+    }
+}
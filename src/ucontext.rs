@@ -0,0 +1,65 @@
+//! Running the user function with `makecontext` and `swapcontext`, on the
+//! Linux targets that eraser has no assembly for.
+//!
+//! glibc switches the stack pointer for us: the user function runs on a
+//! context that is made on the ephemeral stack, and that links back to the
+//! context of the caller, which `swapcontext` saved.  When the user function
+//! returns, `setcontext` restores all the registers that the calling
+//! convention preserves to the values of the caller, which overwrites whatever
+//! the user function left in them.  The other registers are not wiped, because
+//! there is no assembly to do that with; [`capabilities`](crate::capabilities)
+//! reports these weaker guarantees.
+//!
+//! `ucontext_t` is opaque to eraser, except for its first fields, which have
+//! the same layout on all of these targets (but not on 32-bit SPARC, where the
+//! signal mask comes first, and which is left out).
+
+use core::ffi::{c_int, c_ulong, c_void};
+use core::mem::MaybeUninit;
+use core::ptr;
+
+/// The number of bytes that are reserved for the rest of a `ucontext_t`, which
+/// is more than glibc needs on any of these targets.
+const CONTEXT_TAIL: usize = 4096;
+
+/// The start of a `ucontext_t`, up to and including `uc_stack`.
+#[repr(C)]
+struct ContextHead {
+    uc_flags: c_ulong,
+    uc_link: *mut Context,
+    ss_sp: *mut c_void,
+    ss_flags: c_int,
+    ss_size: usize,
+}
+
+/// Storage for a `ucontext_t`.
+#[repr(C, align(16))]
+struct Context {
+    head: ContextHead,
+    tail: MaybeUninit<[u8; CONTEXT_TAIL]>,
+}
+
+extern "C" {
+    fn getcontext(ucp: *mut Context) -> c_int;
+    fn makecontext(ucp: *mut Context, func: extern "C" fn(), argc: c_int, ...);
+    fn swapcontext(oucp: *mut Context, ucp: *const Context) -> c_int;
+}
+
+/// Run the user function on the stack between `stack_bottom` and `stack_top`.
+///
+/// The contexts are kept on the heap, because they are large, and because the
+/// caller's context does not have to outlive this function.
+#[inline(never)]
+pub(crate) unsafe fn stack_switch(stack_bottom: *mut u8, stack_top: *mut u8) {
+    let mut contexts = Box::<[Context; 2]>::new_uninit();
+    let caller = contexts.as_mut_ptr() as *mut Context;
+    let callee = caller.add(1);
+
+    assert_eq!(getcontext(callee), 0, "getcontext failed");
+    ptr::addr_of_mut!((*callee).head.uc_link).write(caller);
+    ptr::addr_of_mut!((*callee).head.ss_sp).write(stack_bottom as *mut c_void);
+    ptr::addr_of_mut!((*callee).head.ss_flags).write(0);
+    ptr::addr_of_mut!((*callee).head.ss_size).write(stack_top as usize - stack_bottom as usize);
+    makecontext(callee, crate::do_run_user_fn, 0);
+    assert_eq!(swapcontext(caller, callee), 0, "swapcontext failed");
+}
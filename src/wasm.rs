@@ -0,0 +1,47 @@
+//! Running the user function on WebAssembly.
+//!
+//! WebAssembly has no registers or stack pointer that code can get at: the
+//! values on the operand stack and in the locals are owned by the engine.
+//! Rust keeps everything that does not fit there (and everything whose
+//! address is taken) on a shadow stack in linear memory, but the pointer to
+//! it (the `__stack_pointer` global) cannot be moved from stable Rust.
+//!
+//! So instead of switching stacks, the user function runs on the current
+//! shadow stack, and the part of the shadow stack below the caller that it may
+//! have used is erased afterwards: a chain of scratch frames, as large as the
+//! ephemeral stack would have been, is laid over it and erased with volatile
+//! writes.  Anything that the user function left further down, or in the
+//! engine, is not erased; [`capabilities`](crate::capabilities) reports these
+//! weaker guarantees.
+
+use core::mem::MaybeUninit;
+use core::ptr;
+
+/// The number of words in a single scratch frame.
+const SCRATCH_WORDS: usize = 1024;
+
+/// Run the user function on the current stack, and erase the
+/// `stack_top - stack_bottom` bytes of the shadow stack below the caller
+/// afterwards.
+#[inline(never)]
+pub(crate) unsafe fn stack_switch(stack_bottom: *mut u8, stack_top: *mut u8) {
+    crate::do_run_user_fn();
+    erase_below(stack_top as usize - stack_bottom as usize);
+}
+
+/// Erase (at least) `len` bytes of the shadow stack below the caller.
+#[inline(never)]
+fn erase_below(len: usize) {
+    let mut scratch = MaybeUninit::<[usize; SCRATCH_WORDS]>::uninit();
+    let words = scratch.as_mut_ptr() as *mut usize;
+    for index in 0..SCRATCH_WORDS {
+        unsafe { ptr::write_volatile(words.add(index), 0) };
+    }
+    let size = core::mem::size_of_val(&scratch);
+    if len > size {
+        erase_below(len - size);
+    }
+    // Keep the frame alive until the recursion returns, so that the recursive
+    // call cannot reuse it
+    core::hint::black_box(&mut scratch);
+}
@@ -0,0 +1,208 @@
+//! Running the user function on bare-metal x86_64 (`x86_64-unknown-none`),
+//! without `std`, for kernels, hypervisors and bootloaders.
+//!
+//! There is no thread-local storage here, so the closure is passed to the
+//! ephemeral stack in a register instead of through `CTX`, like on the other
+//! bare-metal targets, and there is no unwinding: a panic in the user function
+//! goes straight to the panic handler of the kernel, and the stack is not
+//! erased.
+//!
+//! The stack pointer is switched directly.  An interrupt or exception that is
+//! taken during the run pushes its frame onto the ephemeral stack (where it is
+//! erased with the rest), unless the IDT entry switches to an IST stack.  With
+//! the `mask_interrupts` feature, the maskable interrupts are masked (with
+//! `cli`) from before the stack switch until the stack has been erased and the
+//! registers have been wiped, which requires the caller to run in ring 0.
+//!
+//! Kernel targets are usually built without SSE (and the FPU may not even be
+//! enabled yet in early boot), so only the vector registers that the target
+//! lets the compiler use are wiped.
+
+use crate::{EraseConfig, Payload};
+use core::arch::asm;
+use core::mem::MaybeUninit;
+
+/// Run the user function on `stack`, which has already been aligned by
+/// `align_stack`.
+pub(crate) unsafe fn run_on_stack<F, R>(
+    f: F,
+    stack: &mut [MaybeUninit<u8>],
+    config: &EraseConfig,
+) -> R
+where
+    F: FnOnce() -> R,
+{
+    let stack_bottom = stack.as_mut_ptr() as *mut u8;
+    let stack_top = stack_bottom.add(stack.len());
+    let mut payload = Payload {
+        f: MaybeUninit::new(f),
+        result: MaybeUninit::<R>::uninit(),
+    };
+    let data = &mut payload as *mut Payload<F, R> as *mut u8;
+
+    crate::events::enter(stack_bottom, stack.len());
+    #[cfg(feature = "mask_interrupts")]
+    let rflags = mask_interrupts();
+    stack_switch::<F, R>(data, stack_top);
+    crate::events::check_overflow(stack_bottom);
+    crate::erase_words(stack_bottom, stack.len(), config);
+    crate::erase_slot(&mut payload.f, config);
+
+    let result = payload.result.as_ptr().read();
+    crate::erase_slot(&mut payload.result, config);
+    crate::custom_wipe::run();
+    wipe_all_registers();
+    #[cfg(feature = "mask_interrupts")]
+    restore_interrupts(rflags);
+    crate::events::erased(stack.len());
+    result
+}
+
+extern "C" fn trampoline<F: FnOnce() -> R, R>(data: *mut u8) {
+    unsafe { crate::call_user_fn::<F, R>(data) }
+}
+
+/// Mask the maskable interrupts, and return the old value of `RFLAGS`.
+#[cfg(feature = "mask_interrupts")]
+unsafe fn mask_interrupts() -> u64 {
+    let rflags: u64;
+    asm!("pushfq", "pop {}", "cli", out(reg) rflags, options(preserves_flags));
+    rflags
+}
+
+/// Unmask the maskable interrupts, if they were enabled in `RFLAGS` (as
+/// returned by `mask_interrupts`).
+#[cfg(feature = "mask_interrupts")]
+unsafe fn restore_interrupts(rflags: u64) {
+    // IF is bit 9
+    if rflags & (1 << 9) != 0 {
+        asm!("sti", options(nomem, nostack, preserves_flags));
+    }
+}
+
+/// Call `trampoline::<F, R>(data)` on the stack that ends at `stack_top`.
+///
+/// The old stack pointer is kept in a 16-byte frame at the top of the new
+/// stack, which keeps the stack aligned to 16 bytes at the call.
+#[inline(never)]
+unsafe fn stack_switch<F: FnOnce() -> R, R>(data: *mut u8, stack_top: *mut u8) {
+    asm!(
+        // Switch stacks, and save the old stack pointer on the new one
+        "mov rax, rsp",
+        "mov rsp, rsi",
+        "sub rsp, 16",
+        "mov [rsp], rax",
+        // Call the running function using the new stack
+        "call {user_fn}",
+        // Restore the original stack pointer
+        "mov rsp, [rsp]",
+        user_fn = sym trampoline::<F, R>,
+        in("rdi") data,
+        in("rsi") stack_top,
+        clobber_abi("C"),
+    );
+}
+
+/// Wipe the general purpose registers and (if the target has SSE) the vector
+/// registers.
+///
+/// `rbx` is reserved by the compiler and `rbp` is the frame pointer, so neither
+/// can be wiped here.  Without SSE, the compiler does not use the vector
+/// registers, and neither MXCSR nor the x87 registers are touched, because the
+/// kernel may not have enabled them.
+pub(crate) unsafe fn wipe_all_registers() {
+    #[cfg(target_feature = "avx")]
+    asm!(
+        "vzeroall",
+        lateout("ymm0") _,
+        lateout("ymm1") _,
+        lateout("ymm2") _,
+        lateout("ymm3") _,
+        lateout("ymm4") _,
+        lateout("ymm5") _,
+        lateout("ymm6") _,
+        lateout("ymm7") _,
+        lateout("ymm8") _,
+        lateout("ymm9") _,
+        lateout("ymm10") _,
+        lateout("ymm11") _,
+        lateout("ymm12") _,
+        lateout("ymm13") _,
+        lateout("ymm14") _,
+        lateout("ymm15") _,
+        options(nomem, nostack, preserves_flags),
+    );
+    #[cfg(all(target_feature = "sse", not(target_feature = "avx")))]
+    asm!(
+        "xorps xmm0, xmm0",
+        "xorps xmm1, xmm1",
+        "xorps xmm2, xmm2",
+        "xorps xmm3, xmm3",
+        "xorps xmm4, xmm4",
+        "xorps xmm5, xmm5",
+        "xorps xmm6, xmm6",
+        "xorps xmm7, xmm7",
+        "xorps xmm8, xmm8",
+        "xorps xmm9, xmm9",
+        "xorps xmm10, xmm10",
+        "xorps xmm11, xmm11",
+        "xorps xmm12, xmm12",
+        "xorps xmm13, xmm13",
+        "xorps xmm14, xmm14",
+        "xorps xmm15, xmm15",
+        lateout("xmm0") _,
+        lateout("xmm1") _,
+        lateout("xmm2") _,
+        lateout("xmm3") _,
+        lateout("xmm4") _,
+        lateout("xmm5") _,
+        lateout("xmm6") _,
+        lateout("xmm7") _,
+        lateout("xmm8") _,
+        lateout("xmm9") _,
+        lateout("xmm10") _,
+        lateout("xmm11") _,
+        lateout("xmm12") _,
+        lateout("xmm13") _,
+        lateout("xmm14") _,
+        lateout("xmm15") _,
+        options(nomem, nostack, preserves_flags),
+    );
+    // Reset `MXCSR`, which clears the exception flags as well
+    #[cfg(target_feature = "sse")]
+    asm!(
+        "sub rsp, 8",
+        "mov dword ptr [rsp], 0x1f80",
+        "ldmxcsr dword ptr [rsp]",
+        "add rsp, 8",
+    );
+    asm!(
+        "xor rax, rax",
+        "xor rcx, rcx",
+        "xor rdx, rdx",
+        "xor rsi, rsi",
+        "xor rdi, rdi",
+        "xor r8, r8",
+        "xor r9, r9",
+        "xor r10, r10",
+        "xor r11, r11",
+        "xor r12, r12",
+        "xor r13, r13",
+        "xor r14, r14",
+        "xor r15, r15",
+        lateout("rax") _,
+        lateout("rcx") _,
+        lateout("rdx") _,
+        lateout("rsi") _,
+        lateout("rdi") _,
+        lateout("r8") _,
+        lateout("r9") _,
+        lateout("r10") _,
+        lateout("r11") _,
+        lateout("r12") _,
+        lateout("r13") _,
+        lateout("r14") _,
+        lateout("r15") _,
+        options(nomem, nostack),
+    );
+}
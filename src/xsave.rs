@@ -0,0 +1,144 @@
+//! A deep register wipe through the XSAVE feature set of x86_64.
+//!
+//! `xrstor` loads every state component that it is asked for (the requested
+//! feature bitmap, in `edx:eax`) from an XSAVE area, except for the components
+//! whose bit is clear in the `XSTATE_BV` field of the area's header: those are
+//! put in their initial configuration instead, which is zero for all of the
+//! register files.  Restoring from an area with an all-zero header therefore
+//! clears every component that the OS enabled in `XCR0`, including the ones
+//! that `wipe_all_registers` does not know about individually.
+//!
+//! A few components are left out:
+//!
+//! - `PKRU` (bit 9), whose initial configuration would give access to the
+//!   memory of every protection key, including the one that protects the
+//!   ephemeral stacks (see the `pkey` module).
+//! - The APX registers (bit 19), which cannot be declared as clobbered, and are
+//!   wiped by `wipe_apx_registers` instead.
+//! - The components that the OS can disable per thread with extended feature
+//!   disable (XFD), like the AMX tile data (bit 18), and the AMX tile
+//!   configuration (bit 17) that goes with it, unless they are in use.  Linux
+//!   arms XFD for a process until it asks for permission to use the component,
+//!   and `xrstor` faults on an armed component.
+//!
+//! `xrstor` also loads `MXCSR` from the area, so the area holds its default.
+//! The initial x87 control word is not the same as the one on every platform,
+//! so that is saved and restored around the `xrstor`.
+
+use core::arch::asm;
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+use std::alloc::{self, Layout};
+use std::sync::OnceLock;
+
+/// The components that are never wiped here: `PKRU` and the APX registers.
+const KEPT: u64 = 1 << 9 | 1 << 19;
+
+/// The AMX tile configuration, which is only restored together with the tile
+/// data.
+const TILECFG: u64 = 1 << 17;
+
+/// The default value of `MXCSR`.
+const MXCSR_DEFAULT: u32 = 0x1f80;
+
+/// The offset of `MXCSR` in the legacy region of an XSAVE area.
+const MXCSR_OFFSET: usize = 24;
+
+/// A zeroed XSAVE area (apart from `MXCSR`), which is only ever read, so it
+/// is shared by all threads.
+struct Area {
+    addr: usize,
+    /// The components that are always restored.
+    components: u64,
+    /// The components that are only restored while they are in use.
+    if_in_use: u64,
+    /// Whether `xgetbv` can tell which components are in use.
+    xinuse: bool,
+}
+
+impl Area {
+    fn new() -> Option<Self> {
+        // XSAVE, and whether the OS enabled it (leaf 1, ECX bits 26 and 27)
+        if __cpuid(0).eax < 13 || __cpuid(1).ecx & (3 << 26) != 3 << 26 {
+            return None;
+        }
+        let xcr0 = unsafe { xgetbv(0) };
+        // The components that support XFD (leaf 13, subleaf i, ECX bit 2)
+        let xfd = (2..64)
+            .filter(|&i| xcr0 & (1 << i) != 0 && __cpuid_count(13, i).ecx & (1 << 2) != 0)
+            .fold(xcr0 & TILECFG, |xfd, i| xfd | 1 << i);
+        // The size of the area for the components in `XCR0` (leaf 13, EBX)
+        let size = __cpuid_count(13, 0).ebx as usize;
+        let layout = Layout::from_size_align(size, 64).ok()?;
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            return None;
+        }
+        unsafe { (ptr.add(MXCSR_OFFSET) as *mut u32).write(MXCSR_DEFAULT) };
+        Some(Area {
+            addr: ptr as usize,
+            components: xcr0 & !KEPT & !xfd,
+            if_in_use: xcr0 & !KEPT & xfd,
+            // Leaf 13, subleaf 1, EAX bit 2
+            xinuse: __cpuid_count(13, 1).eax & (1 << 2) != 0,
+        })
+    }
+}
+
+/// Read the extended control register `xcr`.
+unsafe fn xgetbv(xcr: u32) -> u64 {
+    let (eax, edx): (u32, u32);
+    asm!(
+        "xgetbv",
+        in("ecx") xcr,
+        out("eax") eax,
+        out("edx") edx,
+        options(nomem, nostack, preserves_flags),
+    );
+    (edx as u64) << 32 | eax as u64
+}
+
+/// Put every enabled state component (with the exceptions listed in the module
+/// documentation) in its initial configuration.  Does nothing on CPUs without
+/// XSAVE.
+pub(crate) unsafe fn wipe() {
+    static AREA: OnceLock<Option<Area>> = OnceLock::new();
+    let Some(area) = AREA.get_or_init(Area::new) else {
+        return;
+    };
+    let mut components = area.components;
+    if area.xinuse && area.if_in_use != 0 {
+        components |= area.if_in_use & xgetbv(1);
+    }
+    asm!(
+        "sub rsp, 8",
+        "fnstcw word ptr [rsp]",
+        "xrstor64 [{area}]",
+        "fldcw word ptr [rsp]",
+        "add rsp, 8",
+        area = in(reg) area.addr,
+        in("eax") components as u32,
+        in("edx") (components >> 32) as u32,
+        // These are callee-saved on Windows, so `clobber_abi` leaves them out
+        #[cfg(windows)]
+        lateout("xmm6") _,
+        #[cfg(windows)]
+        lateout("xmm7") _,
+        #[cfg(windows)]
+        lateout("xmm8") _,
+        #[cfg(windows)]
+        lateout("xmm9") _,
+        #[cfg(windows)]
+        lateout("xmm10") _,
+        #[cfg(windows)]
+        lateout("xmm11") _,
+        #[cfg(windows)]
+        lateout("xmm12") _,
+        #[cfg(windows)]
+        lateout("xmm13") _,
+        #[cfg(windows)]
+        lateout("xmm14") _,
+        #[cfg(windows)]
+        lateout("xmm15") _,
+        clobber_abi("C"),
+    );
+}
@@ -0,0 +1,347 @@
+//! Running the user function on bare-metal Xtensa (ESP32-class chips),
+//! without `std`.
+//!
+//! Like on Cortex-M and RISC-V, there are no threads and no unwinding, so the
+//! closure is passed to the ephemeral stack in a register, and a panic in the
+//! user function goes straight to the panic handler of the application.
+//!
+//! Xtensa uses register windows: every call rotates the window of 16 visible
+//! address registers over a file of 64, and the registers of the callers are
+//! only spilled to their stack frames when the file runs out (by the window
+//! overflow exception handlers).  Before the stack pointer is switched, all the
+//! windows of the callers are spilled, so that none of them has to be spilled
+//! later on (or reloaded through) the ephemeral stack.  The windows that the
+//! user function spilled end up on the ephemeral stack, where they are erased
+//! with the rest; the ones that it left behind in the register file are wiped
+//! by `wipe_all_registers`.
+//!
+//! Interrupts are masked while the windows are spilled and wiped, because the
+//! window is rotated away from the current stack pointer in the meantime.
+//! They are not masked while the user function runs: an interrupt that is
+//! taken then stores its frame on the ephemeral stack (where it is erased with
+//! the rest).  With the `mask_interrupts` feature, they are masked (with
+//! `rsil`) from before the stack switch until the stack has been erased and
+//! the registers have been wiped instead.  The NMI is not masked.
+
+use crate::{EraseConfig, Payload};
+use core::arch::asm;
+use core::mem::MaybeUninit;
+
+/// Run the user function on `stack`, which has already been aligned by
+/// `align_stack`.
+pub(crate) unsafe fn run_on_stack<F, R>(
+    f: F,
+    stack: &mut [MaybeUninit<u8>],
+    config: &EraseConfig,
+) -> R
+where
+    F: FnOnce() -> R,
+{
+    let stack_bottom = stack.as_mut_ptr() as *mut u8;
+    let stack_top = stack_bottom.add(stack.len());
+    let mut payload = Payload {
+        f: MaybeUninit::new(f),
+        result: MaybeUninit::<R>::uninit(),
+    };
+    let data = &mut payload as *mut Payload<F, R> as *mut u8;
+
+    crate::events::enter(stack_bottom, stack.len());
+    #[cfg(feature = "mask_interrupts")]
+    let ps = mask_interrupts();
+    stack_switch::<F, R>(data, stack_top);
+    crate::events::check_overflow(stack_bottom);
+    crate::erase_words(stack_bottom, stack.len(), config);
+    crate::erase_slot(&mut payload.f, config);
+
+    let result = payload.result.as_ptr().read();
+    crate::erase_slot(&mut payload.result, config);
+    crate::custom_wipe::run();
+    wipe_all_registers();
+    #[cfg(feature = "mask_interrupts")]
+    restore_interrupts(ps);
+    crate::events::erased(stack.len());
+    result
+}
+
+extern "C" fn trampoline<F: FnOnce() -> R, R>(data: *mut u8) {
+    unsafe { crate::call_user_fn::<F, R>(data) }
+}
+
+/// Raise the interrupt level to 15, and return the old value of `PS`.
+#[cfg(feature = "mask_interrupts")]
+unsafe fn mask_interrupts() -> u32 {
+    let ps: u32;
+    asm!("rsil {}, 15", out(reg) ps, options(nostack));
+    ps
+}
+
+/// Restore `PS` to a value that `mask_interrupts` returned.
+#[cfg(feature = "mask_interrupts")]
+unsafe fn restore_interrupts(ps: u32) {
+    asm!("wsr.ps {}", "rsync", in(reg) ps, options(nostack));
+}
+
+/// Call `trampoline::<F, R>(data)` on the stack that ends at `stack_top`.
+///
+/// The windowed ABI keeps the registers of the caller (`a0`–`a3`) in the 16
+/// bytes below its callee's stack pointer, and finds the stack pointer of the
+/// caller's caller there when it spills the caller's other registers.  So,
+/// like `alloca`, this copies those 16 bytes below the new stack pointer before
+/// it moves the stack pointer with `movsp`.  The old stack pointer is kept in
+/// the new frame, which is 32 bytes to keep the new stack pointer aligned.
+/// `callx8` hands `a8`–`a15` to the callee, so they are all clobbered.
+#[inline(never)]
+unsafe fn stack_switch<F: FnOnce() -> R, R>(data: *mut u8, stack_top: *mut u8) {
+    asm!(
+        // Spill the register windows of all the callers to their stack frames
+        "rsil a9, 15",
+        "and a12, a12, a12",
+        "rotw 3",
+        "and a12, a12, a12",
+        "rotw 3",
+        "and a12, a12, a12",
+        "rotw 3",
+        "and a12, a12, a12",
+        "rotw 3",
+        "and a12, a12, a12",
+        "rotw 4",
+        "wsr.ps a9",
+        "rsync",
+        // Copy the base save area below the new stack pointer, and keep the
+        // old stack pointer above it
+        "addi a9, a11, -32",
+        "l32i a8, a1, -16",
+        "s32i a8, a9, -16",
+        "l32i a8, a1, -12",
+        "s32i a8, a9, -12",
+        "l32i a8, a1, -8",
+        "s32i a8, a9, -8",
+        "l32i a8, a1, -4",
+        "s32i a8, a9, -4",
+        "s32i a1, a9, 0",
+        // Switch stacks
+        "movsp a1, a9",
+        // Call the running function using the new stack
+        "callx8 a12",
+        // Restore the original stack
+        "l32i a8, a1, 0",
+        "movsp a1, a8",
+        lateout("a8") _,
+        lateout("a9") _,
+        inout("a10") data => _,
+        inout("a11") stack_top => _,
+        inout("a12") trampoline::<F, R> as *const () => _,
+        lateout("a13") _,
+        lateout("a14") _,
+        lateout("a15") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f0") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f1") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f2") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f3") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f4") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f5") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f6") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f7") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f8") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f9") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f10") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f11") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f12") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f13") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f14") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f15") _,
+    );
+}
+
+/// Wipe the address registers in all the windows, and (with the FPU) the
+/// floating-point registers.
+///
+/// First, the windows of all the callers are spilled, so that the other 48
+/// address registers belong to no frame.  Those are zeroed by rotating the
+/// window over them, after which the registers of the current window are
+/// zeroed, except for the return address (`a0`), the stack pointer (`a1`) and
+/// the frame pointer (`a7`).  `SAR` is reset, and so are the floating-point
+/// control and status registers.
+pub(crate) unsafe fn wipe_all_registers() {
+    asm!(
+        "rsil a2, 15",
+        // Spill the register windows of all the callers to their stack frames
+        "and a12, a12, a12",
+        "rotw 3",
+        "and a12, a12, a12",
+        "rotw 3",
+        "and a12, a12, a12",
+        "rotw 3",
+        "and a12, a12, a12",
+        "rotw 3",
+        "and a12, a12, a12",
+        "rotw 4",
+        // Zero the registers in the other three windows
+        "rotw 4",
+        "movi a0, 0",
+        "movi a1, 0",
+        "movi a2, 0",
+        "movi a3, 0",
+        "movi a4, 0",
+        "movi a5, 0",
+        "movi a6, 0",
+        "movi a7, 0",
+        "movi a8, 0",
+        "movi a9, 0",
+        "movi a10, 0",
+        "movi a11, 0",
+        "movi a12, 0",
+        "movi a13, 0",
+        "movi a14, 0",
+        "movi a15, 0",
+        "rotw 4",
+        "movi a0, 0",
+        "movi a1, 0",
+        "movi a2, 0",
+        "movi a3, 0",
+        "movi a4, 0",
+        "movi a5, 0",
+        "movi a6, 0",
+        "movi a7, 0",
+        "movi a8, 0",
+        "movi a9, 0",
+        "movi a10, 0",
+        "movi a11, 0",
+        "movi a12, 0",
+        "movi a13, 0",
+        "movi a14, 0",
+        "movi a15, 0",
+        "rotw 4",
+        "movi a0, 0",
+        "movi a1, 0",
+        "movi a2, 0",
+        "movi a3, 0",
+        "movi a4, 0",
+        "movi a5, 0",
+        "movi a6, 0",
+        "movi a7, 0",
+        "movi a8, 0",
+        "movi a9, 0",
+        "movi a10, 0",
+        "movi a11, 0",
+        "movi a12, 0",
+        "movi a13, 0",
+        "movi a14, 0",
+        "movi a15, 0",
+        "rotw 4",
+        // Zero the registers in the current window
+        "movi a3, 0",
+        "movi a4, 0",
+        "movi a5, 0",
+        "movi a6, 0",
+        "movi a8, 0",
+        "movi a9, 0",
+        "movi a10, 0",
+        "movi a11, 0",
+        "movi a12, 0",
+        "movi a13, 0",
+        "movi a14, 0",
+        "movi a15, 0",
+        "wsr.sar a3",
+        #[cfg(target_feature = "fp")]
+        "wfr f0, a3",
+        #[cfg(target_feature = "fp")]
+        "wfr f1, a3",
+        #[cfg(target_feature = "fp")]
+        "wfr f2, a3",
+        #[cfg(target_feature = "fp")]
+        "wfr f3, a3",
+        #[cfg(target_feature = "fp")]
+        "wfr f4, a3",
+        #[cfg(target_feature = "fp")]
+        "wfr f5, a3",
+        #[cfg(target_feature = "fp")]
+        "wfr f6, a3",
+        #[cfg(target_feature = "fp")]
+        "wfr f7, a3",
+        #[cfg(target_feature = "fp")]
+        "wfr f8, a3",
+        #[cfg(target_feature = "fp")]
+        "wfr f9, a3",
+        #[cfg(target_feature = "fp")]
+        "wfr f10, a3",
+        #[cfg(target_feature = "fp")]
+        "wfr f11, a3",
+        #[cfg(target_feature = "fp")]
+        "wfr f12, a3",
+        #[cfg(target_feature = "fp")]
+        "wfr f13, a3",
+        #[cfg(target_feature = "fp")]
+        "wfr f14, a3",
+        #[cfg(target_feature = "fp")]
+        "wfr f15, a3",
+        #[cfg(target_feature = "fp")]
+        "wur.fcr a3",
+        #[cfg(target_feature = "fp")]
+        "wur.fsr a3",
+        "wsr.ps a2",
+        "rsync",
+        "movi a2, 0",
+        lateout("a2") _,
+        lateout("a3") _,
+        lateout("a4") _,
+        lateout("a5") _,
+        lateout("a6") _,
+        lateout("a8") _,
+        lateout("a9") _,
+        lateout("a10") _,
+        lateout("a11") _,
+        lateout("a12") _,
+        lateout("a13") _,
+        lateout("a14") _,
+        lateout("a15") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f0") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f1") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f2") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f3") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f4") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f5") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f6") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f7") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f8") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f9") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f10") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f11") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f12") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f13") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f14") _,
+        #[cfg(target_feature = "fp")]
+        lateout("f15") _,
+    )
+}